@@ -0,0 +1,42 @@
+//! Stores for the benchmarks, filled by `EventFactory` so results of
+//! different releases compare the same work.
+
+use std::path::PathBuf;
+
+use eventstore::backend::sqlite::SqliteBackend;
+pub use eventstore::testing::EventFactory;
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+/// A store in a temporary file.
+pub struct BenchStore {
+    pub backend: SqliteBackend,
+    path: PathBuf,
+}
+
+impl BenchStore {
+    pub fn empty() -> Self {
+        let path = std::env::temp_dir().join(format!("eventstore-bench-{}.db", Uuid::new_v4()));
+        Self {
+            backend: SqliteBackend::new(SqliteConnectionManager::file(&path)),
+            path,
+        }
+    }
+
+    /// A store with the events of `factory` appended.
+    pub fn populated(factory: &EventFactory) -> Self {
+        let store = Self::empty();
+        factory.populate(&store.backend).unwrap();
+        store
+    }
+}
+
+impl Drop for BenchStore {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
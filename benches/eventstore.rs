@@ -0,0 +1,153 @@
+//! Performance of the SQLite backend, run with `make bench`. To compare a
+//! change against the current state, run `make bench-baseline` before and
+//! `make bench-compare` after it.
+
+mod dataset;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use eventstore::backend::model::Event;
+use eventstore::backend::sqlite::GetAggOpts;
+use eventstore::ids;
+use uuid::Uuid;
+
+use dataset::{BenchStore, EventFactory};
+
+fn append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append");
+    let factory = EventFactory::new(1, 1_000);
+    for batch_size in [1u32, 10, 100] {
+        group.throughput(Throughput::Elements(batch_size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(batch_size),
+            &batch_size,
+            |b, &batch_size| {
+                let store = BenchStore::empty();
+                let mut version = 0;
+                b.iter_batched(
+                    || {
+                        let batch: Vec<Event> = (1..=batch_size)
+                            .map(|i| factory.event(0, version + i))
+                            .collect();
+                        version += batch_size;
+                        batch
+                    },
+                    |batch| store.backend.append_events(&batch).unwrap(),
+                    BatchSize::SmallInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+fn hydrate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hydrate");
+    for stream_length in [10u32, 100, 1_000, 10_000] {
+        // the stream to hydrate shares the store with others
+        let factory = EventFactory::new(10, stream_length);
+        let store = BenchStore::populated(&factory);
+        let aggregate_id = factory.aggregate_id(3);
+        group.throughput(Throughput::Elements(stream_length as u64));
+        group.bench_function(BenchmarkId::from_parameter(stream_length), |b| {
+            b.iter(|| store.backend.get_aggretate(aggregate_id).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn snapshot_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot_load");
+    let factory = EventFactory::new(10, 1_000);
+    let store = BenchStore::populated(&factory);
+    let aggregate_id = factory.aggregate_id(3);
+    let mut snapshot = factory.event(3, 900);
+    snapshot.data = vec![b' '; 4_096];
+    store.backend.save_snapshot(&snapshot).unwrap();
+    group.bench_function("snapshot", |b| {
+        b.iter(|| {
+            store
+                .backend
+                .get_snapshot_by_version(aggregate_id, 900)
+                .unwrap()
+        })
+    });
+    // what hydrating from the snapshot reads in total
+    group.bench_function("snapshot_and_tail", |b| {
+        b.iter(|| {
+            let snapshot = store
+                .backend
+                .get_snapshot_by_version(aggregate_id, 900)
+                .unwrap();
+            let opts = GetAggOpts {
+                agg_id: aggregate_id,
+                since_version: snapshot.version,
+            };
+            store
+                .backend
+                .get_aggretate_with_opts(aggregate_id, &opts)
+                .unwrap()
+        })
+    });
+    // the same in a single statement
+    group.bench_function("load_aggregate", |b| {
+        b.iter(|| store.backend.load_aggregate(aggregate_id).unwrap())
+    });
+    group.finish();
+}
+
+fn read_all(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_all");
+    group.sample_size(20);
+    let factory = EventFactory::new(100, 1_000);
+    let store = BenchStore::populated(&factory);
+    group.throughput(Throughput::Elements(factory.len() as u64));
+    for page_size in [100usize, 1_000] {
+        group.bench_function(BenchmarkId::new("scan", page_size), |b| {
+            b.iter(|| {
+                let mut position = 0;
+                loop {
+                    let events = store.backend.read_all(position, page_size).unwrap();
+                    match events.last() {
+                        Some(last) => position = last.position,
+                        None => break,
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// First events of new streams in a store of 10k streams, with random and
+/// with time-ordered aggregate ids.
+fn new_streams(c: &mut Criterion) {
+    let mut group = c.benchmark_group("new_streams");
+    let factory = EventFactory::new(10_000, 1);
+    for (name, new_id) in [
+        ("v4", Uuid::new_v4 as fn() -> Uuid),
+        ("v7", ids::ordered_id),
+    ] {
+        let store = BenchStore::populated(&factory);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || Event {
+                    id: new_id(),
+                    ..factory.event(0, 1)
+                },
+                |event| store.backend.append_event(&event).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    append,
+    hydrate,
+    snapshot_load,
+    read_all,
+    new_streams
+);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(feature = "grpc")]
+    {
+        let descriptors = protox::compile(["proto/eventstore.proto"], ["proto"])?;
+        tonic_prost_build::configure().compile_fds(descriptors)?;
+        let descriptors = protox::compile(["streams.proto"], ["proto/esdb"])?;
+        tonic_prost_build::configure().compile_fds(descriptors)?;
+    }
+    Ok(())
+}
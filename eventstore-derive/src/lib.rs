@@ -0,0 +1,126 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, LitInt, LitStr, Path};
+
+/// Derives `eventstore::event::DomainEvent`.
+///
+/// The event type defaults to the name of the type and can be overridden with
+/// `#[event(name = "...")]`, the schema version defaults to 1 and can be set
+/// via `#[event(schema_version = 2)]`. Non-generic types are also registered
+/// in the global `EventRegistry`.
+#[proc_macro_derive(DomainEvent, attributes(event))]
+pub fn derive_domain_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_domain_event(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_domain_event(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let mut name = ident.to_string();
+    let mut schema_version = 1u32;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("event")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("schema_version") {
+                schema_version = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported event attribute"))
+            }
+        })?;
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let registration = if input.generics.params.is_empty() {
+        quote! {
+            ::eventstore::inventory::submit! {
+                ::eventstore::event::Registration::new(#name, ::eventstore::event::decode_boxed::<#ident>)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::eventstore::event::DomainEvent for #ident #ty_generics #where_clause {
+            fn event_type() -> &'static str {
+                #name
+            }
+
+            fn schema_version() -> u32 {
+                #schema_version
+            }
+        }
+
+        #registration
+    })
+}
+
+/// Derives `eventstore::aggregate::Aggregate`.
+///
+/// The handled event types are listed via `#[aggregate(events(A, B, ...))]`,
+/// every one of them needs an `Apply` implementation on the aggregate.
+/// The aggregate type defaults to the name of the type and can be overridden
+/// with `#[aggregate(name = "...")]`.
+#[proc_macro_derive(Aggregate, attributes(aggregate))]
+pub fn derive_aggregate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_aggregate(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_aggregate(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let mut name = ident.to_string();
+    let mut events: Vec<Path> = Vec::new();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("aggregate"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("events") {
+                meta.parse_nested_meta(|event| {
+                    events.push(event.path);
+                    Ok(())
+                })
+            } else {
+                Err(meta.error("unsupported aggregate attribute"))
+            }
+        })?;
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::eventstore::aggregate::Aggregate for #ident #ty_generics #where_clause {
+            fn aggregate_type() -> &'static str {
+                #name
+            }
+
+            fn apply_event(
+                &mut self,
+                event: &::eventstore::backend::model::Event,
+            ) -> ::std::result::Result<(), ::eventstore::backend::sqlite::Error> {
+                #(
+                    if event.event_type == <#events as ::eventstore::event::DomainEvent>::event_type() {
+                        ::eventstore::aggregate::Apply::<#events>::apply(self, event.decode::<#events>()?);
+                        return ::std::result::Result::Ok(());
+                    }
+                )*
+                ::std::result::Result::Err(::eventstore::backend::sqlite::Error::UnknownEventType(
+                    event.event_type.clone(),
+                ))
+            }
+        }
+    })
+}
@@ -0,0 +1,182 @@
+/* Generated by cbindgen from eventstore-ffi, do not edit. */
+
+#ifndef EVENTSTORE_H
+#define EVENTSTORE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Outcome of a call.
+ */
+typedef enum EsStatus {
+  ES_STATUS_OK = 0,
+  ES_STATUS_NOT_FOUND = 1,
+  /**
+   * The stream is not at the expected version.
+   */
+  ES_STATUS_CONFLICT = 2,
+  ES_STATUS_INVALID_ARGUMENT = 3,
+  ES_STATUS_STREAM_DELETED = 4,
+  ES_STATUS_READ_ONLY = 5,
+  ES_STATUS_UNAUTHORIZED = 6,
+  ES_STATUS_ERROR = 7,
+} EsStatus;
+
+/**
+ * Result of a read, owns the events and their buffers.
+ */
+typedef struct EsEvents EsEvents;
+
+/**
+ * An open database, created by `es_open` and released by `es_close`.
+ */
+typedef struct EsStore EsStore;
+
+/**
+ * Event to append.
+ */
+typedef struct EsNewEvent {
+  /**
+   * NUL terminated event type.
+   */
+  const char *event_type;
+  /**
+   * NUL terminated content type, JSON if null.
+   */
+  const char *content_type;
+  const uint8_t *data;
+  size_t data_len;
+} EsNewEvent;
+
+/**
+ * A stored event, valid as long as the `EsEvents` it was taken from.
+ */
+typedef struct EsEvent {
+  uint8_t aggregate_id[16];
+  uint32_t version;
+  uint64_t position;
+  uint32_t schema_version;
+  const char *event_type;
+  const char *content_type;
+  const uint8_t *data;
+  size_t data_len;
+  /**
+   * Metadata as a NUL terminated JSON object.
+   */
+  const char *metadata_json;
+} EsEvent;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Open or create the database at `path`, migrating it to the current
+ * schema, and store the handle in `out`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL terminated string and `out` a valid pointer.
+ */
+enum EsStatus es_open(const char *path, struct EsStore **out);
+
+/**
+ * Open the database at `path` for reading only.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL terminated string and `out` a valid pointer.
+ */
+enum EsStatus es_open_read_only(const char *path, struct EsStore **out);
+
+/**
+ * Close a store opened by `es_open`, null is ignored.
+ *
+ * # Safety
+ *
+ * `store` must come from `es_open` and not be used afterwards.
+ */
+void es_close(struct EsStore *store);
+
+/**
+ * Append `len` events to the stream `aggregate_id`, failing with
+ * `ES_STATUS_CONFLICT` unless the stream is at `expected_version`.
+ *
+ * # Safety
+ *
+ * `aggregate_id` must point to 16 bytes and `events` to `len` events
+ * whose strings are NUL terminated and whose data holds `data_len` bytes.
+ */
+enum EsStatus es_append(const struct EsStore *store,
+                        const uint8_t *aggregate_id,
+                        uint32_t expected_version,
+                        const struct EsNewEvent *events,
+                        size_t len);
+
+/**
+ * Read the events of the stream `aggregate_id` after `since_version`.
+ *
+ * # Safety
+ *
+ * `aggregate_id` must point to 16 bytes and `out` be a valid pointer.
+ */
+enum EsStatus es_read_stream(const struct EsStore *store,
+                             const uint8_t *aggregate_id,
+                             uint32_t since_version,
+                             struct EsEvents **out);
+
+/**
+ * Read up to `limit` events of all streams after `from_position` in
+ * commit order.
+ *
+ * # Safety
+ *
+ * `out` must be a valid pointer.
+ */
+enum EsStatus es_read_all(const struct EsStore *store,
+                          uint64_t from_position,
+                          size_t limit,
+                          struct EsEvents **out);
+
+/**
+ * Number of events in a read result.
+ *
+ * # Safety
+ *
+ * `events` must come from a read and not be freed yet.
+ */
+size_t es_events_len(const struct EsEvents *events);
+
+/**
+ * The event at `index` of a read result, null if out of range.
+ *
+ * # Safety
+ *
+ * `events` must come from a read and not be freed yet.
+ */
+const struct EsEvent *es_events_get(const struct EsEvents *events, size_t index);
+
+/**
+ * Release a read result, null is ignored.
+ *
+ * # Safety
+ *
+ * `events` must come from a read and not be used afterwards.
+ */
+void es_events_free(struct EsEvents *events);
+
+/**
+ * Message of the last failure on the calling thread, null if none. Valid
+ * until the next call on the thread.
+ */
+const char *es_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EVENTSTORE_H */
@@ -0,0 +1,380 @@
+//! C API of the event store for embedding in C and C++ applications, see
+//! `include/eventstore.h`.
+//!
+//! Functions return an `EsStatus`, details of the last failure on the
+//! calling thread are available from `es_last_error`. Aggregate ids are
+//! passed as 16 raw UUID bytes. Results of reads are owned by the caller
+//! and released with `es_events_free`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use eventstore::backend::model::Event;
+use eventstore::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use eventstore::codec;
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+/// Outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EsStatus {
+    Ok = 0,
+    NotFound = 1,
+    /// The stream is not at the expected version.
+    Conflict = 2,
+    InvalidArgument = 3,
+    StreamDeleted = 4,
+    ReadOnly = 5,
+    Unauthorized = 6,
+    Error = 7,
+}
+
+/// An open database, created by `es_open` and released by `es_close`.
+pub struct EsStore {
+    backend: SqliteBackend,
+}
+
+/// Event to append.
+#[repr(C)]
+pub struct EsNewEvent {
+    /// NUL terminated event type.
+    pub event_type: *const c_char,
+    /// NUL terminated content type, JSON if null.
+    pub content_type: *const c_char,
+    pub data: *const u8,
+    pub data_len: usize,
+}
+
+/// A stored event, valid as long as the `EsEvents` it was taken from.
+#[repr(C)]
+pub struct EsEvent {
+    pub aggregate_id: [u8; 16],
+    pub version: u32,
+    pub position: u64,
+    pub schema_version: u32,
+    pub event_type: *const c_char,
+    pub content_type: *const c_char,
+    pub data: *const u8,
+    pub data_len: usize,
+    /// Metadata as a NUL terminated JSON object.
+    pub metadata_json: *const c_char,
+}
+
+/// Result of a read, owns the events and their buffers.
+pub struct EsEvents {
+    events: Vec<EsEvent>,
+    _strings: Vec<CString>,
+    _data: Vec<Vec<u8>>,
+}
+
+impl EsEvents {
+    fn new(events: Vec<Event>) -> Result<Self, Error> {
+        let mut owned = EsEvents {
+            events: Vec::with_capacity(events.len()),
+            _strings: Vec::with_capacity(events.len() * 3),
+            _data: Vec::with_capacity(events.len()),
+        };
+        for event in events {
+            let mut string = |value: String| -> Result<*const c_char, Error> {
+                let value = CString::new(value)
+                    .map_err(|_| Error::WithMsg("string contains a NUL byte".to_string()))?;
+                // the heap buffer stays in place when the CString is moved
+                let pointer = value.as_ptr();
+                owned._strings.push(value);
+                Ok(pointer)
+            };
+            let event_type = string(event.event_type)?;
+            let content_type = string(event.content_type)?;
+            let metadata_json = string(serde_json::to_string(&event.metadata)?)?;
+            owned.events.push(EsEvent {
+                aggregate_id: *event.id.as_bytes(),
+                version: event.version,
+                position: event.position,
+                schema_version: event.schema_version,
+                event_type,
+                content_type,
+                data: event.data.as_ptr(),
+                data_len: event.data.len(),
+                metadata_json,
+            });
+            owned._data.push(event.data);
+        }
+        Ok(owned)
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn status(err: Error) -> EsStatus {
+    let status = match err {
+        Error::NotFound => EsStatus::NotFound,
+        Error::VersionConflict => EsStatus::Conflict,
+        Error::InvalidUUID
+        | Error::UnknownEventType(_)
+        | Error::UnexpectedEventType { .. }
+        | Error::SchemaViolation(_)
+        | Error::SchemaVersionMismatch { .. }
+        | Error::Codec(_)
+        | Error::PayloadTooLarge { .. } => EsStatus::InvalidArgument,
+        Error::StreamDeleted(_) => EsStatus::StreamDeleted,
+        Error::ReadOnly => EsStatus::ReadOnly,
+        Error::Unauthorized(_) => EsStatus::Unauthorized,
+        _ => EsStatus::Error,
+    };
+    set_last_error(err.to_string());
+    status
+}
+
+fn invalid(message: &str) -> EsStatus {
+    set_last_error(message.to_string());
+    EsStatus::InvalidArgument
+}
+
+/// Run `f`, turning errors and panics into a status.
+fn guard<F: FnOnce() -> Result<(), EsStatus>>(f: F) -> EsStatus {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => EsStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => {
+            set_last_error("panic in eventstore".to_string());
+            EsStatus::Error
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(value: *const c_char, name: &str) -> Result<&'a str, EsStatus> {
+    if value.is_null() {
+        return Err(invalid(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| invalid(&format!("{} is not UTF-8", name)))
+}
+
+unsafe fn id_arg(aggregate_id: *const u8) -> Result<Uuid, EsStatus> {
+    if aggregate_id.is_null() {
+        return Err(invalid("aggregate_id is null"));
+    }
+    Ok(Uuid::from_bytes(*(aggregate_id as *const [u8; 16])))
+}
+
+unsafe fn store_arg<'a>(store: *const EsStore) -> Result<&'a SqliteBackend, EsStatus> {
+    store
+        .as_ref()
+        .map(|store| &store.backend)
+        .ok_or_else(|| invalid("store is null"))
+}
+
+unsafe fn open(
+    path: *const c_char,
+    out: *mut *mut EsStore,
+    open: fn(&str) -> Result<SqliteBackend, Error>,
+) -> EsStatus {
+    guard(|| {
+        let path = str_arg(path, "path")?;
+        if out.is_null() {
+            return Err(invalid("out is null"));
+        }
+        let backend = open(path).map_err(status)?;
+        *out = Box::into_raw(Box::new(EsStore { backend }));
+        Ok(())
+    })
+}
+
+/// Open or create the database at `path`, migrating it to the current
+/// schema, and store the handle in `out`.
+///
+/// # Safety
+///
+/// `path` must be a NUL terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn es_open(path: *const c_char, out: *mut *mut EsStore) -> EsStatus {
+    open(path, out, |path| {
+        SqliteBackend::try_with_tables(SqliteConnectionManager::file(path), Default::default())
+    })
+}
+
+/// Open the database at `path` for reading only.
+///
+/// # Safety
+///
+/// `path` must be a NUL terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn es_open_read_only(
+    path: *const c_char,
+    out: *mut *mut EsStore,
+) -> EsStatus {
+    open(path, out, |path| SqliteBackend::read_only(path))
+}
+
+/// Close a store opened by `es_open`, null is ignored.
+///
+/// # Safety
+///
+/// `store` must come from `es_open` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn es_close(store: *mut EsStore) {
+    if !store.is_null() {
+        drop(Box::from_raw(store));
+    }
+}
+
+/// Append `len` events to the stream `aggregate_id`, failing with
+/// `ES_STATUS_CONFLICT` unless the stream is at `expected_version`.
+///
+/// # Safety
+///
+/// `aggregate_id` must point to 16 bytes and `events` to `len` events
+/// whose strings are NUL terminated and whose data holds `data_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn es_append(
+    store: *const EsStore,
+    aggregate_id: *const u8,
+    expected_version: u32,
+    events: *const EsNewEvent,
+    len: usize,
+) -> EsStatus {
+    guard(|| {
+        let backend = store_arg(store)?;
+        let id = id_arg(aggregate_id)?;
+        if events.is_null() && len > 0 {
+            return Err(invalid("events is null"));
+        }
+        let new_events = match len {
+            0 => &[][..],
+            len => std::slice::from_raw_parts(events, len),
+        };
+        let mut events = Vec::with_capacity(len);
+        for (event, version) in new_events.iter().zip(expected_version + 1..) {
+            let content_type = if event.content_type.is_null() {
+                codec::JSON.to_string()
+            } else {
+                str_arg(event.content_type, "content_type")?.to_string()
+            };
+            let data = match event.data_len {
+                0 => Vec::new(),
+                _ if event.data.is_null() => return Err(invalid("data is null")),
+                len => std::slice::from_raw_parts(event.data, len).to_vec(),
+            };
+            events.push(Event {
+                id,
+                version,
+                event_type: str_arg(event.event_type, "event_type")?.to_string(),
+                content_type,
+                data,
+                ..Default::default()
+            });
+        }
+        backend.append_events(&events).map_err(status)
+    })
+}
+
+unsafe fn read(
+    out: *mut *mut EsEvents,
+    read: impl FnOnce() -> Result<Vec<Event>, Error>,
+) -> Result<(), EsStatus> {
+    if out.is_null() {
+        return Err(invalid("out is null"));
+    }
+    let events = read().and_then(EsEvents::new).map_err(status)?;
+    *out = Box::into_raw(Box::new(events));
+    Ok(())
+}
+
+/// Read the events of the stream `aggregate_id` after `since_version`.
+///
+/// # Safety
+///
+/// `aggregate_id` must point to 16 bytes and `out` be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn es_read_stream(
+    store: *const EsStore,
+    aggregate_id: *const u8,
+    since_version: u32,
+    out: *mut *mut EsEvents,
+) -> EsStatus {
+    guard(|| {
+        let backend = store_arg(store)?;
+        let id = id_arg(aggregate_id)?;
+        let opts = GetAggOpts {
+            agg_id: id,
+            since_version,
+        };
+        read(out, || backend.get_aggretate_with_opts(id, &opts))
+    })
+}
+
+/// Read up to `limit` events of all streams after `from_position` in
+/// commit order.
+///
+/// # Safety
+///
+/// `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn es_read_all(
+    store: *const EsStore,
+    from_position: u64,
+    limit: usize,
+    out: *mut *mut EsEvents,
+) -> EsStatus {
+    guard(|| {
+        let backend = store_arg(store)?;
+        read(out, || backend.read_all(from_position, limit))
+    })
+}
+
+/// Number of events in a read result.
+///
+/// # Safety
+///
+/// `events` must come from a read and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn es_events_len(events: *const EsEvents) -> usize {
+    events.as_ref().map_or(0, |events| events.events.len())
+}
+
+/// The event at `index` of a read result, null if out of range.
+///
+/// # Safety
+///
+/// `events` must come from a read and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn es_events_get(events: *const EsEvents, index: usize) -> *const EsEvent {
+    events
+        .as_ref()
+        .and_then(|events| events.events.get(index))
+        .map_or(ptr::null(), |event| event as *const EsEvent)
+}
+
+/// Release a read result, null is ignored.
+///
+/// # Safety
+///
+/// `events` must come from a read and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn es_events_free(events: *mut EsEvents) {
+    if !events.is_null() {
+        drop(Box::from_raw(events));
+    }
+}
+
+/// Message of the last failure on the calling thread, null if none. Valid
+/// until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn es_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
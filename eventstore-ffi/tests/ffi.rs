@@ -0,0 +1,75 @@
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use eventstore_ffi::*;
+
+#[test]
+fn appends_and_reads_through_the_c_api() {
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+    let aggregate_id = *uuid::Uuid::new_v4().as_bytes();
+    let event_type = CString::new("Deposited").unwrap();
+    let data = br#"{"amount":5}"#;
+    let event = EsNewEvent {
+        event_type: event_type.as_ptr(),
+        content_type: ptr::null(),
+        data: data.as_ptr(),
+        data_len: data.len(),
+    };
+
+    unsafe {
+        let mut store = ptr::null_mut();
+        assert_eq!(es_open(c_path.as_ptr(), &mut store), EsStatus::Ok);
+        let events = [EsNewEvent { ..event }, EsNewEvent { ..event }];
+        assert_eq!(
+            es_append(store, aggregate_id.as_ptr(), 0, events.as_ptr(), 2),
+            EsStatus::Ok
+        );
+        assert_eq!(
+            es_append(store, aggregate_id.as_ptr(), 0, &event, 1),
+            EsStatus::Conflict
+        );
+        assert!(!es_last_error().is_null());
+
+        let mut events = ptr::null_mut();
+        assert_eq!(
+            es_read_stream(store, aggregate_id.as_ptr(), 1, &mut events),
+            EsStatus::Ok
+        );
+        assert_eq!(es_events_len(events), 1);
+        let read = &*es_events_get(events, 0);
+        assert_eq!(read.aggregate_id, aggregate_id);
+        assert_eq!(read.version, 2);
+        assert_eq!(CStr::from_ptr(read.event_type).to_str(), Ok("Deposited"));
+        assert_eq!(
+            CStr::from_ptr(read.content_type).to_str(),
+            Ok("application/json")
+        );
+        assert_eq!(std::slice::from_raw_parts(read.data, read.data_len), data);
+        assert_eq!(CStr::from_ptr(read.metadata_json).to_str(), Ok("{}"));
+        assert!(es_events_get(events, 1).is_null());
+        es_events_free(events);
+
+        let mut events = ptr::null_mut();
+        assert_eq!(es_read_all(store, 0, 10, &mut events), EsStatus::Ok);
+        assert_eq!(es_events_len(events), 2);
+        es_events_free(events);
+        es_close(store);
+
+        let mut reader = ptr::null_mut();
+        assert_eq!(
+            es_open_read_only(c_path.as_ptr(), &mut reader),
+            EsStatus::Ok
+        );
+        assert_eq!(
+            es_append(reader, aggregate_id.as_ptr(), 2, &event, 1),
+            EsStatus::ReadOnly
+        );
+        assert_eq!(
+            es_append(ptr::null(), aggregate_id.as_ptr(), 2, &event, 1),
+            EsStatus::InvalidArgument
+        );
+        es_close(reader);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,29 @@
+'use strict'
+
+// Loads the addon built by `napi build --platform`, named after the
+// platform, e.g. `eventstore.linux-x64-gnu.node`.
+const { readdirSync } = require('fs')
+const { join } = require('path')
+
+const prefix = `eventstore.${process.platform}-${process.arch}`
+const binary = readdirSync(__dirname).find(
+  (file) => file.startsWith(prefix) && file.endsWith('.node'),
+)
+if (!binary) {
+  throw new Error(`no eventstore addon for ${process.platform}-${process.arch}, run npm run build`)
+}
+const addon = require(join(__dirname, binary))
+
+addon.Subscription.prototype[Symbol.asyncIterator] = async function* () {
+  try {
+    for (;;) {
+      const event = await this.next()
+      if (event === null) return
+      yield event
+    }
+  } finally {
+    this.close()
+  }
+}
+
+module.exports = addon
@@ -0,0 +1,297 @@
+//! Node.js addon, built with `napi build --platform --release` from this
+//! directory and loaded through `index.js`.
+//!
+//! ```js
+//! const { EventStore } = require('minimal-eventstore')
+//!
+//! const store = new EventStore('events.db')
+//! await store.append(orderId, 0, [{ eventType: 'OrderPlaced', data: '{"total":5}' }])
+//! const events = await store.readStream(orderId)
+//! for await (const event of store.subscribe({ fromPosition: 0 })) { ... }
+//! ```
+//!
+//! Database calls run on a blocking thread pool, so they never stall the
+//! event loop. Failures reject with an error whose message starts with
+//! its kind: `ConcurrencyError`, `NotFound`, `ReadOnly`, `InvalidArgument`
+//! or `EventStoreError`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use eventstore::backend::model::Event as StoredEvent;
+use eventstore::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use eventstore::codec;
+use napi::bindgen_prelude::{Buffer, Either};
+use napi_derive::napi;
+use r2d2_sqlite::SqliteConnectionManager;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Events fetched per query by subscriptions.
+const PAGE: usize = 256;
+const DEFAULT_POLL_INTERVAL_MS: u32 = 100;
+
+fn js_error(kind: &str, reason: impl std::fmt::Display) -> napi::Error {
+    napi::Error::from_reason(format!("{}: {}", kind, reason))
+}
+
+fn to_js_error(err: Error) -> napi::Error {
+    let code = match err {
+        Error::VersionConflict => "ConcurrencyError",
+        Error::NotFound => "NotFound",
+        Error::ReadOnly => "ReadOnly",
+        _ => "EventStoreError",
+    };
+    js_error(code, err)
+}
+
+fn invalid(reason: impl std::fmt::Display) -> napi::Error {
+    js_error("InvalidArgument", reason)
+}
+
+fn parse_id(id: &str) -> napi::Result<Uuid> {
+    Uuid::parse_str(id).map_err(invalid)
+}
+
+/// Run a database call on the blocking pool.
+async fn blocking<T, F>(f: F) -> napi::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| js_error("EventStoreError", err))?
+        .map_err(to_js_error)
+}
+
+/// Event to append.
+#[napi(object)]
+pub struct NewEvent {
+    pub event_type: String,
+    /// Strings are stored as UTF-8.
+    pub data: Either<String, Buffer>,
+    /// Defaults to `application/json`.
+    pub content_type: Option<String>,
+}
+
+/// A stored event.
+#[napi(object)]
+pub struct Event {
+    pub aggregate_id: String,
+    pub version: u32,
+    /// Position in `$all`, exact up to 2^53.
+    pub position: i64,
+    pub event_type: String,
+    pub schema_version: u32,
+    pub content_type: String,
+    pub data: Buffer,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<StoredEvent> for Event {
+    fn from(event: StoredEvent) -> Self {
+        Self {
+            aggregate_id: event.id.to_string(),
+            version: event.version,
+            position: event.position as i64,
+            event_type: event.event_type,
+            schema_version: event.schema_version,
+            content_type: event.content_type,
+            data: event.data.into(),
+            metadata: event.metadata,
+        }
+    }
+}
+
+fn to_js(events: Vec<StoredEvent>) -> Vec<Event> {
+    events.into_iter().map(Event::from).collect()
+}
+
+#[napi(object)]
+pub struct SubscribeOptions {
+    /// Deliver events committed after this position, 0 for all.
+    pub from_position: Option<i64>,
+    /// Only events of this stream.
+    pub aggregate_id: Option<String>,
+    /// How often to look for new events once caught up.
+    pub poll_interval_ms: Option<u32>,
+}
+
+/// An event database, opened or created at `path`.
+#[napi]
+pub struct EventStore {
+    backend: SqliteBackend,
+}
+
+#[napi]
+impl EventStore {
+    #[napi(constructor)]
+    pub fn new(path: String, read_only: Option<bool>) -> napi::Result<Self> {
+        let backend = match read_only.unwrap_or(false) {
+            true => SqliteBackend::read_only(&path),
+            false => SqliteBackend::try_with_tables(
+                SqliteConnectionManager::file(&path),
+                Default::default(),
+            ),
+        }
+        .map_err(to_js_error)?;
+        Ok(Self { backend })
+    }
+
+    /// Append `events` to the stream `aggregateId`, rejecting with
+    /// `ConcurrencyError` unless it is at `expectedVersion`.
+    #[napi]
+    pub async fn append(
+        &self,
+        aggregate_id: String,
+        expected_version: u32,
+        events: Vec<NewEvent>,
+    ) -> napi::Result<()> {
+        let id = parse_id(&aggregate_id)?;
+        let events: Vec<StoredEvent> = events
+            .into_iter()
+            .zip(expected_version + 1..)
+            .map(|(event, version)| StoredEvent {
+                id,
+                version,
+                event_type: event.event_type,
+                content_type: event
+                    .content_type
+                    .unwrap_or_else(|| codec::JSON.to_string()),
+                data: match event.data {
+                    Either::A(text) => text.into_bytes(),
+                    Either::B(buffer) => buffer.to_vec(),
+                },
+                ..Default::default()
+            })
+            .collect();
+        let backend = self.backend.clone();
+        blocking(move || backend.append_events(&events)).await
+    }
+
+    /// Version of the stream `aggregateId`, 0 if it has no events.
+    #[napi]
+    pub async fn stream_version(&self, aggregate_id: String) -> napi::Result<u32> {
+        let id = parse_id(&aggregate_id)?;
+        let backend = self.backend.clone();
+        blocking(move || backend.stream_version(id)).await
+    }
+
+    /// The events of the stream `aggregateId` after `sinceVersion`.
+    #[napi]
+    pub async fn read_stream(
+        &self,
+        aggregate_id: String,
+        since_version: Option<u32>,
+    ) -> napi::Result<Vec<Event>> {
+        let id = parse_id(&aggregate_id)?;
+        let opts = GetAggOpts {
+            agg_id: id,
+            since_version: since_version.unwrap_or(0),
+        };
+        let backend = self.backend.clone();
+        blocking(move || backend.get_aggretate_with_opts(id, &opts))
+            .await
+            .map(to_js)
+    }
+
+    /// Up to `limit` events of all streams after `fromPosition` in commit
+    /// order.
+    #[napi]
+    pub async fn read_all(
+        &self,
+        from_position: Option<i64>,
+        limit: u32,
+    ) -> napi::Result<Vec<Event>> {
+        let from = position(from_position)?;
+        let backend = self.backend.clone();
+        blocking(move || backend.read_all(from, limit as usize))
+            .await
+            .map(to_js)
+    }
+
+    /// Events of all streams, or of one, after `fromPosition`: first the
+    /// committed ones, then new ones as they are appended.
+    #[napi]
+    pub fn subscribe(&self, options: Option<SubscribeOptions>) -> napi::Result<Subscription> {
+        let options = options.unwrap_or(SubscribeOptions {
+            from_position: None,
+            aggregate_id: None,
+            poll_interval_ms: None,
+        });
+        let state = SubscriptionState {
+            position: position(options.from_position)?,
+            filter: options.aggregate_id.as_deref().map(parse_id).transpose()?,
+            buffer: VecDeque::new(),
+        };
+        Ok(Subscription {
+            backend: self.backend.clone(),
+            poll_interval: Duration::from_millis(
+                options.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS) as u64,
+            ),
+            state: Arc::new(Mutex::new(state)),
+            closed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+fn position(position: Option<i64>) -> napi::Result<u64> {
+    u64::try_from(position.unwrap_or(0)).map_err(|_| invalid("position must not be negative"))
+}
+
+struct SubscriptionState {
+    position: u64,
+    filter: Option<Uuid>,
+    buffer: VecDeque<StoredEvent>,
+}
+
+/// A running subscription, consumed with `next()` or `for await` through
+/// `index.js`.
+#[napi]
+pub struct Subscription {
+    backend: SqliteBackend,
+    poll_interval: Duration,
+    state: Arc<Mutex<SubscriptionState>>,
+    closed: Arc<AtomicBool>,
+}
+
+#[napi]
+impl Subscription {
+    /// The next event, waiting for one to be appended if caught up.
+    /// Resolves to `null` once the subscription is closed.
+    #[napi]
+    pub async fn next(&self) -> napi::Result<Option<Event>> {
+        let mut state = self.state.lock().await;
+        loop {
+            if self.closed.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            if let Some(event) = state.buffer.pop_front() {
+                return Ok(Some(event.into()));
+            }
+            let backend = self.backend.clone();
+            let from = state.position;
+            let events = blocking(move || backend.read_all(from, PAGE)).await?;
+            match events.last() {
+                Some(last) => state.position = last.position,
+                None => tokio::time::sleep(self.poll_interval).await,
+            }
+            let filter = state.filter;
+            state.buffer.extend(
+                events
+                    .into_iter()
+                    .filter(|event| filter.is_none_or(|id| id == event.id)),
+            );
+        }
+    }
+
+    /// Stop the subscription, pending and later `next()` calls resolve to
+    /// `null`.
+    #[napi]
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,49 @@
+'use strict'
+
+const assert = require('node:assert')
+const { randomUUID } = require('node:crypto')
+const { rmSync } = require('node:fs')
+const { tmpdir } = require('node:os')
+const { join } = require('node:path')
+const { test } = require('node:test')
+
+const { EventStore } = require('..')
+
+test('appends, reads and subscribes', async () => {
+  const path = join(tmpdir(), `eventstore-${randomUUID()}.db`)
+  const store = new EventStore(path)
+  const order = randomUUID()
+  await store.append(order, 0, [
+    { eventType: 'OrderPlaced', data: '{"total":5}' },
+    { eventType: 'OrderPaid', data: Buffer.from([1]), contentType: 'application/octet-stream' },
+  ])
+  await assert.rejects(store.append(order, 0, [{ eventType: 'OrderPlaced', data: '{}' }]), /^Error: ConcurrencyError/)
+
+  const events = await store.readStream(order)
+  assert.deepStrictEqual(events.map((e) => e.version), [1, 2])
+  assert.strictEqual(events[0].aggregateId, order)
+  assert.strictEqual(events[0].contentType, 'application/json')
+  assert.strictEqual(events[0].data.toString(), '{"total":5}')
+  assert.deepStrictEqual([...events[1].data], [1])
+  assert.deepStrictEqual((await store.readStream(order, 1)).map((e) => e.version), [2])
+  assert.strictEqual(await store.streamVersion(order), 2)
+
+  await store.append(randomUUID(), 0, [{ eventType: 'OrderPlaced', data: '{}' }])
+  assert.deepStrictEqual((await store.readAll(1, 10)).map((e) => e.position), [2, 3])
+
+  const subscription = store.subscribe({ aggregateId: order, pollIntervalMs: 10 })
+  const seen = []
+  for await (const event of subscription) {
+    seen.push(event.version)
+    if (seen.length === 2) {
+      await store.append(order, 2, [{ eventType: 'OrderShipped', data: '{}' }])
+    }
+    if (seen.length === 3) break
+  }
+  assert.deepStrictEqual(seen, [1, 2, 3])
+  assert.strictEqual(await subscription.next(), null)
+
+  const reader = new EventStore(path, true)
+  await assert.rejects(reader.append(order, 3, [{ eventType: 'OrderShipped', data: '{}' }]), /^Error: ReadOnly/)
+  rmSync(path)
+})
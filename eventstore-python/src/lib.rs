@@ -0,0 +1,326 @@
+//! Python module `eventstore_py`, built with `maturin build` from this
+//! directory.
+//!
+//! ```python
+//! import uuid
+//! from eventstore_py import Store
+//!
+//! store = Store("events.db")
+//! order = uuid.uuid4()
+//! store.append(order, 0, [("OrderPlaced", '{"total": 5}')])
+//! for event in store.read_stream(order):
+//!     print(event.version, event.event_type, event.data)
+//! for event in store.subscribe(from_position=0):
+//!     ...
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use eventstore::backend::model::Event as StoredEvent;
+use eventstore::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use eventstore::codec;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+/// Events fetched per query while iterating `$all`.
+const PAGE: usize = 256;
+
+create_exception!(eventstore_py, EventStoreError, PyException);
+create_exception!(eventstore_py, ConcurrencyError, EventStoreError);
+create_exception!(eventstore_py, NotFoundError, EventStoreError);
+create_exception!(eventstore_py, ReadOnlyError, EventStoreError);
+
+fn to_py_err(err: Error) -> PyErr {
+    match err {
+        Error::VersionConflict => ConcurrencyError::new_err(err.to_string()),
+        Error::NotFound => NotFoundError::new_err(err.to_string()),
+        Error::ReadOnly => ReadOnlyError::new_err(err.to_string()),
+        err => EventStoreError::new_err(err.to_string()),
+    }
+}
+
+/// Aggregate ids are accepted as `uuid.UUID` or their string form.
+#[derive(FromPyObject)]
+enum AggregateId {
+    Uuid(Uuid),
+    Text(String),
+}
+
+impl AggregateId {
+    fn parse(self) -> PyResult<Uuid> {
+        match self {
+            AggregateId::Uuid(id) => Ok(id),
+            AggregateId::Text(id) => {
+                Uuid::parse_str(&id).map_err(|err| PyValueError::new_err(err.to_string()))
+            }
+        }
+    }
+}
+
+/// Payloads are accepted as `bytes` or `str`, the latter encoded as UTF-8.
+#[derive(FromPyObject)]
+enum Data {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+/// A stored event.
+#[pyclass(frozen, module = "eventstore_py")]
+pub struct Event {
+    #[pyo3(get)]
+    aggregate_id: Uuid,
+    #[pyo3(get)]
+    version: u32,
+    #[pyo3(get)]
+    position: u64,
+    #[pyo3(get)]
+    event_type: String,
+    #[pyo3(get)]
+    schema_version: u32,
+    #[pyo3(get)]
+    content_type: String,
+    #[pyo3(get)]
+    metadata: BTreeMap<String, String>,
+    data: Vec<u8>,
+}
+
+impl From<StoredEvent> for Event {
+    fn from(event: StoredEvent) -> Self {
+        Self {
+            aggregate_id: event.id,
+            version: event.version,
+            position: event.position,
+            event_type: event.event_type,
+            schema_version: event.schema_version,
+            content_type: event.content_type,
+            metadata: event.metadata,
+            data: event.data,
+        }
+    }
+}
+
+#[pymethods]
+impl Event {
+    /// The payload as `bytes`.
+    #[getter]
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Event(aggregate_id='{}', version={}, position={}, event_type='{}')",
+            self.aggregate_id, self.version, self.position, self.event_type
+        )
+    }
+}
+
+enum Source {
+    Stream { id: Uuid, since_version: u32 },
+    All { position: u64, filter: Option<Uuid> },
+}
+
+/// Iterator over events, fetching them as it goes. Subscriptions wait for
+/// new events instead of stopping at the end.
+#[pyclass(module = "eventstore_py")]
+pub struct Events {
+    backend: SqliteBackend,
+    source: Source,
+    buffer: VecDeque<StoredEvent>,
+    done: bool,
+    poll_interval: Option<Duration>,
+}
+
+impl Events {
+    fn fetch(&mut self) -> Result<(), Error> {
+        match &mut self.source {
+            Source::Stream { id, since_version } => {
+                let opts = GetAggOpts {
+                    agg_id: *id,
+                    since_version: *since_version,
+                };
+                let events = self.backend.get_aggretate_with_opts(*id, &opts)?;
+                if let Some(last) = events.last() {
+                    *since_version = last.version;
+                }
+                self.done = self.poll_interval.is_none();
+                self.buffer.extend(events);
+            }
+            Source::All { position, filter } => {
+                let events = self.backend.read_all(*position, PAGE)?;
+                if let Some(last) = events.last() {
+                    *position = last.position;
+                }
+                self.done = self.poll_interval.is_none() && events.len() < PAGE;
+                self.buffer.extend(
+                    events
+                        .into_iter()
+                        .filter(|event| filter.is_none_or(|id| id == event.id)),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl Events {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Event>> {
+        loop {
+            if let Some(event) = self.buffer.pop_front() {
+                return Ok(Some(event.into()));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            py.detach(|| self.fetch()).map_err(to_py_err)?;
+            if self.buffer.is_empty() {
+                match self.poll_interval {
+                    Some(interval) => {
+                        py.detach(|| std::thread::sleep(interval));
+                        // let KeyboardInterrupt end a subscription
+                        py.check_signals()?;
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+    }
+}
+
+/// An event database, opened or created at `path`.
+#[pyclass(frozen, module = "eventstore_py")]
+pub struct Store {
+    backend: SqliteBackend,
+}
+
+#[pymethods]
+impl Store {
+    #[new]
+    #[pyo3(signature = (path, read_only = false))]
+    fn new(py: Python<'_>, path: String, read_only: bool) -> PyResult<Self> {
+        let backend = py
+            .detach(|| match read_only {
+                true => SqliteBackend::read_only(&path),
+                false => SqliteBackend::try_with_tables(
+                    SqliteConnectionManager::file(&path),
+                    Default::default(),
+                ),
+            })
+            .map_err(to_py_err)?;
+        Ok(Self { backend })
+    }
+
+    /// Append `(event_type, data)` pairs to the stream `aggregate_id`,
+    /// raising `ConcurrencyError` unless it is at `expected_version`.
+    #[pyo3(signature = (aggregate_id, expected_version, events, content_type = codec::JSON.to_string()))]
+    fn append(
+        &self,
+        py: Python<'_>,
+        aggregate_id: AggregateId,
+        expected_version: u32,
+        events: Vec<(String, Data)>,
+        content_type: String,
+    ) -> PyResult<()> {
+        let id = aggregate_id.parse()?;
+        let events: Vec<StoredEvent> = events
+            .into_iter()
+            .zip(expected_version + 1..)
+            .map(|((event_type, data), version)| StoredEvent {
+                id,
+                version,
+                event_type,
+                content_type: content_type.clone(),
+                data: match data {
+                    Data::Text(text) => text.into_bytes(),
+                    Data::Bytes(bytes) => bytes,
+                },
+                ..Default::default()
+            })
+            .collect();
+        py.detach(|| self.backend.append_events(&events))
+            .map_err(to_py_err)
+    }
+
+    /// Version of the stream `aggregate_id`, 0 if it has no events.
+    fn stream_version(&self, py: Python<'_>, aggregate_id: AggregateId) -> PyResult<u32> {
+        let id = aggregate_id.parse()?;
+        py.detach(|| self.backend.stream_version(id))
+            .map_err(to_py_err)
+    }
+
+    /// The events of the stream `aggregate_id` after `since_version`.
+    #[pyo3(signature = (aggregate_id, since_version = 0))]
+    fn read_stream(&self, aggregate_id: AggregateId, since_version: u32) -> PyResult<Events> {
+        Ok(self.events(
+            Source::Stream {
+                id: aggregate_id.parse()?,
+                since_version,
+            },
+            None,
+        ))
+    }
+
+    /// The events of all streams after `from_position` in commit order.
+    #[pyo3(signature = (from_position = 0))]
+    fn read_all(&self, from_position: u64) -> Events {
+        self.events(
+            Source::All {
+                position: from_position,
+                filter: None,
+            },
+            None,
+        )
+    }
+
+    /// Like `read_all`, optionally limited to the stream `aggregate_id`,
+    /// but waits for new events instead of stopping at the end.
+    #[pyo3(signature = (from_position = 0, aggregate_id = None, poll_interval = 0.1))]
+    fn subscribe(
+        &self,
+        from_position: u64,
+        aggregate_id: Option<AggregateId>,
+        poll_interval: f64,
+    ) -> PyResult<Events> {
+        let interval = Duration::try_from_secs_f64(poll_interval)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        let source = Source::All {
+            position: from_position,
+            filter: aggregate_id.map(AggregateId::parse).transpose()?,
+        };
+        Ok(self.events(source, Some(interval)))
+    }
+}
+
+impl Store {
+    fn events(&self, source: Source, poll_interval: Option<Duration>) -> Events {
+        Events {
+            backend: self.backend.clone(),
+            source,
+            buffer: VecDeque::new(),
+            done: false,
+            poll_interval,
+        }
+    }
+}
+
+#[pymodule]
+pub fn eventstore_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Store>()?;
+    m.add_class::<Event>()?;
+    m.add_class::<Events>()?;
+    let py = m.py();
+    m.add("EventStoreError", py.get_type::<EventStoreError>())?;
+    m.add("ConcurrencyError", py.get_type::<ConcurrencyError>())?;
+    m.add("NotFoundError", py.get_type::<NotFoundError>())?;
+    m.add("ReadOnlyError", py.get_type::<ReadOnlyError>())?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use pyo3::prelude::*;
+use pyo3::py_run;
+
+#[test]
+fn appends_reads_and_subscribes_from_python() {
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let path_str = path.to_str().unwrap().to_string();
+    Python::attach(|py| {
+        let es = pyo3::wrap_pymodule!(eventstore_py::eventstore_py)(py);
+        py_run!(
+            py,
+            es path_str,
+            r#"
+            import uuid
+
+            store = es.Store(path_str)
+            order = uuid.uuid4()
+            store.append(order, 0, [("OrderPlaced", '{"total": 5}'), ("OrderPaid", b"\x01")])
+            try:
+                store.append(str(order), 0, [("OrderPlaced", "{}")])
+                raise AssertionError("expected a conflict")
+            except es.ConcurrencyError:
+                pass
+
+            events = list(store.read_stream(order))
+            assert [e.version for e in events] == [1, 2]
+            assert events[0].aggregate_id == order
+            assert events[0].event_type == "OrderPlaced"
+            assert events[0].content_type == "application/json"
+            assert events[1].data == b"\x01"
+            assert [e.version for e in store.read_stream(order, since_version=1)] == [2]
+            assert store.stream_version(order) == 2
+
+            other = uuid.uuid4()
+            store.append(other, 0, [("OrderPlaced", "{}")])
+            assert [e.position for e in store.read_all(from_position=1)] == [2, 3]
+
+            subscription = store.subscribe(aggregate_id=order, poll_interval=0.01)
+            assert next(subscription).version == 1
+            assert next(subscription).version == 2
+            store.append(order, 2, [("OrderShipped", "{}")])
+            assert next(subscription).position == 4
+
+            reader = es.Store(path_str, read_only=True)
+            try:
+                reader.append(order, 3, [("OrderShipped", "{}")])
+                raise AssertionError("expected a read-only error")
+            except es.ReadOnlyError:
+                pass
+            "#
+        );
+    });
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,304 @@
+//! actix-web integration, the counterpart of `http` for applications on
+//! that framework:
+//!
+//! ```ignore
+//! HttpServer::new(move || {
+//!     App::new()
+//!         .app_data(actix::app_data(backend.clone()))
+//!         .configure(actix::configure)
+//! })
+//! ```
+//!
+//! Handlers of the application take the backend as `actix::Store`, return
+//! `StoreError` for store failures and `EventStream` for reads that should
+//! not be buffered in memory.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use actix_web::body::BoxBody;
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, Responder, ResponseError};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use crate::jsonl::Envelope;
+
+/// Content type of `EventStream` reads, one `Envelope` per line.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Content type of followed `EventStream`s, server-sent events.
+pub const EVENT_STREAM: &str = "text/event-stream";
+
+/// Events fetched per query while streaming a response.
+const PAGE: usize = 256;
+
+/// The backend as app data, extracted by handlers.
+pub type Store = web::Data<SqliteBackend>;
+
+/// App data for `App::app_data`, shared by all workers.
+pub fn app_data(backend: SqliteBackend) -> Store {
+    web::Data::new(backend)
+}
+
+/// Read endpoints on the `Store` of the app:
+///
+/// - `GET /streams/{id}?since_version=` streams the events of a stream
+/// - `GET /all?from_position=` streams all events in commit order
+/// - `GET /subscribe?from_position=&stream=` sends matching events as
+///   server-sent events, first the committed ones, then new ones as they
+///   are appended
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.route("/streams/{id}", web::get().to(read_stream))
+        .route("/all", web::get().to(read_all))
+        .route("/subscribe", web::get().to(subscribe));
+}
+
+/// Store error as a response with a JSON body `{"error": "..."}`, statuses
+/// match the `http` module.
+#[derive(Debug)]
+pub struct StoreError(pub Error);
+
+impl From<Error> for StoreError {
+    fn from(err: Error) -> Self {
+        Self(err)
+    }
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl ResponseError for StoreError {
+    fn status_code(&self) -> StatusCode {
+        match self.0 {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidUUID
+            | Error::UnknownEventType(_)
+            | Error::UnexpectedEventType { .. }
+            | Error::SchemaViolation(_)
+            | Error::SchemaVersionMismatch { .. }
+            | Error::Codec(_) => StatusCode::BAD_REQUEST,
+            Error::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::StreamDeleted(_) => StatusCode::GONE,
+            Error::StreamRenamed(_) => StatusCode::GONE,
+            Error::ReadOnly => StatusCode::METHOD_NOT_ALLOWED,
+            Error::VersionConflict | Error::WithMsg(_) => StatusCode::CONFLICT,
+            Error::Unauthorized(_) => StatusCode::FORBIDDEN,
+            Error::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({ "error": self.0.to_string() }))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Stream { id: Uuid, since_version: u32 },
+    All { position: u64, filter: Option<Uuid> },
+}
+
+impl Source {
+    fn fetch(&self, backend: &SqliteBackend) -> Result<Vec<Event>, Error> {
+        match *self {
+            Source::Stream { id, since_version } => {
+                let opts = GetAggOpts {
+                    agg_id: id,
+                    since_version,
+                };
+                let mut events = backend.get_aggretate_with_opts(id, &opts)?;
+                events.truncate(PAGE);
+                Ok(events)
+            }
+            Source::All { position, .. } => backend.read_all(position, PAGE),
+        }
+    }
+
+    /// Continue after `last`.
+    fn advance(&mut self, last: &Event) {
+        match self {
+            Source::Stream { since_version, .. } => *since_version = last.version,
+            Source::All { position, .. } => *position = last.position,
+        }
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        match self {
+            Source::Stream { .. } => true,
+            Source::All { filter, .. } => filter.is_none_or(|id| id == event.id),
+        }
+    }
+}
+
+/// Response streaming events page by page as they are read. By default the
+/// events are sent as JSON lines and the response ends with the last one;
+/// followed streams stay open and send server-sent events.
+#[derive(Debug, Clone)]
+pub struct EventStream {
+    backend: SqliteBackend,
+    source: Source,
+    follow: Option<Duration>,
+}
+
+impl EventStream {
+    /// The events of the stream `aggregate_id` after `since_version`.
+    pub fn stream(backend: SqliteBackend, aggregate_id: Uuid, since_version: u32) -> Self {
+        Self {
+            backend,
+            source: Source::Stream {
+                id: aggregate_id,
+                since_version,
+            },
+            follow: None,
+        }
+    }
+
+    /// The events of all streams after `from_position` in commit order.
+    pub fn all(backend: SqliteBackend, from_position: u64) -> Self {
+        Self {
+            backend,
+            source: Source::All {
+                position: from_position,
+                filter: None,
+            },
+            follow: None,
+        }
+    }
+
+    /// Only the events of `aggregate_id`, read from `$all` so positions are
+    /// kept.
+    pub fn with_stream_filter(mut self, aggregate_id: Uuid) -> Self {
+        if let Source::All { filter, .. } = &mut self.source {
+            *filter = Some(aggregate_id);
+        }
+        self
+    }
+
+    /// Keep the response open and look for new events every
+    /// `poll_interval` once caught up.
+    pub fn follow(mut self, poll_interval: Duration) -> Self {
+        self.follow = Some(poll_interval);
+        self
+    }
+
+    fn encode(follow: bool, event: &Event) -> Result<Bytes, actix_web::Error> {
+        let json = serde_json::to_string(&Envelope::from_event(event))?;
+        Ok(Bytes::from(match follow {
+            true => format!(
+                "id: {}\nevent: {}\ndata: {}\n\n",
+                event.position, event.event_type, json
+            ),
+            false => format!("{}\n", json),
+        }))
+    }
+}
+
+struct Cursor {
+    stream: EventStream,
+    buffer: VecDeque<Event>,
+    done: bool,
+}
+
+impl Responder for EventStream {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let follow = self.follow.is_some();
+        let cursor = Cursor {
+            stream: self,
+            buffer: VecDeque::new(),
+            done: false,
+        };
+        let body = futures_util::stream::unfold(cursor, move |mut cursor| async move {
+            loop {
+                if let Some(event) = cursor.buffer.pop_front() {
+                    return Some((EventStream::encode(follow, &event), cursor));
+                }
+                if cursor.done {
+                    return None;
+                }
+                let backend = cursor.stream.backend.clone();
+                let source = cursor.stream.source;
+                let events = match web::block(move || source.fetch(&backend)).await {
+                    Ok(Ok(events)) => events,
+                    Ok(Err(err)) => {
+                        cursor.done = true;
+                        return Some((Err(StoreError(err).into()), cursor));
+                    }
+                    Err(err) => {
+                        cursor.done = true;
+                        return Some((Err(err.into()), cursor));
+                    }
+                };
+                match (events.last(), cursor.stream.follow) {
+                    (Some(last), _) => cursor.stream.source.advance(last),
+                    (None, Some(poll_interval)) => actix_web::rt::time::sleep(poll_interval).await,
+                    (None, None) => {}
+                }
+                cursor.done = cursor.stream.follow.is_none() && events.len() < PAGE;
+                let source = cursor.stream.source;
+                cursor
+                    .buffer
+                    .extend(events.into_iter().filter(|event| source.matches(event)));
+            }
+        });
+        let content_type = if follow { EVENT_STREAM } else { NDJSON };
+        HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, content_type))
+            .insert_header((CACHE_CONTROL, "no-cache"))
+            .streaming(body)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct StreamQuery {
+    #[serde(default)]
+    since_version: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AllQuery {
+    #[serde(default)]
+    from_position: u64,
+    stream: Option<String>,
+}
+
+fn parse_id(id: &str) -> Result<Uuid, StoreError> {
+    Uuid::parse_str(id).map_err(|_| StoreError(Error::InvalidUUID))
+}
+
+async fn read_stream(
+    store: Store,
+    id: web::Path<String>,
+    query: web::Query<StreamQuery>,
+) -> Result<EventStream, StoreError> {
+    let id = parse_id(&id)?;
+    Ok(EventStream::stream(
+        store.get_ref().clone(),
+        id,
+        query.since_version,
+    ))
+}
+
+async fn read_all(store: Store, query: web::Query<AllQuery>) -> EventStream {
+    EventStream::all(store.get_ref().clone(), query.from_position)
+}
+
+async fn subscribe(store: Store, query: web::Query<AllQuery>) -> Result<EventStream, StoreError> {
+    let mut stream = EventStream::all(store.get_ref().clone(), query.from_position)
+        .follow(Duration::from_millis(250));
+    if let Some(id) = &query.stream {
+        stream = stream.with_stream_filter(parse_id(id)?);
+    }
+    Ok(stream)
+}
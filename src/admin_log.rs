@@ -0,0 +1,18 @@
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Record of an administrative operation, see `SqliteBackend::admin_log`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminLogEntry {
+    pub id: u64,
+    /// Milliseconds since the Unix epoch.
+    pub recorded_at: i64,
+    /// Principal of the caller, `None` for handles without caller context.
+    pub principal: Option<String>,
+    pub tenant_id: String,
+    /// Name of the API, e.g. `delete_stream`.
+    pub operation: String,
+    pub aggregate_id: Option<Uuid>,
+    /// Arguments and outcome of the operation.
+    pub details: Value,
+}
@@ -0,0 +1,153 @@
+use std::marker::PhantomData;
+
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::authorization::Operation;
+use crate::backend::{
+    model::Event,
+    sqlite::{Error, SqliteBackend},
+    EventStore,
+};
+use crate::context::AppendContext;
+use crate::event::DomainEvent;
+
+/// State rebuilt by folding the events of a single stream.
+///
+/// Usually derived via `#[derive(Aggregate)]` together with one `Apply`
+/// implementation per event type.
+pub trait Aggregate: Default {
+    fn aggregate_type() -> &'static str;
+
+    /// Apply a stored event, dispatching on its event type.
+    fn apply_event(&mut self, event: &Event) -> Result<(), Error>;
+}
+
+/// Applies a single typed event to an aggregate.
+pub trait Apply<E: DomainEvent> {
+    fn apply(&mut self, event: E);
+}
+
+/// Loads and saves aggregates of type `A` through a store, a
+/// `SqliteBackend` unless another `EventStore` is given, e.g. a
+/// `testing::RecordingBackend`.
+pub struct Repository<A: Aggregate, S = SqliteBackend> {
+    backend: S,
+    _aggregate: PhantomData<A>,
+}
+
+impl<A: Aggregate, S: EventStore + Clone> Clone for Repository<A, S> {
+    fn clone(&self) -> Self {
+        Self::new(self.backend.clone())
+    }
+}
+
+impl<A: Aggregate, S: std::fmt::Debug> std::fmt::Debug for Repository<A, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Repository")
+            .field("aggregate_type", &A::aggregate_type())
+            .field("backend", &self.backend)
+            .finish()
+    }
+}
+
+impl<A: Aggregate, S: EventStore> Repository<A, S> {
+    pub fn new(backend: S) -> Self {
+        Self {
+            backend,
+            _aggregate: PhantomData,
+        }
+    }
+
+    pub fn backend(&self) -> &S {
+        &self.backend
+    }
+
+    /// Rebuild the aggregate, returns the state and the current version.
+    /// An unknown aggregate yields the default state at version 0.
+    #[instrument(skip(self), fields(aggregate_type = A::aggregate_type()))]
+    pub fn load(&self, aggregate_id: Uuid) -> Result<(A, u32), Error> {
+        fold(&self.backend.get_aggretate(aggregate_id)?)
+    }
+
+    /// Append `events` after `expected_version`, assigning aggregate id and
+    /// versions. Returns the new version of the aggregate.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the aggregate was modified
+    /// concurrently, i.e., its version is no longer `expected_version`.
+    #[instrument(skip(self, events), fields(aggregate_type = A::aggregate_type()))]
+    pub fn save(
+        &self,
+        aggregate_id: Uuid,
+        expected_version: u32,
+        events: Vec<Event>,
+    ) -> Result<u32, Error> {
+        let mut version = expected_version;
+        let events: Vec<Event> = events
+            .into_iter()
+            .map(|event| {
+                version += 1;
+                Event {
+                    id: aggregate_id,
+                    version,
+                    ..event
+                }
+            })
+            .collect();
+        self.backend.append_events(&events)?;
+        Ok(version)
+    }
+}
+
+impl<A: Aggregate> Repository<A> {
+    /// A repository saving through `SqliteBackend::with_append_context`,
+    /// e.g. one per request.
+    pub fn with_append_context(&self, context: AppendContext) -> Self {
+        Self::new(self.backend.with_append_context(context))
+    }
+
+    /// Like `load`, but served from the read cache of the backend if it has
+    /// one, see `SqliteBackend::with_read_cache`. The folded state is cached
+    /// until the stream changes or one of its events expires.
+    #[instrument]
+    pub fn load_cached(&self, aggregate_id: Uuid) -> Result<(A, u32), Error>
+    where
+        A: Clone + Send + Sync + 'static,
+    {
+        let Some(cache) = self.backend.read_cache() else {
+            return self.load(aggregate_id);
+        };
+        self.backend
+            .authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.backend.stream_connection(aggregate_id)?;
+        let now = self.backend.now_millis();
+        // only the folded state is cached, not the events it was built from
+        cache.get_or_load_until(self.backend.tenant_id(), stored, now, || {
+            let expires_at = self.backend.stream_expiry(&conn, stored)?;
+            Ok((fold(&self.backend.load_stream(&conn, stored)?)?, expires_at))
+        })
+    }
+
+    /// Rebuild several aggregates, see `SqliteBackend::load_aggregates`.
+    /// Returns state and version in the order of `aggregate_ids`.
+    #[instrument(skip(aggregate_ids), fields(aggregates = aggregate_ids.len()))]
+    pub fn load_many(&self, aggregate_ids: &[Uuid]) -> Result<Vec<(A, u32)>, Error> {
+        let events = self.backend.load_aggregates(aggregate_ids)?;
+        aggregate_ids
+            .iter()
+            .map(|aggregate_id| fold(&events[aggregate_id]))
+            .collect()
+    }
+}
+
+fn fold<A: Aggregate>(events: &[Event]) -> Result<(A, u32), Error> {
+    let mut state = A::default();
+    let mut version = 0;
+    for event in events {
+        state.apply_event(event)?;
+        version = event.version;
+    }
+    Ok((state, version))
+}
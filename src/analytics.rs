@@ -0,0 +1,106 @@
+//! SQL aggregations over the event history with DataFusion.
+//!
+//! The events are registered as the table `events` with the columns of
+//! `parquet::schema()`, either loaded from a store or read from a Parquet
+//! export:
+//!
+//! ```ignore
+//! let analytics = Analytics::from_store(&backend, &ExportOpts::default())?;
+//! let batches = analytics
+//!     .sql("SELECT event_type, count(*) FROM events GROUP BY event_type")
+//!     .await?;
+//! ```
+//!
+//! Counts per type and subscription lag over time are also kept by the
+//! store itself, see `SqliteBackend::analytics`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::RecordBatch;
+use datafusion::datasource::MemTable;
+use datafusion::prelude::{ParquetReadOptions, SessionContext};
+
+use crate::backend::sqlite::{Error, SqliteBackend};
+use crate::jsonl::ExportOpts;
+use crate::parquet::schema;
+
+/// Name the events are registered under.
+pub const EVENTS_TABLE: &str = "events";
+
+fn analytics_error(err: impl std::fmt::Display) -> Error {
+    Error::WithMsg(format!("analytics: {}", err))
+}
+
+/// A DataFusion session with the `events` table registered. Further
+/// tables, e.g. other exports, can be added through `context`.
+pub struct Analytics {
+    ctx: SessionContext,
+}
+
+impl std::fmt::Debug for Analytics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Analytics").finish_non_exhaustive()
+    }
+}
+
+impl Analytics {
+    /// Load the events selected by `opts` into memory. Later appends are
+    /// not visible, load again for fresh numbers.
+    pub fn from_store(backend: &SqliteBackend, opts: &ExportOpts) -> Result<Self, Error> {
+        let mut batches = Vec::new();
+        backend.export_arrow(opts, |batch| {
+            batches.push(batch);
+            Ok(())
+        })?;
+        let table = MemTable::try_new(schema(), vec![batches]).map_err(analytics_error)?;
+        let ctx = SessionContext::new();
+        ctx.register_table(EVENTS_TABLE, Arc::new(table))
+            .map_err(analytics_error)?;
+        Ok(Self { ctx })
+    }
+
+    /// Query a file written by `SqliteBackend::export_parquet`.
+    pub async fn from_parquet<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| analytics_error("path is not UTF-8"))?;
+        let ctx = SessionContext::new();
+        // keep the column types of the export instead of string views
+        let schema = schema();
+        let options = ParquetReadOptions::default().schema(&schema);
+        ctx.register_parquet(EVENTS_TABLE, path, options)
+            .await
+            .map_err(analytics_error)?;
+        Ok(Self { ctx })
+    }
+
+    pub fn context(&self) -> &SessionContext {
+        &self.ctx
+    }
+
+    /// Run `query` and collect its result.
+    pub async fn sql(&self, query: &str) -> Result<Vec<RecordBatch>, Error> {
+        self.ctx
+            .sql(query)
+            .await
+            .map_err(analytics_error)?
+            .collect()
+            .await
+            .map_err(analytics_error)
+    }
+
+    /// Number of events per type and UTC day as rows of `event_type`,
+    /// `day` and `events`, ordered by day and type. Events without a
+    /// timestamp are counted with a null day.
+    pub async fn events_per_type_per_day(&self) -> Result<Vec<RecordBatch>, Error> {
+        self.sql(
+            "SELECT event_type, date_trunc('day', timestamp) AS day, count(*) AS events
+             FROM events
+             GROUP BY event_type, day
+             ORDER BY day, event_type",
+        )
+        .await
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+/// Kind of operation passed to an `Authorizer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Reading a stream, its snapshots, or all events when no stream is
+    /// given (`read_all`, exports, projections).
+    Read,
+    /// Appending events or saving a snapshot.
+    Append,
+    /// Deleting or tombstoning a stream.
+    Delete,
+    /// Changing stream metadata or projection checkpoints, redacting events
+    /// or forgetting subjects.
+    Manage,
+    /// Database wide maintenance and imports.
+    Maintain,
+}
+
+/// Identity and attributes of the caller, attached to a handle with
+/// `SqliteBackend::as_caller`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallerContext {
+    pub principal: Option<String>,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl CallerContext {
+    pub fn with_principal(mut self, principal: &str) -> Self {
+        self.principal = Some(principal.to_string());
+        self
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Hook run before every operation of a backend, returning `Err` with a
+/// reason denies the operation with `Error::Unauthorized`.
+pub trait Authorizer: Send + Sync {
+    fn authorize(
+        &self,
+        operation: Operation,
+        stream: Option<Uuid>,
+        caller: &CallerContext,
+    ) -> Result<(), String>;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(Operation, Option<Uuid>, &CallerContext) -> Result<(), String> + Send + Sync,
+{
+    fn authorize(
+        &self,
+        operation: Operation,
+        stream: Option<Uuid>,
+        caller: &CallerContext,
+    ) -> Result<(), String> {
+        self(operation, stream, caller)
+    }
+}
@@ -1,2 +1,68 @@
 pub mod model;
 pub mod sqlite;
+
+use uuid::Uuid;
+
+use self::model::Event;
+use self::sqlite::{Error, SqliteBackend};
+
+/// The operations repositories and projections need from a store,
+/// implemented by `SqliteBackend` and by the doubles in `crate::testing`.
+pub trait EventStore: Send + Sync {
+    /// Append events in a single transaction, either all of them are
+    /// stored or none. Each event must follow the current version of its
+    /// stream.
+    fn append_events(&self, events: &[Event]) -> Result<(), Error>;
+
+    /// The events of a stream in version order.
+    fn get_aggretate(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error>;
+
+    /// Version of the latest event of the stream, 0 if it has none.
+    fn stream_version(&self, aggregate_id: Uuid) -> Result<u32, Error>;
+
+    /// Events of all streams in commit order after `from_position`.
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, Error>;
+
+    fn save_snapshot(&self, snapshot: &Event) -> Result<(), Error>;
+
+    fn get_snapshot_by_version(&self, aggregate_id: Uuid, version: u32) -> Result<Event, Error>;
+
+    /// Position stored for the projection `name`, 0 if none.
+    fn get_checkpoint(&self, name: &str) -> Result<u64, Error>;
+
+    fn save_checkpoint(&self, name: &str, position: u64) -> Result<(), Error>;
+}
+
+impl EventStore for SqliteBackend {
+    fn append_events(&self, events: &[Event]) -> Result<(), Error> {
+        SqliteBackend::append_events(self, events)
+    }
+
+    fn get_aggretate(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
+        SqliteBackend::get_aggretate(self, aggregate_id)
+    }
+
+    fn stream_version(&self, aggregate_id: Uuid) -> Result<u32, Error> {
+        SqliteBackend::stream_version(self, aggregate_id)
+    }
+
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, Error> {
+        SqliteBackend::read_all(self, from_position, limit)
+    }
+
+    fn save_snapshot(&self, snapshot: &Event) -> Result<(), Error> {
+        SqliteBackend::save_snapshot(self, snapshot)
+    }
+
+    fn get_snapshot_by_version(&self, aggregate_id: Uuid, version: u32) -> Result<Event, Error> {
+        SqliteBackend::get_snapshot_by_version(self, aggregate_id, version)
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<u64, Error> {
+        SqliteBackend::get_checkpoint(self, name)
+    }
+
+    fn save_checkpoint(&self, name: &str, position: u64) -> Result<(), Error> {
+        SqliteBackend::save_checkpoint(self, name, position)
+    }
+}
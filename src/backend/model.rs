@@ -1,6 +1,335 @@
-#[derive(Debug)]
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::backend::sqlite::Error;
+use crate::codec::{self, Codec, EventCodec, JsonCodec, Transcoders};
+use crate::event::DomainEvent;
+use crate::ids;
+
+/// Metadata key of the time to live of an event in milliseconds, see
+/// `Event::with_ttl`.
+pub const TTL: &str = "ttl_ms";
+
+/// Metadata key of the start of the valid time of an event in
+/// milliseconds since the Unix epoch, see `Event::with_valid_time`.
+pub const VALID_FROM: &str = "valid_from";
+
+/// Metadata key of the end of the valid time, exclusive.
+pub const VALID_TO: &str = "valid_to";
+
+/// Metadata key of the id of the event that caused an event, see
+/// `Event::caused_by`.
+pub const CAUSATION_ID: &str = "causation_id";
+
+/// Metadata key of the id shared by the events of one request or cascade.
+pub const CORRELATION_ID: &str = "correlation_id";
+
+/// Event type of link events, see `Event::link_to`.
+pub const LINK_EVENT_TYPE: &str = "$>";
+
+/// Metadata key of the event a link points to, as `<version>@<aggregate_id>`.
+pub const LINK_TO: &str = "link_to";
+
+/// Metadata key of the event a split or merged event was copied from, as
+/// `<version>@<aggregate_id>` like `LINK_TO`.
+pub const PROVENANCE: &str = "provenance";
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Event {
     pub id: uuid::Uuid,
     pub version: u32,
+    pub event_type: String,
+    pub schema_version: u32,
+    /// Content type of `data`, identifies the codec used to write it.
+    pub content_type: String,
     pub data: Vec<u8>,
+    /// Free-form key/value pairs stored alongside the payload.
+    pub metadata: BTreeMap<String, String>,
+    /// Global commit order across all aggregates, assigned on append.
+    pub position: u64,
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self {
+            id: uuid::Uuid::nil(),
+            version: 0,
+            event_type: String::new(),
+            schema_version: 1,
+            content_type: codec::JSON.to_string(),
+            data: Vec::new(),
+            metadata: BTreeMap::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Event {
+    /// Serialize a domain event as JSON. Aggregate id and version are left
+    /// empty, they are assigned once the event is appended to a stream.
+    pub fn encode<E: DomainEvent>(event: &E) -> Result<Self, Error> {
+        Self::encode_with(&JsonCodec, event)
+    }
+
+    /// Like `encode`, but serializes the payload with `codec`.
+    pub fn encode_with<C: EventCodec, E: DomainEvent>(codec: &C, event: &E) -> Result<Self, Error> {
+        Ok(Self {
+            event_type: E::event_type().to_string(),
+            schema_version: E::schema_version(),
+            content_type: codec.content_type().to_string(),
+            data: codec.encode(event)?,
+            ..Default::default()
+        })
+    }
+
+    /// Deserialize the payload into `E`, fails if the stored event type or
+    /// schema version differs, i.e., the event was not upcasted.
+    pub fn decode<E: DomainEvent>(&self) -> Result<E, Error> {
+        self.decode_with(&Transcoders::default())
+    }
+
+    /// Like `decode`, but payloads of content types without a serde codec
+    /// are converted to JSON by a matching transcoder first.
+    pub fn decode_with<E: DomainEvent>(&self, transcoders: &Transcoders) -> Result<E, Error> {
+        check_decodable::<E>(&self.event_type, self.schema_version)?;
+        match Codec::for_content_type(&self.content_type) {
+            Ok(codec) => codec.decode(&self.data),
+            Err(err) => match transcoders.get(&self.content_type, &self.event_type) {
+                Some(transcoder) => JsonCodec.decode(&transcoder.to_json(self)?),
+                None => Err(err),
+            },
+        }
+    }
+
+    /// Store `ids::event_id` of this event as `producer` in the metadata
+    /// under `ids::EVENT_ID`. Set aggregate id and version first.
+    pub fn with_event_id(mut self, producer: &str) -> Self {
+        let event_id = ids::event_id(self.id, self.version, producer);
+        self.metadata
+            .insert(ids::EVENT_ID.to_string(), event_id.to_string());
+        self
+    }
+
+    /// The id set by `with_event_id`.
+    pub fn event_id(&self) -> Option<uuid::Uuid> {
+        self.metadata
+            .get(ids::EVENT_ID)
+            .and_then(|id| uuid::Uuid::parse_str(id).ok())
+    }
+
+    /// Record `cause` as the cause of this event under `CAUSATION_ID` and
+    /// continue its correlation, or start one with the id of `cause`.
+    /// `cause` needs an id set by `with_event_id`.
+    pub fn caused_by(mut self, cause: &Event) -> Self {
+        if let Some(cause_id) = cause.event_id() {
+            self.metadata
+                .insert(CAUSATION_ID.to_string(), cause_id.to_string());
+            let correlation_id = match cause.metadata.get(CORRELATION_ID) {
+                Some(correlation_id) => correlation_id.clone(),
+                None => cause_id.to_string(),
+            };
+            self.metadata
+                .insert(CORRELATION_ID.to_string(), correlation_id);
+        }
+        self
+    }
+
+    /// The id of the event that caused this one, see `caused_by`.
+    pub fn causation_id(&self) -> Option<uuid::Uuid> {
+        self.metadata
+            .get(CAUSATION_ID)
+            .and_then(|id| uuid::Uuid::parse_str(id).ok())
+    }
+
+    /// Let the event expire `ttl` after it was appended: reads skip it from
+    /// then on and `scavenge` deletes it. Stored in the metadata under
+    /// `TTL`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata
+            .insert(TTL.to_string(), ttl.as_millis().to_string());
+        self
+    }
+
+    /// Let the event be in effect in the domain from `from` until `to`,
+    /// independent of when it is appended, e.g. a correction backdated to
+    /// the start of a policy. Events without a valid time are in effect
+    /// from when they were appended on, see
+    /// `SqliteBackend::get_aggregate_valid_at`. Stored in the metadata
+    /// under `VALID_FROM` and `VALID_TO`.
+    pub fn with_valid_time(mut self, from: SystemTime, to: Option<SystemTime>) -> Self {
+        self.metadata
+            .insert(VALID_FROM.to_string(), unix_millis(from).to_string());
+        match to {
+            Some(to) => self
+                .metadata
+                .insert(VALID_TO.to_string(), unix_millis(to).to_string()),
+            None => self.metadata.remove(VALID_TO),
+        };
+        self
+    }
+
+    /// Start of the valid time set by `with_valid_time`.
+    pub fn valid_from(&self) -> Option<SystemTime> {
+        self.metadata
+            .get(VALID_FROM)
+            .and_then(|ms| from_unix_millis(ms))
+    }
+
+    /// End of the valid time set by `with_valid_time`, `None` if open.
+    pub fn valid_to(&self) -> Option<SystemTime> {
+        self.metadata
+            .get(VALID_TO)
+            .and_then(|ms| from_unix_millis(ms))
+    }
+
+    /// A link to `target`, appended to a stream instead of a copy of it,
+    /// e.g. to collect the events about one customer from several streams.
+    /// The link has no payload, `SqliteBackend::resolve_links` replaces it
+    /// with `target` on read. Aggregate id and version are left empty.
+    pub fn link_to(target: &Event) -> Self {
+        Self {
+            event_type: LINK_EVENT_TYPE.to_string(),
+            metadata: [(
+                LINK_TO.to_string(),
+                format!("{}@{}", target.version, target.id),
+            )]
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    /// Aggregate id and version of the event a link points to, `None` for
+    /// other events.
+    pub fn link_target(&self) -> Option<(uuid::Uuid, u32)> {
+        if self.event_type != LINK_EVENT_TYPE {
+            return None;
+        }
+        let (version, id) = self.metadata.get(LINK_TO)?.split_once('@')?;
+        Some((uuid::Uuid::parse_str(id).ok()?, version.parse().ok()?))
+    }
+
+    /// The time to live set by `with_ttl`, `None` for permanent events.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.metadata
+            .get(TTL)
+            .and_then(|ttl| ttl.parse().ok())
+            .map(Duration::from_millis)
+    }
+}
+
+/// An event and the events it caused, directly and transitively, see
+/// `SqliteBackend::get_causation_tree`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CausationTree {
+    pub event: Event,
+    /// Trees of the events caused directly, in commit order.
+    pub caused: Vec<CausationTree>,
+}
+
+impl CausationTree {
+    /// The events of the tree depth first, in commit order among siblings.
+    pub fn events(&self) -> Vec<&Event> {
+        let mut events = vec![&self.event];
+        for caused in &self.caused {
+            events.extend(caused.events());
+        }
+        events
+    }
+}
+
+fn check_decodable<E: DomainEvent>(event_type: &str, schema_version: u32) -> Result<(), Error> {
+    if event_type != E::event_type() {
+        return Err(Error::UnexpectedEventType {
+            expected: E::event_type().to_string(),
+            actual: event_type.to_string(),
+        });
+    }
+    if schema_version != E::schema_version() {
+        return Err(Error::SchemaVersionMismatch {
+            expected: E::schema_version(),
+            actual: schema_version,
+        });
+    }
+    Ok(())
+}
+
+/// An `Event` with a reference-counted payload, clones share it instead
+/// of copying it, e.g. when handing events to several subscribers or
+/// projections. Converting from and to `Event` does not copy the payload
+/// either, unless it is shared at the time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedEvent {
+    pub id: uuid::Uuid,
+    pub version: u32,
+    pub event_type: String,
+    pub schema_version: u32,
+    pub content_type: String,
+    pub data: Bytes,
+    pub metadata: BTreeMap<String, String>,
+    pub position: u64,
+}
+
+impl SharedEvent {
+    /// See `Event::decode`.
+    pub fn decode<E: DomainEvent>(&self) -> Result<E, Error> {
+        self.decode_with(&Transcoders::default())
+    }
+
+    /// See `Event::decode_with`.
+    pub fn decode_with<E: DomainEvent>(&self, transcoders: &Transcoders) -> Result<E, Error> {
+        check_decodable::<E>(&self.event_type, self.schema_version)?;
+        match Codec::for_content_type(&self.content_type) {
+            Ok(codec) => codec.decode(&self.data),
+            // transcoders work on events, the payload is copied for them
+            Err(_) => Event::from(self.clone()).decode_with(transcoders),
+        }
+    }
+}
+
+impl From<Event> for SharedEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            id: event.id,
+            version: event.version,
+            event_type: event.event_type,
+            schema_version: event.schema_version,
+            content_type: event.content_type,
+            data: Bytes::from(event.data),
+            metadata: event.metadata,
+            position: event.position,
+        }
+    }
+}
+
+impl From<SharedEvent> for Event {
+    fn from(event: SharedEvent) -> Self {
+        Self {
+            id: event.id,
+            version: event.version,
+            event_type: event.event_type,
+            schema_version: event.schema_version,
+            content_type: event.content_type,
+            data: Vec::from(event.data),
+            metadata: event.metadata,
+            position: event.position,
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, negative before it.
+pub(crate) fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis().min(i64::MAX as u128) as i64,
+        Err(err) => -(err.duration().as_millis().min(i64::MAX as u128) as i64),
+    }
+}
+
+fn from_unix_millis(ms: &str) -> Option<SystemTime> {
+    let ms: i64 = ms.parse().ok()?;
+    match ms >= 0 {
+        true => UNIX_EPOCH.checked_add(Duration::from_millis(ms as u64)),
+        false => UNIX_EPOCH.checked_sub(Duration::from_millis(ms.unsigned_abs())),
+    }
 }
@@ -1,16 +1,152 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Display};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, params_from_iter, Statement, Transaction};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, params_from_iter, OpenFlags, Statement, ToSql, Transaction};
 use tracing::{debug, instrument, warn};
 use uuid::Uuid;
 
-use crate::backend::model::Event;
+use crate::authorization::{Authorizer, CallerContext, Operation};
+use crate::backend::model::{
+    Event, SharedEvent, CAUSATION_ID, CORRELATION_ID, LINK_EVENT_TYPE, LINK_TO, TTL, VALID_FROM,
+    VALID_TO,
+};
+use crate::cache::AggregateCache;
+use crate::clock::{Clock, SystemClock};
+use crate::codec::{Codec, Transcoders};
+#[cfg(feature = "compression")]
+use crate::compression::Compression;
+use crate::compression::{self, Dictionaries};
+use crate::context::AppendContext;
+#[cfg(feature = "encryption")]
+use crate::encryption;
+use crate::encryption::{Encryptor, FORGOTTEN};
+use crate::event::DomainEvent;
+use crate::handler::HandlerRegistry;
+use crate::ids::EVENT_ID;
+use crate::metrics;
+use crate::middleware::MiddlewareChain;
+#[cfg(feature = "schema-registry")]
+use crate::schema::SchemaRegistry;
+use crate::upcast::UpcasterChain;
+
+mod admin_log;
+mod aliases;
+mod analytics;
+mod backup;
+mod blobs;
+mod causation;
+mod changes;
+mod commands;
+mod ids;
+mod jsonl;
+mod latest;
+mod links;
+mod maintenance;
+pub mod migrations;
+mod optimizer;
+mod options;
+mod pagination;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod profile;
+mod quarantine;
+mod quota;
+mod redaction;
+mod restructure;
+mod schedule;
+mod streams;
+mod system;
+mod tables;
+mod temporal;
+
+pub use analytics::{EventCount, LagSample, StoreAnalytics};
+pub use backup::BackupOptions;
+pub use blobs::{PayloadReader, SnapshotWriter, STREAMING_THRESHOLD};
+pub use ids::{convert_ids_to_blob, IdFormat};
+pub use maintenance::{
+    CheckpointMode, IntegrityIssue, IntegrityReport, Maintenance, ScavengeOpts, ScavengeReport,
+    StoreStats, WalCheckpoint,
+};
+pub use optimizer::OptimizerHandle;
+pub use options::ConnectionOptions;
+pub use pagination::{Cursor, Page};
+pub use profile::Durability;
+pub use tables::Tables;
+#[cfg(feature = "encryption")]
+mod shredding;
+
+/// Prepared statements kept per pooled connection, enough for the
+/// statements of appends and reads of all configured features.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Rows per multi-row INSERT of a batch append, the 17 parameters per row
+/// stay below the 999 bound parameters older SQLite builds allow.
+const INSERT_CHUNK_ROWS: usize = 56;
+
+/// Aggregates read per query by `load_aggregates`, the bound parameters
+/// stay below the 999 older SQLite builds allow.
+const LOAD_CHUNK_IDS: usize = 500;
+
+/// Columns of an event row as read by `result_from_stmt`, which expects
+/// the first five in this order. The stream index holds the columns
+/// `retained` checks, the others are read from the rows it matches.
+pub(super) const EVENT_COLUMNS: &str = "aggregate_id, data, version, event_type, schema_version, content_type, metadata, compression, key_id, position";
+
+/// Like `EVENT_COLUMNS` for snapshots, which have no position.
+const SNAPSHOT_COLUMNS: &str =
+    "aggregate_id, data, version, event_type, schema_version, content_type, metadata, compression, key_id";
 
 #[derive(Clone)]
 pub struct SqliteBackend {
     pool: Pool<SqliteConnectionManager>,
+    upcasters: Arc<UpcasterChain>,
+    middleware: Arc<MiddlewareChain>,
+    handlers: Arc<HandlerRegistry>,
+    tables: Arc<Tables>,
+    codec: Codec,
+    transcoders: Arc<Transcoders>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    dictionaries: Arc<RwLock<Dictionaries>>,
+    encryptor: Option<Arc<dyn Encryptor>>,
+    max_event_size: Option<usize>,
+    encrypt_metadata: bool,
+    #[cfg(feature = "encryption")]
+    crypto_shredding: bool,
+    #[cfg(feature = "opentelemetry")]
+    trace_context: bool,
+    #[cfg(feature = "schema-registry")]
+    schemas: Option<Arc<SchemaRegistry>>,
+    read_only: bool,
+    id_format: IdFormat,
+    read_cache: Option<Arc<AggregateCache>>,
+    /// Events written since the last `Maintenance::analyze`.
+    written: Arc<AtomicU64>,
+    tenant: Arc<str>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    caller: Arc<CallerContext>,
+    /// The system clock if `None`.
+    clock: Option<Arc<dyn Clock>>,
+    quotas: Option<Arc<quota::QuotaState>>,
+    system_streams: bool,
+    latest_by_type: bool,
+    analytics: bool,
+    append_context: Option<Arc<AppendContext>>,
+}
+
+struct StoredRow<'a> {
+    data: Cow<'a, [u8]>,
+    compression: String,
+    metadata: Value,
+    key_id: String,
 }
 
 #[derive(Debug)]
@@ -19,12 +155,45 @@ pub struct GetAggOpts {
     pub since_version: u32,
 }
 
+/// An aggregate as read by `SqliteBackend::load_aggregate`: its latest
+/// snapshot and the events after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedAggregate {
+    pub snapshot: Option<Event>,
+    pub events: Vec<Event>,
+}
+
+impl LoadedAggregate {
+    /// Version of the last event, or of the snapshot if there are no
+    /// events after it, 0 for unknown aggregates.
+    pub fn version(&self) -> u32 {
+        self.events
+            .last()
+            .or(self.snapshot.as_ref())
+            .map_or(0, |event| event.version)
+    }
+}
+
 pub enum Error {
     WithMsg(String),
     InvalidUUID,
     NotFound,
     Sqlite(rusqlite::Error),
     R2D2Sqlite(r2d2::Error),
+    Serde(serde_json::Error),
+    UnknownEventType(String),
+    UnexpectedEventType { expected: String, actual: String },
+    SchemaViolation(String),
+    SchemaVersionMismatch { expected: u32, actual: u32 },
+    Codec(String),
+    Encryption(String),
+    PayloadTooLarge { size: usize, max: usize },
+    StreamDeleted(Uuid),
+    StreamRenamed(Uuid),
+    VersionConflict,
+    ReadOnly,
+    Unauthorized(String),
+    QuotaExceeded(String),
 }
 
 impl Display for Error {
@@ -35,6 +204,31 @@ impl Display for Error {
             Error::R2D2Sqlite(err) => f.write_fmt(format_args!("r2d2_sqlite: {}", err)),
             Error::WithMsg(msg) => f.write_fmt(format_args!("plain error: {}", msg)),
             Error::NotFound => f.write_fmt(format_args!("not found")),
+            Error::Serde(err) => f.write_fmt(format_args!("serde: {}", err)),
+            Error::UnknownEventType(event_type) => {
+                f.write_fmt(format_args!("unknown event type: {}", event_type))
+            }
+            Error::UnexpectedEventType { expected, actual } => f.write_fmt(format_args!(
+                "unexpected event type: expected {}, got {}",
+                expected, actual
+            )),
+            Error::SchemaViolation(msg) => f.write_fmt(format_args!("schema violation: {}", msg)),
+            Error::SchemaVersionMismatch { expected, actual } => f.write_fmt(format_args!(
+                "schema version mismatch: expected {}, got {}",
+                expected, actual
+            )),
+            Error::Codec(msg) => f.write_fmt(format_args!("codec: {}", msg)),
+            Error::Encryption(msg) => f.write_fmt(format_args!("encryption: {}", msg)),
+            Error::PayloadTooLarge { size, max } => f.write_fmt(format_args!(
+                "payload too large: {} bytes, at most {} allowed",
+                size, max
+            )),
+            Error::StreamDeleted(id) => f.write_fmt(format_args!("stream {} was deleted", id)),
+            Error::StreamRenamed(id) => f.write_fmt(format_args!("stream was renamed to {}", id)),
+            Error::VersionConflict => f.write_fmt(format_args!("version conflict")),
+            Error::ReadOnly => f.write_fmt(format_args!("backend is read-only")),
+            Error::Unauthorized(reason) => f.write_fmt(format_args!("unauthorized: {}", reason)),
+            Error::QuotaExceeded(reason) => f.write_fmt(format_args!("quota exceeded: {}", reason)),
         }
     }
 }
@@ -47,10 +241,45 @@ impl Debug for Error {
             Error::R2D2Sqlite(err) => f.write_fmt(format_args!("r2d2_sqlite: {}", err)),
             Error::WithMsg(msg) => f.write_fmt(format_args!("plain error: {}", msg)),
             Error::NotFound => f.write_fmt(format_args!("not found")),
+            Error::Serde(err) => f.write_fmt(format_args!("serde: {}", err)),
+            Error::UnknownEventType(event_type) => {
+                f.write_fmt(format_args!("unknown event type: {}", event_type))
+            }
+            Error::UnexpectedEventType { expected, actual } => f.write_fmt(format_args!(
+                "unexpected event type: expected {}, got {}",
+                expected, actual
+            )),
+            Error::SchemaViolation(msg) => f.write_fmt(format_args!("schema violation: {}", msg)),
+            Error::SchemaVersionMismatch { expected, actual } => f.write_fmt(format_args!(
+                "schema version mismatch: expected {}, got {}",
+                expected, actual
+            )),
+            Error::Codec(msg) => f.write_fmt(format_args!("codec: {}", msg)),
+            Error::Encryption(msg) => f.write_fmt(format_args!("encryption: {}", msg)),
+            Error::PayloadTooLarge { size, max } => f.write_fmt(format_args!(
+                "payload too large: {} bytes, at most {} allowed",
+                size, max
+            )),
+            Error::StreamDeleted(id) => f.write_fmt(format_args!("stream {} was deleted", id)),
+            Error::StreamRenamed(id) => f.write_fmt(format_args!("stream was renamed to {}", id)),
+            Error::VersionConflict => f.write_fmt(format_args!("version conflict")),
+            Error::ReadOnly => f.write_fmt(format_args!("backend is read-only")),
+            Error::Unauthorized(reason) => f.write_fmt(format_args!("unauthorized: {}", reason)),
+            Error::QuotaExceeded(reason) => f.write_fmt(format_args!("quota exceeded: {}", reason)),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Whether an append failed because its version was not the next one
+    /// of the stream, e.g. taken by a concurrent writer.
+    pub fn is_version_conflict(&self) -> bool {
+        matches!(self, Error::VersionConflict)
+    }
+}
+
 impl From<rusqlite::Error> for Error {
     fn from(value: rusqlite::Error) -> Self {
         Error::Sqlite(value)
@@ -63,29 +292,11 @@ impl From<r2d2::Error> for Error {
     }
 }
 
-static CREATE_AGGREGATE_OVERVIEW_TABLE_STMT: &'static str = "CREATE TABLE aggregate_index(
-                aggregate_id TEXT PRIMARY KEY,
-                type_name TEXT,
-                version INTEGER
-            )";
-
-static CREATE_AGGREGATE_TABLE_STMT: &'static str = "CREATE TABLE eventstore(
-                aggregate_id TEXT,
-                data BLOB,
-                version INTEGER
-            )";
-
-static CREATE_SNAPSHOT_OVERVIEW_TABLE_STMT: &'static str = "CREATE TABLE snapshot_index(
-                aggregate_id TEXT PRIMARY KEY,
-                type_name TEXT,
-                version INTEGER
-            )";
-
-static CREATE_SNAPSHOT_TABLE_STMT: &'static str = "CREATE TABLE snapshot(
-                aggregate_id TEXT,
-                data BLOB,
-                version INTEGER
-            )";
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Serde(value)
+    }
+}
 
 impl Debug for SqliteBackend {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -95,58 +306,706 @@ impl Debug for SqliteBackend {
     }
 }
 
+/// Fails unless `event` directly follows the stream version `current`.
+fn check_version(event: &Event, current: u32) -> Result<(), Error> {
+    let expected_version = current + 1;
+    if event.version != expected_version {
+        warn!("version conflict {} != {}", event.version, expected_version);
+        metrics::append_conflict();
+        return Err(Error::VersionConflict);
+    }
+    Ok(())
+}
+
+/// When `event` appended at `created_at` expires, by its `TTL`.
+fn expires_at(event: &Event, created_at: i64) -> Option<i64> {
+    let ttl = event.ttl()?;
+    Some(created_at.saturating_add(ttl.as_millis().min(i64::MAX as u128) as i64))
+}
+
+/// `VALID_FROM` and `VALID_TO` of `event` for their columns.
+fn valid_time(event: &Event) -> [Option<i64>; 2] {
+    [VALID_FROM, VALID_TO].map(|key| event.metadata.get(key).and_then(|ms| ms.parse().ok()))
+}
+
+/// Copies of `events` with their `positions` set.
+fn positioned(events: &[Event], positions: impl IntoIterator<Item = u64>) -> Vec<Event> {
+    events
+        .iter()
+        .zip(positions)
+        .map(|(event, position)| Event {
+            position,
+            ..event.clone()
+        })
+        .collect()
+}
+
+/// A pooled connection with the `secure_delete` pragma set, which is reset
+/// before the connection goes back to the pool.
+struct SecureDelete {
+    conn: PooledConnection<SqliteConnectionManager>,
+    previous: bool,
+}
+
+impl std::ops::Deref for SecureDelete {
+    type Target = PooledConnection<SqliteConnectionManager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl std::ops::DerefMut for SecureDelete {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl Drop for SecureDelete {
+    fn drop(&mut self) {
+        if let Err(err) = self
+            .conn
+            .pragma_update(None, "secure_delete", self.previous)
+        {
+            warn!("could not reset secure_delete: {}", err);
+        }
+    }
+}
+
+/// Events `SqliteBackend::write_events` appended in a transaction, for
+/// `SqliteBackend::committed` once it is committed.
+#[derive(Default)]
+#[must_use]
+struct Written {
+    ids: Vec<Uuid>,
+    /// The events with their positions if there is middleware to pass
+    /// them to.
+    events: Vec<Event>,
+    charges: Vec<quota::Charge>,
+    started: Option<Instant>,
+}
+
+impl Written {
+    fn extend(&mut self, other: Written) {
+        self.ids.extend(other.ids);
+        self.events.extend(other.events);
+        self.charges.extend(other.charges);
+    }
+}
+
+/// Result of `SqliteBackend::write_events`.
+enum Outcome {
+    Written(Written),
+    /// The guard returned false, nothing was written.
+    Skipped,
+    Rejected(quota::Rejection),
+}
+
+fn shared(events: Vec<Event>) -> Vec<SharedEvent> {
+    events.into_iter().map(SharedEvent::from).collect()
+}
+
+/// Error of an event INSERT, a version taken by a concurrent writer
+/// violates the unique index of stream versions.
+fn insert_error(err: rusqlite::Error) -> Error {
+    warn!(sqlite_error = err.to_string());
+    match err.sqlite_error_code() {
+        Some(rusqlite::ErrorCode::ConstraintViolation) => {
+            metrics::append_conflict();
+            Error::VersionConflict
+        }
+        _ => Error::Sqlite(err),
+    }
+}
+
 impl SqliteBackend {
     pub fn new(manager: r2d2_sqlite::SqliteConnectionManager) -> Self {
-        let pool = r2d2::Pool::new(manager).unwrap(); // TODO(juf): this should also be the
-                                                      // responsibility of the caller in the future to make this lib even thinner.
-        let backend = Self { pool };
-        backend.init_tables().unwrap();
-        backend.init_indices().unwrap();
-        return backend;
+        Self::with_tables(manager, Tables::default())
+    }
+
+    /// Like `new`, but with custom table names, e.g.
+    /// `Tables::with_prefix("billing_")`.
+    pub fn with_tables(manager: r2d2_sqlite::SqliteConnectionManager, tables: Tables) -> Self {
+        Self::try_with_tables(manager, tables).unwrap()
+    }
+
+    /// Like `with_tables`, but returns an error instead of panicking if the
+    /// database can not be opened or migrated.
+    pub fn try_with_tables(
+        manager: r2d2_sqlite::SqliteConnectionManager,
+        tables: Tables,
+    ) -> Result<Self, Error> {
+        tables.validate()?;
+        let pool = r2d2::Pool::new(manager)?; // TODO(juf): this should also be the
+                                              // responsibility of the caller in the future to make this lib even thinner.
+        let mut backend = Self::from_pool(pool, tables);
+        backend.migrate()?;
+        backend.id_format = ids::detect_id_format(&*backend.connection()?, &backend.tables)?;
+        backend.load_dictionaries()?;
+        Ok(backend)
+    }
+
+    /// Open the database file at `path` with SQLite's read-only flag, e.g.
+    /// for reporting replicas. All mutating calls fail with
+    /// `Error::ReadOnly`. The schema is not migrated, so the file must have
+    /// been opened by a writable backend of the same release before.
+    pub fn read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read_only_with_tables(path, Tables::default())
+    }
+
+    /// Like `read_only`, but with custom table names.
+    pub fn read_only_with_tables<P: AsRef<Path>>(path: P, tables: Tables) -> Result<Self, Error> {
+        Self::read_only_with_options(path, tables, ConnectionOptions::default())
     }
 
+    /// Like `read_only_with_tables`, applying `options` to each connection,
+    /// e.g. memory-mapped I/O for replicas serving large scans. The
+    /// durability of `options` is ignored, it is up to the writer.
+    pub fn read_only_with_options<P: AsRef<Path>>(
+        path: P,
+        tables: Tables,
+        mut options: ConnectionOptions,
+    ) -> Result<Self, Error> {
+        tables.validate()?;
+        options.durability = None;
+        let manager = SqliteConnectionManager::file(path)
+            .with_flags(
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_URI
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+            .with_init(move |conn| options.configure(conn));
+        let mut backend = Self {
+            read_only: true,
+            ..Self::from_pool(r2d2::Pool::new(manager)?, tables)
+        };
+        let version = backend.schema_version()?;
+        let latest = migrations::MIGRATIONS.last().map_or(0, |m| m.version);
+        if version != latest {
+            return Err(Error::WithMsg(format!(
+                "database schema version {} does not match the supported version {}",
+                version, latest
+            )));
+        }
+        backend.id_format = ids::detect_id_format(&*backend.connection()?, &backend.tables)?;
+        backend.load_dictionaries()?;
+        Ok(backend)
+    }
+
+    fn from_pool(pool: Pool<SqliteConnectionManager>, tables: Tables) -> Self {
+        Self {
+            pool,
+            tables: Arc::new(tables),
+            upcasters: Arc::new(UpcasterChain::new()),
+            middleware: Arc::new(MiddlewareChain::new()),
+            handlers: Arc::new(HandlerRegistry::new()),
+            codec: Codec::default(),
+            transcoders: Arc::new(Transcoders::new()),
+            #[cfg(feature = "compression")]
+            compression: None,
+            dictionaries: Arc::new(RwLock::new(Dictionaries::default())),
+            encryptor: None,
+            max_event_size: None,
+            encrypt_metadata: false,
+            #[cfg(feature = "encryption")]
+            crypto_shredding: false,
+            #[cfg(feature = "opentelemetry")]
+            trace_context: false,
+            #[cfg(feature = "schema-registry")]
+            schemas: None,
+            read_only: false,
+            id_format: IdFormat::Blob,
+            read_cache: None,
+            written: Arc::new(AtomicU64::new(0)),
+            tenant: Arc::from(""),
+            authorizer: None,
+            caller: Arc::new(CallerContext::default()),
+            clock: None,
+            quotas: None,
+            system_streams: false,
+            latest_by_type: false,
+            analytics: false,
+            append_context: None,
+        }
+    }
+
+    /// A handle on the same store scoped to `tenant_id`. Reads, appends and
+    /// checkpoints of the handle only see the rows of the tenant, aggregate
+    /// ids, projection names and crypto-shredding subjects are unique per
+    /// tenant. Backends start out in the default tenant `""`. Maintenance
+    /// always covers the whole database.
+    pub fn tenant(&self, tenant_id: &str) -> Self {
+        Self {
+            tenant: Arc::from(tenant_id),
+            ..self.clone()
+        }
+    }
+
+    /// The tenant this handle is scoped to.
+    pub fn tenant_id(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Ask `authorizer` before every read, append and administrative call.
+    /// Handles created with `as_caller` pass their caller along.
+    pub fn with_authorizer<A: Authorizer + 'static>(mut self, authorizer: A) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// A handle on the same store acting on behalf of `caller`, e.g. one per
+    /// request of a server.
+    pub fn as_caller(&self, caller: CallerContext) -> Self {
+        Self {
+            caller: Arc::new(caller),
+            ..self.clone()
+        }
+    }
+
+    /// A handle on the same store adding `context` to the metadata of the
+    /// events it appends, e.g. one per request like `as_caller`.
+    pub fn with_append_context(&self, context: AppendContext) -> Self {
+        Self {
+            append_context: Some(Arc::new(context)),
+            ..self.clone()
+        }
+    }
+
+    /// Fails with `Error::Unauthorized` if the authorizer denies `operation`.
+    pub(crate) fn authorize(
+        &self,
+        operation: Operation,
+        stream: Option<Uuid>,
+    ) -> Result<(), Error> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(());
+        };
+        authorizer
+            .authorize(operation, stream, &self.caller)
+            .map_err(|reason| {
+                warn!(?operation, ?stream, reason, "operation denied");
+                Error::Unauthorized(reason)
+            })
+    }
+
+    /// Fails with `Error::ReadOnly` for backends opened with `read_only`.
+    pub(crate) fn ensure_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Use `upcasters` to bring events read via `get_aggretate` and
+    /// `get_aggretate_with_opts` to their current schema version.
+    pub fn with_upcasters(mut self, upcasters: UpcasterChain) -> Self {
+        self.upcasters = Arc::new(upcasters);
+        self
+    }
+
+    /// Run appends and reads through `middleware`, see `crate::middleware`.
+    pub fn with_middleware(mut self, middleware: MiddlewareChain) -> Self {
+        self.middleware = Arc::new(middleware);
+        self
+    }
+
+    /// Keep up to `capacity` hydrated aggregates in memory, see
+    /// `crate::cache`. Handles created from this one share the cache.
+    pub fn with_read_cache(mut self, capacity: usize) -> Self {
+        self.read_cache = Some(Arc::new(AggregateCache::new(capacity)));
+        self
+    }
+
+    /// Take the current time from `clock` instead of the system clock,
+    /// for `created_at` and the `max_age` retention of streams. Handles
+    /// created from this one share the clock, so set it before the first
+    /// call.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Milliseconds since the Unix epoch, stored in `created_at`.
+    pub(crate) fn now_millis(&self) -> i64 {
+        match &self.clock {
+            Some(clock) => clock.now_millis(),
+            None => SystemClock.now_millis(),
+        }
+    }
+
+    pub fn read_cache(&self) -> Option<&AggregateCache> {
+        self.read_cache.as_deref()
+    }
+
+    /// Drop the cached values of the streams, called once their changes
+    /// are committed.
+    pub(crate) fn invalidate_cached(&self, aggregate_ids: impl IntoIterator<Item = Uuid>) {
+        if let Some(cache) = &self.read_cache {
+            for aggregate_id in aggregate_ids {
+                cache.invalidate(self.tenant_id(), aggregate_id);
+            }
+        }
+    }
+
+    /// Drop all cached values, for changes of an unknown set of streams.
+    pub(crate) fn clear_cached(&self) {
+        if let Some(cache) = &self.read_cache {
+            cache.clear();
+        }
+    }
+
+    pub fn tables(&self) -> &Tables {
+        &self.tables
+    }
+
+    /// Render a statement with `{table}` placeholders for the configured
+    /// table names.
+    pub(crate) fn sql(&self, template: &str) -> String {
+        self.tables.sql(template)
+    }
+
+    pub(crate) fn connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, Error> {
+        let started = Instant::now();
+        let conn = self.pool.get()?;
+        metrics::pool_wait(started.elapsed());
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        if let Some(clock) = &self.clock {
+            streams::register_clock(&conn, clock)?;
+        }
+        Ok(conn)
+    }
+
+    /// A connection overwriting deleted content, see `SecureDelete`.
+    fn secure_delete_connection(&self) -> Result<SecureDelete, Error> {
+        let conn = self.connection()?;
+        let previous = conn.pragma_query_value(None, "secure_delete", |row| row.get(0))?;
+        conn.pragma_update(None, "secure_delete", true)?;
+        Ok(SecureDelete { conn, previous })
+    }
+
+    /// Serialize payloads passed to `encode` with `codec`. Events written with
+    /// other codecs stay readable since the content type is stored per event.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Use `transcoders` in typed reads for content types no codec handles.
+    pub fn with_transcoders(mut self, transcoders: Transcoders) -> Self {
+        self.transcoders = Arc::new(transcoders);
+        self
+    }
+
+    /// Read the events of `aggregate_id` holding an `E`, the codec is picked
+    /// per event from its content type so mixed histories stay readable.
     #[instrument]
-    fn init_tables(&self) -> Result<(), Error> {
-        let _span = tracing::debug_span!("creating tables").entered();
-        for qry in vec![
-            CREATE_AGGREGATE_TABLE_STMT,
-            CREATE_AGGREGATE_OVERVIEW_TABLE_STMT,
-            CREATE_SNAPSHOT_TABLE_STMT,
-            CREATE_SNAPSHOT_OVERVIEW_TABLE_STMT,
-        ] {
-            self.pool.get()?.execute(qry, params![])?;
+    pub fn get_events<E: DomainEvent>(&self, aggregate_id: Uuid) -> Result<Vec<E>, Error> {
+        self.get_aggretate(aggregate_id)?
+            .iter()
+            .filter(|event| event.event_type == E::event_type())
+            .map(|event| event.decode_with(&self.transcoders))
+            .collect()
+    }
+
+    /// Compress payloads of appended events and snapshots, reads decompress
+    /// transparently.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Encrypt payloads of appended events and snapshots with `encryptor`,
+    /// after they have been compressed. The key id is stored per event.
+    pub fn with_encryptor<E: Encryptor + 'static>(mut self, encryptor: E) -> Self {
+        self.encryptor = Some(Arc::new(encryptor));
+        self
+    }
+
+    /// Encrypt the metadata along with the payload, requires an encryptor.
+    pub fn with_encrypted_metadata(mut self) -> Self {
+        self.encrypt_metadata = true;
+        self
+    }
+
+    /// Encrypt every event with a data key of its subject, see `forget`.
+    /// The subject is taken from the `subject_id` metadata and defaults to
+    /// the aggregate id. Subject keys are wrapped with the encryptor if one
+    /// is configured.
+    #[cfg(feature = "encryption")]
+    pub fn with_crypto_shredding(mut self) -> Self {
+        self.crypto_shredding = true;
+        self
+    }
+
+    /// Columns of an event or snapshot row as they are written to the
+    /// database: payload, compression, metadata and key id.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn stored_row<'a>(
+        &self,
+        conn: &rusqlite::Connection,
+        event: &'a Event,
+    ) -> Result<StoredRow<'a>, Error> {
+        let (data, compression) = self.stored_payload(&event.event_type, &event.data)?;
+        let metadata = serde_json::to_string(&event.metadata)?;
+        #[cfg(feature = "encryption")]
+        if self.crypto_shredding {
+            let subject = match event.metadata.get(encryption::SUBJECT_ID) {
+                Some(subject) => subject.clone(),
+                None => event.id.to_string(),
+            };
+            let key = self.subject_key(conn, &subject, true)?.ok_or_else(|| {
+                Error::Encryption(format!("subject {} has been forgotten", subject))
+            })?;
+            let metadata = if self.encrypt_metadata {
+                Value::Blob(encryption::seal(&key, metadata.as_bytes())?)
+            } else {
+                Value::Text(metadata)
+            };
+            return Ok(StoredRow {
+                data: Cow::Owned(encryption::seal(&key, &data)?),
+                compression,
+                metadata,
+                key_id: format!("{}{}", encryption::SUBJECT_KEY_PREFIX, subject),
+            });
+        }
+        let Some(encryptor) = &self.encryptor else {
+            return Ok(StoredRow {
+                data,
+                compression,
+                metadata: Value::Text(metadata),
+                key_id: String::new(),
+            });
+        };
+        let (data, key_id) = encryptor.encrypt(&data)?;
+        let metadata = if self.encrypt_metadata {
+            // stored as BLOB, which tells encrypted and plain metadata apart
+            let (metadata, metadata_key_id) = encryptor.encrypt(metadata.as_bytes())?;
+            if metadata_key_id != key_id {
+                return Err(Error::Encryption("key changed during append".to_string()));
+            }
+            Value::Blob(metadata)
+        } else {
+            Value::Text(metadata)
+        };
+        Ok(StoredRow {
+            data: Cow::Owned(data),
+            compression,
+            metadata,
+            key_id,
+        })
+    }
+
+    /// Decrypt a stored payload, `None` if the subject key was forgotten.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn decrypt(
+        &self,
+        conn: &rusqlite::Connection,
+        key_id: &str,
+        data: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if key_id.is_empty() {
+            return Ok(Some(data));
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(subject) = key_id.strip_prefix(encryption::SUBJECT_KEY_PREFIX) {
+            return match self.subject_key(conn, subject, false)? {
+                Some(key) => encryption::open(&key, &data).map(Some),
+                None => Ok(None),
+            };
+        }
+        self.unwrap(key_id, &data).map(Some)
+    }
+
+    fn unwrap(&self, key_id: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match &self.encryptor {
+            Some(encryptor) => encryptor.decrypt(key_id, data),
+            None => Err(Error::Encryption(format!(
+                "no encryptor configured to decrypt with key {}",
+                key_id
+            ))),
+        }
+    }
+
+    /// Undo encryption and compression of a stored payload, `None` if the
+    /// subject key was forgotten.
+    fn open_payload(
+        &self,
+        conn: &rusqlite::Connection,
+        compression: &str,
+        key_id: &str,
+        data: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        match self.decrypt(conn, key_id, data)? {
+            Some(data) => self.decompress(compression, data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn open_metadata(
+        &self,
+        conn: &rusqlite::Connection,
+        key_id: &str,
+        metadata: ValueRef,
+    ) -> Result<BTreeMap<String, String>, Error> {
+        match metadata {
+            ValueRef::Blob(sealed) => match self.decrypt(conn, key_id, sealed.to_vec())? {
+                Some(metadata) => Ok(serde_json::from_slice(&metadata)?),
+                None => Ok(BTreeMap::new()),
+            },
+            other => Ok(serde_json::from_str(
+                other.as_str().map_err(rusqlite::Error::from)?,
+            )?),
+        }
+    }
+
+    /// Payload as it is written to the database along with the value for the
+    /// `compression` column.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    fn stored_payload<'a>(
+        &self,
+        event_type: &str,
+        data: &'a [u8],
+    ) -> Result<(Cow<'a, [u8]>, String), Error> {
+        #[cfg(feature = "compression")]
+        if let Some(compression) = &self.compression {
+            let dictionaries = self.dictionaries.read().unwrap();
+            return compression.compress(data, dictionaries.latest(event_type));
         }
-        return Ok(());
+        Ok((Cow::Borrowed(data), String::new()))
     }
 
+    /// Train a zstd dictionary from the latest `sample_limit` payloads of
+    /// `event_type`. Events of that type appended afterwards are compressed
+    /// with it, which pays off for many small and similar payloads that are
+    /// below the compression threshold. Returns the id of the dictionary.
+    #[cfg(feature = "compression")]
     #[instrument]
-    fn init_indices(&self) -> Result<(), Error> {
-        self.pool.get()?.execute(
-            "CREATE INDEX IF NOT EXISTS eventstore_agg_id_idx ON eventstore (aggregate_id)",
-            params![],
+    pub fn train_dictionary(
+        &self,
+        event_type: &str,
+        sample_limit: usize,
+        max_size: usize,
+    ) -> Result<i64, Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Maintain, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(
+            &self.sql("SELECT data, compression, key_id FROM {eventstore} WHERE event_type = ? ORDER BY position DESC LIMIT ?"),
         )?;
-        self.pool.get()?.execute(
-            "CREATE INDEX IF NOT EXISTS snapshot_agg_id_idx ON snapshot (aggregate_id)",
-            params![],
+        let samples = stmt
+            .query_map(params![event_type, sample_limit], |r| {
+                Ok((
+                    r.get::<_, Vec<u8>>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, String>(2)?,
+                ))
+            })?
+            .filter_map(|row| {
+                let (data, compression, key_id) = match row {
+                    Ok(row) => row,
+                    Err(err) => return Some(Err(err.into())),
+                };
+                self.open_payload(&conn, &compression, &key_id, data)
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let dictionary = compression::train_dictionary(&samples, max_size)?;
+        conn.execute(
+            &self.sql("INSERT INTO {compression_dictionary}(event_type, dictionary) VALUES(?, ?)"),
+            params![event_type, dictionary],
         )?;
-        self.pool.get()?.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS snapshot_unique_idx ON snapshot (aggregate_id, version)",
-            params![],
+        let id = conn.last_insert_rowid();
+        self.dictionaries
+            .write()
+            .unwrap()
+            .insert(id, event_type, dictionary);
+        Ok(id)
+    }
+
+    fn load_dictionaries(&self) -> Result<(), Error> {
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(
+            &self.sql("SELECT id, event_type, dictionary FROM {compression_dictionary}"),
         )?;
-        return Ok(());
+        let rows = stmt.query_map(params![], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+        let mut dictionaries = self.dictionaries.write().unwrap();
+        for row in rows {
+            let (id, event_type, dictionary): (i64, String, Vec<u8>) = row?;
+            dictionaries.insert(id, &event_type, dictionary);
+        }
+        Ok(())
+    }
+
+    /// Undo the compression of a stored payload, dictionaries trained by
+    /// other processes are loaded on demand.
+    fn decompress(&self, compression: &str, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let known = match compression.strip_prefix(compression::ZSTD_DICT_PREFIX) {
+            Some(id) => id
+                .parse()
+                .map(|id| self.dictionaries.read().unwrap().get(id).is_some())
+                .unwrap_or(true),
+            None => true,
+        };
+        if !known {
+            self.load_dictionaries()?;
+        }
+        compression::decompress(compression, data, &self.dictionaries.read().unwrap())
+    }
+
+    /// Serialize a domain event with the configured codec.
+    pub fn encode<E: DomainEvent>(&self, event: &E) -> Result<Event, Error> {
+        Event::encode_with(&self.codec, event)
+    }
+
+    /// Store the trace context of the appending span in the metadata of
+    /// every event, projections continue the trace when processing it.
+    /// Events written with a trace context keep it, scheduled events the
+    /// one of the span that scheduled them.
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_trace_context(mut self) -> Self {
+        self.trace_context = true;
+        self
+    }
+
+    /// Reject appends of events with payloads larger than `max` bytes, the
+    /// size is checked before compression and encryption.
+    pub fn with_max_event_size(mut self, max: usize) -> Self {
+        self.max_event_size = Some(max);
+        self
+    }
+
+    /// Run `handlers` inside the transaction of every append.
+    pub fn with_handlers(mut self, handlers: HandlerRegistry) -> Self {
+        self.handlers = Arc::new(handlers);
+        self
+    }
+
+    /// Validate payloads against `registry` before they are appended.
+    #[cfg(feature = "schema-registry")]
+    pub fn with_schema_registry(mut self, registry: SchemaRegistry) -> Self {
+        self.schemas = Some(Arc::new(registry));
+        self
     }
 
     #[instrument]
     pub fn get_agg_max_version(&self, tx: &Transaction, agg_id_str: &str) -> Result<u32, Error> {
         let mut stmt = tx
-            .prepare("SELECT COALESCE(MAX(version), 0) as max_version FROM aggregate_index WHERE aggregate_id = ?")?;
-        let version = stmt.query_row(params![agg_id_str], |row| match row.get(0) {
-            Ok(val) => Ok(val),
-            Err(err) => {
-                warn!(sqlite_error = err.to_string());
-                Err(err)
-            }
-        })?;
+            .prepare_cached(&self.sql("SELECT COALESCE(MAX(version), 0) as max_version FROM {aggregate_index} WHERE tenant_id = ? AND aggregate_id = ?"))?;
+        let version = stmt.query_row(
+            params![self.tenant_id(), self.id_param_str(agg_id_str)],
+            |row| match row.get(0) {
+                Ok(val) => Ok(val),
+                Err(err) => {
+                    warn!(sqlite_error = err.to_string());
+                    Err(err)
+                }
+            },
+        )?;
         debug!(current_event_version = version);
         Ok(version)
     }
@@ -159,21 +1018,43 @@ impl SqliteBackend {
     /// This function will return an error if .
     #[instrument]
     pub fn save_snapshot(&self, event: &Event) -> Result<(), Error> {
-        let mut conn = self.pool.get()?;
+        self.ensure_writable()?;
+        self.authorize(Operation::Append, Some(event.id))?;
+        let mut conn = self.connection()?;
+        let event = &self.with_stored_ids(&conn, std::slice::from_ref(event))?[0];
         let tx = conn.transaction()?;
+        let row = self.stored_row(&tx, event)?;
         tx.execute(
-            "INSERT INTO snapshot(aggregate_id, version, data) VALUES(?,?,?)
-                ON CONFLICT(aggregate_id, version) DO UPDATE SET version = excluded.version, data = excluded.data",
-            params![&event.id.to_string(), event.version, event.data],
+            &self.sql("INSERT INTO {snapshot}(aggregate_id, version, data, event_type, schema_version, content_type, metadata, compression, key_id, tenant_id)
+                VALUES(?,?,?,?,?,?,?,?,?,?)
+                ON CONFLICT(tenant_id, aggregate_id, version) DO UPDATE SET version = excluded.version, data = excluded.data,
+                    event_type = excluded.event_type, schema_version = excluded.schema_version,
+                    content_type = excluded.content_type, metadata = excluded.metadata,
+                    compression = excluded.compression, key_id = excluded.key_id"),
+            params![
+                self.id_param(event.id),
+                event.version,
+                row.data,
+                event.event_type,
+                event.schema_version,
+                event.content_type,
+                row.metadata,
+                row.compression,
+                row.key_id,
+                self.tenant_id()
+            ],
         )?;
         let res = tx.execute(
-            "INSERT INTO snapshot_index(version, aggregate_id, type_name) VALUES(?,?, 'todo_implement_type_name')
-                ON CONFLICT(aggregate_id) DO UPDATE SET version = ?",
-            params![event.version, &event.id.to_string(), event.version],
+            &self.sql("INSERT INTO {snapshot_index}(version, aggregate_id, type_name, tenant_id) VALUES(?,?, 'todo_implement_type_name', ?)
+                ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET version = ?"),
+            params![event.version, self.id_param(event.id), self.tenant_id(), event.version],
         );
         match res {
             Ok(_) => match tx.commit() {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    self.snapshot_saved(event.id);
+                    Ok(())
+                }
                 Err(err) => {
                     warn!(sqlite_error = err.to_string());
                     Err(Error::Sqlite(err))
@@ -186,9 +1067,37 @@ impl SqliteBackend {
         }
     }
 
+    /// Drop the cached values of the stored id `aggregate_id` and count
+    /// the snapshot, called once a snapshot of it is committed.
+    fn snapshot_saved(&self, aggregate_id: Uuid) {
+        self.invalidate_cached([aggregate_id]);
+        metrics::snapshot_saved();
+    }
+
     #[instrument]
     pub fn append_event(&self, event: &Event) -> Result<(), Error> {
-        let mut conn = self.pool.get()?;
+        self.append_events(std::slice::from_ref(event))
+    }
+
+    /// Append several events in a single transaction, either all of them are
+    /// stored or none.
+    #[instrument]
+    pub fn append_events(&self, events: &[Event]) -> Result<(), Error> {
+        self.append_guarded(events, |_| Ok(true)).map(|_| ())
+    }
+
+    /// Append `events` in one transaction with `guard`, unless `guard`
+    /// returns false. Returns whether the events were appended.
+    fn append_guarded(
+        &self,
+        events: &[Event],
+        guard: impl FnOnce(&Transaction) -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        self.ensure_writable()?;
+        for event in events {
+            self.authorize(Operation::Append, Some(event.id))?;
+        }
+        let mut conn = self.connection()?;
         let tx = match conn.transaction() {
             Ok(tx) => tx,
             Err(err) => {
@@ -196,38 +1105,412 @@ impl SqliteBackend {
                 return Err(Error::Sqlite(err));
             }
         };
-        let version = match self.get_agg_max_version(&tx, &event.id.to_string()) {
-            Ok(version) => version,
+        let written = match self.write_events(&tx, events, guard)? {
+            Outcome::Written(written) => written,
+            Outcome::Skipped => return Ok(false),
+            Outcome::Rejected(rejection) => {
+                drop(tx);
+                return Err(self.reject(&conn, rejection)?);
+            }
+        };
+        match tx.commit() {
+            Ok(_) => {
+                self.committed(written);
+                Ok(true)
+            }
             Err(err) => {
-                return Err(err);
+                warn!(sqlite_error = err.to_string());
+                Err(Error::Sqlite(err))
             }
+        }
+    }
+
+    /// Append `events` in `tx` the way every write does: add the append
+    /// and trace context, pass them through the middleware, validate them,
+    /// replace aliases by stored ids, run `guard`, append them with the
+    /// synchronous handlers and charge the quotas. First events of streams
+    /// are recorded in the system streams. Pass the result to `committed`
+    /// once `tx` is, a rejection to `reject` once it is rolled back.
+    fn write_events(
+        &self,
+        tx: &Transaction,
+        events: &[Event],
+        guard: impl FnOnce(&Transaction) -> Result<bool, Error>,
+    ) -> Result<Outcome, Error> {
+        let started = Instant::now();
+        let mut events = self.enriched(events);
+        if !self.middleware.is_empty() {
+            self.middleware.before_append(events.to_mut())?;
+        }
+        self.validate_events(&events)?;
+        let events = self.with_stored_ids(tx, &events)?;
+        if !guard(tx)? {
+            return Ok(Outcome::Skipped);
+        }
+        let positions = self.append_batch(tx, &events)?;
+        let charge = match self.charge_quotas(tx, &events, &positions)? {
+            Ok(charge) => charge,
+            Err(rejection) => return Ok(Outcome::Rejected(rejection)),
         };
-        let expected_version = version + 1;
-        if event.version != expected_version {
-            warn!("version mismtach {} != {}", event.version, expected_version);
-            return Err(Error::WithMsg("version mismtach".to_string()));
+        let mut written = Written {
+            ids: events.iter().map(|event| event.id).collect(),
+            events: match self.middleware.is_empty() {
+                true => Vec::new(),
+                false => positioned(&events, positions),
+            },
+            charges: charge.into_iter().collect(),
+            started: Some(started),
+        };
+        for event in events.iter().filter(|event| event.version == 1) {
+            written.extend(self.stream_created(tx, event.id)?);
         }
-        let res = tx.execute(
-            "INSERT INTO eventstore(aggregate_id, version, data) VALUES(?,?,?)",
-            params![&event.id.to_string(), event.version, event.data],
-        );
-        if let Err(err) = res {
-            warn!(sqlite_error = err.to_string());
-            return Err(Error::Sqlite(err));
+        Ok(Outcome::Written(written))
+    }
+
+    /// `write_events` of events exempt from quotas, system events and
+    /// tombstones.
+    fn write_exempt(&self, tx: &Transaction, events: &[Event]) -> Result<Written, Error> {
+        match self.write_events(tx, events, |_| Ok(true))? {
+            Outcome::Written(written) => Ok(written),
+            Outcome::Skipped => Ok(Written::default()),
+            Outcome::Rejected(rejection) => Err(rejection.into()),
         }
-        let res = tx.execute(
-            "INSERT INTO aggregate_index(version, aggregate_id, type_name) VALUES(?,?, 'todo_implement_type_name')
-                ON CONFLICT(aggregate_id) DO UPDATE SET version = ?",
-            params![event.version, &event.id.to_string(), event.version],
+    }
+
+    /// Finish the writes of a committed transaction: keep the quotas
+    /// charged, drop cached values, count the events and run the
+    /// `after_append` middleware.
+    fn committed(&self, written: Written) {
+        for charge in written.charges {
+            charge.settle();
+        }
+        if written.ids.is_empty() {
+            return;
+        }
+        let count = written.ids.len();
+        self.invalidate_cached(written.ids);
+        self.written.fetch_add(count as u64, Ordering::Relaxed);
+        metrics::appended(
+            count,
+            written
+                .started
+                .map(|started| started.elapsed())
+                .unwrap_or_default(),
         );
-        match res {
-            Ok(_) => match tx.commit() {
-                Ok(_) => Ok(()),
-                Err(err) => {
-                    warn!(sqlite_error = err.to_string());
-                    Err(Error::Sqlite(err))
+        if !written.events.is_empty() {
+            self.middleware.after_append(&written.events);
+        }
+    }
+
+    /// Events with the append context of the handle and the current trace
+    /// context, if enabled, added to their metadata.
+    fn enriched<'a>(&self, events: &'a [Event]) -> Cow<'a, [Event]> {
+        #[cfg(feature = "opentelemetry")]
+        let traced = self.trace_context;
+        #[cfg(not(feature = "opentelemetry"))]
+        let traced = false;
+        if !traced && self.append_context.is_none() {
+            return Cow::Borrowed(events);
+        }
+        Cow::Owned(
+            events
+                .iter()
+                .cloned()
+                .map(|mut event| {
+                    if let Some(context) = &self.append_context {
+                        context.apply(&mut event.metadata);
+                    }
+                    #[cfg(feature = "opentelemetry")]
+                    if traced {
+                        // scheduled, imported and copied events keep theirs
+                        let mut trace = BTreeMap::new();
+                        crate::telemetry::inject_current(&mut trace);
+                        for (key, value) in trace {
+                            event.metadata.entry(key).or_insert(value);
+                        }
+                    }
+                    event
+                })
+                .collect(),
+        )
+    }
+
+    /// Whether `enriched` may add the metadata `key`, these differ between
+    /// appends of the same event under another request or trace.
+    fn is_enrichment_key(key: &str) -> bool {
+        #[cfg(feature = "opentelemetry")]
+        if crate::telemetry::is_trace_key(key) {
+            return true;
+        }
+        AppendContext::is_context_key(key)
+    }
+
+    /// `events` as reads return them, upcast and passed through the
+    /// middleware.
+    fn decoded(&self, events: Vec<Event>) -> Result<Vec<Event>, Error> {
+        let mut events = self.upcasters.upcast_all(events)?;
+        if !self.middleware.is_empty() {
+            self.middleware.on_read(&mut events)?;
+        }
+        Ok(events)
+    }
+
+    /// Checks run before events are written, time to live, valid time,
+    /// link targets, size limit and schemas.
+    fn validate_events(&self, events: &[Event]) -> Result<(), Error> {
+        for event in events {
+            if let Some(ttl) = event.metadata.get(TTL) {
+                if ttl.parse::<u64>().is_err() {
+                    return Err(Error::WithMsg(format!("invalid {}: {:?}", TTL, ttl)));
                 }
-            },
+            }
+            for key in [VALID_FROM, VALID_TO] {
+                if let Some(ms) = event.metadata.get(key) {
+                    if ms.parse::<i64>().is_err() {
+                        return Err(Error::WithMsg(format!("invalid {}: {:?}", key, ms)));
+                    }
+                }
+            }
+            if let [Some(from), Some(to)] = valid_time(event) {
+                if from >= to {
+                    return Err(Error::WithMsg(format!(
+                        "{} must be before {}",
+                        VALID_FROM, VALID_TO
+                    )));
+                }
+            }
+            if event.event_type == LINK_EVENT_TYPE && event.link_target().is_none() {
+                return Err(Error::WithMsg(format!(
+                    "invalid {}: {:?}",
+                    LINK_TO,
+                    event.metadata.get(LINK_TO)
+                )));
+            }
+        }
+        if let Some(max) = self.max_event_size {
+            for event in events {
+                if event.data.len() > max {
+                    warn!(size = event.data.len(), max, "payload too large");
+                    return Err(Error::PayloadTooLarge {
+                        size: event.data.len(),
+                        max,
+                    });
+                }
+            }
+        }
+        #[cfg(feature = "schema-registry")]
+        if let Some(schemas) = &self.schemas {
+            for event in events {
+                schemas.validate(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append `event` and run the synchronous handlers in `tx`.
+    fn append_and_dispatch(&self, tx: &Transaction, event: &Event) -> Result<u64, Error> {
+        let position = self.append_in_tx(tx, event)?;
+        if !self.handlers.is_empty() {
+            let appended = Event {
+                position,
+                ..event.clone()
+            };
+            self.handlers.dispatch(tx, &appended)?;
+        }
+        Ok(position)
+    }
+
+    /// Append `events` with one INSERT per chunk of rows and one index
+    /// update per stream, then run the synchronous handlers in `tx`.
+    /// Returns the positions of the events.
+    fn append_batch(&self, tx: &Transaction, events: &[Event]) -> Result<Vec<u64>, Error> {
+        if let [event] = events {
+            return self
+                .append_and_dispatch(tx, event)
+                .map(|position| vec![position]);
+        }
+        let mut versions: HashMap<Uuid, u32> = HashMap::new();
+        for event in events {
+            let current = match versions.get(&event.id) {
+                Some(version) => *version,
+                None => self.current_version(tx, event.id)?,
+            };
+            check_version(event, current)?;
+            versions.insert(event.id, event.version);
+        }
+        let rows = events
+            .iter()
+            .map(|event| self.stored_row(tx, event))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let created_at = self.now_millis();
+        let mut positions = Vec::with_capacity(events.len());
+        for (events, rows) in events
+            .chunks(INSERT_CHUNK_ROWS)
+            .zip(rows.chunks(INSERT_CHUNK_ROWS))
+        {
+            let last = self.insert_rows(tx, events, rows, created_at)?;
+            positions.extend(last + 1 - events.len() as u64..=last);
+        }
+        for (id, version) in versions {
+            self.update_index(tx, id, version)?;
+        }
+        for (event, position) in events.iter().zip(&positions) {
+            self.track_latest(tx, event, *position)?;
+        }
+        self.count_appended(tx, events, created_at)?;
+        if !self.handlers.is_empty() {
+            for appended in positioned(events, positions.iter().copied()) {
+                self.handlers.dispatch(tx, &appended)?;
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Insert `event` and update the index, returns the position of the event.
+    ///
+    /// The INSERT only writes the row if the stream is at the preceding
+    /// version and not deleted, so the happy path takes two statements. The
+    /// reason is only looked up if nothing was written.
+    fn append_in_tx(&self, tx: &Transaction, event: &Event) -> Result<u64, Error> {
+        let row = self.stored_row(tx, event)?;
+        let created_at = self.now_millis();
+        let [valid_from, valid_to] = valid_time(event);
+        let res = tx
+            .prepare_cached(&self.sql("INSERT INTO {eventstore}(aggregate_id, version, data, event_type, schema_version, content_type, metadata, compression, key_id, created_at, tenant_id, expires_at, event_id, causation_id, correlation_id, valid_from, valid_to)
+                SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17
+                WHERE ?2 = 1 + COALESCE((SELECT version FROM {aggregate_index} WHERE tenant_id = ?11 AND aggregate_id = ?1), 0)
+                AND NOT EXISTS (SELECT 1 FROM {stream_metadata} WHERE tenant_id = ?11 AND aggregate_id = ?1 AND deleted)"))
+            .and_then(|mut stmt| stmt.execute(params![
+                self.id_param(event.id),
+                event.version,
+                row.data,
+                event.event_type,
+                event.schema_version,
+                event.content_type,
+                row.metadata,
+                row.compression,
+                row.key_id,
+                created_at,
+                self.tenant_id(),
+                expires_at(event, created_at),
+                event.metadata.get(EVENT_ID),
+                event.metadata.get(CAUSATION_ID),
+                event.metadata.get(CORRELATION_ID),
+                valid_from,
+                valid_to
+            ]));
+        match res {
+            Ok(0) => {
+                check_version(event, self.current_version(tx, event.id)?)?;
+                // the stream was at the expected version after all
+                Err(Error::VersionConflict)
+            }
+            Ok(_) => {
+                let position = tx.last_insert_rowid() as u64;
+                self.update_index(tx, event.id, event.version)?;
+                self.track_latest(tx, event, position)?;
+                self.count_appended(tx, std::slice::from_ref(event), created_at)?;
+                Ok(position)
+            }
+            Err(err) => Err(insert_error(err)),
+        }
+    }
+
+    /// Version of the stream `aggregate_id`, failing if it was deleted.
+    fn current_version(&self, tx: &Transaction, aggregate_id: Uuid) -> Result<u32, Error> {
+        let (version, deleted): (u32, bool) = tx
+            .prepare_cached(&self.sql(
+                "SELECT COALESCE((SELECT version FROM {aggregate_index} WHERE tenant_id = ?1 AND aggregate_id = ?2), 0),
+                    COALESCE((SELECT deleted FROM {stream_metadata} WHERE tenant_id = ?1 AND aggregate_id = ?2), 0)",
+            ))?
+            .query_row(params![self.tenant_id(), self.id_param(aggregate_id)], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+        if deleted {
+            warn!(aggregate_id = %aggregate_id, "append to deleted stream");
+            return Err(Error::StreamDeleted(aggregate_id));
+        }
+        debug!(current_event_version = version);
+        Ok(version)
+    }
+
+    /// Insert the rows of `events` with a single statement, returns the
+    /// position of the last one. The positions of a multi-row INSERT are
+    /// consecutive, so the others follow from it.
+    fn insert_rows(
+        &self,
+        tx: &Transaction,
+        events: &[Event],
+        rows: &[StoredRow],
+        created_at: i64,
+    ) -> Result<u64, Error> {
+        let ids: Vec<Value> = events.iter().map(|event| self.id_param(event.id)).collect();
+        let expiries: Vec<Option<i64>> = events
+            .iter()
+            .map(|event| expires_at(event, created_at))
+            .collect();
+        let causation: Vec<[Option<&String>; 3]> = events
+            .iter()
+            .map(|event| {
+                [EVENT_ID, CAUSATION_ID, CORRELATION_ID].map(|key| event.metadata.get(key))
+            })
+            .collect();
+        let valid_times: Vec<[Option<i64>; 2]> = events.iter().map(valid_time).collect();
+        let mut params: Vec<&dyn ToSql> = Vec::with_capacity(events.len() * 17);
+        for (
+            ((((event, row), id), expires_at), [event_id, causation_id, correlation_id]),
+            [valid_from, valid_to],
+        ) in events
+            .iter()
+            .zip(rows)
+            .zip(&ids)
+            .zip(&expiries)
+            .zip(&causation)
+            .zip(&valid_times)
+        {
+            params.extend_from_slice(&[
+                id,
+                &event.version,
+                &row.data,
+                &event.event_type,
+                &event.schema_version,
+                &event.content_type,
+                &row.metadata,
+                &row.compression,
+                &row.key_id,
+                &created_at,
+                &self.tenant,
+                expires_at,
+                event_id,
+                causation_id,
+                correlation_id,
+                valid_from,
+                valid_to,
+            ]);
+        }
+        let values = vec!["(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)"; events.len()].join(",");
+        let res = tx
+            .prepare_cached(&self.sql(&format!("INSERT INTO {{eventstore}}(aggregate_id, version, data, event_type, schema_version, content_type, metadata, compression, key_id, created_at, tenant_id, expires_at, event_id, causation_id, correlation_id, valid_from, valid_to)
+                VALUES{}", values)))
+            .and_then(|mut stmt| stmt.execute(params_from_iter(params)));
+        match res {
+            Ok(_) => Ok(tx.last_insert_rowid() as u64),
+            Err(err) => Err(insert_error(err)),
+        }
+    }
+
+    fn update_index(
+        &self,
+        tx: &Transaction,
+        aggregate_id: Uuid,
+        version: u32,
+    ) -> Result<(), Error> {
+        let res = tx
+            .prepare_cached(&self.sql("INSERT INTO {aggregate_index}(version, aggregate_id, type_name, tenant_id) VALUES(?,?, 'todo_implement_type_name', ?)
+                ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET version = ?"))
+            .and_then(|mut stmt| stmt.execute(params![version, self.id_param(aggregate_id), self.tenant_id(), version]));
+        match res {
+            Ok(_) => Ok(()),
             Err(err) => {
                 warn!(sqlite_error = err.to_string());
                 Err(Error::Sqlite(err))
@@ -236,41 +1519,64 @@ impl SqliteBackend {
     }
 
     #[instrument]
-    fn result_from_stmt(stmt: &mut Statement, agg_id_str: &str) -> Result<Vec<Event>, Error> {
-        let params = vec![agg_id_str];
-        Self::result_from_stmt_with_params(stmt, &params)
+    fn result_from_stmt(
+        &self,
+        conn: &rusqlite::Connection,
+        stmt: &mut Statement,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<Event>, Error> {
+        let id = self.id_param(aggregate_id);
+        self.result_from_stmt_with_params(conn, stmt, &[&self.tenant_id(), &id])
+    }
+
+    /// The event of a row selecting `EVENT_COLUMNS` or `SNAPSHOT_COLUMNS`.
+    fn event_from_row(
+        &self,
+        conn: &rusqlite::Connection,
+        r: &rusqlite::Row,
+    ) -> Result<Event, Error> {
+        let id = ids::read_id(r.get_ref(0)?)?;
+        let key_id: String = r.get("key_id")?;
+        let data = self.open_payload(
+            conn,
+            &r.get::<_, String>("compression")?,
+            &key_id,
+            r.get(1)?,
+        )?;
+        let mut metadata = self.open_metadata(conn, &key_id, r.get_ref("metadata")?)?;
+        if data.is_none() {
+            metadata.insert(FORGOTTEN.to_string(), "true".to_string());
+        }
+        Ok(Event {
+            id,
+            data: data.unwrap_or_default(),
+            version: r.get(2)?,
+            event_type: r.get(3)?,
+            schema_version: r.get(4)?,
+            content_type: r.get("content_type")?,
+            metadata,
+            // snapshots have no global position
+            position: match r.as_ref().column_index("position") {
+                Ok(idx) => r.get(idx)?,
+                Err(_) => 0,
+            },
+        })
     }
 
     fn result_from_stmt_with_params(
+        &self,
+        conn: &rusqlite::Connection,
         stmt: &mut Statement,
-        params: &Vec<&str>,
+        params: &[&dyn ToSql],
     ) -> Result<Vec<Event>, Error> {
-        let mut events: Vec<_> = Vec::new();
-        let query_res = stmt.query_and_then(params_from_iter(params), |r| {
-            let id = if let Ok(tmp) = r.get::<_, String>(0) {
-                match uuid::Uuid::parse_str(tmp.as_str()) {
-                    Ok(id) => id,
-                    Err(_) => return Err(Error::InvalidUUID),
-                }
-            } else {
-                return Err(Error::WithMsg("could not read uuid from row".to_string()));
-            };
-            Ok(Event {
-                id,
-                data: r.get(1)?,
-                version: r.get(2)?,
-            })
-        });
+        let started = Instant::now();
+        let query_res =
+            stmt.query_and_then(params_from_iter(params), |r| self.event_from_row(conn, r));
         match query_res {
             Ok(iter) => {
-                iter.filter_map(|e| match e {
-                    Ok(val) => Some(val),
-                    Err(_) => None,
-                })
-                .fold(&mut events, |acc, e| {
-                    acc.push(e);
-                    acc
-                });
+                // rows failing to decrypt, decompress or decode fail the read
+                let events = iter.collect::<Result<Vec<_>, Error>>()?;
+                metrics::read(events.len(), started.elapsed());
                 Ok(events)
             }
             Err(err) => {
@@ -282,20 +1588,182 @@ impl SqliteBackend {
 
     #[instrument]
     pub fn get_aggretate(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
-        let agg_id_str: String = aggregate_id.to_string();
-        let conn = self.pool.get()?;
-        let mut stmt =
-            conn.prepare("SELECT * FROM eventstore WHERE aggregate_id = ? ORDER BY version ASC")?;
-        SqliteBackend::result_from_stmt(&mut stmt, &agg_id_str)
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        let events = match &self.read_cache {
+            Some(cache) => self
+                .cached_stream(cache, &conn, stored)?
+                .into_iter()
+                .map(Event::from)
+                .collect(),
+            None => self.load_stream(&conn, stored)?,
+        };
+        match stored == aggregate_id {
+            true => Ok(events),
+            false => Ok(aliases::relabel(events, aggregate_id)),
+        }
+    }
+
+    /// Like `get_aggretate`, with payloads that are shared instead of
+    /// copied, also with the read cache.
+    #[instrument]
+    pub fn get_aggretate_shared(&self, aggregate_id: Uuid) -> Result<Vec<SharedEvent>, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        if stored != aggregate_id {
+            return Ok(shared(aliases::relabel(
+                self.load_stream(&conn, stored)?,
+                aggregate_id,
+            )));
+        }
+        match &self.read_cache {
+            Some(cache) => self.cached_stream(cache, &conn, stored),
+            None => Ok(shared(self.load_stream(&conn, stored)?)),
+        }
+    }
+
+    fn cached_stream(
+        &self,
+        cache: &AggregateCache,
+        conn: &rusqlite::Connection,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<SharedEvent>, Error> {
+        cache.get_or_load_until(self.tenant_id(), aggregate_id, self.now_millis(), || {
+            // taken first, events expiring while loading make it stale
+            let expires_at = self.stream_expiry(conn, aggregate_id)?;
+            Ok((shared(self.load_stream(conn, aggregate_id)?), expires_at))
+        })
+    }
+
+    /// The events of `get_aggretate` of the stored id `aggregate_id` read
+    /// from the database on `conn`, without authorization and caching.
+    pub(crate) fn load_stream(
+        &self,
+        conn: &rusqlite::Connection,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<Event>, Error> {
+        if self.is_deleted(conn, aggregate_id)? {
+            return Err(Error::StreamDeleted(aggregate_id));
+        }
+        self.read_stream(conn, aggregate_id)
+    }
+
+    /// The events of several aggregates as returned by `get_aggretate`, read
+    /// with one query per 500 aggregates instead of one per aggregate, e.g.
+    /// for list pages. Unknown aggregates map to no events, tombstoned ones
+    /// fail the call with `Error::StreamDeleted`.
+    #[instrument(skip(aggregate_ids), fields(aggregates = aggregate_ids.len()))]
+    pub fn load_aggregates(
+        &self,
+        aggregate_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, Vec<Event>>, Error> {
+        for aggregate_id in aggregate_ids {
+            self.authorize(Operation::Read, Some(*aggregate_id))?;
+        }
+        let conn = self.connection()?;
+        // the ids read through each stored id
+        let mut requested: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for aggregate_id in aggregate_ids {
+            let ids = requested
+                .entry(self.stored_id(&conn, *aggregate_id)?)
+                .or_default();
+            if !ids.contains(aggregate_id) {
+                ids.push(*aggregate_id);
+            }
+        }
+        let mut loaded: HashMap<Uuid, Vec<Event>> = HashMap::new();
+        let unique: Vec<Uuid> = requested.keys().copied().collect();
+        for chunk in unique.chunks(LOAD_CHUNK_IDS) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            let ids: Vec<Value> = chunk.iter().map(|id| self.id_param(*id)).collect();
+            let tenant_id = self.tenant_id();
+            let mut params: Vec<&dyn ToSql> = vec![&tenant_id];
+            params.extend(ids.iter().map(|id| id as &dyn ToSql));
+
+            let deleted = conn
+                .prepare(&self.sql(&format!(
+                    "SELECT aggregate_id FROM {{stream_metadata}}
+                        WHERE tenant_id = ? AND aggregate_id IN ({}) AND deleted LIMIT 1",
+                    placeholders
+                )))?
+                .query_and_then(params_from_iter(&params), |row| {
+                    ids::read_id(row.get_ref(0)?)
+                })?
+                .next()
+                .transpose()?;
+            if let Some(stored) = deleted {
+                return Err(Error::StreamDeleted(requested[&stored][0]));
+            }
+
+            let mut stmt = conn.prepare(&format!(
+                "{} AND {} ORDER BY aggregate_id, version ASC",
+                self.sql(&format!(
+                    "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND aggregate_id IN ({})",
+                    EVENT_COLUMNS, placeholders
+                )),
+                self.retained()
+            ))?;
+            let events = self.result_from_stmt_with_params(&conn, &mut stmt, &params)?;
+            let mut streams: HashMap<Uuid, Vec<Event>> = HashMap::new();
+            for event in self.decoded(events)? {
+                streams.entry(event.id).or_default().push(event);
+            }
+            for stored in chunk {
+                let events = streams.remove(stored).unwrap_or_default();
+                for aggregate_id in &requested[stored] {
+                    let events = match aggregate_id == stored {
+                        true => events.clone(),
+                        false => aliases::relabel(events.clone(), *aggregate_id),
+                    };
+                    loaded.insert(*aggregate_id, events);
+                }
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Like `get_aggretate`, but returns the events of tombstoned streams
+    /// including the tombstone instead of failing.
+    #[instrument]
+    pub fn get_aggretate_including_tombstoned(
+        &self,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<Event>, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        let events = self.read_stream(&conn, stored)?;
+        match stored == aggregate_id {
+            true => Ok(events),
+            false => Ok(aliases::relabel(events, aggregate_id)),
+        }
+    }
+
+    fn read_stream(
+        &self,
+        conn: &rusqlite::Connection,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<Event>, Error> {
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY version ASC",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND aggregate_id = ?",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt(conn, &mut stmt, aggregate_id)?;
+        self.decoded(events)
     }
 
     #[instrument]
     pub fn get_snapshots(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
-        let agg_id_str: String = aggregate_id.to_string();
-        let conn = self.pool.get()?;
-        let mut stmt =
-            conn.prepare("SELECT * FROM snapshot WHERE aggregate_id = ? ORDER BY version ASC")?;
-        SqliteBackend::result_from_stmt(&mut stmt, &agg_id_str)
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        let mut stmt = conn.prepare_cached(
+            &self.sql(&format!("SELECT {} FROM {{snapshot}} WHERE tenant_id = ? AND aggregate_id = ? ORDER BY version ASC", SNAPSHOT_COLUMNS)),
+        )?;
+        let snapshots = self.result_from_stmt(&conn, &mut stmt, stored)?;
+        Ok(aliases::relabel(snapshots, aggregate_id))
     }
 
     #[instrument]
@@ -304,17 +1772,24 @@ impl SqliteBackend {
         aggregate_id: Uuid,
         version: u32,
     ) -> Result<Event, Error> {
-        let agg_id_str: String = aggregate_id.to_string();
-        let conn = self.pool.get()?;
-        let mut stmt = conn.prepare(
-            "SELECT * FROM snapshot WHERE aggregate_id = ? AND version = ? ORDER BY version ASC",
-        )?;
-        SqliteBackend::result_from_stmt_with_params(
-            &mut stmt,
-            &vec![&agg_id_str, &version.to_string()],
-        )?
-        .pop()
-        .ok_or(Error::NotFound)
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        let mut stmt = conn.prepare_cached(&self.sql(&format!(
+            "SELECT {} FROM {{snapshot}} WHERE tenant_id = ? AND aggregate_id = ? AND version = ?",
+            SNAPSHOT_COLUMNS
+        )))?;
+        let snapshot = self
+            .result_from_stmt_with_params(
+                &conn,
+                &mut stmt,
+                &[&self.tenant_id(), &self.id_param(stored), &version],
+            )?
+            .pop()
+            .ok_or(Error::NotFound)?;
+        Ok(Event {
+            id: aggregate_id,
+            ..snapshot
+        })
     }
 
     #[instrument]
@@ -323,14 +1798,191 @@ impl SqliteBackend {
         aggregate_id: Uuid,
         opts: &GetAggOpts,
     ) -> Result<Vec<Event>, Error> {
-        let agg_id_str: String = aggregate_id.to_string();
-        let conn = self.pool.get()?;
-        let mut stmt = conn.prepare(
-            "SELECT * FROM eventstore WHERE aggregate_id = ? AND version > ? ORDER BY version ASC",
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        if self.is_deleted(&conn, stored)? {
+            return Err(Error::StreamDeleted(aggregate_id));
+        }
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY version ASC",
+            self.sql(&format!("SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND aggregate_id = ? AND version > ?", EVENT_COLUMNS)),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
+            &mut stmt,
+            &[
+                &self.tenant_id(),
+                &self.id_param(stored),
+                &opts.since_version,
+            ],
         )?;
-        SqliteBackend::result_from_stmt_with_params(
+        let events = self.decoded(events)?;
+        match stored == aggregate_id {
+            true => Ok(events),
+            false => Ok(aliases::relabel(events, aggregate_id)),
+        }
+    }
+
+    /// The latest snapshot of `aggregate_id` and the events after it, read
+    /// with a single statement on one connection instead of a snapshot
+    /// lookup followed by `get_aggretate_with_opts`.
+    #[instrument]
+    pub fn load_aggregate(&self, aggregate_id: Uuid) -> Result<LoadedAggregate, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let started = Instant::now();
+        let (conn, stored) = self.stream_connection(aggregate_id)?;
+        // kind orders the rows: the deletion marker, the snapshot, the events
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY kind, version",
+            self.sql(&format!(
+                "WITH latest AS (
+                    SELECT {snapshot} FROM {{snapshot}} WHERE tenant_id = ?1 AND aggregate_id = ?2
+                    ORDER BY version DESC LIMIT 1
+                )
+                SELECT {snapshot}, 0 AS position, 1 AS kind FROM latest
+                UNION ALL
+                SELECT aggregate_id, NULL, 0, '', 0, '', '{{}}', '', '', 0, 0 FROM {{stream_metadata}}
+                    WHERE tenant_id = ?1 AND aggregate_id = ?2 AND deleted
+                UNION ALL
+                SELECT {events}, 2 FROM {{eventstore}} WHERE tenant_id = ?1 AND aggregate_id = ?2
+                    AND version > COALESCE((SELECT version FROM latest), 0)",
+                snapshot = SNAPSHOT_COLUMNS,
+                events = EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let mut rows = stmt.query(params![self.tenant_id(), self.id_param(stored)])?;
+        let mut loaded = LoadedAggregate {
+            snapshot: None,
+            events: Vec::new(),
+        };
+        while let Some(row) = rows.next()? {
+            match row.get::<_, i64>("kind")? {
+                0 => return Err(Error::StreamDeleted(aggregate_id)),
+                1 => loaded.snapshot = Some(self.event_from_row(&conn, row)?),
+                _ => loaded.events.push(self.event_from_row(&conn, row)?),
+            }
+        }
+        metrics::read(loaded.events.len(), started.elapsed());
+        loaded.events = self.decoded(loaded.events)?;
+        if stored != aggregate_id {
+            loaded.events = aliases::relabel(loaded.events, aggregate_id);
+            if let Some(snapshot) = &mut loaded.snapshot {
+                snapshot.id = aggregate_id;
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Like `read_all`, with payloads that are shared instead of copied
+    /// when the events are cloned.
+    #[instrument]
+    pub fn read_all_shared(
+        &self,
+        from_position: u64,
+        limit: usize,
+    ) -> Result<Vec<SharedEvent>, Error> {
+        Ok(shared(self.read_all(from_position, limit)?))
+    }
+
+    /// Read events of all aggregates in commit order, starting after
+    /// `from_position`.
+    #[instrument]
+    pub fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY position ASC LIMIT ?",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND position > ?",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
             &mut stmt,
-            &vec![&agg_id_str, &opts.since_version.to_string()],
-        )
+            &[&self.tenant_id(), &from_position, &limit],
+        )?;
+        self.decoded(events)
+    }
+
+    /// Read events of all aggregates in reverse commit order, starting
+    /// before `before_position`.
+    #[instrument]
+    pub fn read_all_backward(
+        &self,
+        before_position: u64,
+        limit: usize,
+    ) -> Result<Vec<Event>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY position DESC LIMIT ?",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND position < ?",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
+            &mut stmt,
+            &[
+                &self.tenant_id(),
+                &before_position.min(i64::MAX as u64),
+                &limit,
+            ],
+        )?;
+        self.decoded(events)
+    }
+
+    /// Highest committed global position of the tenant, 0 if it has no
+    /// events. Readers have caught up once they processed events up to it,
+    /// health checks compare it with checkpoints to measure lag. A single
+    /// seek on the `(tenant_id, position)` index, cheap to poll.
+    #[instrument]
+    pub fn current_position(&self) -> Result<u64, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(
+            &self.sql("SELECT COALESCE(MAX(position), 0) FROM {eventstore} WHERE tenant_id = ?"),
+        )?;
+        Ok(stmt.query_row(params![self.tenant_id()], |row| row.get(0))?)
+    }
+
+    /// Position up to which the projection `name` has processed events,
+    /// 0 if it never ran.
+    #[instrument]
+    pub fn get_checkpoint(&self, name: &str) -> Result<u64, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&self.sql(
+            "SELECT COALESCE(MAX(position), 0) FROM {projection_checkpoint} WHERE tenant_id = ? AND name = ?",
+        ))?;
+        Ok(stmt.query_row(params![self.tenant_id(), name], |row| row.get(0))?)
+    }
+
+    #[instrument]
+    pub fn save_checkpoint(&self, name: &str, position: u64) -> Result<(), Error> {
+        let conn = self.connection()?;
+        self.save_checkpoint_in(&conn, name, position)
+    }
+
+    pub(crate) fn save_checkpoint_in(
+        &self,
+        conn: &rusqlite::Connection,
+        name: &str,
+        position: u64,
+    ) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, None)?;
+        conn.prepare_cached(&self.sql(
+            "INSERT INTO {projection_checkpoint}(tenant_id, name, position) VALUES(?,?,?)
+                ON CONFLICT(tenant_id, name) DO UPDATE SET position = excluded.position",
+        ))?
+        .execute(params![self.tenant_id(), name, position])?;
+        self.sample_lag_in(conn, name, position)
     }
 }
@@ -0,0 +1,75 @@
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend};
+use crate::admin_log::AdminLogEntry;
+use crate::authorization::Operation;
+
+impl SqliteBackend {
+    /// Append an entry to the admin log, inside the transaction of the
+    /// operation where there is one.
+    pub(super) fn record_admin(
+        &self,
+        conn: &Connection,
+        operation: &str,
+        aggregate_id: Option<Uuid>,
+        details: Value,
+    ) -> Result<(), Error> {
+        conn.execute(
+            &self.sql(
+                "INSERT INTO {admin_log}(recorded_at, principal, tenant_id, operation, aggregate_id, details)
+                    VALUES(?, ?, ?, ?, ?, ?)",
+            ),
+            params![
+                self.now_millis(),
+                self.caller.principal,
+                self.tenant_id(),
+                operation,
+                aggregate_id.map(|id| id.to_string()),
+                details.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Administrative operations of the tenant after the entry `after_id`
+    /// in the order they happened: stream deletions, truncations, redactions,
+    /// forgotten subjects, imports and scavenges.
+    #[instrument]
+    pub fn admin_log(&self, after_id: u64, limit: usize) -> Result<Vec<AdminLogEntry>, Error> {
+        self.authorize(Operation::Maintain, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(&self.sql(
+            "SELECT id, recorded_at, principal, tenant_id, operation, aggregate_id, details FROM {admin_log}
+                WHERE tenant_id = ? AND id > ? ORDER BY id LIMIT ?",
+        ))?;
+        let rows = stmt.query_map(params![self.tenant_id(), after_id, limit], |r| {
+            Ok((
+                AdminLogEntry {
+                    id: r.get(0)?,
+                    recorded_at: r.get(1)?,
+                    principal: r.get(2)?,
+                    tenant_id: r.get(3)?,
+                    operation: r.get(4)?,
+                    aggregate_id: None,
+                    details: Value::Null,
+                },
+                r.get::<_, Option<String>>(5)?,
+                r.get::<_, String>(6)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (entry, aggregate_id, details) = row?;
+            Ok(AdminLogEntry {
+                aggregate_id: aggregate_id
+                    .map(|id| Uuid::parse_str(&id).map_err(|_| Error::InvalidUUID))
+                    .transpose()?,
+                details: serde_json::from_str(&details)?,
+                ..entry
+            })
+        })
+        .collect()
+    }
+}
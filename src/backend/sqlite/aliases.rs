@@ -0,0 +1,152 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::ids::read_id;
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+
+impl SqliteBackend {
+    /// Make `alias` a second id of the stream `aggregate_id`: reads and
+    /// appends under `alias` go to the events stored under `aggregate_id`,
+    /// events read through the alias carry it as their id. `alias` must
+    /// not have events of its own, aliases of aliases point to the stream
+    /// itself. Reads of all streams and projections see the stored id.
+    #[instrument]
+    pub fn alias_stream(&self, aggregate_id: Uuid, alias: Uuid) -> Result<(), Error> {
+        self.add_alias(aggregate_id, alias, false)
+    }
+
+    /// Re-key the stream `old_id` to `new_id` without rewriting its events,
+    /// e.g. after a change of the id scheme. Like `alias_stream`, but reads
+    /// and appends under `old_id` fail with `Error::StreamRenamed` from then
+    /// on.
+    #[instrument]
+    pub fn rename_stream(&self, old_id: Uuid, new_id: Uuid) -> Result<(), Error> {
+        self.add_alias(old_id, new_id, true)
+    }
+
+    /// The id the events of `aggregate_id` are stored under, itself unless
+    /// it is an alias.
+    #[instrument]
+    pub fn resolve_stream(&self, aggregate_id: Uuid) -> Result<Uuid, Error> {
+        self.stream_connection(aggregate_id)
+            .map(|(_, stored)| stored)
+    }
+
+    /// A connection and the stored id of `aggregate_id` resolved on it, how
+    /// reads of a single stream start. Writes resolve with `stored_id` in
+    /// their transaction.
+    pub(crate) fn stream_connection(
+        &self,
+        aggregate_id: Uuid,
+    ) -> Result<(PooledConnection<SqliteConnectionManager>, Uuid), Error> {
+        let conn = self.connection()?;
+        let stored = self.stored_id(&conn, aggregate_id)?;
+        Ok((conn, stored))
+    }
+
+    fn add_alias(&self, aggregate_id: Uuid, alias: Uuid, renamed: bool) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, Some(aggregate_id))?;
+        self.authorize(Operation::Manage, Some(alias))?;
+        let mut conn = self.connection()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let stored = self.stored_id(&tx, aggregate_id)?;
+        if stored == alias {
+            return Err(Error::WithMsg(format!("stream {} is its own alias", alias)));
+        }
+        if renamed && stored != aggregate_id {
+            return Err(Error::WithMsg(format!(
+                "stream {} is an alias",
+                aggregate_id
+            )));
+        }
+        if self.stored_id(&tx, alias)? != alias {
+            return Err(Error::WithMsg(format!("stream {} is an alias", alias)));
+        }
+        let taken: bool = tx.query_row(
+            &self.sql(
+                "SELECT EXISTS(SELECT 1 FROM {aggregate_index} WHERE tenant_id = ?1 AND aggregate_id = ?2)
+                    OR EXISTS(SELECT 1 FROM {stream_aliases} WHERE tenant_id = ?1 AND aggregate_id = ?3)",
+            ),
+            params![self.tenant_id(), self.id_param(alias), self.id_param(alias)],
+            |r| r.get(0),
+        )?;
+        if taken {
+            return Err(Error::WithMsg(format!("stream {} has events", alias)));
+        }
+        tx.execute(
+            &self.sql(
+                "INSERT INTO {stream_aliases}(tenant_id, alias, aggregate_id, renamed, created_at)
+                    VALUES(?, ?, ?, ?, ?)",
+            ),
+            params![
+                self.tenant_id(),
+                self.id_param(alias),
+                self.id_param(stored),
+                renamed,
+                self.now_millis()
+            ],
+        )?;
+        tx.commit()?;
+        self.invalidate_cached([stored]);
+        info!(aggregate_id = %stored, %alias, renamed, "aliased stream");
+        Ok(())
+    }
+
+    /// Like `resolve_stream` on `conn`, fails for renamed streams.
+    pub(super) fn stored_id(&self, conn: &Connection, aggregate_id: Uuid) -> Result<Uuid, Error> {
+        let row = conn
+            .prepare_cached(&self.sql(
+                "SELECT aggregate_id, 0 FROM {stream_aliases} WHERE tenant_id = ?1 AND alias = ?2
+                UNION ALL
+                SELECT alias, 1 FROM {stream_aliases} WHERE tenant_id = ?1 AND aggregate_id = ?2 AND renamed
+                LIMIT 1",
+            ))?
+            .query_row(params![self.tenant_id(), self.id_param(aggregate_id)], |r| {
+                Ok((read_id(r.get_ref(0)?), r.get::<_, bool>(1)?))
+            })
+            .optional()?;
+        match row {
+            None => Ok(aggregate_id),
+            Some((stored, false)) => stored,
+            Some((renamed, true)) => Err(Error::StreamRenamed(renamed?)),
+        }
+    }
+
+    /// `events` with the aliases among their ids replaced by the stored ids.
+    pub(super) fn with_stored_ids<'a>(
+        &self,
+        conn: &Connection,
+        events: &'a [Event],
+    ) -> Result<Cow<'a, [Event]>, Error> {
+        let mut events = Cow::Borrowed(events);
+        let mut stored_ids = HashMap::new();
+        for i in 0..events.len() {
+            let id = events[i].id;
+            let stored = match stored_ids.get(&id) {
+                Some(stored) => *stored,
+                None => *stored_ids.entry(id).or_insert(self.stored_id(conn, id)?),
+            };
+            if stored != id {
+                events.to_mut()[i].id = stored;
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// `events` read through `aggregate_id`, with it as their id.
+pub(super) fn relabel(mut events: Vec<Event>, aggregate_id: Uuid) -> Vec<Event> {
+    for event in &mut events {
+        event.id = aggregate_id;
+    }
+    events
+}
@@ -0,0 +1,273 @@
+//! Event counts and subscription lag kept in small aggregate tables with
+//! one row per minute, for capacity monitoring from the store itself.
+//! Recorded by backends configured with `SqliteBackend::with_analytics`:
+//!
+//! ```ignore
+//! let backend = SqliteBackend::new(manager).with_analytics();
+//! let day_ago = SystemTime::now() - Duration::from_secs(86_400);
+//! for count in backend.analytics().events_per_type(Duration::from_secs(3600), day_ago)? {
+//!     println!("{:?} {} {}", count.start, count.event_type, count.events);
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, Transaction};
+use tracing::{info, instrument};
+
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+use crate::backend::model::{unix_millis, Event};
+
+/// Length of the recorded buckets, intervals of the queries are multiples.
+const BUCKET_MILLIS: i64 = 60_000;
+
+/// Events of one type appended in an interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventCount {
+    /// Start of the interval.
+    pub start: SystemTime,
+    pub event_type: String,
+    pub events: u64,
+}
+
+/// Lag of a subscription in an interval, in events behind the head of
+/// the tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LagSample {
+    /// Start of the interval.
+    pub start: SystemTime,
+    /// Lag at the last sample of the interval.
+    pub lag: u64,
+    pub max_lag: u64,
+}
+
+/// Time series of the tenant, obtained via `SqliteBackend::analytics`.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreAnalytics<'a> {
+    backend: &'a SqliteBackend,
+}
+
+impl SqliteBackend {
+    /// Count appended events per type and minute and sample the lag of
+    /// subscriptions when they save checkpoints, see `analytics`.
+    pub fn with_analytics(mut self) -> Self {
+        self.analytics = true;
+        self
+    }
+
+    pub fn analytics(&self) -> StoreAnalytics<'_> {
+        StoreAnalytics { backend: self }
+    }
+
+    /// Add `events` appended at `created_at` to the counts in `tx`.
+    pub(super) fn count_appended(
+        &self,
+        tx: &Transaction,
+        events: &[Event],
+        created_at: i64,
+    ) -> Result<(), Error> {
+        if !self.analytics {
+            return Ok(());
+        }
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        for event in events {
+            *counts.entry(&event.event_type).or_default() += 1;
+        }
+        let mut stmt = tx.prepare_cached(&self.sql(
+            "INSERT INTO {event_counts}(tenant_id, bucket, event_type, events) VALUES(?, ?, ?, ?)
+                ON CONFLICT(tenant_id, bucket, event_type) DO UPDATE SET events = events + excluded.events",
+        ))?;
+        let bucket = bucket_of(created_at);
+        for (event_type, events) in counts {
+            stmt.execute(params![self.tenant_id(), bucket, event_type, events])?;
+        }
+        Ok(())
+    }
+
+    /// Record on `conn` that the subscription `name` is at `position`.
+    pub(super) fn sample_lag_in(
+        &self,
+        conn: &Connection,
+        name: &str,
+        position: u64,
+    ) -> Result<(), Error> {
+        if !self.analytics {
+            return Ok(());
+        }
+        conn.prepare_cached(&self.sql(
+            "INSERT INTO {lag_samples}(tenant_id, name, bucket, lag, max_lag)
+                SELECT ?1, ?2, ?3, lag, lag FROM (SELECT MAX(COALESCE(MAX(position), 0) - ?4, 0) AS lag
+                    FROM {eventstore} WHERE tenant_id = ?1)
+                WHERE true
+                ON CONFLICT(tenant_id, name, bucket)
+                DO UPDATE SET lag = excluded.lag, max_lag = MAX(max_lag, excluded.lag)",
+        ))?
+        .execute(params![
+            self.tenant_id(),
+            name,
+            bucket_of(self.now_millis()),
+            position
+        ])?;
+        Ok(())
+    }
+}
+
+impl StoreAnalytics<'_> {
+    /// Events appended per type and `interval` from the interval holding
+    /// `since` on, ordered by interval and type. Intervals are whole
+    /// minutes aligned to the Unix epoch, intervals without events are
+    /// left out.
+    #[instrument]
+    pub fn events_per_type(
+        &self,
+        interval: Duration,
+        since: SystemTime,
+    ) -> Result<Vec<EventCount>, Error> {
+        let backend = self.backend;
+        backend.authorize(Operation::Read, None)?;
+        let interval = interval_millis(interval)?;
+        let conn = backend.connection()?;
+        let mut stmt = conn.prepare_cached(&backend.sql(
+            "SELECT bucket / ?2 * ?2 AS start, event_type, SUM(events) FROM {event_counts}
+                WHERE tenant_id = ?1 AND bucket >= ?3
+                GROUP BY start, event_type ORDER BY start, event_type",
+        ))?;
+        let rows = stmt.query_and_then(
+            params![
+                backend.tenant_id(),
+                interval,
+                start_of(unix_millis(since), interval)
+            ],
+            |row| {
+                Ok(EventCount {
+                    start: time_of(row.get(0)?),
+                    event_type: row.get(1)?,
+                    events: row.get(2)?,
+                })
+            },
+        )?;
+        rows.collect()
+    }
+
+    /// Lag of the subscription `name` per `interval` from the interval
+    /// holding `since` on, like `events_per_type`. Intervals without
+    /// samples are left out.
+    #[instrument]
+    pub fn subscription_lag(
+        &self,
+        name: &str,
+        interval: Duration,
+        since: SystemTime,
+    ) -> Result<Vec<LagSample>, Error> {
+        let backend = self.backend;
+        backend.authorize(Operation::Read, None)?;
+        let interval = interval_millis(interval)?;
+        let conn = backend.connection()?;
+        let mut stmt = conn.prepare_cached(&backend.sql(
+            "SELECT bucket, lag, max_lag FROM {lag_samples}
+                WHERE tenant_id = ? AND name = ? AND bucket >= ? ORDER BY bucket",
+        ))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    backend.tenant_id(),
+                    name,
+                    start_of(unix_millis(since), interval)
+                ],
+                |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)),
+            )?
+            .collect::<Result<Vec<(i64, u64, u64)>, _>>()?;
+        let mut samples: Vec<(i64, LagSample)> = Vec::new();
+        for (bucket, lag, max_lag) in rows {
+            let start = start_of(bucket, interval);
+            match samples.last_mut() {
+                Some((last, sample)) if *last == start => {
+                    sample.lag = lag;
+                    sample.max_lag = sample.max_lag.max(max_lag);
+                }
+                _ => samples.push((
+                    start,
+                    LagSample {
+                        start: time_of(start),
+                        lag,
+                        max_lag,
+                    },
+                )),
+            }
+        }
+        Ok(samples.into_iter().map(|(_, sample)| sample).collect())
+    }
+
+    /// Sample the lag of every subscription with a checkpoint, e.g. from a
+    /// timer so stalled ones show up as well. Returns how many.
+    #[instrument]
+    pub fn sample_lag(&self) -> Result<usize, Error> {
+        let backend = self.backend;
+        backend.ensure_writable()?;
+        backend.authorize(Operation::Maintain, None)?;
+        let conn = backend.connection()?;
+        let checkpoints = conn
+            .prepare(
+                &backend
+                    .sql("SELECT name, position FROM {projection_checkpoint} WHERE tenant_id = ?"),
+            )?
+            .query_map(params![backend.tenant_id()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (name, position) in &checkpoints {
+            backend.sample_lag_in(&conn, name, *position)?;
+        }
+        Ok(checkpoints.len())
+    }
+
+    /// Delete the counts and samples older than `older_than`, returns how
+    /// many rows.
+    #[instrument]
+    pub fn prune(&self, older_than: Duration) -> Result<usize, Error> {
+        let backend = self.backend;
+        backend.ensure_writable()?;
+        backend.authorize(Operation::Maintain, None)?;
+        let conn = backend.connection()?;
+        let cutoff = backend
+            .now_millis()
+            .saturating_sub(older_than.as_millis().min(i64::MAX as u128) as i64);
+        let mut pruned = 0;
+        for table in ["{event_counts}", "{lag_samples}"] {
+            pruned += conn.execute(
+                &backend.sql(&format!(
+                    "DELETE FROM {} WHERE tenant_id = ? AND bucket < ?",
+                    table
+                )),
+                params![backend.tenant_id(), bucket_of(cutoff)],
+            )?;
+        }
+        info!(pruned, "pruned analytics");
+        Ok(pruned)
+    }
+}
+
+fn bucket_of(millis: i64) -> i64 {
+    start_of(millis, BUCKET_MILLIS)
+}
+
+fn start_of(millis: i64, interval: i64) -> i64 {
+    millis.div_euclid(interval) * interval
+}
+
+fn time_of(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn interval_millis(interval: Duration) -> Result<i64, Error> {
+    let millis = interval.as_millis();
+    if millis == 0 || !millis.is_multiple_of(BUCKET_MILLIS as u128) || millis > i64::MAX as u128 {
+        return Err(Error::WithMsg(format!(
+            "interval {:?} is no whole number of minutes",
+            interval
+        )));
+    }
+    Ok(millis as i64)
+}
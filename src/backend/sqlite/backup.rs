@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::backup::{Backup, Progress, StepResult};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+
+/// Pacing of online backups. The database is copied in steps and writers
+/// may append between two steps, changes made meanwhile restart the copy
+/// of the affected pages so the backup is always consistent.
+#[derive(Debug, Clone, Copy)]
+pub struct BackupOptions {
+    pub pages_per_step: i32,
+    pub pause: Duration,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            pages_per_step: 256,
+            pause: Duration::from_millis(5),
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::WithMsg(format!("backup: {}", err))
+}
+
+impl SqliteBackend {
+    /// Copy the database to `path` using SQLite's online backup API while
+    /// the store keeps accepting appends.
+    #[instrument]
+    pub fn backup_to<P: AsRef<Path> + std::fmt::Debug>(&self, path: P) -> Result<(), Error> {
+        self.backup_to_with(path, BackupOptions::default(), |_| {})
+    }
+
+    /// Like `backup_to`, reporting the progress after every step.
+    pub fn backup_to_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: BackupOptions,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<(), Error> {
+        self.authorize(Operation::Maintain, None)?;
+        let conn = self.connection()?;
+        let mut target = rusqlite::Connection::open(path)?;
+        let backup = Backup::new(&conn, &mut target)?;
+        loop {
+            match backup.step(options.pages_per_step)? {
+                StepResult::Done => break,
+                StepResult::More => progress(backup.progress()),
+                // a writer holds the lock, retry after the pause
+                _ => debug!("backup step deferred, database is locked"),
+            }
+            std::thread::sleep(options.pause);
+        }
+        progress(backup.progress());
+        Ok(())
+    }
+
+    /// Stream a consistent backup into `writer`, e.g. an upload, returns the
+    /// number of bytes written. The backup is staged in a temporary file.
+    #[instrument(skip(writer))]
+    pub fn backup_to_writer<W: Write>(&self, writer: &mut W) -> Result<u64, Error> {
+        self.authorize(Operation::Maintain, None)?;
+        let staged = std::env::temp_dir().join(format!("eventstore-backup-{}.db", Uuid::new_v4()));
+        let res = self.backup_to(&staged).and_then(|_| {
+            let mut file = std::fs::File::open(&staged).map_err(io_error)?;
+            std::io::copy(&mut file, writer).map_err(io_error)
+        });
+        let _ = std::fs::remove_file(&staged);
+        res
+    }
+}
@@ -0,0 +1,343 @@
+//! Streaming access to large payloads with SQLite's incremental BLOB I/O,
+//! so a 100 MB snapshot is never held in memory at once:
+//!
+//! ```ignore
+//! let mut writer = backend.snapshot_writer(&header, file.metadata()?.len())?;
+//! std::io::copy(&mut file, &mut writer)?;
+//! writer.finish()?;
+//!
+//! let reader = backend.open_snapshot_payload(aggregate_id, version)?;
+//! std::io::copy(&mut BufReader::with_capacity(1 << 20, reader), &mut out)?;
+//! ```
+//!
+//! Payloads are streamed as stored, so compressed and encrypted payloads
+//! can not be streamed: opening them fails, as does opening a writer on a
+//! backend that compresses or encrypts.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::blob::ZeroBlob;
+use rusqlite::{params, DatabaseName, OptionalExtension};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use super::{Error, Operation, SqliteBackend};
+use crate::backend::model::Event;
+
+/// Payload size from which the streaming handles are preferable to
+/// `save_snapshot` and `get_snapshot_by_version`, which hold the whole
+/// payload in memory.
+pub const STREAMING_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+fn io_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+/// Location and size of a stored payload.
+struct StoredBlob {
+    rowid: i64,
+    len: u64,
+    compression: String,
+    key_id: String,
+}
+
+/// Reads a stored payload. Every read opens the BLOB, wrap the handle in a
+/// `BufReader` with a large capacity rather than reading small pieces.
+///
+/// The handle holds a read transaction, so it sees the payload as it was
+/// when opened, and keeps WAL checkpoints from completing until dropped.
+#[derive(Debug)]
+pub struct PayloadReader {
+    conn: PooledConnection<SqliteConnectionManager>,
+    table: String,
+    rowid: i64,
+    len: u64,
+    offset: u64,
+}
+
+impl PayloadReader {
+    /// Size of the payload in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.offset >= self.len {
+            return Ok(0);
+        }
+        let blob = self
+            .conn
+            .blob_open(DatabaseName::Main, &self.table, "data", self.rowid, true)
+            .map_err(io_error)?;
+        let read = blob.read_at(buf, self.offset as usize).map_err(io_error)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for PayloadReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+        };
+        self.offset = offset.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the payload")
+        })?;
+        Ok(self.offset)
+    }
+}
+
+impl Drop for PayloadReader {
+    fn drop(&mut self) {
+        let _ = self.conn.execute_batch("ROLLBACK");
+    }
+}
+
+/// Writes the payload of a snapshot announced with its size by
+/// `SqliteBackend::snapshot_writer`. The snapshot is saved by `finish`,
+/// dropping the writer discards it.
+///
+/// The handle holds a write transaction, other writers wait for it until
+/// it is finished or dropped.
+#[derive(Debug)]
+pub struct SnapshotWriter {
+    backend: SqliteBackend,
+    conn: PooledConnection<SqliteConnectionManager>,
+    aggregate_id: Uuid,
+    version: u32,
+    rowid: i64,
+    len: u64,
+    offset: u64,
+    finished: bool,
+}
+
+impl SnapshotWriter {
+    /// Bytes still to be written.
+    pub fn remaining(&self) -> u64 {
+        self.len - self.offset
+    }
+
+    /// Save the snapshot, fails if fewer bytes than announced were written.
+    #[instrument]
+    pub fn finish(mut self) -> Result<(), Error> {
+        if self.offset != self.len {
+            return Err(Error::WithMsg(format!(
+                "snapshot payload incomplete, {} of {} bytes written",
+                self.offset, self.len
+            )));
+        }
+        self.conn.execute(
+            &self.backend.sql("INSERT INTO {snapshot_index}(version, aggregate_id, type_name, tenant_id) VALUES(?,?, 'todo_implement_type_name', ?)
+                ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET version = excluded.version"),
+            params![
+                self.version,
+                self.backend.id_param(self.aggregate_id),
+                self.backend.tenant_id()
+            ],
+        )?;
+        self.conn.execute_batch("COMMIT")?;
+        self.finished = true;
+        self.backend.snapshot_saved(self.aggregate_id);
+        debug!(aggregate_id = %self.aggregate_id, version = self.version, bytes = self.len, "streamed snapshot");
+        Ok(())
+    }
+}
+
+impl Write for SnapshotWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "snapshot payload longer than announced",
+            ));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let table = self.backend.tables.snapshot.clone();
+        let mut blob = self
+            .conn
+            .blob_open(DatabaseName::Main, &table, "data", self.rowid, false)
+            .map_err(io_error)?;
+        blob.write_all_at(buf, self.offset as usize)
+            .map_err(io_error)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+impl SqliteBackend {
+    /// Whether payloads are stored as written, which streaming requires.
+    fn stores_plain_payloads(&self) -> bool {
+        #[cfg(feature = "compression")]
+        if self.compression.is_some() {
+            return false;
+        }
+        #[cfg(feature = "encryption")]
+        if self.crypto_shredding {
+            return false;
+        }
+        self.encryptor.is_none()
+    }
+
+    fn open_blob(
+        &self,
+        table: &str,
+        lookup: impl FnOnce(&rusqlite::Connection) -> Result<Option<StoredBlob>, Error>,
+    ) -> Result<PayloadReader, Error> {
+        let conn = self.connection()?;
+        conn.execute_batch("BEGIN")?;
+        // the lookup starts the read transaction the reader keeps
+        let stored = match lookup(&conn) {
+            Ok(Some(stored)) => stored,
+            Ok(None) => {
+                conn.execute_batch("ROLLBACK")?;
+                return Err(Error::NotFound);
+            }
+            Err(err) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                return Err(err);
+            }
+        };
+        if !stored.compression.is_empty() || !stored.key_id.is_empty() {
+            conn.execute_batch("ROLLBACK")?;
+            return Err(Error::WithMsg(
+                "payload is compressed or encrypted and can not be streamed".to_string(),
+            ));
+        }
+        Ok(PayloadReader {
+            conn,
+            table: table.to_string(),
+            rowid: stored.rowid,
+            len: stored.len,
+            offset: 0,
+        })
+    }
+
+    /// Stream the payload of the event at `position`.
+    #[instrument]
+    pub fn open_event_payload(&self, position: u64) -> Result<PayloadReader, Error> {
+        self.authorize(Operation::Read, None)?;
+        let sql = format!(
+            "{} AND {}",
+            self.sql("SELECT rowid, COALESCE(length(data), 0), compression, key_id FROM {eventstore} WHERE tenant_id = ? AND position = ?"),
+            self.retained()
+        );
+        self.open_blob(&self.tables.eventstore, |conn| {
+            Ok(conn
+                .query_row(&sql, params![self.tenant_id(), position], stored_blob)
+                .optional()?)
+        })
+    }
+
+    /// Stream the payload of the snapshot of `aggregate_id` at `version`.
+    #[instrument]
+    pub fn open_snapshot_payload(
+        &self,
+        aggregate_id: Uuid,
+        version: u32,
+    ) -> Result<PayloadReader, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let sql = self.sql("SELECT rowid, COALESCE(length(data), 0), compression, key_id FROM {snapshot} WHERE tenant_id = ? AND aggregate_id = ? AND version = ?");
+        self.open_blob(&self.tables.snapshot, |conn| {
+            let stored = self.stored_id(conn, aggregate_id)?;
+            Ok(conn
+                .query_row(
+                    &sql,
+                    params![self.tenant_id(), self.id_param(stored), version],
+                    stored_blob,
+                )
+                .optional()?)
+        })
+    }
+
+    /// Save `snapshot` with a payload of `len` bytes written through the
+    /// returned handle instead of taken from `snapshot.data`. Overwrites
+    /// an existing snapshot of the version once finished, like
+    /// `save_snapshot`.
+    #[instrument(skip(snapshot), fields(aggregate_id = %snapshot.id, version = snapshot.version))]
+    pub fn snapshot_writer(&self, snapshot: &Event, len: u64) -> Result<SnapshotWriter, Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Append, Some(snapshot.id))?;
+        if !self.stores_plain_payloads() {
+            return Err(Error::WithMsg(
+                "snapshots can not be streamed with compression or encryption".to_string(),
+            ));
+        }
+        let blob_len = i32::try_from(len)
+            .map_err(|_| Error::WithMsg(format!("snapshot of {} bytes is too large", len)))?;
+        let metadata = serde_json::to_string(&snapshot.metadata)?;
+        let conn = self.connection()?;
+        conn.execute_batch("BEGIN IMMEDIATE")?;
+        let stored = match self.stored_id(&conn, snapshot.id) {
+            Ok(stored) => stored,
+            Err(err) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                return Err(err);
+            }
+        };
+        // from here on dropping the writer rolls back
+        let mut writer = SnapshotWriter {
+            backend: self.clone(),
+            conn,
+            aggregate_id: stored,
+            version: snapshot.version,
+            rowid: 0,
+            len,
+            offset: 0,
+            finished: false,
+        };
+        writer.rowid = writer.conn.query_row(
+            &self.sql("INSERT INTO {snapshot}(aggregate_id, version, data, event_type, schema_version, content_type, metadata, compression, key_id, tenant_id)
+                VALUES(?,?,?,?,?,?,?,'','',?)
+                ON CONFLICT(tenant_id, aggregate_id, version) DO UPDATE SET data = excluded.data,
+                    event_type = excluded.event_type, schema_version = excluded.schema_version,
+                    content_type = excluded.content_type, metadata = excluded.metadata,
+                    compression = excluded.compression, key_id = excluded.key_id
+                RETURNING rowid"),
+            params![
+                self.id_param(stored),
+                snapshot.version,
+                ZeroBlob(blob_len),
+                snapshot.event_type,
+                snapshot.schema_version,
+                snapshot.content_type,
+                metadata,
+                self.tenant_id()
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(writer)
+    }
+}
+
+fn stored_blob(row: &rusqlite::Row) -> rusqlite::Result<StoredBlob> {
+    Ok(StoredBlob {
+        rowid: row.get(0)?,
+        len: row.get(1)?,
+        compression: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+        key_id: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+    })
+}
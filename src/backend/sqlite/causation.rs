@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::backend::model::{CausationTree, Event};
+
+impl SqliteBackend {
+    /// The event with the id `event_id` set by `Event::with_event_id` and
+    /// the events it caused across all streams, following `CAUSATION_ID`
+    /// of the events appended with `Event::caused_by`. Caused events
+    /// without an id of their own are leaves.
+    #[instrument]
+    pub fn get_causation_tree(&self, event_id: Uuid) -> Result<CausationTree, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY position ASC",
+            self.sql(&format!(
+                "WITH RECURSIVE tree(position, event_id) AS (
+                    SELECT position, event_id FROM {{eventstore}} WHERE tenant_id = ?1 AND event_id = ?2
+                    UNION
+                    SELECT e.position, e.event_id FROM {{eventstore}} e
+                        JOIN tree t ON e.tenant_id = ?1 AND e.causation_id = t.event_id
+                )
+                SELECT {} FROM {{eventstore}}
+                    WHERE tenant_id = ?1 AND position IN (SELECT position FROM tree)",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
+            &mut stmt,
+            &[&self.tenant_id(), &event_id.to_string()],
+        )?;
+        let mut events = self.decoded(events)?;
+        let root = events
+            .iter()
+            .position(|event| event.event_id() == Some(event_id))
+            .ok_or(Error::NotFound)?;
+        let root = events.remove(root);
+        let mut caused: HashMap<Uuid, Vec<Event>> = HashMap::new();
+        for event in events {
+            if let Some(cause) = event.causation_id() {
+                caused.entry(cause).or_default().push(event);
+            }
+        }
+        Ok(build(root, &mut caused))
+    }
+}
+
+/// The tree below `event`, taking its caused events out of `caused` so
+/// cycles end.
+fn build(event: Event, caused: &mut HashMap<Uuid, Vec<Event>>) -> CausationTree {
+    let children = event
+        .event_id()
+        .and_then(|id| caused.remove(&id))
+        .unwrap_or_default();
+    CausationTree {
+        event,
+        caused: children
+            .into_iter()
+            .map(|child| build(child, caused))
+            .collect(),
+    }
+}
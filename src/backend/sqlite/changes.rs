@@ -0,0 +1,45 @@
+use rusqlite::params;
+use tracing::instrument;
+
+use super::ids::read_id;
+use super::{Cursor, Error, Operation, SqliteBackend};
+use crate::stream::Change;
+
+impl SqliteBackend {
+    /// The streams with events appended after `token`, or since the
+    /// beginning, each with the version it reached, in the order of their
+    /// latest event. At most `limit` events are looked at, the returned
+    /// token continues after them and is `token` again once there are no
+    /// changes. Payloads are not read, caches call this to find what to
+    /// invalidate and keep the token to resume after restarts.
+    ///
+    /// Streams are listed under the ids their events are stored with, not
+    /// their aliases. Deleted streams are not listed.
+    #[instrument]
+    pub fn changes(
+        &self,
+        token: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<(Vec<Change>, Cursor), Error> {
+        self.authorize(Operation::Read, None)?;
+        let after = Cursor::position(token)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&self.sql(
+            "SELECT aggregate_id, MAX(version), MAX(position) FROM (
+                SELECT aggregate_id, version, position FROM {eventstore}
+                WHERE tenant_id = ? AND position > ? ORDER BY position LIMIT ?)
+            GROUP BY aggregate_id ORDER BY MAX(position)",
+        ))?;
+        let changes = stmt
+            .query_and_then(params![self.tenant_id(), after, limit.max(1)], |row| {
+                Ok(Change {
+                    aggregate_id: read_id(row.get_ref(0)?)?,
+                    version: row.get(1)?,
+                    position: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, Error>>()?;
+        let next = changes.last().map_or(after, |change| change.position);
+        Ok((changes, Cursor::after_position(next)))
+    }
+}
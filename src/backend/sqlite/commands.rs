@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use rusqlite::params;
+use tracing::{debug, instrument};
+
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+
+impl SqliteBackend {
+    /// Append `events` unless the command `command_id` was processed
+    /// before, e.g. an HTTP request retried with the same idempotency key.
+    /// The id is recorded in the transaction of the append, so a command
+    /// appends its events exactly once. Returns false for repeated
+    /// commands, the events are not checked against the first ones.
+    ///
+    /// A failed append does not record the command, it can be retried.
+    #[instrument(skip(events))]
+    pub fn append_if_new_command(&self, command_id: &str, events: &[Event]) -> Result<bool, Error> {
+        let appended = self.append_guarded(events, |tx| {
+            let inserted = tx.execute(
+                &self.sql(
+                    "INSERT INTO {processed_commands}(tenant_id, command_id, processed_at)
+                        VALUES(?, ?, ?) ON CONFLICT DO NOTHING",
+                ),
+                params![self.tenant_id(), command_id, self.now_millis()],
+            )?;
+            Ok(inserted > 0)
+        })?;
+        if !appended {
+            debug!(command_id, "command processed before");
+        }
+        Ok(appended)
+    }
+
+    /// Forget the commands processed more than `older_than` ago, returns
+    /// how many. Retries arriving later append their events again.
+    #[instrument]
+    pub fn prune_processed_commands(&self, older_than: Duration) -> Result<usize, Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Maintain, None)?;
+        let conn = self.connection()?;
+        let cutoff = self
+            .now_millis()
+            .saturating_sub(older_than.as_millis().min(i64::MAX as u128) as i64);
+        Ok(conn.execute(
+            &self.sql("DELETE FROM {processed_commands} WHERE tenant_id = ? AND processed_at < ?"),
+            params![self.tenant_id(), cutoff],
+        )?)
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend, Tables};
+
+/// Tables and columns of ids written in the id format of the store. The
+/// admin log keeps the text form, it is append-only.
+const ID_COLUMNS: [(&str, &str); 9] = [
+    ("{eventstore}", "aggregate_id"),
+    ("{aggregate_index}", "aggregate_id"),
+    ("{snapshot}", "aggregate_id"),
+    ("{snapshot_index}", "aggregate_id"),
+    ("{stream_metadata}", "aggregate_id"),
+    ("{latest_by_type}", "aggregate_id"),
+    ("{scheduled_events}", "aggregate_id"),
+    ("{stream_aliases}", "alias"),
+    ("{stream_aliases}", "aggregate_id"),
+];
+
+/// SQL function converting a text id to the BLOB format, for migrations
+/// of tables that stored text ids in every database.
+const UUID_BLOB_FUNCTION: &str = "eventstore_uuid_blob";
+
+/// How aggregate ids are stored, see `SqliteBackend::id_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdFormat {
+    /// 36-character hyphenated strings, written by earlier releases.
+    Text,
+    /// The 16 bytes of the UUID.
+    Blob,
+}
+
+/// The aggregate id of a row in either format.
+pub(super) fn read_id(value: ValueRef) -> Result<Uuid, Error> {
+    match value {
+        ValueRef::Blob(bytes) => Uuid::from_slice(bytes).map_err(|_| Error::InvalidUUID),
+        ValueRef::Text(text) => std::str::from_utf8(text)
+            .ok()
+            .and_then(|text| Uuid::parse_str(text).ok())
+            .ok_or(Error::InvalidUUID),
+        _ => Err(Error::WithMsg("could not read uuid from row".to_string())),
+    }
+}
+
+/// The aggregate id of a row as text, also for values that are no UUID.
+pub(super) fn id_text(value: ValueRef) -> Result<String, Error> {
+    match value {
+        ValueRef::Text(text) => Ok(String::from_utf8_lossy(text).into_owned()),
+        other => read_id(other).map(|id| id.to_string()),
+    }
+}
+
+/// Format of the ids in the database: text if any table holds a text id,
+/// which is the case for all files written before ids were stored as BLOBs
+/// and not converted yet, BLOB otherwise.
+pub(super) fn detect_id_format(conn: &Connection, tables: &Tables) -> Result<IdFormat, Error> {
+    for (table, column) in ID_COLUMNS {
+        let kind: Option<String> = conn
+            .query_row(
+                &tables.sql(&format!("SELECT typeof({}) FROM {} LIMIT 1", column, table)),
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if kind.as_deref() == Some("text") {
+            return Ok(IdFormat::Text);
+        }
+    }
+    Ok(IdFormat::Blob)
+}
+
+/// Define `UUID_BLOB_FUNCTION` on `conn` unless it is defined.
+pub(super) fn register_uuid_blob(conn: &Connection) -> Result<(), Error> {
+    if conn
+        .prepare_cached(&format!("SELECT {}('')", UUID_BLOB_FUNCTION))
+        .is_ok()
+    {
+        return Ok(());
+    }
+    conn.create_scalar_function(
+        UUID_BLOB_FUNCTION,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let id = read_id(ctx.get_raw(0))
+                .map_err(|err| rusqlite::Error::UserFunctionError(err.to_string().into()))?;
+            Ok(id.as_bytes().to_vec())
+        },
+    )?;
+    Ok(())
+}
+
+impl SqliteBackend {
+    /// How the aggregate ids of this database are stored. New databases
+    /// store BLOBs, files of earlier releases keep their text ids until
+    /// converted with `convert_ids_to_blob`.
+    pub fn id_format(&self) -> IdFormat {
+        self.id_format
+    }
+
+    /// `aggregate_id` as a parameter matching the stored ids.
+    pub(super) fn id_param(&self, aggregate_id: Uuid) -> Value {
+        match self.id_format {
+            IdFormat::Text => Value::Text(aggregate_id.to_string()),
+            IdFormat::Blob => Value::Blob(aggregate_id.as_bytes().to_vec()),
+        }
+    }
+
+    /// Like `id_param` for an id given as text, which is bound as is if it
+    /// is no UUID.
+    pub(super) fn id_param_str(&self, aggregate_id: &str) -> Value {
+        match Uuid::parse_str(aggregate_id) {
+            Ok(id) => self.id_param(id),
+            Err(_) => Value::Text(aggregate_id.to_string()),
+        }
+    }
+}
+
+/// Convert the text aggregate ids of the database file at `path` to BLOBs
+/// in one transaction, returns the number of converted rows. Run it while
+/// no backend has the file open, backends opened afterwards use the BLOB
+/// format.
+#[instrument(skip(path))]
+pub fn convert_ids_to_blob<P: AsRef<Path>>(path: P, tables: &Tables) -> Result<usize, Error> {
+    tables.validate()?;
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let mut converted = 0;
+    for (table, column) in ID_COLUMNS {
+        let rows = {
+            let mut stmt = tx.prepare(&tables.sql(&format!(
+                "SELECT rowid, {1} FROM {0} WHERE typeof({1}) = 'text'",
+                table, column
+            )))?;
+            let rows = stmt.query_and_then(params![], |row| {
+                Ok::<_, Error>((row.get::<_, i64>(0)?, read_id(row.get_ref(1)?)?))
+            })?;
+            rows.collect::<Result<Vec<_>, Error>>()?
+        };
+        let mut update = tx.prepare(&tables.sql(&format!(
+            "UPDATE {} SET {} = ? WHERE rowid = ?",
+            table, column
+        )))?;
+        for (rowid, id) in &rows {
+            update.execute(params![id.as_bytes().to_vec(), rowid])?;
+        }
+        converted += rows.len();
+    }
+    tx.commit()?;
+    info!(converted, "converted aggregate ids to blobs");
+    Ok(converted)
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+use rusqlite::types::Value;
+use rusqlite::{params, ToSql, Transaction, TransactionBehavior};
+use serde_json::json;
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
+
+use super::{Error, Outcome, SqliteBackend, Written, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+use crate::codec;
+use crate::jsonl::{Envelope, ExportOpts, ImportOpts, ImportSummary};
+use crate::system::{self, ImportCompleted};
+
+/// Secondary indexes of the event table dropped for a bulk load, they are
+/// recreated by `restore` or once dropped, e.g. on a panic.
+struct DroppedIndexes<'a> {
+    backend: &'a SqliteBackend,
+    /// Names and definitions of the indexes not recreated yet.
+    indexes: Vec<(String, String)>,
+}
+
+impl DroppedIndexes<'_> {
+    fn restore(&mut self) -> Result<(), Error> {
+        let conn = self.backend.connection()?;
+        while let Some((name, sql)) = self.indexes.last() {
+            conn.execute(sql, [])?;
+            debug!(index = name, "rebuilt index");
+            self.indexes.pop();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DroppedIndexes<'_> {
+    fn drop(&mut self) {
+        if self.indexes.is_empty() {
+            return;
+        }
+        if let Err(err) = self.restore() {
+            warn!(
+                error = %err,
+                indexes = ?self.indexes,
+                "indexes dropped for a bulk load are missing"
+            );
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::WithMsg(format!("jsonl: {}", err))
+}
+
+/// JSON payloads are compared by value, exports do not preserve their
+/// formatting.
+fn same_payload(stored: &Event, imported: &Event) -> bool {
+    if stored.content_type == codec::JSON {
+        let parse = |data: &[u8]| serde_json::from_slice::<serde_json::Value>(data).ok();
+        if let (Some(stored), Some(imported)) = (parse(&stored.data), parse(&imported.data)) {
+            return stored == imported;
+        }
+    }
+    stored.data == imported.data
+}
+
+impl SqliteBackend {
+    /// Write the stored events selected by `opts` to `writer` in commit
+    /// order, one JSON envelope per line. Events are exported as stored,
+    /// i.e., without upcasting, events expired by retention are skipped. Returns the number of exported events.
+    #[instrument(skip(writer))]
+    pub fn export_jsonl<W: Write>(
+        &self,
+        writer: &mut W,
+        opts: &ExportOpts,
+    ) -> Result<usize, Error> {
+        let exported = self.for_each_export_batch(opts, |_, events| {
+            for event in events {
+                serde_json::to_writer(&mut *writer, &Envelope::from_event(event))?;
+                writer.write_all(b"\n").map_err(io_error)?;
+            }
+            Ok(())
+        })?;
+        writer.flush().map_err(io_error)?;
+        Ok(exported)
+    }
+
+    /// Call `f` with the stored events selected by `opts` in commit order,
+    /// `opts.batch_size` at a time. Returns the number of events.
+    pub(super) fn for_each_export_batch<F>(
+        &self,
+        opts: &ExportOpts,
+        mut f: F,
+    ) -> Result<usize, Error>
+    where
+        F: FnMut(&rusqlite::Connection, &[Event]) -> Result<(), Error>,
+    {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut query = format!(
+            "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND position > ?",
+            EVENT_COLUMNS
+        );
+        let mut filters: Vec<Value> = Vec::new();
+        if !opts.aggregates.is_empty() {
+            query += &format!(
+                " AND aggregate_id IN ({})",
+                vec!["?"; opts.aggregates.len()].join(",")
+            );
+            filters.extend(opts.aggregates.iter().map(|id| self.id_param(*id)));
+        }
+        if !opts.event_types.is_empty() {
+            query += &format!(
+                " AND event_type IN ({})",
+                vec!["?"; opts.event_types.len()].join(",")
+            );
+            filters.extend(opts.event_types.iter().cloned().map(Value::Text));
+        }
+        query += &format!(" AND {} ORDER BY position ASC LIMIT ?", self.retained());
+        let mut stmt = conn.prepare(&self.sql(&query))?;
+
+        let batch_size = opts.batch_size.max(1);
+        let mut position = opts.from_position;
+        let mut exported = 0;
+        loop {
+            let tenant_id = self.tenant_id();
+            let mut params: Vec<&dyn ToSql> = vec![&tenant_id, &position];
+            params.extend(filters.iter().map(|filter| filter as &dyn ToSql));
+            params.push(&batch_size);
+            let events = self.result_from_stmt_with_params(&conn, &mut stmt, &params)?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            position = last.position;
+            f(&conn, &events)?;
+            exported += events.len();
+        }
+        Ok(exported)
+    }
+
+    /// Append the events of a JSON Lines export read from `reader`, e.g. to
+    /// clone an environment. Events keep their aggregate id (unless
+    /// remapped), version and metadata, positions are assigned anew.
+    ///
+    /// Versions of every aggregate must continue its stream without gaps.
+    /// Events already stored with identical content are skipped, differing
+    /// ones fail the import. Events are written in batches, batches
+    /// committed before a failure stay.
+    #[instrument(skip(reader))]
+    pub fn import_jsonl<R: BufRead>(
+        &self,
+        reader: R,
+        opts: &ImportOpts,
+    ) -> Result<ImportSummary, Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Maintain, None)?;
+        let mut summary = ImportSummary::default();
+        let res = if opts.bulk_load {
+            self.bulk_load(reader, opts, &mut summary)
+        } else {
+            self.import_lines(reader, opts, &mut summary)
+        };
+        let conn = self.connection()?;
+        self.record_admin(
+            &conn,
+            "import_jsonl",
+            None,
+            json!({
+                "imported": summary.imported,
+                "skipped": summary.skipped,
+                "bulk_load": opts.bulk_load,
+                "error": res.as_ref().err().map(ToString::to_string),
+            }),
+        )?;
+        self.emit_system_now(
+            &conn,
+            system::IMPORTS,
+            &ImportCompleted {
+                imported: summary.imported,
+                skipped: summary.skipped,
+                error: res.as_ref().err().map(ToString::to_string),
+            },
+        )?;
+        res.map(|_| summary)
+    }
+
+    /// `import_lines` without the secondary indexes of the event table,
+    /// they are recreated from their stored definitions afterwards, also if
+    /// the import failed or panicked. The unique stream index stays, the
+    /// import checks versions with it. Returns the result of the import
+    /// also if recreating an index failed.
+    fn bulk_load<R: BufRead>(
+        &self,
+        reader: R,
+        opts: &ImportOpts,
+        summary: &mut ImportSummary,
+    ) -> Result<(), Error> {
+        let mut dropped = {
+            let mut conn = self.connection()?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let indexes = {
+                let mut stmt = tx.prepare(
+                    "SELECT name, sql FROM sqlite_master
+                        WHERE type = 'index' AND tbl_name = ? AND name != ? AND sql IS NOT NULL",
+                )?;
+                let rows = stmt.query_map(
+                    params![
+                        self.sql("{eventstore}"),
+                        self.sql("{eventstore}_agg_id_idx")
+                    ],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                )?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            for (name, _) in &indexes {
+                tx.execute(&format!("DROP INDEX \"{}\"", name), [])?;
+            }
+            tx.commit()?;
+            DroppedIndexes {
+                backend: self,
+                indexes,
+            }
+        };
+        info!(
+            dropped = dropped.indexes.len(),
+            "dropped indexes for bulk load"
+        );
+        let res = self.import_lines(reader, opts, summary);
+        if let Err(err) = dropped.restore() {
+            warn!(error = %err, "rebuilding indexes after bulk load failed");
+        }
+        res
+    }
+
+    fn import_lines<R: BufRead>(
+        &self,
+        reader: R,
+        opts: &ImportOpts,
+        summary: &mut ImportSummary,
+    ) -> Result<(), Error> {
+        let mut batch = Vec::new();
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line.map_err(io_error)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let envelope: Envelope = serde_json::from_str(&line)
+                .map_err(|err| Error::WithMsg(format!("line {}: {}", line_no + 1, err)))?;
+            let mut event = envelope.into_event()?;
+            if let Some(id) = opts.remap.get(&event.id) {
+                event.id = *id;
+            }
+            batch.push(event);
+            if batch.len() >= opts.batch_size.max(1) {
+                self.import_batch(&batch, summary)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.import_batch(&batch, summary)?;
+        }
+        Ok(())
+    }
+
+    fn import_batch(&self, events: &[Event], summary: &mut ImportSummary) -> Result<(), Error> {
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+        let events = self.with_stored_ids(&tx, events)?;
+        let mut versions: HashMap<Uuid, (u32, u32)> = HashMap::new();
+        let mut appended = Vec::new();
+        let mut skipped = 0;
+        for event in events.iter() {
+            let (stored, current) = match versions.get(&event.id) {
+                Some(versions) => *versions,
+                None => {
+                    let stored = self.get_agg_max_version(&tx, &event.id.to_string())?;
+                    (stored, stored)
+                }
+            };
+            if event.version <= stored {
+                self.check_existing(&tx, event)?;
+                skipped += 1;
+            } else if event.version == current + 1 {
+                versions.insert(event.id, (stored, event.version));
+                appended.push(event.clone());
+            } else {
+                warn!(aggregate_id = %event.id, version = event.version, current, "version gap");
+                return Err(Error::WithMsg(format!(
+                    "version gap in aggregate {}: expected {}, got {}",
+                    event.id,
+                    current + 1,
+                    event.version
+                )));
+            }
+        }
+        let written = match self.write_events(&tx, &appended, |_| Ok(true))? {
+            Outcome::Written(written) => written,
+            Outcome::Skipped => Written::default(),
+            Outcome::Rejected(rejection) => {
+                drop(tx);
+                return Err(self.reject(&conn, rejection)?);
+            }
+        };
+        tx.commit()?;
+        self.committed(written);
+        summary.imported += appended.len();
+        summary.skipped += skipped;
+        Ok(())
+    }
+
+    /// Fails unless the stored event at the version of `event` matches it.
+    fn check_existing(&self, tx: &Transaction, event: &Event) -> Result<(), Error> {
+        let mut stmt = tx.prepare(&self.sql(&format!(
+            "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND aggregate_id = ? AND version = ?",
+            EVENT_COLUMNS
+        )))?;
+        let mut existing = self
+            .result_from_stmt_with_params(
+                tx,
+                &mut stmt,
+                &[&self.tenant_id(), &self.id_param(event.id), &event.version],
+            )?
+            .pop()
+            .ok_or(Error::NotFound)?;
+        // compared as appended and read back
+        if !self.middleware.is_empty() {
+            self.middleware
+                .on_read(std::slice::from_mut(&mut existing))?;
+        }
+        // the request and trace of the import that stored it are not compared
+        let metadata = |event: &Event| -> BTreeMap<String, String> {
+            event
+                .metadata
+                .iter()
+                .filter(|(key, _)| !Self::is_enrichment_key(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        };
+        let same = existing.event_type == event.event_type
+            && existing.schema_version == event.schema_version
+            && existing.content_type == event.content_type
+            && same_payload(&existing, event)
+            && metadata(&existing) == metadata(event);
+        if !same {
+            return Err(Error::WithMsg(format!(
+                "aggregate {} already has a different event at version {}",
+                event.id, event.version
+            )));
+        }
+        Ok(())
+    }
+}
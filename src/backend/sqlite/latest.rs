@@ -0,0 +1,162 @@
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::aliases::relabel;
+use super::{Error, Maintenance, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+
+impl SqliteBackend {
+    /// Keep the position of the latest event of each type per stream in
+    /// the `latest_by_type` table on append, so `latest_by_type` and
+    /// `latest_of_type` look them up instead of scanning the events. Every
+    /// backend writing the database should keep it, or rebuild it with
+    /// `Maintenance::rebuild_latest_by_type` before turning it on.
+    pub fn with_latest_by_type(mut self) -> Self {
+        self.latest_by_type = true;
+        self
+    }
+
+    /// The latest event of type `event_type` of every stream that has
+    /// one, in commit order, e.g. the current price of every product.
+    /// Types are matched as stored, before upcasting.
+    #[instrument]
+    pub fn latest_by_type(&self, event_type: &str) -> Result<Vec<Event>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let latest = match self.latest_by_type {
+            true => {
+                "SELECT position FROM {latest_by_type} WHERE tenant_id = ?1 AND event_type = ?2"
+            }
+            false => {
+                "SELECT MAX(position) FROM {eventstore}
+                WHERE tenant_id = ?1 AND event_type = ?2 GROUP BY aggregate_id"
+            }
+        };
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY position",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ?1 AND position IN ({})",
+                EVENT_COLUMNS, latest
+            )),
+            self.retained()
+        ))?;
+        let events =
+            self.result_from_stmt_with_params(&conn, &mut stmt, &[&self.tenant_id(), &event_type])?;
+        self.decoded(events)
+    }
+
+    /// The latest event of type `event_type` of the stream, if it has one.
+    #[instrument]
+    pub fn latest_of_type(
+        &self,
+        aggregate_id: Uuid,
+        event_type: &str,
+    ) -> Result<Option<Event>, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let latest = match self.latest_by_type {
+            true => {
+                "SELECT position FROM {latest_by_type}
+                WHERE tenant_id = ?1 AND event_type = ?2 AND aggregate_id = ?3"
+            }
+            false => {
+                "SELECT MAX(position) FROM {eventstore}
+                WHERE tenant_id = ?1 AND event_type = ?2 AND aggregate_id = ?3"
+            }
+        };
+        let conn = self.connection()?;
+        let stored = self.stored_id(&conn, aggregate_id)?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {}",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ?1 AND position = ({})",
+                EVENT_COLUMNS, latest
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
+            &mut stmt,
+            &[&self.tenant_id(), &event_type, &self.id_param(stored)],
+        )?;
+        let events = self.decoded(events)?;
+        Ok(relabel(events, aggregate_id).pop())
+    }
+
+    /// Record in `tx` that `event` was appended at `position`.
+    pub(super) fn track_latest(
+        &self,
+        tx: &Transaction,
+        event: &Event,
+        position: u64,
+    ) -> Result<(), Error> {
+        if !self.latest_by_type {
+            return Ok(());
+        }
+        tx.prepare_cached(&self.sql(
+            "INSERT INTO {latest_by_type}(tenant_id, event_type, aggregate_id, position)
+                VALUES(?, ?, ?, ?)
+                ON CONFLICT(tenant_id, event_type, aggregate_id)
+                DO UPDATE SET position = excluded.position WHERE excluded.position > position",
+        ))?
+        .execute(params![
+            self.tenant_id(),
+            event.event_type,
+            self.id_param(event.id),
+            position
+        ])?;
+        Ok(())
+    }
+
+    /// Point the `latest_by_type` rows of events deleted by a scavenge at
+    /// the latest remaining event of their type and stream, or drop them if
+    /// there is none.
+    pub(super) fn repair_latest(&self, conn: &Connection) -> Result<(), Error> {
+        let stale = "NOT EXISTS (SELECT 1 FROM {eventstore} e WHERE e.position = {latest_by_type}.position)";
+        let latest = "SELECT MAX(e.position) FROM {eventstore} e
+            WHERE e.tenant_id = {latest_by_type}.tenant_id AND e.event_type = {latest_by_type}.event_type
+            AND e.aggregate_id = {latest_by_type}.aggregate_id";
+        conn.execute(
+            &self.sql(&format!(
+                "UPDATE {{latest_by_type}} SET position = ({latest})
+                    WHERE {stale} AND ({latest}) IS NOT NULL",
+            )),
+            params![],
+        )?;
+        conn.execute(
+            &self.sql(&format!("DELETE FROM {{latest_by_type}} WHERE {stale}")),
+            params![],
+        )?;
+        Ok(())
+    }
+}
+
+impl Maintenance<'_> {
+    /// Refill the `latest_by_type` table of the tenant from its events,
+    /// e.g. after appends of backends without `with_latest_by_type`.
+    /// Returns the number of rows.
+    #[instrument]
+    pub fn rebuild_latest_by_type(&self) -> Result<usize, Error> {
+        let backend = self.backend;
+        backend.ensure_writable()?;
+        backend.authorize(Operation::Maintain, None)?;
+        let mut conn = backend.connection()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            &backend.sql("DELETE FROM {latest_by_type} WHERE tenant_id = ?"),
+            params![backend.tenant_id()],
+        )?;
+        let rows = tx.execute(
+            &backend.sql(
+                "INSERT INTO {latest_by_type}(tenant_id, event_type, aggregate_id, position)
+                    SELECT tenant_id, event_type, aggregate_id, MAX(position) FROM {eventstore}
+                    WHERE tenant_id = ? GROUP BY event_type, aggregate_id",
+            ),
+            params![backend.tenant_id()],
+        )?;
+        tx.commit()?;
+        info!(rows, "rebuilt latest events by type");
+        Ok(rows)
+    }
+}
@@ -0,0 +1,59 @@
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+
+impl SqliteBackend {
+    /// Like `get_aggretate`, with the link events of the stream replaced by
+    /// the events they point to, see `resolve_links`.
+    #[instrument]
+    pub fn get_aggretate_resolved(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
+        let events = self.get_aggretate(aggregate_id)?;
+        self.resolve_links(events)
+    }
+
+    /// Replace the links among `events`, appended with `Event::link_to`,
+    /// with the events they point to, e.g. of a `read_all` page. Links to
+    /// events that are gone, deleted, expired or scavenged, are left out.
+    /// Links are followed once, a link to a link stays a link. Needs read
+    /// access to the streams of the targets.
+    #[instrument(skip(events))]
+    pub fn resolve_links(&self, events: Vec<Event>) -> Result<Vec<Event>, Error> {
+        if events.iter().all(|event| event.link_target().is_none()) {
+            return Ok(events);
+        }
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {}",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND aggregate_id = ? AND version = ?",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let mut resolved = Vec::with_capacity(events.len());
+        for event in events {
+            let Some((id, version)) = event.link_target() else {
+                resolved.push(event);
+                continue;
+            };
+            self.authorize(Operation::Read, Some(id))?;
+            if self.is_deleted(&conn, id)? {
+                debug!(aggregate_id = %id, version, "link to deleted stream");
+                continue;
+            }
+            let target = self.result_from_stmt_with_params(
+                &conn,
+                &mut stmt,
+                &[&self.tenant_id(), &self.id_param(id), &version],
+            )?;
+            if target.is_empty() {
+                debug!(aggregate_id = %id, version, "link to missing event");
+            }
+            resolved.extend(self.decoded(target)?);
+        }
+        Ok(resolved)
+    }
+}
@@ -0,0 +1,435 @@
+use std::sync::atomic::Ordering;
+
+use rusqlite::params;
+use serde_json::json;
+use tracing::{info, instrument, warn};
+
+use super::ids::id_text;
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+use crate::stream::TOMBSTONE;
+use crate::system::{self, ScavengeCompleted};
+
+/// Rows `PRAGMA optimize` samples per index when it analyzes a table, so
+/// runs stay cheap on large stores.
+const ANALYSIS_LIMIT: u32 = 1_000;
+
+/// How much work `Maintenance::wal_checkpoint` does, see SQLite's
+/// `wal_checkpoint` pragma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Copy as many frames as possible without waiting for readers or
+    /// writers.
+    Passive,
+    /// Wait for writers, then copy all frames.
+    Full,
+    /// Like `Full`, then wait for readers so the WAL restarts.
+    Restart,
+    /// Like `Restart`, then truncate the WAL file to zero bytes.
+    Truncate,
+}
+
+impl CheckpointMode {
+    fn pragma(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PRAGMA wal_checkpoint(PASSIVE)",
+            CheckpointMode::Full => "PRAGMA wal_checkpoint(FULL)",
+            CheckpointMode::Restart => "PRAGMA wal_checkpoint(RESTART)",
+            CheckpointMode::Truncate => "PRAGMA wal_checkpoint(TRUNCATE)",
+        }
+    }
+}
+
+/// Outcome of a WAL checkpoint, frame counts are -1 if the database is not
+/// in WAL mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalCheckpoint {
+    /// The checkpoint could not complete because of concurrent readers or
+    /// writers.
+    pub busy: bool,
+    /// Frames in the WAL.
+    pub log_frames: i64,
+    /// Frames copied into the database.
+    pub checkpointed_frames: i64,
+}
+
+/// Inconsistency between the tables of the store found by
+/// `Maintenance::integrity_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// The index does not record the latest version of the stream, appends
+    /// to it would be rejected or accepted with the wrong version.
+    IndexVersionMismatch {
+        aggregate_id: String,
+        indexed: Option<u32>,
+        stored: u32,
+    },
+    /// The index lists an aggregate without events.
+    OrphanedIndexEntry { aggregate_id: String, version: u32 },
+    /// The stream does not hold exactly the versions 1 to `max_version`.
+    VersionGap {
+        aggregate_id: String,
+        versions: u32,
+        max_version: u32,
+    },
+    /// Several events share a version of the stream.
+    DuplicateVersion { aggregate_id: String, version: u32 },
+}
+
+/// Result of `Maintenance::integrity_check`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Problems reported by `PRAGMA integrity_check`, empty if the file is
+    /// intact.
+    pub sqlite: Vec<String>,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.sqlite.is_empty() && self.issues.is_empty()
+    }
+}
+
+/// Controls `Maintenance::scavenge_with`.
+#[derive(Debug, Clone, Copy)]
+pub struct ScavengeOpts {
+    /// Rows deleted per transaction.
+    pub batch_size: usize,
+    /// Also delete the events of tombstoned streams, keeping only the
+    /// tombstone. Off by default since the history serves as audit trail.
+    pub purge_tombstoned: bool,
+}
+
+impl Default for ScavengeOpts {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            purge_tombstoned: false,
+        }
+    }
+}
+
+/// Outcome of a scavenge run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScavengeReport {
+    pub deleted_events: usize,
+    pub deleted_snapshots: usize,
+    /// Growth of the free pages in the database file, reused by later
+    /// writes or returned to the file system by `vacuum`.
+    pub reclaimed_bytes: u64,
+}
+
+/// Size of the store, see `Maintenance::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub events: u64,
+    pub streams: u64,
+    pub snapshots: u64,
+    /// Position of the latest event.
+    pub head_position: u64,
+    /// Size of the database file, excluding the WAL.
+    pub size_bytes: u64,
+    /// Part of `size_bytes` in free pages.
+    pub free_bytes: u64,
+    pub schema_version: u32,
+    /// Appends rejected by quotas, see `Maintenance::quota_usage`.
+    pub quota_rejected: u64,
+}
+
+/// Housekeeping on the database file, obtained via
+/// `SqliteBackend::maintenance`.
+#[derive(Debug, Clone, Copy)]
+pub struct Maintenance<'a> {
+    pub(super) backend: &'a SqliteBackend,
+}
+
+impl SqliteBackend {
+    pub fn maintenance(&self) -> Maintenance<'_> {
+        Maintenance { backend: self }
+    }
+}
+
+impl Maintenance<'_> {
+    /// Rebuild the database file to reclaim the space of deleted rows.
+    /// Needs exclusive access and up to twice the size of the database in
+    /// free disk space.
+    #[instrument]
+    pub fn vacuum(&self) -> Result<(), Error> {
+        self.backend.ensure_writable()?;
+        self.backend.authorize(Operation::Maintain, None)?;
+        let conn = self.backend.connection()?;
+        conn.execute_batch("VACUUM")?;
+        info!("vacuumed database");
+        Ok(())
+    }
+
+    /// Copy WAL frames into the database file.
+    #[instrument]
+    pub fn wal_checkpoint(&self, mode: CheckpointMode) -> Result<WalCheckpoint, Error> {
+        self.backend.ensure_writable()?;
+        self.backend.authorize(Operation::Maintain, None)?;
+        let conn = self.backend.connection()?;
+        let checkpoint = conn.query_row(mode.pragma(), [], |row| {
+            Ok(WalCheckpoint {
+                busy: row.get::<_, i64>(0)? != 0,
+                log_frames: row.get(1)?,
+                checkpointed_frames: row.get(2)?,
+            })
+        })?;
+        Ok(checkpoint)
+    }
+
+    /// Refresh the statistics the query planner picks indices by.
+    #[instrument]
+    pub fn analyze(&self) -> Result<(), Error> {
+        self.backend.ensure_writable()?;
+        self.backend.authorize(Operation::Maintain, None)?;
+        let conn = self.backend.connection()?;
+        let written = self.written_since_analyze();
+        conn.execute_batch("ANALYZE")?;
+        self.backend.written.fetch_sub(written, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Run `PRAGMA optimize`, which analyzes the tables whose statistics
+    /// the planner found lacking. Cheap enough to run regularly, see
+    /// `SqliteBackend::spawn_optimizer`.
+    #[instrument]
+    pub fn optimize(&self) -> Result<(), Error> {
+        self.backend.ensure_writable()?;
+        self.backend.authorize(Operation::Maintain, None)?;
+        let conn = self.backend.connection()?;
+        conn.pragma_update(None, "analysis_limit", ANALYSIS_LIMIT)?;
+        conn.execute_batch("PRAGMA optimize")?;
+        Ok(())
+    }
+
+    /// Events appended or imported through this backend and the handles
+    /// created from it since the last `analyze`.
+    pub fn written_since_analyze(&self) -> u64 {
+        self.backend.written.load(Ordering::Relaxed)
+    }
+
+    /// `analyze` after a burst of at least `analyze_after` written events,
+    /// `optimize` otherwise. Returns whether it analyzed.
+    #[instrument]
+    pub fn optimize_after_writes(&self, analyze_after: u64) -> Result<bool, Error> {
+        if self.written_since_analyze() >= analyze_after {
+            self.analyze()?;
+            return Ok(true);
+        }
+        self.optimize()?;
+        Ok(false)
+    }
+
+    /// Row counts and file size of the whole database, all tenants
+    /// included.
+    #[instrument]
+    pub fn stats(&self) -> Result<StoreStats, Error> {
+        let backend = self.backend;
+        backend.authorize(Operation::Maintain, None)?;
+        let schema_version = backend.schema_version()?;
+        let conn = backend.connection()?;
+        let count = |sql: &str| -> Result<u64, Error> {
+            Ok(conn.query_row(&backend.sql(sql), [], |row| row.get(0))?)
+        };
+        let page_size = count("PRAGMA page_size")?;
+        Ok(StoreStats {
+            events: count("SELECT COUNT(*) FROM {eventstore}")?,
+            streams: count("SELECT COUNT(*) FROM {aggregate_index}")?,
+            snapshots: count("SELECT COUNT(*) FROM {snapshot}")?,
+            head_position: count("SELECT COALESCE(MAX(position), 0) FROM {eventstore}")?,
+            size_bytes: count("PRAGMA page_count")? * page_size,
+            free_bytes: count("PRAGMA freelist_count")? * page_size,
+            schema_version,
+            quota_rejected: count("SELECT COALESCE(SUM(rejected), 0) FROM {quota_usage}")?,
+        })
+    }
+
+    /// Run SQLite's integrity check and verify that the aggregate index
+    /// agrees with the stored streams. Nothing is repaired.
+    #[instrument]
+    pub fn integrity_check(&self) -> Result<IntegrityReport, Error> {
+        self.backend.authorize(Operation::Maintain, None)?;
+        let backend = self.backend;
+        let conn = backend.connection()?;
+        let mut report = IntegrityReport::default();
+
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        for message in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            let message = message?;
+            if message != "ok" {
+                report.sqlite.push(message);
+            }
+        }
+
+        let mut stmt = conn.prepare(&backend.sql(
+            "SELECT e.aggregate_id, i.version, MAX(e.version) FROM {eventstore} e
+                LEFT JOIN {aggregate_index} i
+                    ON i.tenant_id = e.tenant_id AND i.aggregate_id = e.aggregate_id
+                GROUP BY e.tenant_id, e.aggregate_id
+                HAVING i.version IS NULL OR i.version != MAX(e.version)",
+        ))?;
+        for issue in stmt.query_and_then([], |row| {
+            Ok::<_, Error>(IntegrityIssue::IndexVersionMismatch {
+                aggregate_id: id_text(row.get_ref(0)?)?,
+                indexed: row.get(1)?,
+                stored: row.get(2)?,
+            })
+        })? {
+            report.issues.push(issue?);
+        }
+
+        let mut stmt = conn.prepare(&backend.sql(
+            "SELECT aggregate_id, version FROM {aggregate_index} i
+                WHERE NOT EXISTS (SELECT 1 FROM {eventstore} e
+                    WHERE e.tenant_id = i.tenant_id AND e.aggregate_id = i.aggregate_id)",
+        ))?;
+        for issue in stmt.query_and_then([], |row| {
+            Ok::<_, Error>(IntegrityIssue::OrphanedIndexEntry {
+                aggregate_id: id_text(row.get_ref(0)?)?,
+                version: row.get(1)?,
+            })
+        })? {
+            report.issues.push(issue?);
+        }
+
+        let mut stmt = conn.prepare(&backend.sql(
+            "SELECT aggregate_id, version FROM {eventstore}
+                GROUP BY tenant_id, aggregate_id, version HAVING COUNT(*) > 1",
+        ))?;
+        for issue in stmt.query_and_then([], |row| {
+            Ok::<_, Error>(IntegrityIssue::DuplicateVersion {
+                aggregate_id: id_text(row.get_ref(0)?)?,
+                version: row.get(1)?,
+            })
+        })? {
+            report.issues.push(issue?);
+        }
+
+        let mut stmt = conn.prepare(&backend.sql(
+            "SELECT aggregate_id, COUNT(DISTINCT version), MAX(version) FROM {eventstore}
+                GROUP BY tenant_id, aggregate_id
+                HAVING MIN(version) != 1 OR COUNT(DISTINCT version) != MAX(version)",
+        ))?;
+        for issue in stmt.query_and_then([], |row| {
+            Ok::<_, Error>(IntegrityIssue::VersionGap {
+                aggregate_id: id_text(row.get_ref(0)?)?,
+                versions: row.get(1)?,
+                max_version: row.get(2)?,
+            })
+        })? {
+            report.issues.push(issue?);
+        }
+
+        if !report.is_ok() {
+            warn!(
+                sqlite = report.sqlite.len(),
+                issues = report.issues.len(),
+                "integrity check failed"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Delete dead rows with the default options, see `scavenge_with`.
+    #[instrument]
+    pub fn scavenge(&self) -> Result<ScavengeReport, Error> {
+        self.scavenge_with(&ScavengeOpts::default())
+    }
+
+    /// Delete expired events, the events hidden by the retention settings or
+    /// truncation of their streams and the snapshots of tombstoned streams. Rows are
+    /// deleted in batches, each in its own transaction, so appends are not
+    /// blocked for long. Meant to be run periodically, e.g. from cron.
+    ///
+    /// Deleted rows free pages for reuse, run `vacuum` to give the space
+    /// back to the file system.
+    #[instrument]
+    pub fn scavenge_with(&self, opts: &ScavengeOpts) -> Result<ScavengeReport, Error> {
+        self.backend.ensure_writable()?;
+        self.backend.authorize(Operation::Maintain, None)?;
+        let backend = self.backend;
+        let conn = backend.connection()?;
+        let free_bytes = || -> Result<i64, Error> {
+            let pages: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+            let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+            Ok(pages * page_size)
+        };
+        let free_before = free_bytes()?;
+
+        let mut dead = format!("NOT {}", backend.retained());
+        if opts.purge_tombstoned {
+            dead += &backend.sql(&format!(
+                " OR ({{eventstore}}.event_type != '{}' AND EXISTS (SELECT 1 FROM {{eventstore}} t
+                    WHERE t.tenant_id = {{eventstore}}.tenant_id AND t.aggregate_id = {{eventstore}}.aggregate_id
+                    AND t.event_type = '{}'))",
+                TOMBSTONE, TOMBSTONE
+            ));
+        }
+        let delete_events = format!(
+            "{} WHERE position IN (SELECT position FROM {{eventstore}} WHERE {} LIMIT ?)",
+            backend.sql("DELETE FROM {eventstore}"),
+            dead
+        );
+        let delete_events = backend.sql(&delete_events);
+        let mut report = ScavengeReport::default();
+        loop {
+            let deleted = conn.execute(&delete_events, params![opts.batch_size.max(1)])?;
+            report.deleted_events += deleted;
+            if deleted < opts.batch_size.max(1) {
+                break;
+            }
+        }
+
+        if report.deleted_events > 0 {
+            backend.repair_latest(&conn)?;
+        }
+
+        let delete_snapshots = backend.sql(&format!(
+            "DELETE FROM {{snapshot}} WHERE rowid IN (SELECT s.rowid FROM {{snapshot}} s
+                JOIN {{eventstore}} t ON t.tenant_id = s.tenant_id AND t.aggregate_id = s.aggregate_id
+                    AND t.event_type = '{}'
+                LIMIT ?)",
+            TOMBSTONE
+        ));
+        loop {
+            let deleted = conn.execute(&delete_snapshots, params![opts.batch_size.max(1)])?;
+            report.deleted_snapshots += deleted;
+            if deleted < opts.batch_size.max(1) {
+                break;
+            }
+        }
+        backend.clear_cached();
+
+        report.reclaimed_bytes = (free_bytes()? - free_before).max(0) as u64;
+        backend.record_admin(
+            &conn,
+            "scavenge",
+            None,
+            json!({
+                "purge_tombstoned": opts.purge_tombstoned,
+                "deleted_events": report.deleted_events,
+                "deleted_snapshots": report.deleted_snapshots,
+                "reclaimed_bytes": report.reclaimed_bytes,
+            }),
+        )?;
+        backend.emit_system_now(
+            &conn,
+            system::MAINTENANCE,
+            &ScavengeCompleted {
+                deleted_events: report.deleted_events,
+                deleted_snapshots: report.deleted_snapshots,
+                reclaimed_bytes: report.reclaimed_bytes,
+            },
+        )?;
+        info!(
+            events = report.deleted_events,
+            snapshots = report.deleted_snapshots,
+            reclaimed_bytes = report.reclaimed_bytes,
+            "scavenged store"
+        );
+        Ok(report)
+    }
+}
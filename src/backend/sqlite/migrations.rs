@@ -0,0 +1,463 @@
+use rusqlite::{params, TransactionBehavior};
+use tracing::{debug, instrument};
+
+use super::{ids, Error, SqliteBackend};
+
+/// A forward-only schema change, applied at most once per database.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// All migrations in order, new schema changes are appended here and never
+/// edited once released.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        // matches the tables created by releases before migrations existed
+        sql: "CREATE TABLE IF NOT EXISTS {eventstore}(
+                aggregate_id TEXT,
+                data BLOB,
+                version INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS {aggregate_index}(
+                aggregate_id TEXT PRIMARY KEY,
+                type_name TEXT,
+                version INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS {snapshot}(
+                aggregate_id TEXT,
+                data BLOB,
+                version INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS {snapshot_index}(
+                aggregate_id TEXT PRIMARY KEY,
+                type_name TEXT,
+                version INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS {eventstore}_agg_id_idx ON {eventstore} (aggregate_id);
+            CREATE INDEX IF NOT EXISTS {snapshot}_agg_id_idx ON {snapshot} (aggregate_id);
+            CREATE UNIQUE INDEX IF NOT EXISTS {snapshot}_unique_idx ON {snapshot} (aggregate_id, version);",
+    },
+    Migration {
+        version: 2,
+        description: "event types, codecs, encryption and global positions",
+        // the position column can not be added in place, so the event table
+        // is rebuilt keeping the insertion order
+        sql: "CREATE TABLE {eventstore}_v2(
+                aggregate_id TEXT,
+                data BLOB,
+                version INTEGER,
+                event_type TEXT NOT NULL DEFAULT '',
+                schema_version INTEGER NOT NULL DEFAULT 1,
+                content_type TEXT NOT NULL DEFAULT 'application/json',
+                metadata TEXT NOT NULL DEFAULT '{}',
+                compression TEXT NOT NULL DEFAULT '',
+                key_id TEXT NOT NULL DEFAULT '',
+                position INTEGER PRIMARY KEY AUTOINCREMENT
+            );
+            INSERT INTO {eventstore}_v2(aggregate_id, data, version)
+                SELECT aggregate_id, data, version FROM {eventstore} ORDER BY rowid;
+            DROP TABLE {eventstore};
+            ALTER TABLE {eventstore}_v2 RENAME TO {eventstore};
+            CREATE INDEX {eventstore}_agg_id_idx ON {eventstore} (aggregate_id);
+            ALTER TABLE {snapshot} ADD COLUMN event_type TEXT NOT NULL DEFAULT '';
+            ALTER TABLE {snapshot} ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;
+            ALTER TABLE {snapshot} ADD COLUMN content_type TEXT NOT NULL DEFAULT 'application/json';
+            ALTER TABLE {snapshot} ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+            ALTER TABLE {snapshot} ADD COLUMN compression TEXT NOT NULL DEFAULT '';
+            ALTER TABLE {snapshot} ADD COLUMN key_id TEXT NOT NULL DEFAULT '';
+            CREATE TABLE {projection_checkpoint}(
+                name TEXT PRIMARY KEY,
+                position INTEGER NOT NULL
+            );
+            CREATE TABLE {compression_dictionary}(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                event_type TEXT NOT NULL,
+                dictionary BLOB NOT NULL
+            );
+            CREATE TABLE {subject_key}(
+                subject_id TEXT PRIMARY KEY,
+                data_key BLOB,
+                key_id TEXT NOT NULL DEFAULT ''
+            );
+            CREATE TABLE {redaction_audit}(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                position INTEGER NOT NULL,
+                fields TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                redacted_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );",
+    },
+    Migration {
+        version: 3,
+        description: "stream metadata and append timestamps",
+        // events appended before have no timestamp (0) and never expire by age
+        sql: "ALTER TABLE {eventstore} ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+            CREATE TABLE {stream_metadata}(
+                aggregate_id TEXT PRIMARY KEY,
+                max_count INTEGER,
+                max_age_ms INTEGER
+            );",
+    },
+    Migration {
+        version: 4,
+        description: "deleted streams",
+        sql: "ALTER TABLE {stream_metadata} ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        version: 5,
+        description: "truncate streams before a version",
+        sql: "ALTER TABLE {stream_metadata} ADD COLUMN truncate_before INTEGER;",
+    },
+    Migration {
+        version: 6,
+        description: "tenants",
+        // existing rows belong to the default tenant '', tables keyed by
+        // aggregate id are rebuilt to key them by tenant and aggregate id
+        sql: "ALTER TABLE {eventstore} ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+            DROP INDEX {eventstore}_agg_id_idx;
+            CREATE INDEX {eventstore}_agg_id_idx ON {eventstore} (tenant_id, aggregate_id, version);
+            CREATE INDEX {eventstore}_tenant_position_idx ON {eventstore} (tenant_id, position);
+            ALTER TABLE {snapshot} ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+            DROP INDEX {snapshot}_agg_id_idx;
+            DROP INDEX {snapshot}_unique_idx;
+            CREATE UNIQUE INDEX {snapshot}_unique_idx ON {snapshot} (tenant_id, aggregate_id, version);
+            ALTER TABLE {redaction_audit} ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+            CREATE TABLE {aggregate_index}_v6(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                aggregate_id TEXT,
+                type_name TEXT,
+                version INTEGER,
+                PRIMARY KEY (tenant_id, aggregate_id)
+            );
+            INSERT INTO {aggregate_index}_v6(aggregate_id, type_name, version)
+                SELECT aggregate_id, type_name, version FROM {aggregate_index};
+            DROP TABLE {aggregate_index};
+            ALTER TABLE {aggregate_index}_v6 RENAME TO {aggregate_index};
+            CREATE TABLE {snapshot_index}_v6(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                aggregate_id TEXT,
+                type_name TEXT,
+                version INTEGER,
+                PRIMARY KEY (tenant_id, aggregate_id)
+            );
+            INSERT INTO {snapshot_index}_v6(aggregate_id, type_name, version)
+                SELECT aggregate_id, type_name, version FROM {snapshot_index};
+            DROP TABLE {snapshot_index};
+            ALTER TABLE {snapshot_index}_v6 RENAME TO {snapshot_index};
+            CREATE TABLE {stream_metadata}_v6(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                aggregate_id TEXT,
+                max_count INTEGER,
+                max_age_ms INTEGER,
+                deleted INTEGER NOT NULL DEFAULT 0,
+                truncate_before INTEGER,
+                PRIMARY KEY (tenant_id, aggregate_id)
+            );
+            INSERT INTO {stream_metadata}_v6(aggregate_id, max_count, max_age_ms, deleted, truncate_before)
+                SELECT aggregate_id, max_count, max_age_ms, deleted, truncate_before FROM {stream_metadata};
+            DROP TABLE {stream_metadata};
+            ALTER TABLE {stream_metadata}_v6 RENAME TO {stream_metadata};
+            CREATE TABLE {projection_checkpoint}_v6(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                name TEXT,
+                position INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, name)
+            );
+            INSERT INTO {projection_checkpoint}_v6(name, position)
+                SELECT name, position FROM {projection_checkpoint};
+            DROP TABLE {projection_checkpoint};
+            ALTER TABLE {projection_checkpoint}_v6 RENAME TO {projection_checkpoint};
+            CREATE TABLE {subject_key}_v6(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                subject_id TEXT,
+                data_key BLOB,
+                key_id TEXT NOT NULL DEFAULT '',
+                PRIMARY KEY (tenant_id, subject_id)
+            );
+            INSERT INTO {subject_key}_v6(subject_id, data_key, key_id)
+                SELECT subject_id, data_key, key_id FROM {subject_key};
+            DROP TABLE {subject_key};
+            ALTER TABLE {subject_key}_v6 RENAME TO {subject_key};",
+    },
+    Migration {
+        version: 7,
+        description: "admin log",
+        sql: "CREATE TABLE {admin_log}(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                recorded_at INTEGER NOT NULL,
+                principal TEXT,
+                tenant_id TEXT NOT NULL DEFAULT '',
+                operation TEXT NOT NULL,
+                aggregate_id TEXT,
+                details TEXT NOT NULL DEFAULT '{}'
+            );
+            CREATE TRIGGER {admin_log}_no_update BEFORE UPDATE ON {admin_log}
+                BEGIN SELECT RAISE(ABORT, 'admin log is append-only'); END;
+            CREATE TRIGGER {admin_log}_no_delete BEFORE DELETE ON {admin_log}
+                BEGIN SELECT RAISE(ABORT, 'admin log is append-only'); END;",
+    },
+    Migration {
+        version: 8,
+        description: "projection quarantine",
+        sql: "CREATE TABLE {quarantine}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                projection TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                error TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                quarantined_at INTEGER NOT NULL,
+                fixed_data BLOB,
+                PRIMARY KEY (tenant_id, projection, position)
+            );",
+    },
+    Migration {
+        version: 9,
+        description: "unique stream versions",
+        // appends rely on the index to reject concurrent writers of a version
+        sql: "DROP INDEX {eventstore}_agg_id_idx;
+            CREATE UNIQUE INDEX {eventstore}_agg_id_idx ON {eventstore} (tenant_id, aggregate_id, version);",
+    },
+    Migration {
+        version: 10,
+        description: "covering stream index",
+        // holds every column read from a stream in version order, so stream
+        // reads are served from the index without looking up the rows, at
+        // the cost of storing the events twice
+        sql: "CREATE INDEX {eventstore}_stream_idx ON {eventstore} (
+                tenant_id, aggregate_id, version, event_type, schema_version, content_type,
+                metadata, compression, key_id, created_at, data
+            );",
+    },
+    Migration {
+        version: 11,
+        description: "event type index",
+        // lets `read_by_type` seek to its cursor instead of scanning
+        sql: "CREATE INDEX {eventstore}_type_idx ON {eventstore} (tenant_id, event_type, position);",
+    },
+    Migration {
+        version: 12,
+        description: "scheduled events",
+        sql: "CREATE TABLE {scheduled_events}(
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tenant_id TEXT NOT NULL DEFAULT '',
+                aggregate_id TEXT NOT NULL,
+                event_type TEXT NOT NULL,
+                schema_version INTEGER NOT NULL,
+                content_type TEXT NOT NULL,
+                data BLOB NOT NULL,
+                metadata TEXT NOT NULL,
+                deliver_at INTEGER NOT NULL,
+                scheduled_at INTEGER NOT NULL
+            );
+            CREATE INDEX {scheduled_events}_due_idx ON {scheduled_events} (tenant_id, deliver_at, id);",
+    },
+    Migration {
+        version: 13,
+        description: "event expiry",
+        // the covering stream index gains the column so stream reads can
+        // still skip expired events from the index alone
+        sql: "ALTER TABLE {eventstore} ADD COLUMN expires_at INTEGER;
+            DROP INDEX {eventstore}_stream_idx;
+            CREATE INDEX {eventstore}_stream_idx ON {eventstore} (
+                tenant_id, aggregate_id, version, event_type, schema_version, content_type,
+                metadata, compression, key_id, created_at, expires_at, data
+            );",
+    },
+    Migration {
+        version: 14,
+        description: "causation columns",
+        // copied from the metadata on append, so causation trees are walked
+        // through indexes also when the metadata is encrypted
+        sql: "ALTER TABLE {eventstore} ADD COLUMN event_id TEXT;
+            ALTER TABLE {eventstore} ADD COLUMN causation_id TEXT;
+            ALTER TABLE {eventstore} ADD COLUMN correlation_id TEXT;
+            UPDATE {eventstore} SET event_id = json_extract(metadata, '$.event_id'),
+                causation_id = json_extract(metadata, '$.causation_id'),
+                correlation_id = json_extract(metadata, '$.correlation_id')
+                WHERE typeof(metadata) = 'text' AND json_valid(metadata);
+            CREATE INDEX {eventstore}_event_id_idx ON {eventstore} (tenant_id, event_id)
+                WHERE event_id IS NOT NULL;
+            CREATE INDEX {eventstore}_causation_idx ON {eventstore} (tenant_id, causation_id)
+                WHERE causation_id IS NOT NULL;",
+    },
+    Migration {
+        version: 15,
+        description: "processed commands",
+        sql: "CREATE TABLE {processed_commands}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                command_id TEXT NOT NULL,
+                processed_at INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, command_id)
+            );
+            CREATE INDEX {processed_commands}_processed_at_idx ON {processed_commands} (processed_at);",
+    },
+    Migration {
+        version: 16,
+        description: "quota usage",
+        // aggregate_id is empty for the totals of the tenant
+        sql: "CREATE TABLE {quota_usage}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                aggregate_id TEXT NOT NULL,
+                events INTEGER NOT NULL,
+                bytes INTEGER NOT NULL,
+                rejected INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, aggregate_id)
+            );",
+    },
+    Migration {
+        version: 17,
+        description: "stream aliases",
+        sql: "CREATE TABLE {stream_aliases}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                alias TEXT NOT NULL,
+                aggregate_id TEXT NOT NULL,
+                renamed INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, alias)
+            );
+            CREATE INDEX {stream_aliases}_aggregate_idx ON {stream_aliases} (tenant_id, aggregate_id);",
+    },
+    Migration {
+        version: 18,
+        description: "valid time columns",
+        // copied from the metadata on append like the causation columns
+        sql: "ALTER TABLE {eventstore} ADD COLUMN valid_from INTEGER;
+            ALTER TABLE {eventstore} ADD COLUMN valid_to INTEGER;
+            UPDATE {eventstore} SET valid_from = CAST(json_extract(metadata, '$.valid_from') AS INTEGER),
+                valid_to = CAST(json_extract(metadata, '$.valid_to') AS INTEGER)
+                WHERE typeof(metadata) = 'text' AND json_valid(metadata)
+                    AND (json_extract(metadata, '$.valid_from') IS NOT NULL
+                        OR json_extract(metadata, '$.valid_to') IS NOT NULL);",
+    },
+    Migration {
+        version: 19,
+        description: "latest event per type",
+        sql: "CREATE TABLE {latest_by_type}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                event_type TEXT NOT NULL,
+                aggregate_id BLOB NOT NULL,
+                position INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, event_type, aggregate_id)
+            );
+            INSERT INTO {latest_by_type}(tenant_id, event_type, aggregate_id, position)
+                SELECT tenant_id, event_type, aggregate_id, MAX(position) FROM {eventstore}
+                GROUP BY tenant_id, event_type, aggregate_id;",
+    },
+    Migration {
+        version: 20,
+        description: "analytics tables",
+        // one row per minute, see `SqliteBackend::with_analytics`
+        sql: "CREATE TABLE {event_counts}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                bucket INTEGER NOT NULL,
+                event_type TEXT NOT NULL,
+                events INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, bucket, event_type)
+            );
+            CREATE TABLE {lag_samples}(
+                tenant_id TEXT NOT NULL DEFAULT '',
+                name TEXT NOT NULL,
+                bucket INTEGER NOT NULL,
+                lag INTEGER NOT NULL,
+                max_lag INTEGER NOT NULL,
+                PRIMARY KEY (tenant_id, name, bucket)
+            );",
+    },
+    Migration {
+        version: 21,
+        description: "encoded scheduled events",
+        // text ids become BLOBs unless the store still has text ids, see
+        // `detect_id_format`, failed deliveries are kept out of the due index
+        sql: "ALTER TABLE {scheduled_events} ADD COLUMN compression TEXT NOT NULL DEFAULT '';
+            ALTER TABLE {scheduled_events} ADD COLUMN key_id TEXT NOT NULL DEFAULT '';
+            ALTER TABLE {scheduled_events} ADD COLUMN failed_at INTEGER;
+            ALTER TABLE {scheduled_events} ADD COLUMN error TEXT;
+            UPDATE {scheduled_events} SET aggregate_id = eventstore_uuid_blob(aggregate_id)
+                WHERE typeof(aggregate_id) = 'text'
+                    AND (SELECT typeof(aggregate_id) FROM {eventstore} LIMIT 1) IS NOT 'text';
+            DROP INDEX {scheduled_events}_due_idx;
+            CREATE INDEX {scheduled_events}_due_idx ON {scheduled_events} (tenant_id, deliver_at, id)
+                WHERE failed_at IS NULL;",
+    },
+    Migration {
+        version: 22,
+        description: "stream alias ids",
+        // like the scheduled events in 21
+        sql: "UPDATE {stream_aliases} SET alias = eventstore_uuid_blob(alias),
+                aggregate_id = eventstore_uuid_blob(aggregate_id)
+                WHERE typeof(alias) = 'text'
+                    AND (SELECT typeof(aggregate_id) FROM {eventstore} LIMIT 1) IS NOT 'text';",
+    },
+    Migration {
+        version: 23,
+        description: "stream index without payloads",
+        // the covering index of 10 and 13 stored every payload twice, stream
+        // reads now filter on the index and read payloads from the rows.
+        // `Maintenance::vacuum` returns the freed pages to the file system
+        sql: "DROP INDEX {eventstore}_stream_idx;
+            CREATE INDEX {eventstore}_stream_idx ON {eventstore} (
+                tenant_id, aggregate_id, version, created_at, expires_at
+            );",
+    },
+];
+
+static CREATE_SCHEMA_MIGRATIONS_TABLE_STMT: &str = "CREATE TABLE IF NOT EXISTS {schema_migrations}(
+                version INTEGER PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )";
+
+impl SqliteBackend {
+    /// Apply all pending migrations, returns the resulting schema version.
+    /// Runs in one immediate transaction so concurrent starts do not race.
+    #[instrument]
+    pub fn migrate(&self) -> Result<u32, Error> {
+        self.ensure_writable()?;
+        let mut conn = self.connection()?;
+        conn.execute(&self.sql(CREATE_SCHEMA_MIGRATIONS_TABLE_STMT), params![])?;
+        ids::register_uuid_blob(&conn)?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: u32 = tx.query_row(
+            &self.sql("SELECT COALESCE(MAX(version), 0) FROM {schema_migrations}"),
+            params![],
+            |r| r.get(0),
+        )?;
+        let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+        if current > latest {
+            return Err(Error::WithMsg(format!(
+                "database schema version {} is newer than the supported version {}",
+                current, latest
+            )));
+        }
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+            debug!(
+                version = migration.version,
+                description = migration.description,
+                "applying migration"
+            );
+            tx.execute_batch(&self.sql(migration.sql))?;
+            tx.execute(
+                &self.sql("INSERT INTO {schema_migrations}(version, description) VALUES(?, ?)"),
+                params![migration.version, migration.description],
+            )?;
+        }
+        tx.commit()?;
+        Ok(latest)
+    }
+
+    /// Version of the most recent migration applied to the database.
+    #[instrument]
+    pub fn schema_version(&self) -> Result<u32, Error> {
+        let conn = self.connection()?;
+        Ok(conn.query_row(
+            &self.sql("SELECT COALESCE(MAX(version), 0) FROM {schema_migrations}"),
+            params![],
+            |r| r.get(0),
+        )?)
+    }
+}
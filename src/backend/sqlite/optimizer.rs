@@ -0,0 +1,82 @@
+//! Keeps the statistics of the query planner current while the store grows,
+//! explicitly with `Maintenance::optimize` or on a background thread:
+//!
+//! ```ignore
+//! let optimizer = backend.spawn_optimizer(Duration::from_secs(3600), 100_000);
+//! // ...
+//! optimizer.stop()?;
+//! ```
+//!
+//! Stale statistics let SQLite pick a worse index once tables grew by
+//! orders of magnitude, e.g. scanning the type index for a stream read.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use super::{Error, SqliteBackend};
+
+impl SqliteBackend {
+    /// Run `Maintenance::optimize_after_writes` every `interval` on a
+    /// background thread until the handle is stopped. Failures are logged
+    /// and retried after `interval`.
+    pub fn spawn_optimizer(&self, interval: Duration, analyze_after: u64) -> OptimizerHandle {
+        let backend = self.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut next_run = Instant::now() + interval;
+            while !stopped.load(Ordering::Relaxed) {
+                let now = Instant::now();
+                if now < next_run {
+                    // woken early by `OptimizerHandle::stop`
+                    std::thread::park_timeout(next_run - now);
+                    continue;
+                }
+                next_run = now + interval;
+                match backend.maintenance().optimize_after_writes(analyze_after) {
+                    Ok(analyzed) => debug!(analyzed, "optimized database"),
+                    Err(err) => warn!("optimizing database failed: {}", err),
+                }
+            }
+        });
+        info!(?interval, analyze_after, "started optimizer");
+        OptimizerHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Stops an optimizer started with `SqliteBackend::spawn_optimizer`.
+#[derive(Debug)]
+pub struct OptimizerHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OptimizerHandle {
+    /// Stop without waiting for the next run.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        thread.thread().unpark();
+        thread
+            .join()
+            .map_err(|_| Error::WithMsg("optimizer thread panicked".to_string()))
+    }
+}
+
+impl Drop for OptimizerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = &self.thread {
+            thread.thread().unpark();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use super::Durability;
+
+/// Settings applied to every connection of a backend, e.g. for a large
+/// read-heavy store:
+///
+/// ```ignore
+/// let options = ConnectionOptions::new()
+///     .with_durability(Durability::Balanced)
+///     .with_mmap_size(8 << 30)
+///     .with_cache_size_kib(256 << 10);
+/// let backend = SqliteBackend::new(options.manager("events.db"));
+/// ```
+///
+/// Settings left unset keep SQLite's defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    pub(super) durability: Option<Durability>,
+    mmap_size: Option<u64>,
+    cache_size_kib: Option<u64>,
+}
+
+impl ConnectionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = Some(durability);
+        self
+    }
+
+    /// Read up to `bytes` of the database file through memory-mapped I/O
+    /// instead of copying pages into the page cache, see the `mmap_size`
+    /// pragma. SQLite caps it at its compile-time maximum, 0 disables it.
+    pub fn with_mmap_size(mut self, bytes: u64) -> Self {
+        self.mmap_size = Some(bytes);
+        self
+    }
+
+    /// Size of the page cache of each connection, see the `cache_size`
+    /// pragma. Pooled connections each have their own cache.
+    pub fn with_cache_size_kib(mut self, kib: u64) -> Self {
+        self.cache_size_kib = Some(kib);
+        self
+    }
+
+    pub fn durability(&self) -> Option<Durability> {
+        self.durability
+    }
+
+    pub fn mmap_size(&self) -> Option<u64> {
+        self.mmap_size
+    }
+
+    pub fn cache_size_kib(&self) -> Option<u64> {
+        self.cache_size_kib
+    }
+
+    /// Apply the settings to `conn`.
+    pub fn configure(&self, conn: &Connection) -> rusqlite::Result<()> {
+        if let Some(durability) = self.durability {
+            durability.configure(conn)?;
+        }
+        if let Some(bytes) = self.mmap_size {
+            conn.query_row(&format!("PRAGMA mmap_size={}", bytes), [], |_| Ok(()))?;
+        }
+        if let Some(kib) = self.cache_size_kib {
+            // negative sizes are in KiB, positive ones in pages
+            conn.pragma_update(None, "cache_size", -(kib.min(i64::MAX as u64) as i64))?;
+        }
+        Ok(())
+    }
+
+    /// Connection manager for the database file at `path` applying the
+    /// settings to each connection it opens.
+    pub fn manager<P: AsRef<Path>>(self, path: P) -> SqliteConnectionManager {
+        SqliteConnectionManager::file(path).with_init(move |conn| self.configure(conn))
+    }
+}
@@ -0,0 +1,187 @@
+//! Keyset pagination: every page ends with an opaque `Cursor` naming the
+//! last row, the next page seeks past it in the index instead of skipping
+//! rows, so reading page 10 000 costs the same as reading the first.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{Error, Operation, SqliteBackend, EVENT_COLUMNS};
+use crate::backend::model::Event;
+use crate::stream::StreamInfo;
+
+/// Where a page ends, returned in `Page::next` and passed to the call for
+/// the following page. Its string form is stable across releases, clients
+/// should store it as is rather than interpreting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Cursor(Key);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    /// Global position, for listings in commit order.
+    Position(u64),
+    /// Aggregate id, for listings of streams.
+    Stream(Uuid),
+}
+
+impl Cursor {
+    /// Cursor continuing after the global `position`, e.g. a projection
+    /// checkpoint.
+    pub fn after_position(position: u64) -> Self {
+        Self(Key::Position(position))
+    }
+
+    /// Cursor continuing after the stream `aggregate_id`.
+    pub fn after_stream(aggregate_id: Uuid) -> Self {
+        Self(Key::Stream(aggregate_id))
+    }
+
+    pub(super) fn position(cursor: Option<&Cursor>) -> Result<u64, Error> {
+        match cursor.map(|cursor| cursor.0) {
+            None => Ok(0),
+            Some(Key::Position(position)) => Ok(position),
+            Some(Key::Stream(_)) => Err(Error::WithMsg(
+                "cursor of a stream listing used for events".to_string(),
+            )),
+        }
+    }
+
+    fn stream(cursor: Option<&Cursor>) -> Result<Option<Uuid>, Error> {
+        match cursor.map(|cursor| cursor.0) {
+            None => Ok(None),
+            Some(Key::Stream(aggregate_id)) => Ok(Some(aggregate_id)),
+            Some(Key::Position(_)) => Err(Error::WithMsg(
+                "cursor of an event listing used for streams".to_string(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Key::Position(position) => write!(f, "p{:016x}", position),
+            Key::Stream(aggregate_id) => write!(f, "s{}", aggregate_id.simple()),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::WithMsg(format!("invalid cursor {:?}", s));
+        let key = match s.split_at_checked(1) {
+            Some(("p", position)) if position.len() == 16 => {
+                Key::Position(u64::from_str_radix(position, 16).map_err(|_| invalid())?)
+            }
+            Some(("s", aggregate_id)) if aggregate_id.len() == 32 => {
+                Key::Stream(Uuid::try_parse(aggregate_id).map_err(|_| invalid())?)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Self(key))
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        cursor.to_string()
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A page of a listing, `next` is `None` on the last page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Page of `limit` items out of `items`, which holds one more if a
+    /// further page follows.
+    fn of(mut items: Vec<T>, limit: usize, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next = (items.len() > limit).then(|| cursor(&items[limit - 1]));
+        items.truncate(limit);
+        Self { items, next }
+    }
+}
+
+impl SqliteBackend {
+    /// Page of `read_all`, starting after `after` or at the first event.
+    #[instrument]
+    pub fn read_all_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Event>, Error> {
+        let limit = limit.max(1);
+        let events = self.read_all(Cursor::position(after)?, limit + 1)?;
+        Ok(Page::of(events, limit, |event| {
+            Cursor::after_position(event.position)
+        }))
+    }
+
+    /// Page of `list_streams`, starting after `after` or at the first
+    /// stream.
+    #[instrument]
+    pub fn list_streams_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<StreamInfo>, Error> {
+        let limit = limit.max(1);
+        let streams = self.list_streams(Cursor::stream(after)?, limit + 1)?;
+        Ok(Page::of(streams, limit, |stream| {
+            Cursor::after_stream(stream.aggregate_id)
+        }))
+    }
+
+    /// Events of all streams with type `event_type` in commit order, page
+    /// by page like `read_all_page`.
+    #[instrument]
+    pub fn read_by_type(
+        &self,
+        event_type: &str,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Page<Event>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let limit = limit.max(1);
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY position ASC LIMIT ?",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND event_type = ? AND position > ?",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let events = self.result_from_stmt_with_params(
+            &conn,
+            &mut stmt,
+            &[
+                &self.tenant_id(),
+                &event_type,
+                &Cursor::position(after)?,
+                &(limit + 1),
+            ],
+        )?;
+        let events = self.decoded(events)?;
+        Ok(Page::of(events, limit, |event| {
+            Cursor::after_position(event.position)
+        }))
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use arrow_array::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use tracing::instrument;
+
+use super::{Error, SqliteBackend};
+use crate::jsonl::ExportOpts;
+use crate::parquet::{arrow_error, record_batch, schema};
+
+impl SqliteBackend {
+    /// Call `f` with the stored events selected by `opts` as Arrow record
+    /// batches of `parquet::schema()`, `opts.batch_size` rows each. Returns
+    /// the number of events.
+    #[instrument(skip(f))]
+    pub fn export_arrow<F>(&self, opts: &ExportOpts, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(RecordBatch) -> Result<(), Error>,
+    {
+        self.for_each_export_batch(opts, |conn, events| {
+            let (first, last) = match (events.first(), events.last()) {
+                (Some(first), Some(last)) => (first.position, last.position),
+                _ => return Ok(()),
+            };
+            let mut stmt = conn.prepare_cached(&self.sql(
+                "SELECT position, created_at FROM {eventstore}
+                 WHERE tenant_id = ? AND position BETWEEN ? AND ?",
+            ))?;
+            let timestamps = stmt
+                .query_map(
+                    rusqlite::params![self.tenant_id(), first as i64, last as i64],
+                    |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)?)),
+                )?
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            f(record_batch(events, &timestamps)?)
+        })
+    }
+
+    /// Write the stored events selected by `opts` to a Parquet file at
+    /// `path`, one row group per `opts.batch_size` events. Events are
+    /// exported as stored, like `export_jsonl`. Returns the number of
+    /// exported events.
+    #[instrument(skip(path))]
+    pub fn export_parquet<P: AsRef<Path>>(
+        &self,
+        path: P,
+        opts: &ExportOpts,
+    ) -> Result<usize, Error> {
+        let file = File::create(path).map_err(arrow_error)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_row_count(Some(opts.batch_size.max(1)))
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(file, schema(), Some(properties)).map_err(arrow_error)?;
+        let exported =
+            self.export_arrow(opts, |batch| writer.write(&batch).map_err(arrow_error))?;
+        writer.close().map_err(arrow_error)?;
+        Ok(exported)
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use super::ConnectionOptions;
+
+/// Trade-off between durability and append throughput, configuring the
+/// journal mode, when SQLite syncs to disk and how often the WAL is
+/// checkpointed in one switch:
+///
+/// ```ignore
+/// let backend = SqliteBackend::new(Durability::Balanced.manager("events.db"));
+/// ```
+///
+/// All profiles use the WAL, so readers do not block the writer and a
+/// crash of the process never loses committed appends. They differ in what
+/// a power loss or crash of the operating system costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Every commit is synced before the append returns, committed appends
+    /// survive power loss. Slowest, one sync per transaction.
+    Durable,
+    /// The WAL is only synced on checkpoints. After a power loss the
+    /// appends committed since the last checkpoint may be rolled back, the
+    /// database stays consistent.
+    #[default]
+    Balanced,
+    /// Never syncs and checkpoints less often, so commits are grouped into
+    /// larger writes. A power loss may roll back recent appends and can
+    /// corrupt the database, for data that can be rebuilt, e.g. tests or
+    /// caches.
+    Fast,
+}
+
+impl Durability {
+    /// Value of the `synchronous` pragma.
+    pub fn synchronous(self) -> &'static str {
+        match self {
+            Durability::Durable => "FULL",
+            Durability::Balanced => "NORMAL",
+            Durability::Fast => "OFF",
+        }
+    }
+
+    /// Pages the WAL grows to before it is checkpointed, see the
+    /// `wal_autocheckpoint` pragma.
+    pub fn wal_autocheckpoint(self) -> u32 {
+        match self {
+            Durability::Durable | Durability::Balanced => 1_000,
+            Durability::Fast => 10_000,
+        }
+    }
+
+    /// Apply the profile to `conn`. The journal mode is stored in the
+    /// database file, the other settings have to be applied to every
+    /// connection.
+    pub fn configure(self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+        conn.pragma_update(None, "synchronous", self.synchronous())?;
+        conn.pragma_update(None, "wal_autocheckpoint", self.wal_autocheckpoint())
+    }
+
+    /// Connection manager for the database file at `path` applying the
+    /// profile to each connection it opens, see `ConnectionOptions` to
+    /// combine it with other settings.
+    pub fn manager<P: AsRef<Path>>(self, path: P) -> SqliteConnectionManager {
+        ConnectionOptions::new().with_durability(self).manager(path)
+    }
+}
@@ -0,0 +1,167 @@
+use rusqlite::params;
+use serde_json::json;
+use tracing::{instrument, warn};
+
+use super::{Error, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::projection::QuarantinedEvent;
+
+impl SqliteBackend {
+    /// Put the event at `position` into the quarantine of `projection`, or
+    /// add `attempts` if it is already there.
+    pub(crate) fn quarantine_event(
+        &self,
+        projection: &str,
+        position: u64,
+        error: &str,
+        attempts: u32,
+    ) -> Result<(), Error> {
+        warn!(projection, position, error, "quarantined event");
+        let conn = self.connection()?;
+        conn.execute(
+            &self.sql(
+                "INSERT INTO {quarantine}(tenant_id, projection, position, error, attempts, quarantined_at)
+                    VALUES(?, ?, ?, ?, ?, ?)
+                    ON CONFLICT(tenant_id, projection, position) DO UPDATE SET error = excluded.error,
+                        attempts = attempts + excluded.attempts, quarantined_at = excluded.quarantined_at",
+            ),
+            params![
+                self.tenant_id(),
+                projection,
+                position,
+                error,
+                attempts,
+                self.now_millis()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Events quarantined by `projection` in commit order. Fixed events carry
+    /// the payload set with `fix_quarantined`.
+    #[instrument]
+    pub fn quarantined(&self, projection: &str) -> Result<Vec<QuarantinedEvent>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(&self.sql(
+            "SELECT position, error, attempts, quarantined_at, fixed_data FROM {quarantine}
+                WHERE tenant_id = ? AND projection = ? ORDER BY position",
+        ))?;
+        let rows = stmt
+            .query_map(params![self.tenant_id(), projection], |r| {
+                Ok((
+                    r.get::<_, u64>(0)?,
+                    r.get::<_, String>(1)?,
+                    r.get::<_, u32>(2)?,
+                    r.get::<_, i64>(3)?,
+                    r.get::<_, Option<Vec<u8>>>(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut event_stmt = conn.prepare(&self.sql(&format!(
+            "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND position = ?",
+            EVENT_COLUMNS
+        )))?;
+        let mut quarantined = Vec::with_capacity(rows.len());
+        for (position, error, attempts, quarantined_at, fixed_data) in rows {
+            let events = self.result_from_stmt_with_params(
+                &conn,
+                &mut event_stmt,
+                &[&self.tenant_id(), &position],
+            )?;
+            // the event may have been deleted since
+            let Some(mut event) = self.decoded(events)?.pop() else {
+                continue;
+            };
+            let fixed = fixed_data.is_some();
+            if let Some(data) = fixed_data {
+                event.data = data;
+            }
+            quarantined.push(QuarantinedEvent {
+                projection: projection.to_string(),
+                event,
+                error,
+                attempts,
+                quarantined_at,
+                fixed,
+            });
+        }
+        Ok(quarantined)
+    }
+
+    /// Replace the payload the projection gets for the quarantined event at
+    /// `position` on replay, e.g. re-serialized in the current schema. The
+    /// stored event is not changed.
+    #[instrument(skip(data))]
+    pub fn fix_quarantined(
+        &self,
+        projection: &str,
+        position: u64,
+        data: Vec<u8>,
+    ) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, None)?;
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+        let updated = tx.execute(
+            &self.sql(
+                "UPDATE {quarantine} SET fixed_data = ? WHERE tenant_id = ? AND projection = ? AND position = ?",
+            ),
+            params![data, self.tenant_id(), projection, position],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+        self.record_admin(
+            &tx,
+            "fix_quarantined",
+            None,
+            json!({ "projection": projection, "position": position }),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Drop the quarantined event at `position` without handling it.
+    #[instrument]
+    pub fn discard_quarantined(&self, projection: &str, position: u64) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, None)?;
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+        if !self.release_quarantined_in(&tx, projection, position)? {
+            return Err(Error::NotFound);
+        }
+        self.record_admin(
+            &tx,
+            "discard_quarantined",
+            None,
+            json!({ "projection": projection, "position": position }),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Remove the event at `position` from the quarantine after a successful
+    /// replay.
+    pub(crate) fn release_quarantined(&self, projection: &str, position: u64) -> Result<(), Error> {
+        let conn = self.connection()?;
+        self.release_quarantined_in(&conn, projection, position)?;
+        Ok(())
+    }
+
+    fn release_quarantined_in(
+        &self,
+        conn: &rusqlite::Connection,
+        projection: &str,
+        position: u64,
+    ) -> Result<bool, Error> {
+        let deleted = conn.execute(
+            &self.sql(
+                "DELETE FROM {quarantine} WHERE tenant_id = ? AND projection = ? AND position = ?",
+            ),
+            params![self.tenant_id(), projection, position],
+        )?;
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rusqlite::{params, Connection};
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+use super::{Error, Maintenance, SqliteBackend};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+use crate::metrics;
+use crate::quota::{Quota, QuotaUsage, Quotas, RateLimiter};
+use crate::stream::TOMBSTONE;
+use crate::system;
+
+/// Quotas of a backend and the rate limiters its handles share.
+#[derive(Debug, Default)]
+pub(super) struct QuotaState {
+    quotas: Quotas,
+    limiter: RateLimiter,
+}
+
+/// Rate an append took from the limiters, given back when dropped unless
+/// the append was committed.
+pub(super) struct Charge {
+    state: Arc<QuotaState>,
+    takes: Vec<(String, Option<Uuid>, u32, u64)>,
+}
+
+impl Charge {
+    /// Keep the rate taken, the append was committed.
+    pub(super) fn settle(mut self) {
+        self.takes.clear();
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        if self.takes.is_empty() {
+            return;
+        }
+        let takes: Vec<_> = self
+            .takes
+            .iter()
+            .map(|(tenant_id, aggregate_id, per_sec, count)| {
+                ((tenant_id.as_str(), *aggregate_id), *per_sec, *count)
+            })
+            .collect();
+        self.state.limiter.refund(&takes);
+    }
+}
+
+/// A limit an append exceeds, counted by `SqliteBackend::reject`.
+#[derive(Debug)]
+pub(super) struct Rejection {
+    aggregate_id: Option<Uuid>,
+    reason: String,
+}
+
+impl From<Rejection> for Error {
+    fn from(rejection: Rejection) -> Self {
+        Error::QuotaExceeded(rejection.reason)
+    }
+}
+
+impl SqliteBackend {
+    /// Enforce `quotas` on appends, see `crate::quota`. Handles created
+    /// from this one share the rate limits.
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = Some(Arc::new(QuotaState {
+            quotas,
+            limiter: RateLimiter::default(),
+        }));
+        self
+    }
+
+    /// Check the quotas of `events` appended at `positions` in the
+    /// transaction on `conn` and add them to the totals. Payloads are
+    /// measured as stored, compressed and encrypted, like the totals
+    /// counted from the stored events. Returns the rate taken, given back
+    /// unless the append is committed, or the rejection if a limit is
+    /// exceeded, the append is to be rolled back then. Events of system
+    /// streams and tombstones are not charged.
+    pub(super) fn charge_quotas(
+        &self,
+        conn: &Connection,
+        events: &[Event],
+        positions: &[u64],
+    ) -> Result<Result<Option<Charge>, Rejection>, Error> {
+        let Some(state) = &self.quotas else {
+            return Ok(Ok(None));
+        };
+        let mut streams: BTreeMap<Uuid, (u64, u64)> = BTreeMap::new();
+        let charged = events.iter().zip(positions).filter(|(event, _)| {
+            !system::is_system_stream(event.id) && event.event_type != TOMBSTONE
+        });
+        let mut stored_len = conn.prepare_cached(
+            &self.sql("SELECT COALESCE(LENGTH(data), 0) FROM {eventstore} WHERE position = ?"),
+        )?;
+        for (event, position) in charged {
+            let (count, bytes) = streams.entry(event.id).or_default();
+            *count += 1;
+            *bytes += stored_len.query_row([position], |row| row.get::<_, u64>(0))?;
+        }
+        drop(stored_len);
+        // the totals counted on first use leave out the append
+        let first = positions.iter().copied().min().unwrap_or(i64::MAX as u64);
+        let total = streams
+            .values()
+            .fold((0, 0), |(count, bytes), (c, b)| (count + c, bytes + b));
+        if total.0 == 0 {
+            return Ok(Ok(None));
+        }
+        let mut charges = vec![(None, state.quotas.tenant(self.tenant_id()), total)];
+        charges.extend(
+            streams
+                .into_iter()
+                .map(|(id, usage)| (Some(id), state.quotas.stream(), usage)),
+        );
+
+        let mut totals = Vec::new();
+        for (aggregate_id, quota, (count, bytes)) in &charges {
+            if !quota.has_totals() {
+                continue;
+            }
+            let (used_events, used_bytes) = self.usage(conn, *aggregate_id, first)?;
+            let exceeded = match quota {
+                Quota {
+                    max_events: Some(max),
+                    ..
+                } if used_events + count > *max => Some(format!("{} events", max)),
+                Quota {
+                    max_bytes: Some(max),
+                    ..
+                } if used_bytes + bytes > *max => Some(format!("{} bytes", max)),
+                _ => None,
+            };
+            if let Some(reason) = exceeded {
+                return Ok(Err(self.rejection(*aggregate_id, reason)));
+            }
+            totals.push((usage_key(*aggregate_id), count, bytes));
+        }
+        let takes: Vec<_> = charges
+            .iter()
+            .filter_map(|(aggregate_id, quota, (count, _))| {
+                let per_sec = quota.events_per_sec?;
+                Some(((self.tenant_id(), *aggregate_id), per_sec, *count))
+            })
+            .collect();
+        if let Err(short) = state.limiter.try_take(&takes, self.now_millis()) {
+            let ((_, aggregate_id), per_sec, _) = takes[short];
+            let reason = format!("{} events per second", per_sec);
+            return Ok(Err(self.rejection(aggregate_id, reason)));
+        }
+        let charge = Charge {
+            state: state.clone(),
+            takes: takes
+                .into_iter()
+                .map(|((tenant_id, aggregate_id), per_sec, count)| {
+                    (tenant_id.to_string(), aggregate_id, per_sec, count)
+                })
+                .collect(),
+        };
+        for (key, count, bytes) in totals {
+            conn.prepare_cached(&self.sql(
+                "UPDATE {quota_usage} SET events = events + ?, bytes = bytes + ?
+                    WHERE tenant_id = ? AND aggregate_id = ?",
+            ))?
+            .execute(params![count, bytes, self.tenant_id(), key])?;
+        }
+        Ok(Ok(Some(charge)))
+    }
+
+    /// Totals of the tenant or the stream `aggregate_id`, counted on first
+    /// use from the stored events before `position`.
+    fn usage(
+        &self,
+        conn: &Connection,
+        aggregate_id: Option<Uuid>,
+        position: u64,
+    ) -> Result<(u64, u64), Error> {
+        let key = usage_key(aggregate_id);
+        let stream_filter = match aggregate_id {
+            Some(_) => "AND aggregate_id = ?3",
+            None => "AND ?3 IS NOT NULL",
+        };
+        conn.prepare_cached(&self.sql(&format!(
+            "INSERT INTO {{quota_usage}}(tenant_id, aggregate_id, events, bytes, rejected)
+                SELECT ?1, ?2, COUNT(*), COALESCE(SUM(LENGTH(data)), 0), 0 FROM {{eventstore}}
+                WHERE tenant_id = ?1 AND position < ?4 {}
+                ON CONFLICT DO NOTHING",
+            stream_filter
+        )))?
+        .execute(params![
+            self.tenant_id(),
+            key,
+            aggregate_id.map(|id| self.id_param(id)),
+            position
+        ])?;
+        Ok(conn
+            .prepare_cached(&self.sql(
+                "SELECT events, bytes FROM {quota_usage} WHERE tenant_id = ? AND aggregate_id = ?",
+            ))?
+            .query_row(params![self.tenant_id(), key], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?)
+    }
+
+    /// Drop the totals of the deleted stream `aggregate_id` and take its
+    /// `events` of `bytes` off the totals of the tenant.
+    pub(super) fn release_usage(
+        &self,
+        conn: &Connection,
+        aggregate_id: Uuid,
+        events: u64,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        conn.execute(
+            &self.sql("DELETE FROM {quota_usage} WHERE tenant_id = ? AND aggregate_id = ?"),
+            params![self.tenant_id(), usage_key(Some(aggregate_id))],
+        )?;
+        conn.execute(
+            &self.sql(
+                "UPDATE {quota_usage} SET events = MAX(events - ?, 0), bytes = MAX(bytes - ?, 0)
+                    WHERE tenant_id = ? AND aggregate_id = ?",
+            ),
+            params![events, bytes, self.tenant_id(), usage_key(None)],
+        )?;
+        Ok(())
+    }
+
+    /// Count `rejection` on `conn`, outside the rolled back transaction of
+    /// the append, and return its error.
+    pub(super) fn reject(&self, conn: &Connection, rejection: Rejection) -> Result<Error, Error> {
+        warn!(tenant_id = self.tenant_id(), "{}", rejection.reason);
+        conn.execute(
+            &self.sql(
+                "INSERT INTO {quota_usage}(tenant_id, aggregate_id, events, bytes, rejected)
+                    VALUES(?, ?, 0, 0, 1)
+                    ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET rejected = rejected + 1",
+            ),
+            params![self.tenant_id(), usage_key(rejection.aggregate_id)],
+        )?;
+        metrics::quota_rejected(self.tenant_id());
+        Ok(rejection.into())
+    }
+
+    fn rejection(&self, aggregate_id: Option<Uuid>, limit: String) -> Rejection {
+        let reason = match aggregate_id {
+            Some(id) => format!("stream {} is limited to {}", id, limit),
+            None => format!("tenant {:?} is limited to {}", self.tenant_id(), limit),
+        };
+        Rejection {
+            aggregate_id,
+            reason,
+        }
+    }
+}
+
+impl Maintenance<'_> {
+    /// Totals of the tenants and streams with a limit on them, and of
+    /// those with rejected appends, all tenants included. The totals of a
+    /// tenant come before those of its streams.
+    #[instrument]
+    pub fn quota_usage(&self) -> Result<Vec<QuotaUsage>, Error> {
+        let backend = self.backend;
+        backend.authorize(Operation::Maintain, None)?;
+        let conn = backend.connection()?;
+        let mut stmt = conn.prepare(&backend.sql(
+            "SELECT tenant_id, aggregate_id, events, bytes, rejected FROM {quota_usage}
+                ORDER BY tenant_id, aggregate_id",
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(tenant_id, aggregate_id, events, bytes, rejected)| {
+                Ok(QuotaUsage {
+                    tenant_id,
+                    aggregate_id: match aggregate_id.as_str() {
+                        "" => None,
+                        id => Some(Uuid::parse_str(id).map_err(|_| Error::InvalidUUID)?),
+                    },
+                    events,
+                    bytes,
+                    rejected,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Key of the usage row of a stream, empty for the tenant totals.
+fn usage_key(aggregate_id: Option<Uuid>) -> String {
+    aggregate_id.map(|id| id.to_string()).unwrap_or_default()
+}
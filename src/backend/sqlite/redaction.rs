@@ -0,0 +1,90 @@
+use rusqlite::params;
+use serde_json::json;
+use tracing::instrument;
+
+use super::{Error, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::codec;
+use crate::encryption::FORGOTTEN;
+use crate::redaction::{Redaction, RedactionRecord};
+
+impl SqliteBackend {
+    /// Rewrite fields of the JSON payload of the event at position
+    /// `event_id` without touching the rest of the history, and record the
+    /// redaction in the audit log. Snapshots are not rewritten.
+    #[instrument]
+    pub fn redact_event(&self, event_id: u64, redaction: &Redaction) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let mut conn = self.secure_delete_connection()?;
+        let tx = conn.transaction()?;
+        let mut event = {
+            let mut stmt = tx.prepare(&self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ? AND position = ?",
+                EVENT_COLUMNS
+            )))?;
+            self.result_from_stmt_with_params(&tx, &mut stmt, &[&self.tenant_id(), &event_id])?
+                .pop()
+                .ok_or(Error::NotFound)?
+        };
+        self.authorize(Operation::Manage, Some(event.id))?;
+        if event.content_type != codec::JSON {
+            return Err(Error::Codec(format!(
+                "can not redact {} payloads",
+                event.content_type
+            )));
+        }
+        if event.metadata.contains_key(FORGOTTEN) {
+            return Err(Error::WithMsg(format!(
+                "subject of event {} has been forgotten",
+                event_id
+            )));
+        }
+        event.data = redaction.apply(&event.data)?;
+        let row = self.stored_row(&tx, &event)?;
+        tx.execute(
+            &self.sql("UPDATE {eventstore} SET data = ?, compression = ?, metadata = ?, key_id = ? WHERE position = ?"),
+            params![row.data, row.compression, row.metadata, row.key_id, event_id],
+        )?;
+        tx.execute(
+            &self.sql("INSERT INTO {redaction_audit}(tenant_id, position, fields, reason) VALUES(?, ?, ?, ?)"),
+            params![
+                self.tenant_id(),
+                event_id,
+                serde_json::to_string(&redaction.fields)?,
+                redaction.reason
+            ],
+        )?;
+        self.record_admin(
+            &tx,
+            "redact_event",
+            Some(event.id),
+            json!({ "position": event_id, "fields": redaction.fields, "reason": redaction.reason }),
+        )?;
+        tx.commit()?;
+        self.invalidate_cached([event.id]);
+        Ok(())
+    }
+
+    /// Audit records of the redactions applied to the event at `event_id`.
+    #[instrument]
+    pub fn get_redactions(&self, event_id: u64) -> Result<Vec<RedactionRecord>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(
+            &self.sql("SELECT position, fields, reason, redacted_at FROM {redaction_audit} WHERE tenant_id = ? AND position = ? ORDER BY id"),
+        )?;
+        let rows = stmt.query_map(params![self.tenant_id(), event_id], |r| {
+            Ok((r.get(0)?, r.get::<_, String>(1)?, r.get(2)?, r.get(3)?))
+        })?;
+        rows.map(|row| {
+            let (position, fields, reason, redacted_at) = row?;
+            Ok(RedactionRecord {
+                position,
+                fields: serde_json::from_str(&fields)?,
+                reason,
+                redacted_at,
+            })
+        })
+        .collect()
+    }
+}
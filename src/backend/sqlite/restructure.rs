@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashSet};
+
+use rusqlite::TransactionBehavior;
+use serde_json::json;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::{Error, Maintenance, Outcome, Written};
+use crate::authorization::Operation;
+use crate::backend::model::{Event, PROVENANCE};
+use crate::system::{self, StreamsCopied};
+
+impl Maintenance<'_> {
+    /// Copy the events of `source` to the streams `route` picks for them,
+    /// e.g. when an aggregate is cut into several. Events `route` maps to
+    /// `None` are not copied. Copies follow the current version of their
+    /// target in the order of `source` and record the event they were
+    /// copied from under `PROVENANCE`. Returns the number of events copied
+    /// per target.
+    ///
+    /// Runs in one transaction, `source` is left as it is, delete or
+    /// rename it once readers moved on. The copy is recorded in the admin
+    /// log and as `StreamsCopied` in the system streams.
+    #[instrument(skip(route))]
+    pub fn split_stream<F>(&self, source: Uuid, route: F) -> Result<BTreeMap<Uuid, usize>, Error>
+    where
+        F: Fn(&Event) -> Option<Uuid>,
+    {
+        self.copy_streams("split_stream", source, &[source], route)
+    }
+
+    /// Copy the events of `sources` into `target` in commit order, e.g. when
+    /// aggregates are combined. Like `split_stream` with every event routed
+    /// to `target`, returns the number of events copied.
+    #[instrument]
+    pub fn merge_streams(&self, sources: &[Uuid], target: Uuid) -> Result<usize, Error> {
+        let copied = self.copy_streams("merge_streams", target, sources, |_| Some(target))?;
+        Ok(copied.get(&target).copied().unwrap_or_default())
+    }
+
+    /// Copy as `split_stream` does, recorded in the admin log as
+    /// `operation` of `aggregate_id`.
+    fn copy_streams<F>(
+        &self,
+        operation: &str,
+        aggregate_id: Uuid,
+        sources: &[Uuid],
+        route: F,
+    ) -> Result<BTreeMap<Uuid, usize>, Error>
+    where
+        F: Fn(&Event) -> Option<Uuid>,
+    {
+        let backend = self.backend;
+        backend.ensure_writable()?;
+        backend.authorize(Operation::Maintain, None)?;
+        let mut conn = backend.connection()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut stored_sources = HashSet::new();
+        let mut events = Vec::new();
+        for source in sources {
+            let stored = backend.stored_id(&tx, *source)?;
+            if backend.is_deleted(&tx, stored)? {
+                return Err(Error::StreamDeleted(*source));
+            }
+            if stored_sources.insert(stored) {
+                events.extend(backend.read_stream(&tx, stored)?);
+            }
+        }
+        events.sort_by_key(|event| event.position);
+
+        let mut versions: BTreeMap<Uuid, u32> = BTreeMap::new();
+        let mut copied: BTreeMap<Uuid, usize> = BTreeMap::new();
+        let mut copies = Vec::new();
+        for event in events {
+            let Some(target) = route(&event) else {
+                continue;
+            };
+            let stored = backend.stored_id(&tx, target)?;
+            if stored_sources.contains(&stored) {
+                return Err(Error::WithMsg(format!(
+                    "cannot copy events into their source {}",
+                    target
+                )));
+            }
+            let version = match versions.get_mut(&stored) {
+                Some(version) => *version + 1,
+                None => backend.current_version(&tx, stored)? + 1,
+            };
+            versions.insert(stored, version);
+            let provenance = format!("{}@{}", event.version, event.id);
+            let mut copy = Event {
+                id: stored,
+                version,
+                position: 0,
+                ..event
+            };
+            copy.metadata.insert(PROVENANCE.to_string(), provenance);
+            copies.push(copy);
+            *copied.entry(target).or_default() += 1;
+        }
+        let mut written = match backend.write_events(&tx, &copies, |_| Ok(true))? {
+            Outcome::Written(written) => written,
+            Outcome::Skipped => Written::default(),
+            Outcome::Rejected(rejection) => {
+                drop(tx);
+                return Err(backend.reject(&conn, rejection)?);
+            }
+        };
+        backend.record_admin(
+            &tx,
+            operation,
+            Some(aggregate_id),
+            json!({ "sources": sources, "copied": copied }),
+        )?;
+        let event = StreamsCopied {
+            sources: sources.to_vec(),
+            copied: copied.clone(),
+        };
+        written.extend(backend.emit_system(&tx, system::STREAMS, &event)?);
+        tx.commit()?;
+        backend.committed(written);
+        info!(?sources, ?copied, "copied streams");
+        Ok(copied)
+    }
+}
@@ -0,0 +1,256 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use tracing::{debug, instrument, warn};
+use uuid::Uuid;
+
+use super::ids::read_id;
+use super::{quota, Error, Outcome, SqliteBackend, Written};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+use crate::encryption::FORGOTTEN;
+use crate::schedule::ScheduledEvent;
+
+/// Columns of a scheduled row, the first nine as `event_from_row` reads
+/// them.
+const SCHEDULED_COLUMNS: &str = "aggregate_id, data, 0 AS version, event_type, schema_version, content_type, metadata, compression, key_id, id, deliver_at, scheduled_at, error";
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Errors of the database that leave a due event scheduled, the next
+/// dispatch retries it. Other errors, also denied appends and exceeded
+/// quotas, mark the event as failed.
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Sqlite(_) | Error::R2D2Sqlite(_) | Error::ReadOnly
+    )
+}
+
+/// What became of a due event.
+enum Delivery {
+    Written(Written),
+    /// Its stream was deleted or its subject forgotten.
+    Dropped,
+    Rejected(quota::Rejection),
+}
+
+impl SqliteBackend {
+    /// Append `event` to its stream once `deliver_at` has passed, by
+    /// `dispatch_due` or a `schedule::Dispatcher`. The event follows the
+    /// version the stream is at then, the version of `event` is ignored.
+    /// Returns the id to `cancel_scheduled` it with.
+    ///
+    /// Payloads wait in the schedule compressed and encrypted like stored
+    /// events.
+    #[instrument(skip(event), fields(aggregate_id = %event.id))]
+    pub fn schedule_event(&self, event: &Event, deliver_at: SystemTime) -> Result<u64, Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Append, Some(event.id))?;
+        // the context of the scheduling handle, not the dispatching one
+        let enriched = self.enriched(std::slice::from_ref(event));
+        let event = &enriched[0];
+        self.validate_events(std::slice::from_ref(event))?;
+        let conn = self.connection()?;
+        let row = self.stored_row(&conn, event)?;
+        conn.execute(
+            &self.sql(
+                "INSERT INTO {scheduled_events}(tenant_id, aggregate_id, event_type, schema_version,
+                    content_type, data, metadata, compression, key_id, deliver_at, scheduled_at)
+                    VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            ),
+            params![
+                self.tenant_id(),
+                self.id_param(event.id),
+                event.event_type,
+                event.schema_version,
+                event.content_type,
+                row.data,
+                row.metadata,
+                row.compression,
+                row.key_id,
+                millis(deliver_at),
+                self.now_millis()
+            ],
+        )?;
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    /// Drop the scheduled event `id` before it is delivered, or after its
+    /// delivery failed.
+    #[instrument]
+    pub fn cancel_scheduled(&self, id: u64) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let conn = self.connection()?;
+        self.authorize(Operation::Append, Some(self.scheduled_stream(&conn, id)?))?;
+        conn.execute(
+            &self.sql("DELETE FROM {scheduled_events} WHERE tenant_id = ? AND id = ?"),
+            params![self.tenant_id(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Schedule the event `id` whose delivery failed again, e.g. once the
+    /// schema it violated was updated. It is delivered by the next dispatch
+    /// if it is due.
+    #[instrument]
+    pub fn retry_scheduled(&self, id: u64) -> Result<(), Error> {
+        self.ensure_writable()?;
+        let conn = self.connection()?;
+        self.authorize(Operation::Append, Some(self.scheduled_stream(&conn, id)?))?;
+        conn.execute(
+            &self.sql(
+                "UPDATE {scheduled_events} SET failed_at = NULL, error = NULL
+                    WHERE tenant_id = ? AND id = ?",
+            ),
+            params![self.tenant_id(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Stream of the scheduled event `id`.
+    fn scheduled_stream(&self, conn: &Connection, id: u64) -> Result<Uuid, Error> {
+        conn.query_row(
+            &self.sql("SELECT aggregate_id FROM {scheduled_events} WHERE tenant_id = ? AND id = ?"),
+            params![self.tenant_id(), id],
+            |r| Ok(read_id(r.get_ref(0)?)),
+        )
+        .optional()?
+        .ok_or(Error::NotFound)?
+    }
+
+    /// Events waiting for delivery, the next due first, and those whose
+    /// delivery failed.
+    #[instrument]
+    pub fn scheduled_events(&self) -> Result<Vec<ScheduledEvent>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(&self.sql(&format!(
+            "SELECT {} FROM {{scheduled_events}} WHERE tenant_id = ? ORDER BY deliver_at, id",
+            SCHEDULED_COLUMNS
+        )))?;
+        let rows = stmt.query_and_then(params![self.tenant_id()], |r| {
+            self.scheduled_from_row(&conn, r)
+        })?;
+        rows.collect()
+    }
+
+    fn scheduled_from_row(
+        &self,
+        conn: &Connection,
+        r: &rusqlite::Row,
+    ) -> Result<ScheduledEvent, Error> {
+        Ok(ScheduledEvent {
+            id: r.get("id")?,
+            event: self.event_from_row(conn, r)?,
+            deliver_at: r.get("deliver_at")?,
+            scheduled_at: r.get("scheduled_at")?,
+            error: r.get("error")?,
+        })
+    }
+
+    /// Append the scheduled events that are due, in the order they are due,
+    /// returns how many were appended. Each event is appended and removed
+    /// from the schedule in one transaction, so it is delivered once also
+    /// with several dispatchers. Events of deleted streams and forgotten
+    /// subjects are dropped. Events failing to append, e.g. on a schema
+    /// violation, a denied append or an exceeded quota, are marked as failed
+    /// with the error and skipped from then on, see `retry_scheduled`. On
+    /// errors of the database the event stays scheduled and dispatching
+    /// stops, the next call retries it.
+    #[instrument]
+    pub fn dispatch_due(&self) -> Result<usize, Error> {
+        self.ensure_writable()?;
+        let now = self.now_millis();
+        let mut dispatched = 0;
+        loop {
+            let mut conn = self.connection()?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let id: Option<u64> = tx
+                .query_row(
+                    &self.sql(
+                        "SELECT id FROM {scheduled_events}
+                            WHERE tenant_id = ? AND deliver_at <= ? AND failed_at IS NULL
+                            ORDER BY deliver_at, id LIMIT 1",
+                    ),
+                    params![self.tenant_id(), now],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            let Some(id) = id else {
+                return Ok(dispatched);
+            };
+            let err = match self.deliver(&tx, id) {
+                Ok(Delivery::Written(written)) => {
+                    tx.commit()?;
+                    self.committed(written);
+                    dispatched += 1;
+                    continue;
+                }
+                Ok(Delivery::Dropped) => {
+                    tx.commit()?;
+                    continue;
+                }
+                Ok(Delivery::Rejected(rejection)) => {
+                    drop(tx);
+                    self.reject(&conn, rejection)?
+                }
+                Err(err) if is_transient(&err) => return Err(err),
+                Err(err) => {
+                    drop(tx);
+                    err
+                }
+            };
+            warn!(id, error = %err, "delivery of scheduled event failed");
+            conn.execute(
+                &self.sql(
+                    "UPDATE {scheduled_events} SET failed_at = ?, error = ?
+                        WHERE tenant_id = ? AND id = ?",
+                ),
+                params![self.now_millis(), err.to_string(), self.tenant_id(), id],
+            )?;
+        }
+    }
+
+    /// Remove the scheduled event `id` and append it in `tx`.
+    fn deliver(&self, tx: &Transaction, id: u64) -> Result<Delivery, Error> {
+        let scheduled = tx.query_row_and_then(
+            &self.sql(&format!(
+                "SELECT {} FROM {{scheduled_events}} WHERE tenant_id = ? AND id = ?",
+                SCHEDULED_COLUMNS
+            )),
+            params![self.tenant_id(), id],
+            |r| self.scheduled_from_row(tx, r),
+        )?;
+        let mut event = scheduled.event;
+        self.authorize(Operation::Append, Some(event.id))?;
+        tx.execute(
+            &self.sql("DELETE FROM {scheduled_events} WHERE tenant_id = ? AND id = ?"),
+            params![self.tenant_id(), id],
+        )?;
+        if event.metadata.contains_key(FORGOTTEN) {
+            warn!(id, aggregate_id = %event.id, "dropped scheduled event of forgotten subject");
+            return Ok(Delivery::Dropped);
+        }
+        event.id = self.stored_id(tx, event.id)?;
+        match self.current_version(tx, event.id) {
+            Ok(version) => event.version = version + 1,
+            Err(Error::StreamDeleted(_)) => {
+                warn!(id, aggregate_id = %event.id, "dropped scheduled event of deleted stream");
+                return Ok(Delivery::Dropped);
+            }
+            Err(err) => return Err(err),
+        }
+        let written = match self.write_events(tx, std::slice::from_ref(&event), |_| Ok(true))? {
+            Outcome::Written(written) => written,
+            Outcome::Skipped => Written::default(),
+            Outcome::Rejected(rejection) => return Ok(Delivery::Rejected(rejection)),
+        };
+        debug!(id, aggregate_id = %event.id, version = event.version, "delivered scheduled event");
+        Ok(Delivery::Written(written))
+    }
+}
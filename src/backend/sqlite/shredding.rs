@@ -0,0 +1,78 @@
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use tracing::instrument;
+
+use super::{Error, SqliteBackend};
+use crate::authorization::Operation;
+use crate::encryption::{self, keys::DataKey};
+
+impl SqliteBackend {
+    /// Data key of `subject`, `None` once the subject was forgotten. A new
+    /// key is generated if `create` is set and the subject is unknown.
+    pub(super) fn subject_key(
+        &self,
+        conn: &rusqlite::Connection,
+        subject: &str,
+        create: bool,
+    ) -> Result<Option<DataKey>, Error> {
+        let stored: Option<(Option<Vec<u8>>, String)> = conn
+            .prepare_cached(&self.sql(
+                "SELECT data_key, key_id FROM {subject_key} WHERE tenant_id = ? AND subject_id = ?",
+            ))?
+            .query_row(params![self.tenant_id(), subject], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .optional()?;
+        match stored {
+            Some((Some(wrapped), key_id)) if key_id.is_empty() => {
+                encryption::keys::data_key(&wrapped).map(Some)
+            }
+            Some((Some(wrapped), key_id)) => {
+                encryption::keys::data_key(&self.unwrap(&key_id, &wrapped)?).map(Some)
+            }
+            Some((None, _)) => Ok(None),
+            None if create => {
+                let key = encryption::generate_key();
+                let (wrapped, key_id) = match &self.encryptor {
+                    Some(encryptor) => encryptor.encrypt(&key)?,
+                    None => (key.to_vec(), String::new()),
+                };
+                conn.execute(
+                    &self.sql(
+                        "INSERT INTO {subject_key}(tenant_id, subject_id, data_key, key_id) VALUES(?, ?, ?, ?)",
+                    ),
+                    params![self.tenant_id(), subject, wrapped, key_id],
+                )?;
+                Ok(Some(key))
+            }
+            // every stored event has a key row, treat a missing one as erased
+            None => Ok(None),
+        }
+    }
+
+    /// Crypto-shredding: destroy the data key of `subject`. Payloads of its
+    /// events become unreadable while the streams keep their versions, such
+    /// events are read with an empty payload and the `forgotten` metadata.
+    /// Appending further events for the subject fails.
+    ///
+    /// The key is overwritten with `secure_delete`, backups and WAL files
+    /// written before still contain it.
+    #[instrument]
+    pub fn forget(&self, subject_id: &str) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, None)?;
+        let mut conn = self.secure_delete_connection()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            &self.sql(
+                "INSERT INTO {subject_key}(tenant_id, subject_id, data_key, key_id) VALUES(?, ?, NULL, '')
+                ON CONFLICT(tenant_id, subject_id) DO UPDATE SET data_key = NULL, key_id = ''",
+            ),
+            params![self.tenant_id(), subject_id],
+        )?;
+        self.record_admin(&tx, "forget", None, json!({ "subject_id": subject_id }))?;
+        tx.commit()?;
+        self.clear_cached();
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::json;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+use super::ids::read_id;
+use super::{Error, IdFormat, SqliteBackend, Written};
+use crate::authorization::Operation;
+use crate::backend::model::Event;
+use crate::clock::Clock;
+use crate::stream::{DeleteMode, StreamInfo, StreamMetadata, TOMBSTONE};
+use crate::system::{self, StreamDeleted};
+
+/// Current time in milliseconds since the Unix epoch as an SQL expression,
+/// constant within one statement.
+const NOW_MILLIS_SQL: &str = "CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)";
+
+/// SQL function returning the configured clock's time in milliseconds
+/// since the Unix epoch.
+const NOW_FUNCTION: &str = "eventstore_now";
+
+/// SQL function identifying the clock `NOW_FUNCTION` reads.
+const CLOCK_FUNCTION: &str = "eventstore_clock";
+
+/// Define `NOW_FUNCTION` on `conn` reading `clock`, unless the connection
+/// already reads it. Pooled connections are shared by handles with
+/// different clocks, a connection registered with another clock is
+/// redefined, which expires its cached statements.
+pub(super) fn register_clock(conn: &Connection, clock: &Arc<dyn Clock>) -> Result<(), Error> {
+    // the registered functions keep their clock alive, so its address
+    // is not reused meanwhile
+    let id = Arc::as_ptr(clock) as *const () as usize as i64;
+    let registered = conn
+        .prepare_cached(&format!("SELECT {}()", CLOCK_FUNCTION))
+        .and_then(|mut stmt| stmt.query_row([], |row| row.get::<_, i64>(0)));
+    if registered.ok() == Some(id) {
+        return Ok(());
+    }
+    let now = AssertUnwindSafe(clock.clone());
+    conn.create_scalar_function(NOW_FUNCTION, 0, FunctionFlags::SQLITE_UTF8, move |_| {
+        Ok(now.now_millis())
+    })?;
+    let registered = AssertUnwindSafe(clock.clone());
+    conn.create_scalar_function(
+        CLOCK_FUNCTION,
+        0,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |_| Ok(Arc::as_ptr(&registered) as *const () as usize as i64),
+    )?;
+    Ok(())
+}
+
+impl SqliteBackend {
+    #[instrument]
+    pub fn set_stream_metadata(
+        &self,
+        aggregate_id: Uuid,
+        metadata: &StreamMetadata,
+    ) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, Some(aggregate_id))?;
+        let conn = self.connection()?;
+        let aggregate_id = self.stored_id(&conn, aggregate_id)?;
+        conn.execute(
+            &self.sql(
+                "INSERT INTO {stream_metadata}(tenant_id, aggregate_id, max_count, max_age_ms, truncate_before) VALUES(?, ?, ?, ?, ?)
+                    ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET max_count = excluded.max_count, max_age_ms = excluded.max_age_ms,
+                        truncate_before = excluded.truncate_before",
+            ),
+            params![
+                self.tenant_id(),
+                self.id_param(aggregate_id),
+                metadata.max_count,
+                metadata.max_age.map(|age| age.as_millis() as i64),
+                metadata.truncate_before
+            ],
+        )?;
+        self.invalidate_cached([aggregate_id]);
+        Ok(())
+    }
+
+    /// Settings of the stream, the defaults if none were stored.
+    #[instrument]
+    pub fn get_stream_metadata(&self, aggregate_id: Uuid) -> Result<StreamMetadata, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let (conn, aggregate_id) = self.stream_connection(aggregate_id)?;
+        let metadata = conn
+            .query_row(
+                &self.sql(
+                    "SELECT max_count, max_age_ms, truncate_before FROM {stream_metadata} WHERE tenant_id = ? AND aggregate_id = ?",
+                ),
+                params![self.tenant_id(), self.id_param(aggregate_id)],
+                |row| {
+                    Ok(StreamMetadata {
+                        max_count: row.get(0)?,
+                        max_age: row
+                            .get::<_, Option<i64>>(1)?
+                            .map(|ms| Duration::from_millis(ms as u64)),
+                        truncate_before: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(metadata.unwrap_or_default())
+    }
+
+    /// Streams of the tenant ordered by aggregate id, starting after
+    /// `after` so large stores can be listed page by page.
+    #[instrument]
+    pub fn list_streams(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<Vec<StreamInfo>, Error> {
+        self.authorize(Operation::Read, None)?;
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare(&self.sql(
+            "SELECT aggregate_id, version FROM {aggregate_index}
+                WHERE tenant_id = ? AND aggregate_id > ? ORDER BY aggregate_id LIMIT ?",
+        ))?;
+        // ids of both formats sort like their text form, the empty value
+        // of the format sorts first
+        let after = match (after, self.id_format()) {
+            (Some(id), _) => self.id_param(id),
+            (None, IdFormat::Text) => Value::Text(String::new()),
+            (None, IdFormat::Blob) => Value::Blob(Vec::new()),
+        };
+        let rows = stmt.query_and_then(params![self.tenant_id(), after, limit], |row| {
+            Ok(StreamInfo {
+                aggregate_id: read_id(row.get_ref(0)?)?,
+                version: row.get(1)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Version of the latest event of the stream, 0 if it has none.
+    #[instrument]
+    pub fn stream_version(&self, aggregate_id: Uuid) -> Result<u32, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let mut conn = self.connection()?;
+        let stored = self.stored_id(&conn, aggregate_id)?;
+        let tx = conn.transaction()?;
+        self.get_agg_max_version(&tx, &stored.to_string())
+    }
+
+    /// Hide the events of the stream before `version` from reads, e.g. once
+    /// a snapshot covers them, `Maintenance::scavenge` deletes them. Other
+    /// stream metadata is kept.
+    #[instrument]
+    pub fn truncate_before(&self, aggregate_id: Uuid, version: u32) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Manage, Some(aggregate_id))?;
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+        let aggregate_id = self.stored_id(&tx, aggregate_id)?;
+        tx.execute(
+            &self.sql(
+                "INSERT INTO {stream_metadata}(tenant_id, aggregate_id, truncate_before) VALUES(?, ?, ?)
+                    ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET truncate_before = excluded.truncate_before",
+            ),
+            params![self.tenant_id(), self.id_param(aggregate_id), version],
+        )?;
+        self.record_admin(
+            &tx,
+            "truncate_before",
+            Some(aggregate_id),
+            json!({ "version": version }),
+        )?;
+        tx.commit()?;
+        self.invalidate_cached([aggregate_id]);
+        Ok(())
+    }
+
+    /// Remove the events, snapshots, index entries, aliases, scheduled
+    /// events and quota totals of the stream in one transaction, the
+    /// aggregate id and its aliases can be used for new streams.
+    #[instrument]
+    pub fn delete_stream(&self, aggregate_id: Uuid) -> Result<(), Error> {
+        self.delete_stream_with(aggregate_id, DeleteMode::AllowRecreate)
+    }
+
+    /// Like `delete_stream`, `mode` decides whether the aggregate id may be
+    /// appended to again.
+    #[instrument]
+    pub fn delete_stream_with(&self, aggregate_id: Uuid, mode: DeleteMode) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Delete, Some(aggregate_id))?;
+        let mut conn = self.secure_delete_connection()?;
+        let tx = conn.transaction()?;
+        let aggregate_id = self.stored_id(&tx, aggregate_id)?;
+        let id = self.id_param(aggregate_id);
+        let bytes: u64 = tx.query_row(
+            &self.sql("SELECT COALESCE(SUM(LENGTH(data)), 0) FROM {eventstore} WHERE tenant_id = ? AND aggregate_id = ?"),
+            params![self.tenant_id(), id],
+            |row| row.get(0),
+        )?;
+        let events = tx.execute(
+            &self.sql("DELETE FROM {eventstore} WHERE tenant_id = ? AND aggregate_id = ?"),
+            params![self.tenant_id(), id],
+        )?;
+        self.release_usage(&tx, aggregate_id, events as u64, bytes)?;
+        // events scheduled for the stream under one of its aliases too
+        tx.execute(
+            &self.sql(
+                "DELETE FROM {scheduled_events} WHERE tenant_id = ?1 AND (aggregate_id = ?2
+                    OR aggregate_id IN (SELECT alias FROM {stream_aliases} WHERE tenant_id = ?1 AND aggregate_id = ?2))",
+            ),
+            params![self.tenant_id(), id],
+        )?;
+        for table in [
+            "{aggregate_index}",
+            "{snapshot}",
+            "{snapshot_index}",
+            "{latest_by_type}",
+        ] {
+            tx.execute(
+                &self.sql(&format!(
+                    "DELETE FROM {} WHERE tenant_id = ? AND aggregate_id = ?",
+                    table
+                )),
+                params![self.tenant_id(), id],
+            )?;
+        }
+        match mode {
+            DeleteMode::AllowRecreate => {
+                tx.execute(
+                    &self.sql("DELETE FROM {stream_aliases} WHERE tenant_id = ? AND aggregate_id = ?"),
+                    params![self.tenant_id(), id],
+                )?;
+                tx.execute(
+                    &self.sql(
+                        "DELETE FROM {stream_metadata} WHERE tenant_id = ? AND aggregate_id = ?",
+                    ),
+                    params![self.tenant_id(), id],
+                )?
+            }
+            // the aliases keep resolving to the deleted stream, which they
+            // may not be appended to either
+            DeleteMode::Forbid => tx.execute(
+                &self.sql(
+                    "INSERT INTO {stream_metadata}(tenant_id, aggregate_id, deleted) VALUES(?, ?, 1)
+                        ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET deleted = 1",
+                ),
+                params![self.tenant_id(), id],
+            )?,
+        };
+        self.record_admin(
+            &tx,
+            "delete_stream",
+            Some(aggregate_id),
+            json!({ "mode": format!("{:?}", mode), "events": events }),
+        )?;
+        let mut written = Written::default();
+        if !system::is_system_stream(aggregate_id) {
+            let deleted = StreamDeleted {
+                aggregate_id,
+                tombstoned: false,
+            };
+            written = self.emit_system(&tx, system::STREAMS, &deleted)?;
+        }
+        tx.commit()?;
+        self.invalidate_cached([aggregate_id]);
+        self.committed(written);
+        info!(%aggregate_id, events, ?mode, "deleted stream");
+        Ok(())
+    }
+
+    /// Close the stream by appending a `TOMBSTONE` event, the history stays
+    /// for auditing. Further appends fail with `Error::StreamDeleted`, so
+    /// does `get_aggretate` unless `get_aggretate_including_tombstoned` is
+    /// used. `read_all` keeps delivering the events and the tombstone.
+    #[instrument]
+    pub fn tombstone_stream(&self, aggregate_id: Uuid) -> Result<(), Error> {
+        self.ensure_writable()?;
+        self.authorize(Operation::Delete, Some(aggregate_id))?;
+        let mut conn = self.connection()?;
+        let tx = conn.transaction()?;
+        let aggregate_id = self.stored_id(&tx, aggregate_id)?;
+        let version = self.get_agg_max_version(&tx, &aggregate_id.to_string())?;
+        let mut written = self.write_exempt(
+            &tx,
+            &[Event {
+                id: aggregate_id,
+                version: version + 1,
+                event_type: TOMBSTONE.to_string(),
+                data: b"{}".to_vec(),
+                ..Default::default()
+            }],
+        )?;
+        tx.execute(
+            &self.sql(
+                "INSERT INTO {stream_metadata}(tenant_id, aggregate_id, deleted) VALUES(?, ?, 1)
+                    ON CONFLICT(tenant_id, aggregate_id) DO UPDATE SET deleted = 1",
+            ),
+            params![self.tenant_id(), self.id_param(aggregate_id)],
+        )?;
+        self.record_admin(
+            &tx,
+            "tombstone_stream",
+            Some(aggregate_id),
+            json!({ "version": version + 1 }),
+        )?;
+        if !system::is_system_stream(aggregate_id) {
+            let deleted = StreamDeleted {
+                aggregate_id,
+                tombstoned: true,
+            };
+            written.extend(self.emit_system(&tx, system::STREAMS, &deleted)?);
+        }
+        tx.commit()?;
+        self.committed(written);
+        info!(%aggregate_id, "tombstoned stream");
+        Ok(())
+    }
+
+    /// Whether the stream was tombstoned or deleted with `DeleteMode::Forbid`.
+    pub(super) fn is_deleted(&self, conn: &Connection, aggregate_id: Uuid) -> Result<bool, Error> {
+        Ok(conn
+            .prepare_cached(&self.sql(
+                "SELECT deleted FROM {stream_metadata} WHERE tenant_id = ? AND aggregate_id = ?",
+            ))?
+            .query_row(
+                params![self.tenant_id(), self.id_param(aggregate_id)],
+                |row| row.get::<_, bool>(0),
+            )
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    /// Milliseconds since the Unix epoch at which the first retained event
+    /// of the stored id `aggregate_id` expires by its `TTL` or the `max_age`
+    /// of the stream, `None` if none will.
+    pub(crate) fn stream_expiry(
+        &self,
+        conn: &Connection,
+        aggregate_id: Uuid,
+    ) -> Result<Option<i64>, Error> {
+        let retained = self.retained();
+        Ok(conn
+            .prepare_cached(&self.sql(&format!(
+                "SELECT MIN(expiry) FROM (
+                    SELECT {{eventstore}}.expires_at AS expiry FROM {{eventstore}}
+                        WHERE tenant_id = ?1 AND aggregate_id = ?2
+                        AND {{eventstore}}.expires_at IS NOT NULL AND {retained}
+                    UNION ALL
+                    SELECT {{eventstore}}.created_at + s.max_age_ms FROM {{eventstore}}
+                        JOIN {{stream_metadata}} s
+                            ON s.tenant_id = {{eventstore}}.tenant_id
+                            AND s.aggregate_id = {{eventstore}}.aggregate_id
+                        WHERE {{eventstore}}.tenant_id = ?1 AND {{eventstore}}.aggregate_id = ?2
+                        AND s.max_age_ms IS NOT NULL AND {{eventstore}}.created_at > 0
+                        AND {retained})",
+            )))?
+            .query_row(
+                params![self.tenant_id(), self.id_param(aggregate_id)],
+                |row| row.get(0),
+            )?)
+    }
+
+    /// Condition on `{eventstore}` rows matching the events not expired by
+    /// their `TTL` and still retained by the metadata of their stream. The
+    /// current time is taken in SQL so the statement text stays the same
+    /// and can be cached, from the clock of the backend if one is set.
+    pub(super) fn retained(&self) -> String {
+        let now = match self.clock {
+            Some(_) => format!("{}()", NOW_FUNCTION),
+            None => NOW_MILLIS_SQL.to_string(),
+        };
+        self.sql(&format!(
+            "(({{eventstore}}.expires_at IS NULL OR {{eventstore}}.expires_at > {now})
+            AND NOT EXISTS (SELECT 1 FROM {{stream_metadata}} m
+                LEFT JOIN {{aggregate_index}} i
+                    ON i.tenant_id = m.tenant_id AND i.aggregate_id = m.aggregate_id
+                WHERE m.tenant_id = {{eventstore}}.tenant_id
+                AND m.aggregate_id = {{eventstore}}.aggregate_id
+                AND ((m.max_count IS NOT NULL AND {{eventstore}}.version <= i.version - m.max_count)
+                    OR (m.truncate_before IS NOT NULL AND {{eventstore}}.version < m.truncate_before)
+                    OR (m.max_age_ms IS NOT NULL AND {{eventstore}}.created_at > 0
+                        AND {{eventstore}}.created_at <= {now} - m.max_age_ms))))",
+        ))
+    }
+}
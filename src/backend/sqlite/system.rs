@@ -0,0 +1,62 @@
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use uuid::Uuid;
+
+use super::{Error, SqliteBackend, Written};
+use crate::event::DomainEvent;
+use crate::system::{self, StreamCreated};
+
+impl SqliteBackend {
+    /// Append store lifecycle events to the system streams, see
+    /// `crate::system`.
+    pub fn with_system_streams(mut self) -> Self {
+        self.system_streams = true;
+        self
+    }
+
+    /// Record in `tx` that the stream `aggregate_id` got its first event.
+    pub(super) fn stream_created(
+        &self,
+        tx: &Transaction,
+        aggregate_id: Uuid,
+    ) -> Result<Written, Error> {
+        if !self.system_streams || system::is_system_stream(aggregate_id) {
+            return Ok(Written::default());
+        }
+        self.emit_system(tx, system::STREAMS, &StreamCreated { aggregate_id })
+    }
+
+    /// Append `event` to the system stream `stream` in `tx` if enabled,
+    /// pass the result to `committed` once `tx` is.
+    pub(super) fn emit_system<E: DomainEvent>(
+        &self,
+        tx: &Transaction,
+        stream: Uuid,
+        event: &E,
+    ) -> Result<Written, Error> {
+        if !self.system_streams {
+            return Ok(Written::default());
+        }
+        let mut event = self.encode(event)?;
+        event.id = stream;
+        event.version = self.current_version(tx, stream)? + 1;
+        self.write_exempt(tx, &[event])
+    }
+
+    /// Like `emit_system` in a transaction of its own on `conn`, for
+    /// changes committed in several.
+    pub(super) fn emit_system_now<E: DomainEvent>(
+        &self,
+        conn: &Connection,
+        stream: Uuid,
+        event: &E,
+    ) -> Result<(), Error> {
+        if !self.system_streams {
+            return Ok(());
+        }
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        let written = self.emit_system(&tx, stream, event)?;
+        tx.commit()?;
+        self.committed(written);
+        Ok(())
+    }
+}
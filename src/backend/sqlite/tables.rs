@@ -0,0 +1,114 @@
+use super::Error;
+
+/// Names of the tables used by the store, so several bounded contexts can
+/// share one database file. Index names are derived from the table names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tables {
+    pub eventstore: String,
+    pub aggregate_index: String,
+    pub snapshot: String,
+    pub snapshot_index: String,
+    pub projection_checkpoint: String,
+    pub compression_dictionary: String,
+    pub subject_key: String,
+    pub redaction_audit: String,
+    pub stream_metadata: String,
+    pub schema_migrations: String,
+    pub admin_log: String,
+    pub quarantine: String,
+    pub scheduled_events: String,
+    pub processed_commands: String,
+    pub quota_usage: String,
+    pub stream_aliases: String,
+    pub latest_by_type: String,
+    pub event_counts: String,
+    pub lag_samples: String,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self::with_prefix("")
+    }
+}
+
+impl Tables {
+    /// Default table names prefixed with `prefix`, e.g. `billing_` gives
+    /// `billing_eventstore`.
+    pub fn with_prefix(prefix: &str) -> Self {
+        let name = |table: &str| format!("{}{}", prefix, table);
+        Self {
+            eventstore: name("eventstore"),
+            aggregate_index: name("aggregate_index"),
+            snapshot: name("snapshot"),
+            snapshot_index: name("snapshot_index"),
+            projection_checkpoint: name("projection_checkpoint"),
+            compression_dictionary: name("compression_dictionary"),
+            subject_key: name("subject_key"),
+            redaction_audit: name("redaction_audit"),
+            stream_metadata: name("stream_metadata"),
+            schema_migrations: name("schema_migrations"),
+            admin_log: name("admin_log"),
+            quarantine: name("quarantine"),
+            scheduled_events: name("scheduled_events"),
+            processed_commands: name("processed_commands"),
+            quota_usage: name("quota_usage"),
+            stream_aliases: name("stream_aliases"),
+            latest_by_type: name("latest_by_type"),
+            event_counts: name("event_counts"),
+            lag_samples: name("lag_samples"),
+        }
+    }
+
+    fn names(&self) -> [(&'static str, &str); 19] {
+        [
+            ("{eventstore}", &self.eventstore),
+            ("{aggregate_index}", &self.aggregate_index),
+            ("{snapshot}", &self.snapshot),
+            ("{snapshot_index}", &self.snapshot_index),
+            ("{projection_checkpoint}", &self.projection_checkpoint),
+            ("{compression_dictionary}", &self.compression_dictionary),
+            ("{subject_key}", &self.subject_key),
+            ("{redaction_audit}", &self.redaction_audit),
+            ("{stream_metadata}", &self.stream_metadata),
+            ("{schema_migrations}", &self.schema_migrations),
+            ("{admin_log}", &self.admin_log),
+            ("{quarantine}", &self.quarantine),
+            ("{scheduled_events}", &self.scheduled_events),
+            ("{processed_commands}", &self.processed_commands),
+            ("{quota_usage}", &self.quota_usage),
+            ("{stream_aliases}", &self.stream_aliases),
+            ("{latest_by_type}", &self.latest_by_type),
+            ("{event_counts}", &self.event_counts),
+            ("{lag_samples}", &self.lag_samples),
+        ]
+    }
+
+    /// Names are interpolated into SQL, so only plain identifiers are
+    /// accepted and no two tables may share a name.
+    pub fn validate(&self) -> Result<(), Error> {
+        let names = self.names();
+        for (i, (_, name)) in names.iter().enumerate() {
+            let valid = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(Error::WithMsg(format!("invalid table name: {:?}", name)));
+            }
+            if names[..i].iter().any(|(_, other)| other == name) {
+                return Err(Error::WithMsg(format!("duplicate table name: {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace `{table}` placeholders in `template` with the table names.
+    pub(crate) fn sql(&self, template: &str) -> String {
+        self.names()
+            .iter()
+            .fold(template.to_string(), |sql, (placeholder, name)| {
+                sql.replace(placeholder, name)
+            })
+    }
+}
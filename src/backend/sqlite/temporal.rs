@@ -0,0 +1,72 @@
+use std::time::SystemTime;
+
+use rusqlite::ToSql;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{aliases, Error, SqliteBackend, EVENT_COLUMNS};
+use crate::authorization::Operation;
+use crate::backend::model::{unix_millis, Event};
+
+impl SqliteBackend {
+    /// The events of `aggregate_id` in effect at `valid_at`, see
+    /// `Event::with_valid_time`, as recorded now. Events are ordered by the
+    /// start of their valid time, then by version, so backdated corrections
+    /// apply before the events they correct.
+    #[instrument]
+    pub fn get_aggregate_valid_at(
+        &self,
+        aggregate_id: Uuid,
+        valid_at: SystemTime,
+    ) -> Result<Vec<Event>, Error> {
+        self.valid_events(aggregate_id, valid_at, None)
+    }
+
+    /// Like `get_aggregate_valid_at` with the events appended until
+    /// `recorded_at` only, i.e. what was known about `valid_at` back then.
+    #[instrument]
+    pub fn get_aggregate_bitemporal(
+        &self,
+        aggregate_id: Uuid,
+        valid_at: SystemTime,
+        recorded_at: SystemTime,
+    ) -> Result<Vec<Event>, Error> {
+        self.valid_events(aggregate_id, valid_at, Some(recorded_at))
+    }
+
+    fn valid_events(
+        &self,
+        aggregate_id: Uuid,
+        valid_at: SystemTime,
+        recorded_at: Option<SystemTime>,
+    ) -> Result<Vec<Event>, Error> {
+        self.authorize(Operation::Read, Some(aggregate_id))?;
+        let conn = self.connection()?;
+        let stored = self.stored_id(&conn, aggregate_id)?;
+        if self.is_deleted(&conn, stored)? {
+            return Err(Error::StreamDeleted(aggregate_id));
+        }
+        let mut stmt = conn.prepare_cached(&format!(
+            "{} AND {} ORDER BY COALESCE(valid_from, created_at), version",
+            self.sql(&format!(
+                "SELECT {} FROM {{eventstore}} WHERE tenant_id = ?1 AND aggregate_id = ?2
+                    AND COALESCE(valid_from, created_at) <= ?3 AND (valid_to IS NULL OR valid_to > ?3)
+                    AND (?4 IS NULL OR created_at <= ?4)",
+                EVENT_COLUMNS
+            )),
+            self.retained()
+        ))?;
+        let params: [&dyn ToSql; 4] = [
+            &self.tenant_id(),
+            &self.id_param(stored),
+            &unix_millis(valid_at),
+            &recorded_at.map(unix_millis),
+        ];
+        let events = self.result_from_stmt_with_params(&conn, &mut stmt, &params)?;
+        let events = self.decoded(events)?;
+        match stored == aggregate_id {
+            true => Ok(events),
+            false => Ok(aliases::relabel(events, aggregate_id)),
+        }
+    }
+}
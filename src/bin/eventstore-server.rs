@@ -0,0 +1,41 @@
+//! gRPC server for an eventstore-rs database file, serving `GrpcService`
+//! and the ESDB compatible `EsdbService`.
+//!
+//! ```text
+//! eventstore-server <database> [listen address, default 127.0.0.1:50051]
+//! ```
+
+use eventstore::backend::sqlite::SqliteBackend;
+use eventstore::grpc::esdb::EsdbService;
+use eventstore::grpc::GrpcService;
+use r2d2_sqlite::SqliteConnectionManager;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+    let mut args = std::env::args().skip(1);
+    let Some(database) = args.next() else {
+        eprintln!("usage: eventstore-server <database> [listen address]");
+        std::process::exit(2);
+    };
+    let addr = args
+        .next()
+        .unwrap_or_else(|| "127.0.0.1:50051".to_string())
+        .parse()?;
+    let backend = SqliteBackend::try_with_tables(
+        SqliteConnectionManager::file(&database),
+        Default::default(),
+    )?;
+    info!(%addr, database, "serving");
+    tonic::transport::Server::builder()
+        .add_service(GrpcService::new(backend.clone()).into_server())
+        .add_service(EsdbService::new(backend).into_server())
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
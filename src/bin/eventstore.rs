@@ -0,0 +1,314 @@
+//! Command line tool for inspecting and maintaining an eventstore-rs
+//! database file.
+//!
+//! ```text
+//! eventstore --db store.db list-streams
+//! eventstore --db store.db read <aggregate id>
+//! eventstore --db store.db append <aggregate id> <event type> '{"amount": 5}'
+//! eventstore --db store.db export > events.jsonl
+//! eventstore --db copy.db import events.jsonl
+//! eventstore --db store.db stats
+//! eventstore --db store.db scavenge
+//! eventstore --db store.db verify
+//! eventstore --db store.db browse
+//! eventstore --db demo.db seed --aggregates 10000 --events 100 --payload-bytes 512
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use eventstore::backend::model::Event;
+use eventstore::backend::sqlite::{Error, ScavengeOpts, SqliteBackend};
+use eventstore::jsonl::{Envelope, ExportOpts, ImportOpts};
+use eventstore::testing::SeedOpts;
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+#[derive(Parser)]
+#[command(
+    name = "eventstore",
+    about = "Inspect and maintain an eventstore database file"
+)]
+struct Cli {
+    /// Path of the database file.
+    #[arg(long)]
+    db: PathBuf,
+    /// Tenant to operate on, the default tenant if omitted.
+    #[arg(long, default_value = "")]
+    tenant: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List streams with their latest version.
+    ListStreams {
+        /// Only list streams after this aggregate id.
+        #[arg(long)]
+        after: Option<Uuid>,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Print the events of a stream as JSON lines.
+    Read {
+        aggregate_id: Uuid,
+        /// Only print events after this version.
+        #[arg(long, default_value_t = 0)]
+        since_version: u32,
+    },
+    /// Append a JSON event to a stream.
+    Append {
+        aggregate_id: Uuid,
+        event_type: String,
+        /// JSON payload, read from stdin if omitted.
+        data: Option<String>,
+        /// Fail unless the stream is at this version.
+        #[arg(long)]
+        expected_version: Option<u32>,
+    },
+    /// Write events as JSON lines to a file or stdout.
+    Export {
+        /// Output file, stdout if omitted.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Export events after this position.
+        #[arg(long, default_value_t = 0)]
+        from_position: u64,
+        /// Only export these aggregates.
+        #[arg(long = "aggregate")]
+        aggregates: Vec<Uuid>,
+        /// Only export these event types.
+        #[arg(long = "event-type")]
+        event_types: Vec<String>,
+    },
+    /// Import events from a JSON lines file or stdin.
+    Import {
+        /// Input file, stdin if omitted.
+        input: Option<PathBuf>,
+        /// Rebuild indexes once at the end, for restores into an idle store.
+        #[arg(long)]
+        bulk: bool,
+    },
+    /// Print event counts and file size.
+    Stats,
+    /// Delete events hidden by retention, truncation or deletion.
+    Scavenge {
+        /// Also delete the history of tombstoned streams.
+        #[arg(long)]
+        purge_tombstoned: bool,
+    },
+    /// Check the file and the stream indexes, exit with 1 on problems.
+    Verify,
+    /// Browse streams and events in a terminal UI.
+    #[cfg(feature = "tui")]
+    Browse,
+    /// Fill the store with generated streams for demos and benchmarks.
+    Seed {
+        #[arg(long, default_value_t = 1_000)]
+        aggregates: usize,
+        /// Events per aggregate.
+        #[arg(long, default_value_t = 100)]
+        events: u32,
+        /// Approximate size of the JSON payloads.
+        #[arg(long, default_value_t = 256)]
+        payload_bytes: usize,
+        /// Seed of the aggregate ids, use another one to add more streams
+        /// to a seeded store.
+        #[arg(long, default_value_t = 0x5eed)]
+        seed: u64,
+    },
+}
+
+impl Command {
+    fn writes(&self) -> bool {
+        matches!(
+            self,
+            Command::Append { .. }
+                | Command::Import { .. }
+                | Command::Scavenge { .. }
+                | Command::Seed { .. }
+        )
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn open(cli: &Cli) -> Result<SqliteBackend, Error> {
+    let backend = if cli.command.writes() {
+        SqliteBackend::try_with_tables(SqliteConnectionManager::file(&cli.db), Default::default())?
+    } else {
+        SqliteBackend::read_only(&cli.db)?
+    };
+    Ok(backend.tenant(&cli.tenant))
+}
+
+fn print_events(events: &[Event]) -> Result<(), Error> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    for event in events {
+        serde_json::to_writer(&mut out, &Envelope::from_event(event))?;
+        writeln!(out).map_err(io_error)?;
+    }
+    out.flush().map_err(io_error)
+}
+
+fn io_error(err: io::Error) -> Error {
+    Error::WithMsg(err.to_string())
+}
+
+fn run(cli: Cli) -> Result<ExitCode, Error> {
+    let backend = open(&cli)?;
+    match cli.command {
+        Command::ListStreams { after, limit } => {
+            for stream in backend.list_streams(after, limit)? {
+                println!("{}\t{}", stream.aggregate_id, stream.version);
+            }
+        }
+        Command::Read {
+            aggregate_id,
+            since_version,
+        } => {
+            let events = backend.get_aggretate(aggregate_id)?;
+            let events: Vec<_> = events
+                .into_iter()
+                .filter(|event| event.version > since_version)
+                .collect();
+            print_events(&events)?;
+        }
+        Command::Append {
+            aggregate_id,
+            event_type,
+            data,
+            expected_version,
+        } => {
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    let mut data = String::new();
+                    io::stdin().read_to_string(&mut data).map_err(io_error)?;
+                    data
+                }
+            };
+            let data: serde_json::Value = serde_json::from_str(&data)?;
+            let version = backend.stream_version(aggregate_id)?;
+            if expected_version.is_some_and(|expected| expected != version) {
+                return Err(Error::WithMsg(format!(
+                    "stream {} is at version {}",
+                    aggregate_id, version
+                )));
+            }
+            let event = Event {
+                id: aggregate_id,
+                version: version + 1,
+                event_type,
+                data: serde_json::to_vec(&data)?,
+                ..Default::default()
+            };
+            backend.append_event(&event)?;
+            println!("{}", event.version);
+        }
+        Command::Export {
+            output,
+            from_position,
+            aggregates,
+            event_types,
+        } => {
+            let opts = ExportOpts {
+                from_position,
+                aggregates,
+                event_types,
+                ..Default::default()
+            };
+            let exported = match output {
+                Some(path) => {
+                    let mut file = BufWriter::new(File::create(path).map_err(io_error)?);
+                    let exported = backend.export_jsonl(&mut file, &opts)?;
+                    file.flush().map_err(io_error)?;
+                    exported
+                }
+                None => backend.export_jsonl(&mut io::stdout().lock(), &opts)?,
+            };
+            eprintln!("exported {} events", exported);
+        }
+        Command::Import { input, bulk } => {
+            let reader: Box<dyn BufRead> = match input {
+                Some(path) => Box::new(BufReader::new(File::open(path).map_err(io_error)?)),
+                None => Box::new(io::stdin().lock()),
+            };
+            let opts = if bulk {
+                ImportOpts::default().bulk_load()
+            } else {
+                ImportOpts::default()
+            };
+            let summary = backend.import_jsonl(reader, &opts)?;
+            println!(
+                "imported {} events, skipped {}",
+                summary.imported, summary.skipped
+            );
+        }
+        Command::Stats => {
+            let stats = backend.maintenance().stats()?;
+            println!("schema version\t{}", stats.schema_version);
+            println!("events\t{}", stats.events);
+            println!("streams\t{}", stats.streams);
+            println!("snapshots\t{}", stats.snapshots);
+            println!("head position\t{}", stats.head_position);
+            println!("size bytes\t{}", stats.size_bytes);
+            println!("free bytes\t{}", stats.free_bytes);
+            println!("quota rejected\t{}", stats.quota_rejected);
+        }
+        Command::Scavenge { purge_tombstoned } => {
+            let report = backend.maintenance().scavenge_with(&ScavengeOpts {
+                purge_tombstoned,
+                ..Default::default()
+            })?;
+            println!(
+                "deleted {} events and {} snapshots, reclaimed {} bytes",
+                report.deleted_events, report.deleted_snapshots, report.reclaimed_bytes
+            );
+        }
+        Command::Verify => {
+            let report = backend.maintenance().integrity_check()?;
+            for problem in &report.sqlite {
+                println!("sqlite: {}", problem);
+            }
+            for issue in &report.issues {
+                println!("{:?}", issue);
+            }
+            if !report.is_ok() {
+                return Ok(ExitCode::from(1));
+            }
+            println!("ok");
+        }
+        #[cfg(feature = "tui")]
+        Command::Browse => eventstore::tui::run(backend)?,
+        Command::Seed {
+            aggregates,
+            events,
+            payload_bytes,
+            seed,
+        } => {
+            let opts = SeedOpts {
+                aggregates,
+                events_per_aggregate: events,
+                payload_bytes,
+                seed,
+            };
+            let seeded = eventstore::testing::seed(&backend, &opts)?;
+            println!("seeded {} events in {} streams", seeded, aggregates);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,194 @@
+//! Size-bounded in-memory cache of hydrated aggregates, enabled with
+//! `SqliteBackend::with_read_cache`:
+//!
+//! ```ignore
+//! let backend = SqliteBackend::new(manager).with_read_cache(10_000);
+//! // served from SQLite once, then from memory until the next append
+//! let events = backend.get_aggretate(aggregate_id)?;
+//! // folded state, for aggregates implementing Clone
+//! let (cart, version) = Repository::<Cart>::new(backend).load_cached(aggregate_id)?;
+//! ```
+//!
+//! Entries are dropped when the stream is appended to, truncated, deleted
+//! or redacted through a handle sharing the cache, when the first of its
+//! events expires by its `TTL` or the stream's `max_age`, and the least
+//! recently used entries once the capacity is reached. Writes of other
+//! processes to the same database file are not seen.
+
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use uuid::Uuid;
+
+type Key = (Arc<str>, Uuid, TypeId);
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    last_used: u64,
+    /// Milliseconds since the Unix epoch from which the value is stale.
+    expires_at: Option<i64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// Keys by last use, the first one is evicted next.
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+    /// Bumped on every invalidation, values loaded before are not stored.
+    generation: u64,
+}
+
+/// Values derived from the events of a stream, e.g. the event list or an
+/// aggregate folded from it, keyed by tenant, aggregate id and type.
+pub struct AggregateCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+impl std::fmt::Debug for AggregateCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AggregateCache")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl AggregateCache {
+    /// Cache holding at most `capacity` values.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached value of type `T` for the stream, or the one returned by
+    /// `load`, which is cached unless the stream changed while loading.
+    pub fn get_or_load<T, E, F>(&self, tenant_id: &str, aggregate_id: Uuid, load: F) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Result<T, E>,
+    {
+        self.get_or_load_until(tenant_id, aggregate_id, 0, || Ok((load()?, None)))
+    }
+
+    /// Like `get_or_load` for values going stale at a point in time: `load`
+    /// also returns the milliseconds since the Unix epoch from which its
+    /// value is stale, if ever, and cached values are loaded again once
+    /// `now_millis` reaches it.
+    pub fn get_or_load_until<T, E, F>(
+        &self,
+        tenant_id: &str,
+        aggregate_id: Uuid,
+        now_millis: i64,
+        load: F,
+    ) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: FnOnce() -> Result<(T, Option<i64>), E>,
+    {
+        let key: Key = (Arc::from(tenant_id), aggregate_id, TypeId::of::<T>());
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(value) = inner.get(&key, now_millis) {
+                if let Some(value) = value.downcast_ref::<T>() {
+                    return Ok(value.clone());
+                }
+            }
+            inner.generation
+        };
+        let (value, expires_at) = load()?;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.insert(key, Arc::new(value.clone()), expires_at, self.capacity);
+        }
+        Ok(value)
+    }
+
+    /// Drop the values of the stream.
+    pub fn invalidate(&self, tenant_id: &str, aggregate_id: Uuid) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        let keys: Vec<Key> = inner
+            .entries
+            .keys()
+            .filter(|(tenant, id, _)| *id == aggregate_id && &**tenant == tenant_id)
+            .cloned()
+            .collect();
+        for key in keys {
+            inner.remove(&key);
+        }
+    }
+
+    /// Drop all values, e.g. after changes affecting many streams.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.lru.clear();
+    }
+}
+
+impl Inner {
+    fn get(&mut self, key: &Key, now_millis: i64) -> Option<Arc<dyn Any + Send + Sync>> {
+        let expires_at = self.entries.get(key)?.expires_at;
+        if expires_at.is_some_and(|expires_at| expires_at <= now_millis) {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.lru.insert(tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    fn insert(
+        &mut self,
+        key: Key,
+        value: Arc<dyn Any + Send + Sync>,
+        expires_at: Option<i64>,
+        capacity: usize,
+    ) {
+        self.remove(&key);
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                last_used: self.tick,
+                expires_at,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Source of the times the store stamps on rows and compares them with,
+//! e.g. `created_at` of events and the `max_age` retention of streams.
+//!
+//! Backends read the system clock unless one is configured, tests move a
+//! `ManualClock` instead of sleeping:
+//!
+//! ```ignore
+//! let clock = ManualClock::new(SystemTime::now());
+//! let backend = SqliteBackend::new(manager).with_clock(clock.clone());
+//! // ...
+//! clock.advance(Duration::from_secs(3600));
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Current wall-clock time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// `now` in milliseconds since the Unix epoch, as stored in the
+    /// database.
+    fn now_millis(&self) -> i64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as i64)
+            .unwrap_or_default()
+    }
+}
+
+/// The clock of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner()) = now;
+    }
+
+    /// Move the clock `by` into the future.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|err| err.into_inner());
+        *now += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::backend::{model::Event, sqlite::Error};
+
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+pub const JSON: &str = "application/json";
+pub const CBOR: &str = "application/cbor";
+pub const BINCODE: &str = "application/x-bincode";
+pub const MSGPACK: &str = "application/msgpack";
+
+/// Serializes event payloads, identified by the content type stored with
+/// every event.
+pub trait EventCodec {
+    fn content_type(&self) -> &'static str;
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl EventCodec for JsonCodec {
+    fn content_type(&self) -> &'static str {
+        JSON
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl EventCodec for CborCodec {
+    fn content_type(&self) -> &'static str {
+        CBOR
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(value, &mut data)
+            .map_err(|err| Error::Codec(err.to_string()))?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        ciborium::de::from_reader(data).map_err(|err| Error::Codec(err.to_string()))
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl EventCodec for BincodeCodec {
+    fn content_type(&self) -> &'static str {
+        BINCODE
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        bincode::serialize(value).map_err(|err| Error::Codec(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(data).map_err(|err| Error::Codec(err.to_string()))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl EventCodec for MessagePackCodec {
+    fn content_type(&self) -> &'static str {
+        MSGPACK
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(value).map_err(|err| Error::Codec(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(data).map_err(|err| Error::Codec(err.to_string()))
+    }
+}
+
+/// The built-in codecs, selected when constructing the backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Json,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "bincode")]
+    Bincode,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl Codec {
+    /// Codec for a stored content type, used to read events written with a
+    /// different codec than the current one.
+    pub fn for_content_type(content_type: &str) -> Result<Codec, Error> {
+        match content_type {
+            JSON => Ok(Codec::Json),
+            #[cfg(feature = "cbor")]
+            CBOR => Ok(Codec::Cbor),
+            #[cfg(feature = "bincode")]
+            BINCODE => Ok(Codec::Bincode),
+            #[cfg(feature = "msgpack")]
+            MSGPACK => Ok(Codec::MessagePack),
+            other => Err(Error::Codec(format!("unsupported content type: {}", other))),
+        }
+    }
+}
+
+impl EventCodec for Codec {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Codec::Json => JsonCodec.content_type(),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.content_type(),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => BincodeCodec.content_type(),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.content_type(),
+        }
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Codec::Json => JsonCodec.encode(value),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.encode(value),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => BincodeCodec.encode(value),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        match self {
+            Codec::Json => JsonCodec.decode(data),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.decode(data),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => BincodeCodec.decode(data),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.decode(data),
+        }
+    }
+}
+
+/// Converts payloads no serde codec understands into JSON, so typed reads
+/// can consume them, e.g. protobuf messages in a mostly JSON history.
+pub trait Transcoder: Send + Sync {
+    fn to_json(&self, event: &Event) -> Result<Vec<u8>, Error>;
+}
+
+impl<F> Transcoder for F
+where
+    F: Fn(&Event) -> Result<Vec<u8>, Error> + Send + Sync,
+{
+    fn to_json(&self, event: &Event) -> Result<Vec<u8>, Error> {
+        self(event)
+    }
+}
+
+/// Transcoders keyed by content type and event type.
+#[derive(Default, Clone)]
+pub struct Transcoders {
+    transcoders: HashMap<(String, String), Arc<dyn Transcoder>>,
+}
+
+impl Transcoders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<T: Transcoder + 'static>(
+        &mut self,
+        content_type: &str,
+        event_type: &str,
+        transcoder: T,
+    ) {
+        self.transcoders.insert(
+            (content_type.to_string(), event_type.to_string()),
+            Arc::new(transcoder),
+        );
+    }
+
+    pub fn get(&self, content_type: &str, event_type: &str) -> Option<&dyn Transcoder> {
+        self.transcoders
+            .get(&(content_type.to_string(), event_type.to_string()))
+            .map(|transcoder| transcoder.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transcoders.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Transcoders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.transcoders.keys()).finish()
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use apache_avro::{
+    rabin::Rabin, reader::datum::GenericDatumReader, writer::datum::GenericDatumWriter, Schema,
+};
+
+use crate::backend::{model::Event, sqlite::Error};
+use crate::event::DomainEvent;
+
+pub const AVRO: &str = "application/avro";
+
+/// Metadata key holding the Rabin fingerprint of the writer schema.
+pub const FINGERPRINT: &str = "avro_fingerprint";
+
+fn avro_error(err: apache_avro::Error) -> Error {
+    Error::Codec(err.to_string())
+}
+
+/// Local store of Avro schemas keyed by their fingerprint.
+///
+/// Payloads are written without the schema, only its fingerprint is stored
+/// in the event metadata. On read the writer schema is looked up here and
+/// resolved against the schema the reader expects, so fields may be added
+/// or removed following the Avro schema evolution rules.
+#[derive(Default, Clone, Debug)]
+pub struct AvroSchemaStore {
+    schemas: HashMap<String, Schema>,
+}
+
+impl AvroSchemaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fingerprint(schema: &Schema) -> String {
+        schema.fingerprint::<Rabin>().to_string()
+    }
+
+    /// Add a schema, returns its fingerprint.
+    pub fn register(&mut self, schema: Schema) -> String {
+        let fingerprint = Self::fingerprint(&schema);
+        self.schemas.insert(fingerprint.clone(), schema);
+        fingerprint
+    }
+
+    /// Parse and add a schema in JSON notation, returns its fingerprint.
+    pub fn register_str(&mut self, schema: &str) -> Result<String, Error> {
+        Ok(self.register(Schema::parse_str(schema).map_err(avro_error)?))
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&Schema> {
+        self.schemas.get(fingerprint)
+    }
+
+    /// Serialize `event` with the schema registered under `fingerprint`.
+    pub fn encode<E: DomainEvent>(&self, fingerprint: &str, event: &E) -> Result<Event, Error> {
+        let schema = self
+            .get(fingerprint)
+            .ok_or_else(|| Error::Codec(format!("unknown avro schema {}", fingerprint)))?;
+        let value = apache_avro::to_value(event).map_err(avro_error)?;
+        let mut encoded = Event {
+            event_type: E::event_type().to_string(),
+            schema_version: E::schema_version(),
+            content_type: AVRO.to_string(),
+            data: GenericDatumWriter::builder(schema)
+                .build()
+                .and_then(|writer| writer.write_value_to_vec(value))
+                .map_err(avro_error)?,
+            ..Default::default()
+        };
+        encoded
+            .metadata
+            .insert(FINGERPRINT.to_string(), fingerprint.to_string());
+        Ok(encoded)
+    }
+
+    /// Deserialize an Avro payload, resolving the writer schema against the
+    /// schema registered under `reader_fingerprint`.
+    pub fn decode<E: DomainEvent>(
+        &self,
+        reader_fingerprint: &str,
+        event: &Event,
+    ) -> Result<E, Error> {
+        if event.content_type != AVRO {
+            return Err(Error::Codec(format!(
+                "expected {} but got {}",
+                AVRO, event.content_type
+            )));
+        }
+        if event.event_type != E::event_type() {
+            return Err(Error::UnexpectedEventType {
+                expected: E::event_type().to_string(),
+                actual: event.event_type.clone(),
+            });
+        }
+        let writer_fingerprint = event
+            .metadata
+            .get(FINGERPRINT)
+            .ok_or_else(|| Error::Codec("missing avro schema fingerprint".to_string()))?;
+        let writer = self
+            .get(writer_fingerprint)
+            .ok_or_else(|| Error::Codec(format!("unknown avro schema {}", writer_fingerprint)))?;
+        let reader = self
+            .get(reader_fingerprint)
+            .ok_or_else(|| Error::Codec(format!("unknown avro schema {}", reader_fingerprint)))?;
+        let value = GenericDatumReader::builder(writer)
+            .reader_schema(reader)
+            .build()
+            .and_then(|datum_reader| datum_reader.read_value(&mut event.data.as_slice()))
+            .map_err(avro_error)?;
+        apache_avro::from_value(&value).map_err(avro_error)
+    }
+}
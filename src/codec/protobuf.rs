@@ -0,0 +1,87 @@
+use prost::{Message, Name};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::backend::{
+    model::Event,
+    sqlite::{Error, SqliteBackend},
+};
+use crate::codec::Transcoders;
+
+pub const PROTOBUF: &str = "application/x-protobuf";
+
+/// Metadata key holding the fully qualified type URL of the message.
+pub const TYPE_URL: &str = "type_url";
+
+impl Event {
+    /// Serialize a prost message. The event type is the full message name,
+    /// the type URL is stored in the metadata.
+    pub fn encode_message<M: Message + Name>(message: &M) -> Self {
+        let mut event = Event {
+            event_type: M::full_name(),
+            content_type: PROTOBUF.to_string(),
+            data: message.encode_to_vec(),
+            ..Default::default()
+        };
+        event.metadata.insert(TYPE_URL.to_string(), M::type_url());
+        event
+    }
+
+    /// Deserialize a prost message, fails if the event holds another message
+    /// type or was not written as protobuf.
+    pub fn decode_message<M: Message + Name + Default>(&self) -> Result<M, Error> {
+        if self.content_type != PROTOBUF {
+            return Err(Error::Codec(format!(
+                "expected {} but got {}",
+                PROTOBUF, self.content_type
+            )));
+        }
+        if self.event_type != M::full_name() {
+            return Err(Error::UnexpectedEventType {
+                expected: M::full_name(),
+                actual: self.event_type.clone(),
+            });
+        }
+        M::decode(self.data.as_slice()).map_err(|err| Error::Codec(err.to_string()))
+    }
+}
+
+impl Transcoders {
+    /// Make protobuf events holding an `M` readable as any `DomainEvent`
+    /// with the same event type, via the serde representation of `M`.
+    pub fn register_message<M: Message + Name + Default + Serialize>(&mut self) {
+        self.register(PROTOBUF, &M::full_name(), |event: &Event| {
+            Ok(serde_json::to_vec(&event.decode_message::<M>()?)?)
+        });
+    }
+}
+
+impl SqliteBackend {
+    /// Append a prost message as the next event of `aggregate_id`.
+    pub fn append_message<M: Message + Name>(
+        &self,
+        aggregate_id: Uuid,
+        version: u32,
+        message: &M,
+    ) -> Result<(), Error> {
+        let event = Event {
+            id: aggregate_id,
+            version,
+            ..Event::encode_message(message)
+        };
+        self.append_event(&event)
+    }
+
+    /// Read all events of `aggregate_id` which hold an `M`, skipping other
+    /// message types.
+    pub fn get_messages<M: Message + Name + Default>(
+        &self,
+        aggregate_id: Uuid,
+    ) -> Result<Vec<M>, Error> {
+        self.get_aggretate(aggregate_id)?
+            .iter()
+            .filter(|event| event.content_type == PROTOBUF && event.event_type == M::full_name())
+            .map(|event| event.decode_message())
+            .collect()
+    }
+}
@@ -0,0 +1,133 @@
+#[cfg(feature = "compression")]
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::backend::sqlite::Error;
+
+/// Value of the `compression` column for zstd compressed payloads, an empty
+/// string marks uncompressed payloads.
+pub const ZSTD: &str = "zstd";
+
+/// Prefix of the `compression` column for payloads compressed with a trained
+/// dictionary, followed by the id of the dictionary.
+pub const ZSTD_DICT_PREFIX: &str = "zstd-dict:";
+
+/// Trained zstd dictionaries, the latest one per event type is used for new
+/// payloads while older ones are kept to read existing events.
+#[derive(Debug, Default)]
+pub struct Dictionaries {
+    by_id: HashMap<i64, Vec<u8>>,
+    latest: HashMap<String, i64>,
+}
+
+impl Dictionaries {
+    pub fn insert(&mut self, id: i64, event_type: &str, dictionary: Vec<u8>) {
+        self.by_id.insert(id, dictionary);
+        let latest = self.latest.entry(event_type.to_string()).or_insert(id);
+        *latest = (*latest).max(id);
+    }
+
+    pub fn get(&self, id: i64) -> Option<&[u8]> {
+        self.by_id.get(&id).map(Vec::as_slice)
+    }
+
+    pub fn latest(&self, event_type: &str) -> Option<(i64, &[u8])> {
+        let id = *self.latest.get(event_type)?;
+        self.get(id).map(|dictionary| (id, dictionary))
+    }
+}
+
+/// Compresses payloads larger than `threshold` bytes with zstd. Event types
+/// with a trained dictionary are compressed with it regardless of size.
+#[cfg(feature = "compression")]
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+    pub threshold: usize,
+    pub level: i32,
+}
+
+#[cfg(feature = "compression")]
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            threshold: 1024,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+fn zstd_error(err: std::io::Error) -> Error {
+    Error::WithMsg(format!("zstd: {}", err))
+}
+
+#[cfg(feature = "compression")]
+impl Compression {
+    /// Returns the payload to store and the value for the `compression`
+    /// column. Payloads are stored as is if compressing does not pay off.
+    pub fn compress<'a>(
+        &self,
+        data: &'a [u8],
+        dictionary: Option<(i64, &[u8])>,
+    ) -> Result<(Cow<'a, [u8]>, String), Error> {
+        let (compressed, compression) = match dictionary {
+            Some((id, dictionary)) => {
+                let mut compressor =
+                    zstd::bulk::Compressor::with_dictionary(self.level, dictionary)
+                        .map_err(zstd_error)?;
+                (
+                    compressor.compress(data).map_err(zstd_error)?,
+                    format!("{}{}", ZSTD_DICT_PREFIX, id),
+                )
+            }
+            None if data.len() >= self.threshold => (
+                zstd::encode_all(data, self.level).map_err(zstd_error)?,
+                ZSTD.to_string(),
+            ),
+            None => return Ok((Cow::Borrowed(data), String::new())),
+        };
+        if compressed.len() >= data.len() {
+            return Ok((Cow::Borrowed(data), String::new()));
+        }
+        Ok((Cow::Owned(compressed), compression))
+    }
+}
+
+/// Train a dictionary of at most `max_size` bytes from sample payloads.
+#[cfg(feature = "compression")]
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>, Error> {
+    zstd::dict::from_samples(samples, max_size).map_err(zstd_error)
+}
+
+/// Undo the compression recorded in the `compression` column.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+pub fn decompress(
+    compression: &str,
+    data: Vec<u8>,
+    dictionaries: &Dictionaries,
+) -> Result<Vec<u8>, Error> {
+    match compression {
+        "" => Ok(data),
+        #[cfg(feature = "compression")]
+        ZSTD => zstd::decode_all(data.as_slice()).map_err(zstd_error),
+        #[cfg(feature = "compression")]
+        other if other.starts_with(ZSTD_DICT_PREFIX) => {
+            let id: i64 = other[ZSTD_DICT_PREFIX.len()..]
+                .parse()
+                .map_err(|_| Error::WithMsg(format!("invalid compression: {}", other)))?;
+            let dictionary = dictionaries
+                .get(id)
+                .ok_or_else(|| Error::WithMsg(format!("unknown compression dictionary {}", id)))?;
+            let mut decompressor =
+                zstd::bulk::Decompressor::with_dictionary(dictionary).map_err(zstd_error)?;
+            // zstd frames record their content size, fall back to a generous
+            // upper bound if it is missing
+            let capacity = zstd::bulk::Decompressor::upper_bound(&data).unwrap_or(data.len() * 64);
+            decompressor.decompress(&data, capacity).map_err(zstd_error)
+        }
+        other => Err(Error::WithMsg(format!(
+            "unsupported payload compression: {}",
+            other
+        ))),
+    }
+}
@@ -0,0 +1,72 @@
+//! Request-scoped details stored with every event a handle appends, so
+//! call sites do not copy them into the metadata by hand:
+//!
+//! ```ignore
+//! let context = AppendContext::default()
+//!     .with_user_id(&user.id)
+//!     .with_request_id(&request_id)
+//!     .with_source_service("checkout");
+//! let repository = repository.with_append_context(context);
+//! repository.save(cart_id, version, vec![checked_out])?;
+//! ```
+//!
+//! Values already in the metadata of an event are kept. Every write of the
+//! handle gets them: imports, copies of `split_stream` and `merge_streams`
+//! and scheduled events, which carry the context of the handle that
+//! scheduled them.
+
+use std::collections::BTreeMap;
+
+/// Metadata key of the user an event was appended for.
+pub const USER_ID: &str = "user_id";
+
+/// Metadata key of the request an event was appended in.
+pub const REQUEST_ID: &str = "request_id";
+
+/// Metadata key of the service that appended an event.
+pub const SOURCE_SERVICE: &str = "source_service";
+
+/// Attached to a handle with `SqliteBackend::with_append_context`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppendContext {
+    pub user_id: Option<String>,
+    pub request_id: Option<String>,
+    pub source_service: Option<String>,
+}
+
+impl AppendContext {
+    pub fn with_user_id(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    pub fn with_source_service(mut self, source_service: &str) -> Self {
+        self.source_service = Some(source_service.to_string());
+        self
+    }
+
+    /// Add the details to `metadata` unless it has them.
+    /// Whether `apply` may write `key`.
+    pub(crate) fn is_context_key(key: &str) -> bool {
+        [USER_ID, REQUEST_ID, SOURCE_SERVICE].contains(&key)
+    }
+
+    pub(crate) fn apply(&self, metadata: &mut BTreeMap<String, String>) {
+        for (key, value) in [
+            (USER_ID, &self.user_id),
+            (REQUEST_ID, &self.request_id),
+            (SOURCE_SERVICE, &self.source_service),
+        ] {
+            if let Some(value) = value {
+                metadata
+                    .entry(key.to_string())
+                    .or_insert_with(|| value.clone());
+            }
+        }
+    }
+}
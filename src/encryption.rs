@@ -0,0 +1,147 @@
+#[cfg(feature = "encryption")]
+use std::collections::HashMap;
+#[cfg(feature = "encryption")]
+use std::sync::RwLock;
+
+#[cfg(feature = "encryption")]
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+
+use crate::backend::sqlite::Error;
+#[cfg(feature = "encryption")]
+use crate::encryption::keys::{DataKey, KeyProvider};
+
+#[cfg(feature = "aws-kms")]
+pub mod aws_kms;
+#[cfg(feature = "azure-key-vault")]
+pub mod azure_key_vault;
+pub mod keys;
+
+/// Metadata key naming the subject whose data key encrypts the event when
+/// crypto-shredding is enabled, defaults to the aggregate id.
+pub const SUBJECT_ID: &str = "subject_id";
+
+/// Prefix of the key id of events encrypted with a subject data key.
+pub const SUBJECT_KEY_PREFIX: &str = "subject:";
+
+/// Metadata key set on events read after their subject was forgotten, the
+/// payload of those events is empty.
+pub const FORGOTTEN: &str = "forgotten";
+
+/// Encrypts payloads before they are written to SQLite and decrypts them on
+/// read. The returned key id is stored per event, so keys can be rotated
+/// while older events stay readable.
+pub trait Encryptor: Send + Sync {
+    /// Returns the ciphertext along with the id of the key used.
+    fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, String), Error>;
+
+    fn decrypt(&self, key_id: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// AES-256-GCM with a random nonce per payload, the nonce is prepended to the
+/// ciphertext.
+///
+/// New payloads are encrypted with the current key of the provider, older
+/// keys are only used to decrypt events written before a rotation.
+#[cfg(feature = "encryption")]
+pub struct AesGcmEncryptor {
+    keys: Box<dyn KeyProvider>,
+    ciphers: RwLock<HashMap<String, Aes256Gcm>>,
+}
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+#[cfg(feature = "encryption")]
+impl AesGcmEncryptor {
+    pub fn new<P: KeyProvider + 'static>(keys: P) -> Self {
+        Self {
+            keys: Box::new(keys),
+            ciphers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn with_cipher<T>(
+        &self,
+        key_id: &str,
+        f: impl FnOnce(&Aes256Gcm) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if let Some(cipher) = self.ciphers.read().unwrap().get(key_id) {
+            return f(cipher);
+        }
+        let cipher = cipher(&self.keys.key(key_id)?);
+        let res = f(&cipher);
+        self.ciphers
+            .write()
+            .unwrap()
+            .insert(key_id.to_string(), cipher);
+        res
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn cipher(key: &DataKey) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+}
+
+#[cfg(feature = "encryption")]
+fn seal_with(cipher: &Aes256Gcm, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|err| Error::Encryption(err.to_string()))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+#[cfg(feature = "encryption")]
+fn open_with(cipher: &Aes256Gcm, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    if sealed.len() < NONCE_LEN {
+        return Err(Error::Encryption("ciphertext too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|err| Error::Encryption(err.to_string()))
+}
+
+/// Random data key.
+#[cfg(feature = "encryption")]
+pub fn generate_key() -> DataKey {
+    Aes256Gcm::generate_key(&mut OsRng).into()
+}
+
+/// Encrypt with AES-256-GCM, the nonce is prepended to the ciphertext.
+#[cfg(feature = "encryption")]
+pub fn seal(key: &DataKey, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    seal_with(&cipher(key), plaintext)
+}
+
+/// Decrypt a payload produced by `seal`.
+#[cfg(feature = "encryption")]
+pub fn open(key: &DataKey, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+    open_with(&cipher(key), sealed)
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for AesGcmEncryptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AesGcmEncryptor").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Encryptor for AesGcmEncryptor {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, String), Error> {
+        let key_id = self.keys.current_key_id()?;
+        let sealed = self.with_cipher(&key_id, |cipher| seal_with(cipher, plaintext))?;
+        Ok((sealed, key_id))
+    }
+
+    fn decrypt(&self, key_id: &str, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        self.with_cipher(key_id, |cipher| open_with(cipher, ciphertext))
+    }
+}
@@ -0,0 +1,66 @@
+use aws_sdk_kms::{error::DisplayErrorContext, primitives::Blob, types::DataKeySpec, Client};
+use tokio::runtime::Runtime;
+
+use crate::backend::sqlite::Error;
+use crate::encryption::keys::KeyUnwrapper;
+
+fn kms_error(err: impl std::error::Error) -> Error {
+    Error::Encryption(format!("aws kms: {}", DisplayErrorContext(err)))
+}
+
+/// Unwraps data keys with AWS KMS, use with `EnvelopeKeyProvider`.
+///
+/// Requests are driven by an own single threaded runtime, so this must not
+/// be called from within an async context.
+#[derive(Debug)]
+pub struct AwsKmsUnwrapper {
+    client: Client,
+    runtime: Runtime,
+}
+
+impl AwsKmsUnwrapper {
+    pub fn new(client: Client) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| Error::Encryption(format!("aws kms runtime: {}", err)))?;
+        Ok(Self { client, runtime })
+    }
+
+    /// Generate a new data key under the KMS key `kms_key_id`, returns the
+    /// wrapped key to register with an `EnvelopeKeyProvider`.
+    pub fn generate_data_key(&self, kms_key_id: &str) -> Result<Vec<u8>, Error> {
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .generate_data_key()
+                    .key_id(kms_key_id)
+                    .key_spec(DataKeySpec::Aes256)
+                    .send(),
+            )
+            .map_err(kms_error)?;
+        output
+            .ciphertext_blob()
+            .map(|blob| blob.as_ref().to_vec())
+            .ok_or_else(|| Error::Encryption("aws kms returned no data key".to_string()))
+    }
+}
+
+impl KeyUnwrapper for AwsKmsUnwrapper {
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+        let output = self
+            .runtime
+            .block_on(
+                self.client
+                    .decrypt()
+                    .ciphertext_blob(Blob::new(wrapped.to_vec()))
+                    .send(),
+            )
+            .map_err(kms_error)?;
+        output
+            .plaintext()
+            .map(|blob| blob.as_ref().to_vec())
+            .ok_or_else(|| Error::Encryption("aws kms returned no plaintext".to_string()))
+    }
+}
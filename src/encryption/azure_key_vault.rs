@@ -0,0 +1,72 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::json;
+
+use crate::backend::sqlite::Error;
+use crate::encryption::keys::KeyUnwrapper;
+
+const API_VERSION: &str = "7.4";
+
+type TokenFn = dyn Fn() -> Result<String, Error> + Send + Sync;
+
+/// Unwraps data keys with a key in Azure Key Vault via its REST API, use
+/// with `EnvelopeKeyProvider`.
+///
+/// `key_url` is the key identifier, e.g.
+/// `https://<vault>.vault.azure.net/keys/<name>/<version>`, and `token`
+/// returns an access token for the vault.
+pub struct AzureKeyVaultUnwrapper {
+    key_url: String,
+    algorithm: String,
+    token: Box<TokenFn>,
+}
+
+impl AzureKeyVaultUnwrapper {
+    pub fn new(
+        key_url: impl Into<String>,
+        token: impl Fn() -> Result<String, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            key_url: key_url.into().trim_end_matches('/').to_string(),
+            algorithm: "RSA-OAEP-256".to_string(),
+            token: Box::new(token),
+        }
+    }
+
+    /// Key wrap algorithm, defaults to `RSA-OAEP-256`.
+    pub fn with_algorithm(mut self, algorithm: impl Into<String>) -> Self {
+        self.algorithm = algorithm.into();
+        self
+    }
+}
+
+impl std::fmt::Debug for AzureKeyVaultUnwrapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AzureKeyVaultUnwrapper")
+            .field("key_url", &self.key_url)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl KeyUnwrapper for AzureKeyVaultUnwrapper {
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+        let response: serde_json::Value = ureq::post(&format!(
+            "{}/unwrapkey?api-version={}",
+            self.key_url, API_VERSION
+        ))
+        .set("Authorization", &format!("Bearer {}", (self.token)()?))
+        .send_json(json!({
+            "alg": self.algorithm,
+            "value": URL_SAFE_NO_PAD.encode(wrapped),
+        }))
+        .map_err(|err| Error::Encryption(format!("azure key vault: {}", err)))?
+        .into_json()
+        .map_err(|err| Error::Encryption(format!("azure key vault: {}", err)))?;
+        let value = response["value"]
+            .as_str()
+            .ok_or_else(|| Error::Encryption("azure key vault returned no key".to_string()))?;
+        URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|err| Error::Encryption(format!("azure key vault: {}", err)))
+    }
+}
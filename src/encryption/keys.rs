@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::backend::sqlite::Error;
+
+/// 256 bit data key used to encrypt payloads.
+pub type DataKey = [u8; 32];
+
+/// Source of the data keys used by the encryption layer, so secrets do not
+/// have to be hardcoded into the store.
+pub trait KeyProvider: Send + Sync {
+    /// Id of the key new payloads are encrypted with.
+    fn current_key_id(&self) -> Result<String, Error>;
+
+    fn key(&self, key_id: &str) -> Result<DataKey, Error>;
+}
+
+fn unknown_key(key_id: &str) -> Error {
+    Error::Encryption(format!("unknown key {}", key_id))
+}
+
+pub(crate) fn data_key(bytes: &[u8]) -> Result<DataKey, Error> {
+    bytes
+        .try_into()
+        .map_err(|_| Error::Encryption(format!("expected 32 byte key, got {}", bytes.len())))
+}
+
+/// Parse a hex encoded key, surrounding whitespace is ignored.
+pub fn decode_hex_key(hex: &str) -> Result<DataKey, Error> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(Error::Encryption("expected 64 hex characters".to_string()));
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|err| Error::Encryption(format!("invalid hex key: {}", err)))?;
+    }
+    Ok(key)
+}
+
+/// Keys held in memory.
+#[derive(Clone)]
+pub struct StaticKeyProvider {
+    current: String,
+    keys: HashMap<String, DataKey>,
+}
+
+impl StaticKeyProvider {
+    pub fn new(key_id: impl Into<String>, key: DataKey) -> Self {
+        let current = key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(current.clone(), key);
+        Self { current, keys }
+    }
+
+    /// Keep `key` around to decrypt events written with it.
+    pub fn with_key(mut self, key_id: impl Into<String>, key: DataKey) -> Self {
+        self.keys.insert(key_id.into(), key);
+        self
+    }
+}
+
+impl std::fmt::Debug for StaticKeyProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticKeyProvider")
+            .field("current", &self.current)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key_id(&self) -> Result<String, Error> {
+        Ok(self.current.clone())
+    }
+
+    fn key(&self, key_id: &str) -> Result<DataKey, Error> {
+        self.keys
+            .get(key_id)
+            .copied()
+            .ok_or_else(|| unknown_key(key_id))
+    }
+}
+
+/// Reads keys from `<dir>/<key_id>.key`, either 32 raw bytes or 64 hex
+/// characters.
+#[derive(Debug, Clone)]
+pub struct FileKeyProvider {
+    dir: PathBuf,
+    current: String,
+}
+
+impl FileKeyProvider {
+    pub fn new(dir: impl Into<PathBuf>, current_key_id: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            current: current_key_id.into(),
+        }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn current_key_id(&self) -> Result<String, Error> {
+        Ok(self.current.clone())
+    }
+
+    fn key(&self, key_id: &str) -> Result<DataKey, Error> {
+        if key_id.contains(['/', '\\']) || key_id.starts_with('.') {
+            return Err(unknown_key(key_id));
+        }
+        let bytes = std::fs::read(self.dir.join(format!("{}.key", key_id)))
+            .map_err(|err| Error::Encryption(format!("reading key {}: {}", key_id, err)))?;
+        match bytes.len() {
+            32 => data_key(&bytes),
+            _ => decode_hex_key(&String::from_utf8_lossy(&bytes)),
+        }
+    }
+}
+
+/// Reads hex encoded keys from the environment variable `<prefix><KEY_ID>`,
+/// the key id is upper cased and `-` replaced with `_`.
+#[derive(Debug, Clone)]
+pub struct EnvKeyProvider {
+    prefix: String,
+    current: String,
+}
+
+impl EnvKeyProvider {
+    pub fn new(prefix: impl Into<String>, current_key_id: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            current: current_key_id.into(),
+        }
+    }
+
+    pub fn variable(&self, key_id: &str) -> String {
+        format!("{}{}", self.prefix, key_id.to_uppercase().replace('-', "_"))
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn current_key_id(&self) -> Result<String, Error> {
+        Ok(self.current.clone())
+    }
+
+    fn key(&self, key_id: &str) -> Result<DataKey, Error> {
+        let value = std::env::var(self.variable(key_id)).map_err(|_| unknown_key(key_id))?;
+        decode_hex_key(&value)
+    }
+}
+
+/// Decrypts data keys that were encrypted by a key management service.
+pub trait KeyUnwrapper: Send + Sync {
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Envelope encryption: only wrapped data keys are known locally, they are
+/// unwrapped by a key management service on first use and cached.
+pub struct EnvelopeKeyProvider<U> {
+    unwrapper: U,
+    current: String,
+    wrapped: HashMap<String, Vec<u8>>,
+    cache: RwLock<HashMap<String, DataKey>>,
+}
+
+impl<U: KeyUnwrapper> EnvelopeKeyProvider<U> {
+    pub fn new(unwrapper: U, key_id: impl Into<String>, wrapped: Vec<u8>) -> Self {
+        let current = key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(current.clone(), wrapped);
+        Self {
+            unwrapper,
+            current,
+            wrapped: keys,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the wrapped `key` around to decrypt events written with it.
+    pub fn with_key(mut self, key_id: impl Into<String>, wrapped: Vec<u8>) -> Self {
+        self.wrapped.insert(key_id.into(), wrapped);
+        self
+    }
+}
+
+impl<U> std::fmt::Debug for EnvelopeKeyProvider<U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnvelopeKeyProvider")
+            .field("current", &self.current)
+            .field("keys", &self.wrapped.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<U: KeyUnwrapper> KeyProvider for EnvelopeKeyProvider<U> {
+    fn current_key_id(&self) -> Result<String, Error> {
+        Ok(self.current.clone())
+    }
+
+    fn key(&self, key_id: &str) -> Result<DataKey, Error> {
+        if let Some(key) = self.cache.read().unwrap().get(key_id) {
+            return Ok(*key);
+        }
+        let wrapped = self
+            .wrapped
+            .get(key_id)
+            .ok_or_else(|| unknown_key(key_id))?;
+        let key = data_key(&self.unwrapper.unwrap_key(wrapped)?)?;
+        self.cache.write().unwrap().insert(key_id.to_string(), key);
+        Ok(key)
+    }
+}
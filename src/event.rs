@@ -0,0 +1,87 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::backend::{model::Event, sqlite::Error};
+
+/// A typed domain event which can be stored as an `Event`.
+///
+/// Usually derived via `#[derive(DomainEvent)]` instead of implemented by hand.
+pub trait DomainEvent: Serialize + DeserializeOwned {
+    fn event_type() -> &'static str;
+
+    /// Version of the payload layout, bump it whenever the shape changes and
+    /// register an upcaster for the previous version.
+    fn schema_version() -> u32 {
+        1
+    }
+}
+
+pub type DecodeFn = fn(&Event) -> Result<Box<dyn Any>, Error>;
+
+/// Decodes an event into a boxed `E`, used by the registry.
+pub fn decode_boxed<E: DomainEvent + 'static>(event: &Event) -> Result<Box<dyn Any>, Error> {
+    Ok(Box::new(event.decode::<E>()?))
+}
+
+/// Static registration emitted by `#[derive(DomainEvent)]`.
+pub struct Registration {
+    event_type: &'static str,
+    decode: DecodeFn,
+}
+
+impl Registration {
+    pub const fn new(event_type: &'static str, decode: DecodeFn) -> Self {
+        Self { event_type, decode }
+    }
+}
+
+inventory::collect!(Registration);
+
+/// Maps event type names to decoders so stored events can be turned back
+/// into their domain types.
+#[derive(Default, Clone)]
+pub struct EventRegistry {
+    decoders: HashMap<String, DecodeFn>,
+}
+
+impl EventRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry containing every type which derived `DomainEvent`.
+    pub fn global() -> &'static EventRegistry {
+        static GLOBAL: OnceLock<EventRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            let mut registry = EventRegistry::new();
+            for reg in inventory::iter::<Registration> {
+                registry
+                    .decoders
+                    .insert(reg.event_type.to_string(), reg.decode);
+            }
+            registry
+        })
+    }
+
+    pub fn register<E: DomainEvent + 'static>(&mut self) -> &mut Self {
+        self.decoders
+            .insert(E::event_type().to_string(), decode_boxed::<E>);
+        self
+    }
+
+    pub fn contains(&self, event_type: &str) -> bool {
+        self.decoders.contains_key(event_type)
+    }
+
+    /// Decode an event into its registered type, use `downcast` on the result
+    /// to get to the concrete type.
+    pub fn decode(&self, event: &Event) -> Result<Box<dyn Any>, Error> {
+        match self.decoders.get(&event.event_type) {
+            Some(decode) => decode(event),
+            None => Err(Error::UnknownEventType(event.event_type.clone())),
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! GraphQL schema over a backend, for admin UIs:
+//!
+//! ```graphql
+//! query {
+//!   streams(first: 20) { edges { cursor node { id version } } pageInfo { hasNextPage } }
+//!   stream(id: "…") { version events(first: 10) { nodes { version eventType data } } }
+//!   events(after: "42", first: 100) { nodes { position aggregateId eventType } }
+//! }
+//! subscription { events(fromPosition: 0) { position eventType data } }
+//! ```
+//!
+//! Lists are relay style connections, cursors are aggregate ids for
+//! streams, versions for the events of a stream and positions for all
+//! events. Serve the schema with any async-graphql integration, e.g.
+//! `async-graphql-axum`.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use async_graphql::connection::{Connection, Edge, EmptyFields};
+use async_graphql::{
+    Context, EmptyMutation, Json, Object, Result, Schema, SimpleObject, Subscription,
+};
+use uuid::Uuid;
+
+use crate::backend::model::Event as StoredEvent;
+use crate::backend::sqlite::{Cursor, Error, GetAggOpts, SqliteBackend};
+use crate::codec;
+use crate::jsonl::encode_hex;
+
+const DEFAULT_PAGE: usize = 20;
+const MAX_PAGE: usize = 1000;
+
+/// Schema of the GraphQL API, built with `schema`.
+pub type EventStoreSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Schema over `backend`, subscriptions look for new events every
+/// `poll_interval` once caught up.
+pub fn schema(backend: SqliteBackend, poll_interval: Duration) -> EventStoreSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot { poll_interval })
+        .data(backend)
+        .finish()
+}
+
+/// Run blocking store calls off the async workers.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f).await??)
+}
+
+fn page_size(first: Option<i32>) -> usize {
+    first
+        .map_or(DEFAULT_PAGE, |first| first.max(1) as usize)
+        .min(MAX_PAGE)
+}
+
+fn parse_cursor<T: std::str::FromStr>(after: Option<String>) -> Result<Option<T>> {
+    after
+        .map(|cursor| cursor.parse().map_err(|_| "invalid cursor".into()))
+        .transpose()
+}
+
+/// A stored event.
+pub struct Event(StoredEvent);
+
+#[Object]
+impl Event {
+    /// Position in the commit order of all streams.
+    async fn position(&self) -> u64 {
+        self.0.position
+    }
+
+    async fn aggregate_id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn version(&self) -> u32 {
+        self.0.version
+    }
+
+    async fn event_type(&self) -> &str {
+        &self.0.event_type
+    }
+
+    async fn schema_version(&self) -> u32 {
+        self.0.schema_version
+    }
+
+    async fn content_type(&self) -> &str {
+        &self.0.content_type
+    }
+
+    /// The payload if it is JSON.
+    async fn data(&self) -> Option<Json<serde_json::Value>> {
+        match self.0.content_type.as_str() {
+            codec::JSON => serde_json::from_slice(&self.0.data).ok().map(Json),
+            _ => None,
+        }
+    }
+
+    /// The payload, hex encoded.
+    async fn data_hex(&self) -> String {
+        encode_hex(&self.0.data)
+    }
+
+    async fn metadata(&self) -> Json<&std::collections::BTreeMap<String, String>> {
+        Json(&self.0.metadata)
+    }
+}
+
+/// A stream, i.e. the events of an aggregate.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Stream {
+    id: Uuid,
+    /// Version of the latest event.
+    version: u32,
+}
+
+#[async_graphql::ComplexObject]
+impl Stream {
+    /// Events of the stream after the version in `after`.
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Event>> {
+        let backend = ctx.data::<SqliteBackend>()?.clone();
+        let limit = page_size(first);
+        let since_version = parse_cursor(after)?.unwrap_or(0);
+        let opts = GetAggOpts {
+            agg_id: self.id,
+            since_version,
+        };
+        let id = self.id;
+        let mut events = blocking(move || backend.get_aggretate_with_opts(id, &opts)).await?;
+        let has_next = events.len() > limit;
+        events.truncate(limit);
+        let mut connection = Connection::new(since_version > 0, has_next);
+        connection.edges.extend(
+            events
+                .into_iter()
+                .map(|event| Edge::new(event.version.to_string(), Event(event))),
+        );
+        Ok(connection)
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Streams ordered by aggregate id.
+    async fn streams(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Stream, EmptyFields, EmptyFields>> {
+        let backend = ctx.data::<SqliteBackend>()?.clone();
+        let limit = page_size(first);
+        let after: Option<Cursor> = parse_cursor(after)?;
+        let page = blocking(move || backend.list_streams_page(after.as_ref(), limit)).await?;
+        let mut connection = Connection::new(after.is_some(), page.next.is_some());
+        connection
+            .edges
+            .extend(page.items.into_iter().map(|stream| {
+                Edge::new(
+                    Cursor::after_stream(stream.aggregate_id).to_string(),
+                    Stream {
+                        id: stream.aggregate_id,
+                        version: stream.version,
+                    },
+                )
+            }));
+        Ok(connection)
+    }
+
+    /// The stream `id`, null if it has no events.
+    async fn stream(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Stream>> {
+        let backend = ctx.data::<SqliteBackend>()?.clone();
+        let version = blocking(move || backend.stream_version(id)).await?;
+        Ok((version > 0).then_some(Stream { id, version }))
+    }
+
+    /// Events of all streams in commit order.
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, Event>> {
+        let backend = ctx.data::<SqliteBackend>()?.clone();
+        let limit = page_size(first);
+        let after: Option<Cursor> = parse_cursor(after)?;
+        let page = blocking(move || backend.read_all_page(after.as_ref(), limit)).await?;
+        let mut connection = Connection::new(after.is_some(), page.next.is_some());
+        connection.edges.extend(page.items.into_iter().map(|event| {
+            Edge::new(
+                Cursor::after_position(event.position).to_string(),
+                Event(event),
+            )
+        }));
+        Ok(connection)
+    }
+}
+
+pub struct SubscriptionRoot {
+    poll_interval: Duration,
+}
+
+struct EventFeed {
+    backend: SqliteBackend,
+    position: u64,
+    stream: Option<Uuid>,
+    buffer: VecDeque<StoredEvent>,
+}
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Events after `fromPosition`, optionally only of `stream`: first the
+    /// committed ones, then new ones as they are appended.
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] from_position: u64,
+        stream: Option<Uuid>,
+    ) -> Result<impl futures_util::Stream<Item = Result<Event>>> {
+        let cursor = EventFeed {
+            backend: ctx.data::<SqliteBackend>()?.clone(),
+            position: from_position,
+            stream,
+            buffer: VecDeque::new(),
+        };
+        let poll_interval = self.poll_interval;
+        Ok(futures_util::stream::unfold(
+            Some(cursor),
+            move |cursor| async move {
+                let mut cursor = cursor?;
+                loop {
+                    if let Some(event) = cursor.buffer.pop_front() {
+                        return Some((Ok(Event(event)), Some(cursor)));
+                    }
+                    let backend = cursor.backend.clone();
+                    let from = cursor.position;
+                    let events = match blocking(move || backend.read_all(from, 256)).await {
+                        Ok(events) => events,
+                        // end the subscription after reporting the error
+                        Err(err) => return Some((Err(err), None)),
+                    };
+                    match events.last() {
+                        Some(last) => cursor.position = last.position,
+                        None => tokio::time::sleep(poll_interval).await,
+                    }
+                    let stream = cursor.stream;
+                    cursor.buffer.extend(
+                        events
+                            .into_iter()
+                            .filter(|event| stream.is_none_or(|id| id == event.id)),
+                    );
+                }
+            },
+        ))
+    }
+}
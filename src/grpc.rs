@@ -0,0 +1,236 @@
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+
+pub mod esdb;
+pub mod replication;
+
+/// Types and service traits generated from `proto/eventstore.proto`.
+pub mod proto {
+    tonic::include_proto!("eventstore.v1");
+}
+
+use proto::event_store_server::{EventStore, EventStoreServer};
+
+/// Request header selecting the tenant, see `SqliteBackend::tenant`.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// gRPC service exposing a backend, mount it with `into_server` or run the
+/// `eventstore-server` binary.
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    backend: SqliteBackend,
+    poll_interval: Duration,
+}
+
+impl GrpcService {
+    pub fn new(backend: SqliteBackend) -> Self {
+        Self {
+            backend,
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+
+    /// How often subscriptions look for new events once caught up.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn into_server(self) -> EventStoreServer<Self> {
+        EventStoreServer::new(self)
+    }
+
+    fn backend<T>(&self, request: &Request<T>) -> Result<SqliteBackend, Status> {
+        match request.metadata().get(TENANT_HEADER) {
+            Some(tenant) => {
+                let tenant = tenant
+                    .to_str()
+                    .map_err(|_| Status::invalid_argument("invalid tenant id"))?;
+                Ok(self.backend.tenant(tenant))
+            }
+            None => Ok(self.backend.clone()),
+        }
+    }
+}
+
+/// Run blocking store calls off the async workers.
+async fn blocking<T, F>(f: F) -> Result<T, Status>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(status)
+}
+
+fn status(err: Error) -> Status {
+    match err {
+        Error::NotFound => Status::not_found(err.to_string()),
+        Error::InvalidUUID
+        | Error::UnknownEventType(_)
+        | Error::UnexpectedEventType { .. }
+        | Error::SchemaViolation(_)
+        | Error::SchemaVersionMismatch { .. }
+        | Error::Codec(_)
+        | Error::PayloadTooLarge { .. } => Status::invalid_argument(err.to_string()),
+        Error::StreamDeleted(_)
+        | Error::StreamRenamed(_)
+        | Error::VersionConflict
+        | Error::ReadOnly
+        | Error::WithMsg(_) => Status::failed_precondition(err.to_string()),
+        Error::Unauthorized(_) => Status::permission_denied(err.to_string()),
+        Error::QuotaExceeded(_) => Status::resource_exhausted(err.to_string()),
+        _ => Status::internal(err.to_string()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument("invalid aggregate id"))
+}
+
+fn recorded(event: Event) -> proto::RecordedEvent {
+    proto::RecordedEvent {
+        aggregate_id: event.id.to_string(),
+        version: event.version,
+        event_type: event.event_type,
+        schema_version: event.schema_version,
+        content_type: event.content_type,
+        data: event.data,
+        metadata: event.metadata.into_iter().collect(),
+        position: event.position,
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::RecordedEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl EventStore for GrpcService {
+    #[instrument(skip(self))]
+    async fn append(
+        &self,
+        request: Request<proto::AppendRequest>,
+    ) -> Result<Response<proto::AppendResponse>, Status> {
+        let backend = self.backend(&request)?;
+        let request = request.into_inner();
+        let id = parse_id(&request.aggregate_id)?;
+        let events: Vec<Event> = request
+            .events
+            .into_iter()
+            .zip(request.expected_version + 1..)
+            .map(|(data, version)| Event {
+                id,
+                version,
+                event_type: data.event_type,
+                schema_version: data.schema_version.max(1),
+                content_type: data.content_type,
+                data: data.data,
+                metadata: data.metadata.into_iter().collect(),
+                ..Default::default()
+            })
+            .collect();
+        let version = request.expected_version + events.len() as u32;
+        blocking(move || backend.append_events(&events)).await?;
+        Ok(Response::new(proto::AppendResponse { version }))
+    }
+
+    #[instrument(skip(self))]
+    async fn read_stream(
+        &self,
+        request: Request<proto::ReadStreamRequest>,
+    ) -> Result<Response<proto::ReadResponse>, Status> {
+        let backend = self.backend(&request)?;
+        let request = request.into_inner();
+        let id = parse_id(&request.aggregate_id)?;
+        let opts = GetAggOpts {
+            agg_id: id,
+            since_version: request.since_version,
+        };
+        let events = blocking(move || backend.get_aggretate_with_opts(id, &opts)).await?;
+        Ok(Response::new(proto::ReadResponse {
+            events: events.into_iter().map(recorded).collect(),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn read_all(
+        &self,
+        request: Request<proto::ReadAllRequest>,
+    ) -> Result<Response<proto::ReadResponse>, Status> {
+        let backend = self.backend(&request)?;
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => 1000,
+            limit => limit as usize,
+        };
+        let events = blocking(move || backend.read_all(request.from_position, limit)).await?;
+        Ok(Response::new(proto::ReadResponse {
+            events: events.into_iter().map(recorded).collect(),
+        }))
+    }
+
+    type SubscribeStream = EventStream;
+
+    #[instrument(skip(self))]
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let backend = self.backend(&request)?;
+        let request = request.into_inner();
+        let filter = request.aggregate_id.as_deref().map(parse_id).transpose()?;
+        let poll_interval = self.poll_interval;
+        let (sender, receiver) = tokio::sync::mpsc::channel(256);
+        tokio::spawn(async move {
+            let mut position = request.from_position;
+            loop {
+                let reader = backend.clone();
+                let events = match blocking(move || reader.read_all(position, 256)).await {
+                    Ok(events) => events,
+                    Err(status) => {
+                        let _ = sender.send(Err(status)).await;
+                        return;
+                    }
+                };
+                if events.is_empty() {
+                    tokio::select! {
+                        _ = tokio::time::sleep(poll_interval) => continue,
+                        _ = sender.closed() => break,
+                    }
+                }
+                for event in events {
+                    position = event.position;
+                    if filter.is_some_and(|id| id != event.id) {
+                        continue;
+                    }
+                    if sender.send(Ok(recorded(event))).await.is_err() {
+                        debug!("subscriber went away");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(
+            tokio_stream::wrappers::ReceiverStream::new(receiver),
+        )))
+    }
+
+    #[instrument(skip(self))]
+    async fn head(
+        &self,
+        request: Request<proto::HeadRequest>,
+    ) -> Result<Response<proto::HeadResponse>, Status> {
+        let backend = self.backend(&request)?;
+        let position = blocking(move || backend.current_position()).await?;
+        Ok(Response::new(proto::HeadResponse { position }))
+    }
+}
@@ -0,0 +1,786 @@
+//! Wire compatible subset of the EventStoreDB `Streams` gRPC service, so
+//! ESDB client SDKs can append to, read and subscribe to an eventstore-rs
+//! database.
+//!
+//! Supported are `Read` of streams and `$all` in both directions,
+//! catch-up subscriptions with prefix filters, `Append`, `Delete` and
+//! `Tombstone`. `BatchAppend`, regex filters, link resolution and the
+//! other ESDB services are not. Stream names must be aggregate ids,
+//! revisions are versions minus one and positions are reported as both
+//! commit and prepare position. Event ids of appended events are kept in
+//! the metadata under `EVENT_ID_KEY`, the JSON object in the custom
+//! metadata is merged into the metadata as well.
+
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+use super::{blocking, TENANT_HEADER};
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+
+/// Types and service traits generated from `proto/esdb`.
+#[allow(clippy::large_enum_variant)]
+pub mod proto {
+    pub mod event_store {
+        pub mod client {
+            tonic::include_proto!("event_store.client");
+
+            pub mod streams {
+                tonic::include_proto!("event_store.client.streams");
+            }
+        }
+    }
+}
+
+use proto::event_store::client as shared;
+use shared::streams::streams_server::{Streams, StreamsServer};
+use shared::streams::{
+    append_req, append_resp, delete_req, read_req, read_resp, tombstone_req, AppendReq, AppendResp,
+    DeleteReq, DeleteResp, ReadReq, ReadResp, TombstoneReq, TombstoneResp,
+};
+
+/// Metadata key holding the id an ESDB client gave an appended event.
+pub const EVENT_ID_KEY: &str = "esdb-event-id";
+
+/// Events read per query while catching up.
+const BATCH: usize = 256;
+
+/// Serves the ESDB `Streams` service for a backend, mount it with
+/// `into_server` next to or instead of `GrpcService`.
+#[derive(Debug, Clone)]
+pub struct EsdbService {
+    backend: SqliteBackend,
+    poll_interval: Duration,
+}
+
+impl EsdbService {
+    pub fn new(backend: SqliteBackend) -> Self {
+        Self {
+            backend,
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+
+    /// How often subscriptions look for new events once caught up.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn into_server(self) -> StreamsServer<Self> {
+        StreamsServer::new(self)
+    }
+
+    fn backend<T>(&self, request: &Request<T>) -> Result<SqliteBackend, Status> {
+        match request.metadata().get(TENANT_HEADER) {
+            Some(tenant) => {
+                let tenant = tenant
+                    .to_str()
+                    .map_err(|_| Status::invalid_argument("invalid tenant id"))?;
+                Ok(self.backend.tenant(tenant))
+            }
+            None => Ok(self.backend.clone()),
+        }
+    }
+}
+
+fn stream_id(identifier: Option<shared::StreamIdentifier>) -> Result<Uuid, Status> {
+    let identifier =
+        identifier.ok_or_else(|| Status::invalid_argument("missing stream identifier"))?;
+    std::str::from_utf8(&identifier.stream_name)
+        .ok()
+        .and_then(|name| Uuid::parse_str(name).ok())
+        .ok_or_else(|| Status::invalid_argument("stream names must be aggregate ids"))
+}
+
+fn identifier(id: Uuid) -> shared::StreamIdentifier {
+    shared::StreamIdentifier {
+        stream_name: id.to_string().into_bytes(),
+    }
+}
+
+fn to_proto_uuid(id: Uuid, structured: bool) -> shared::Uuid {
+    let value = if structured {
+        let (most, least) = id.as_u64_pair();
+        shared::uuid::Value::Structured(shared::uuid::Structured {
+            most_significant_bits: most as i64,
+            least_significant_bits: least as i64,
+        })
+    } else {
+        shared::uuid::Value::String(id.to_string())
+    };
+    shared::Uuid { value: Some(value) }
+}
+
+fn from_proto_uuid(id: Option<shared::Uuid>) -> Option<Uuid> {
+    match id?.value? {
+        shared::uuid::Value::Structured(id) => Some(Uuid::from_u64_pair(
+            id.most_significant_bits as u64,
+            id.least_significant_bits as u64,
+        )),
+        shared::uuid::Value::String(id) => Uuid::parse_str(&id).ok(),
+    }
+}
+
+/// Expected state of a stream before a write, in versions.
+#[derive(Debug, Clone, Copy)]
+enum Expected {
+    Version(u64),
+    NoStream,
+    Any,
+    Exists,
+}
+
+impl Expected {
+    fn matches(self, current: u32) -> bool {
+        match self {
+            Expected::Version(version) => version == current as u64,
+            Expected::NoStream => current == 0,
+            Expected::Any => true,
+            Expected::Exists => current > 0,
+        }
+    }
+
+    fn wrong_expected_version(self, current: u32) -> append_resp::WrongExpectedVersion {
+        use append_resp::wrong_expected_version::*;
+        let empty = shared::Empty {};
+        let (current_2060, current) = match current {
+            0 => (
+                CurrentRevisionOption2060::NoStream2060(empty),
+                CurrentRevisionOption::CurrentNoStream(empty),
+            ),
+            version => (
+                CurrentRevisionOption2060::CurrentRevision2060(version as u64 - 1),
+                CurrentRevisionOption::CurrentRevision(version as u64 - 1),
+            ),
+        };
+        let (expected_2060, expected) = match self {
+            Expected::Version(version) => (
+                Some(ExpectedRevisionOption2060::ExpectedRevision2060(
+                    version - 1,
+                )),
+                ExpectedRevisionOption::ExpectedRevision(version - 1),
+            ),
+            Expected::NoStream => (None, ExpectedRevisionOption::ExpectedNoStream(empty)),
+            Expected::Any => (
+                Some(ExpectedRevisionOption2060::Any2060(empty)),
+                ExpectedRevisionOption::ExpectedAny(empty),
+            ),
+            Expected::Exists => (
+                Some(ExpectedRevisionOption2060::StreamExists2060(empty)),
+                ExpectedRevisionOption::ExpectedStreamExists(empty),
+            ),
+        };
+        append_resp::WrongExpectedVersion {
+            current_revision_option_20_6_0: Some(current_2060),
+            expected_revision_option_20_6_0: expected_2060,
+            current_revision_option: Some(current),
+            expected_revision_option: Some(expected),
+        }
+    }
+
+    /// Error for deletes, which report a wrong expected version in the
+    /// `exception` metadata like ESDB.
+    fn status(self, current: u32) -> Status {
+        let mut status = Status::failed_precondition(format!(
+            "wrong expected version: expected {:?}, stream is at version {}",
+            self, current
+        ));
+        status
+            .metadata_mut()
+            .insert("exception", "wrong-expected-version".parse().unwrap());
+        status
+    }
+}
+
+impl From<append_req::options::ExpectedStreamRevision> for Expected {
+    fn from(revision: append_req::options::ExpectedStreamRevision) -> Self {
+        use append_req::options::ExpectedStreamRevision::*;
+        match revision {
+            Revision(revision) => Expected::Version(revision.saturating_add(1)),
+            NoStream(_) => Expected::NoStream,
+            Any(_) => Expected::Any,
+            StreamExists(_) => Expected::Exists,
+        }
+    }
+}
+
+impl From<delete_req::options::ExpectedStreamRevision> for Expected {
+    fn from(revision: delete_req::options::ExpectedStreamRevision) -> Self {
+        use delete_req::options::ExpectedStreamRevision::*;
+        match revision {
+            Revision(revision) => Expected::Version(revision.saturating_add(1)),
+            NoStream(_) => Expected::NoStream,
+            Any(_) => Expected::Any,
+            StreamExists(_) => Expected::Exists,
+        }
+    }
+}
+
+impl From<tombstone_req::options::ExpectedStreamRevision> for Expected {
+    fn from(revision: tombstone_req::options::ExpectedStreamRevision) -> Self {
+        use tombstone_req::options::ExpectedStreamRevision::*;
+        match revision {
+            Revision(revision) => Expected::Version(revision.saturating_add(1)),
+            NoStream(_) => Expected::NoStream,
+            Any(_) => Expected::Any,
+            StreamExists(_) => Expected::Exists,
+        }
+    }
+}
+
+/// Event to append from a proposed message, `version` is set by the caller.
+fn proposed(id: Uuid, message: append_req::ProposedMessage) -> Result<Event, Status> {
+    let mut metadata = BTreeMap::new();
+    if !message.custom_metadata.is_empty() {
+        match serde_json::from_slice(&message.custom_metadata) {
+            Ok(Value::Object(object)) => {
+                for (key, value) in object {
+                    let value = match value {
+                        Value::String(value) => value,
+                        value => value.to_string(),
+                    };
+                    metadata.insert(key, value);
+                }
+            }
+            _ => {
+                return Err(Status::invalid_argument(
+                    "custom metadata must be a JSON object",
+                ))
+            }
+        }
+    }
+    if let Some(event_id) = from_proto_uuid(message.id) {
+        metadata.insert(EVENT_ID_KEY.to_string(), event_id.to_string());
+    }
+    let mut system = message.metadata;
+    let event_type = system
+        .remove("type")
+        .ok_or_else(|| Status::invalid_argument("missing event type"))?;
+    let content_type = system
+        .remove("content-type")
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok(Event {
+        id,
+        event_type,
+        content_type,
+        data: message.data,
+        metadata,
+        ..Default::default()
+    })
+}
+
+/// Read response for `event`, events without an ESDB id get one derived
+/// from their position.
+fn recorded(event: Event, structured: bool) -> ReadResp {
+    let mut metadata = event.metadata;
+    let event_id = metadata
+        .remove(EVENT_ID_KEY)
+        .and_then(|id| Uuid::parse_str(&id).ok())
+        .unwrap_or_else(|| Uuid::from_u64_pair(0, event.position));
+    let custom_metadata = if metadata.is_empty() {
+        Vec::new()
+    } else {
+        serde_json::to_vec(&metadata).unwrap_or_default()
+    };
+    let system = HashMap::from([
+        ("type".to_string(), event.event_type),
+        ("content-type".to_string(), event.content_type),
+    ]);
+    ReadResp {
+        content: Some(read_resp::Content::Event(read_resp::ReadEvent {
+            event: Some(read_resp::read_event::RecordedEvent {
+                id: Some(to_proto_uuid(event_id, structured)),
+                stream_identifier: Some(identifier(event.id)),
+                stream_revision: event.version as u64 - 1,
+                prepare_position: event.position,
+                commit_position: event.position,
+                metadata: system,
+                custom_metadata,
+                data: event.data,
+            }),
+            link: None,
+            position: Some(read_resp::read_event::Position::CommitPosition(
+                event.position,
+            )),
+        })),
+    }
+}
+
+fn message(content: read_resp::Content) -> ReadResp {
+    ReadResp {
+        content: Some(content),
+    }
+}
+
+/// Prefix filter of `$all` reads.
+#[derive(Debug, Clone, Default)]
+struct Filter {
+    streams: Vec<String>,
+    event_types: Vec<String>,
+}
+
+impl Filter {
+    fn parse(option: Option<read_req::options::FilterOption>) -> Result<Option<Self>, Status> {
+        use read_req::options::filter_options::Filter as Kind;
+        let Some(read_req::options::FilterOption::Filter(options)) = option else {
+            return Ok(None);
+        };
+        let (expression, streams) = match options.filter {
+            Some(Kind::StreamIdentifier(expression)) => (expression, true),
+            Some(Kind::EventType(expression)) => (expression, false),
+            None => return Ok(None),
+        };
+        if !expression.regex.is_empty() {
+            return Err(Status::unimplemented("regex filters are not supported"));
+        }
+        Ok(Some(if streams {
+            Filter {
+                streams: expression.prefix,
+                ..Default::default()
+            }
+        } else {
+            Filter {
+                event_types: expression.prefix,
+                ..Default::default()
+            }
+        }))
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let id = event.id.to_string();
+        (self.streams.is_empty() || self.streams.iter().any(|p| id.starts_with(p.as_str())))
+            && (self.event_types.is_empty()
+                || self
+                    .event_types
+                    .iter()
+                    .any(|p| event.event_type.starts_with(p.as_str())))
+    }
+}
+
+/// Where a read or subscription starts.
+#[derive(Debug, Clone, Copy)]
+enum Start {
+    Stream(Uuid, read_req::options::stream_options::RevisionOption),
+    All(read_req::options::all_options::AllOption),
+}
+
+impl Start {
+    fn parse(option: Option<read_req::options::StreamOption>) -> Result<Self, Status> {
+        use read_req::options::StreamOption;
+        match option {
+            Some(StreamOption::Stream(options)) => Ok(Start::Stream(
+                stream_id(options.stream_identifier)?,
+                options
+                    .revision_option
+                    .ok_or_else(|| Status::invalid_argument("missing revision"))?,
+            )),
+            Some(StreamOption::All(options)) => {
+                Ok(Start::All(options.all_option.ok_or_else(|| {
+                    Status::invalid_argument("missing position")
+                })?))
+            }
+            None => Err(Status::invalid_argument("missing stream option")),
+        }
+    }
+}
+
+/// Up to `count` events read from `start`, like an ESDB read.
+fn read(
+    backend: &SqliteBackend,
+    start: Start,
+    backwards: bool,
+    count: usize,
+    filter: Option<&Filter>,
+) -> Result<Vec<Event>, Error> {
+    use read_req::options::all_options::AllOption;
+    use read_req::options::stream_options::RevisionOption;
+    match start {
+        Start::Stream(id, revision) => {
+            let current = backend.stream_version(id)?;
+            let (since_version, upper) = match (revision, backwards) {
+                (RevisionOption::Revision(revision), false) => (revision, u64::MAX),
+                (RevisionOption::Start(_), false) => (0, u64::MAX),
+                (RevisionOption::End(_), false) => (current as u64, u64::MAX),
+                (RevisionOption::Revision(revision), true) => {
+                    let upper = revision + 1;
+                    (upper.saturating_sub(count as u64), upper)
+                }
+                (RevisionOption::Start(_), true) => (0, 1),
+                (RevisionOption::End(_), true) => (
+                    (current as u64).saturating_sub(count as u64),
+                    current as u64,
+                ),
+            };
+            let opts = GetAggOpts {
+                agg_id: id,
+                since_version: since_version.min(u32::MAX as u64) as u32,
+            };
+            let mut events = backend.get_aggretate_with_opts(id, &opts)?;
+            events.retain(|event| event.version as u64 <= upper);
+            if backwards {
+                events.reverse();
+            }
+            events.truncate(count);
+            Ok(events)
+        }
+        Start::All(position) => {
+            let mut cursor = match (position, backwards) {
+                (AllOption::Position(position), false) => {
+                    position.commit_position.saturating_sub(1)
+                }
+                (AllOption::Start(_), false) => 0,
+                (AllOption::End(_), false) => return Ok(Vec::new()),
+                (AllOption::Position(position), true) => position.commit_position.saturating_add(1),
+                (AllOption::Start(_), true) => return Ok(Vec::new()),
+                (AllOption::End(_), true) => u64::MAX,
+            };
+            let mut events = Vec::new();
+            while events.len() < count {
+                let batch = match backwards {
+                    false => backend.read_all(cursor, BATCH)?,
+                    true => backend.read_all_backward(cursor, BATCH)?,
+                };
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                cursor = last.position;
+                let done = batch.len() < BATCH;
+                events.extend(
+                    batch
+                        .into_iter()
+                        .filter(|event| filter.is_none_or(|filter| filter.matches(event))),
+                );
+                if done {
+                    break;
+                }
+            }
+            events.truncate(count);
+            Ok(events)
+        }
+    }
+}
+
+type ReadStream = Pin<Box<dyn Stream<Item = Result<ReadResp, Status>> + Send>>;
+
+/// Send the events after `start` and keep following the stream or `$all`
+/// until the subscriber goes away.
+async fn subscribe(
+    backend: SqliteBackend,
+    start: Start,
+    filter: Option<Filter>,
+    structured: bool,
+    poll_interval: Duration,
+    sender: Sender<Result<ReadResp, Status>>,
+) {
+    use read_req::options::all_options::AllOption;
+    use read_req::options::stream_options::RevisionOption;
+    let confirmation = read_resp::SubscriptionConfirmation {
+        subscription_id: Uuid::new_v4().to_string(),
+    };
+    if sender
+        .send(Ok(message(read_resp::Content::Confirmation(confirmation))))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    let reader = backend.clone();
+    // subscriptions start after the given revision or position
+    let cursor = blocking(move || match start {
+        Start::Stream(_, RevisionOption::Revision(revision)) => Ok(revision + 1),
+        Start::Stream(id, RevisionOption::End(_)) => Ok(reader.stream_version(id)? as u64),
+        Start::All(AllOption::Position(position)) => Ok(position.commit_position),
+        Start::All(AllOption::End(_)) => reader.current_position(),
+        Start::Stream(_, RevisionOption::Start(_)) | Start::All(AllOption::Start(_)) => Ok(0),
+    })
+    .await;
+    let mut cursor = match cursor {
+        Ok(cursor) => cursor,
+        Err(status) => {
+            let _ = sender.send(Err(status)).await;
+            return;
+        }
+    };
+    let mut caught_up = false;
+    loop {
+        let reader = backend.clone();
+        let events = blocking(move || match start {
+            Start::Stream(id, _) => {
+                let opts = GetAggOpts {
+                    agg_id: id,
+                    since_version: cursor.min(u32::MAX as u64) as u32,
+                };
+                reader.get_aggretate_with_opts(id, &opts)
+            }
+            Start::All(_) => reader.read_all(cursor, BATCH),
+        })
+        .await;
+        let events = match events {
+            Ok(events) => events,
+            Err(status) => {
+                let _ = sender.send(Err(status)).await;
+                return;
+            }
+        };
+        if events.is_empty() {
+            if !caught_up {
+                caught_up = true;
+                let content = read_resp::Content::CaughtUp(read_resp::CaughtUp {});
+                if sender.send(Ok(message(content))).await.is_err() {
+                    return;
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => continue,
+                _ = sender.closed() => break,
+            }
+        }
+        let mut skipped = false;
+        for event in events {
+            cursor = match start {
+                Start::Stream(..) => event.version as u64,
+                Start::All(_) => event.position,
+            };
+            if filter
+                .as_ref()
+                .is_some_and(|filter| !filter.matches(&event))
+            {
+                skipped = true;
+                continue;
+            }
+            if sender.send(Ok(recorded(event, structured))).await.is_err() {
+                debug!("subscriber went away");
+                return;
+            }
+        }
+        if skipped {
+            let checkpoint = read_resp::Checkpoint {
+                commit_position: cursor,
+                prepare_position: cursor,
+            };
+            let content = read_resp::Content::Checkpoint(checkpoint);
+            if sender.send(Ok(message(content))).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Streams for EsdbService {
+    type ReadStream = ReadStream;
+
+    #[instrument(skip(self))]
+    async fn read(&self, request: Request<ReadReq>) -> Result<Response<ReadStream>, Status> {
+        use read_req::options::{uuid_option, CountOption, ReadDirection};
+        let backend = self.backend(&request)?;
+        let options = request
+            .into_inner()
+            .options
+            .ok_or_else(|| Status::invalid_argument("missing options"))?;
+        let structured = matches!(
+            options.uuid_option.as_ref().and_then(|o| o.content),
+            Some(uuid_option::Content::Structured(_))
+        );
+        let backwards = options.read_direction() == ReadDirection::Backwards;
+        let start = Start::parse(options.stream_option)?;
+        let filter = Filter::parse(options.filter_option)?;
+
+        match options.count_option {
+            Some(CountOption::Count(count)) => {
+                let count = count.min(u32::MAX as u64) as usize;
+                let messages = blocking(move || {
+                    if let Start::Stream(id, _) = start {
+                        if backend.stream_version(id)? == 0 {
+                            let not_found = read_resp::StreamNotFound {
+                                stream_identifier: Some(identifier(id)),
+                            };
+                            return Ok(vec![message(read_resp::Content::StreamNotFound(
+                                not_found,
+                            ))]);
+                        }
+                    }
+                    let events = read(&backend, start, backwards, count, filter.as_ref())?;
+                    Ok(events
+                        .into_iter()
+                        .map(|event| recorded(event, structured))
+                        .collect())
+                })
+                .await?;
+                Ok(Response::new(Box::pin(tokio_stream::iter(
+                    messages.into_iter().map(Ok),
+                ))))
+            }
+            Some(CountOption::Subscription(_)) => {
+                if backwards {
+                    return Err(Status::invalid_argument(
+                        "subscriptions can only read forwards",
+                    ));
+                }
+                let (sender, receiver) = tokio::sync::mpsc::channel(BATCH);
+                tokio::spawn(subscribe(
+                    backend,
+                    start,
+                    filter,
+                    structured,
+                    self.poll_interval,
+                    sender,
+                ));
+                Ok(Response::new(Box::pin(
+                    tokio_stream::wrappers::ReceiverStream::new(receiver),
+                )))
+            }
+            None => Err(Status::invalid_argument("missing count option")),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn append(
+        &self,
+        request: Request<Streaming<AppendReq>>,
+    ) -> Result<Response<AppendResp>, Status> {
+        let backend = self.backend(&request)?;
+        let mut messages = request.into_inner();
+        let options = match messages.message().await? {
+            Some(AppendReq {
+                content: Some(append_req::Content::Options(options)),
+            }) => options,
+            _ => return Err(Status::invalid_argument("expected append options first")),
+        };
+        let id = stream_id(options.stream_identifier)?;
+        let expected = options
+            .expected_stream_revision
+            .map_or(Expected::Any, Expected::from);
+        let mut events = Vec::new();
+        while let Some(message) = messages.message().await? {
+            match message.content {
+                Some(append_req::Content::ProposedMessage(message)) => {
+                    events.push(proposed(id, message)?)
+                }
+                _ => return Err(Status::invalid_argument("expected proposed messages")),
+            }
+        }
+
+        let result = blocking(move || {
+            let current = backend.stream_version(id)?;
+            if !expected.matches(current) {
+                return Ok(Err(current));
+            }
+            for (event, version) in events.iter_mut().zip(current + 1..) {
+                event.version = version;
+            }
+            if !events.is_empty() {
+                match backend.append_events(&events) {
+                    Ok(()) => {}
+                    Err(err) if err.is_version_conflict() => {
+                        return Ok(Err(backend.stream_version(id)?))
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            let version = current + events.len() as u32;
+            let position = match version {
+                0 => None,
+                version => {
+                    let opts = GetAggOpts {
+                        agg_id: id,
+                        since_version: version - 1,
+                    };
+                    backend
+                        .get_aggretate_with_opts(id, &opts)?
+                        .first()
+                        .map(|event| event.position)
+                }
+            };
+            Ok(Ok((version, position)))
+        })
+        .await?;
+
+        use append_resp::success::{CurrentRevisionOption, PositionOption};
+        let result = match result {
+            Ok((version, position)) => append_resp::Result::Success(append_resp::Success {
+                current_revision_option: Some(match version {
+                    0 => CurrentRevisionOption::NoStream(shared::Empty {}),
+                    version => CurrentRevisionOption::CurrentRevision(version as u64 - 1),
+                }),
+                position_option: Some(match position {
+                    Some(position) => PositionOption::Position(append_resp::Position {
+                        commit_position: position,
+                        prepare_position: position,
+                    }),
+                    None => PositionOption::NoPosition(shared::Empty {}),
+                }),
+            }),
+            Err(current) => {
+                append_resp::Result::WrongExpectedVersion(expected.wrong_expected_version(current))
+            }
+        };
+        Ok(Response::new(AppendResp {
+            result: Some(result),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn delete(&self, request: Request<DeleteReq>) -> Result<Response<DeleteResp>, Status> {
+        let backend = self.backend(&request)?;
+        let options = request
+            .into_inner()
+            .options
+            .ok_or_else(|| Status::invalid_argument("missing options"))?;
+        let id = stream_id(options.stream_identifier)?;
+        let expected = options
+            .expected_stream_revision
+            .map_or(Expected::Any, Expected::from);
+        blocking(move || {
+            let current = backend.stream_version(id)?;
+            if !expected.matches(current) {
+                return Ok(Err(expected.status(current)));
+            }
+            backend.delete_stream(id).map(Ok)
+        })
+        .await??;
+        Ok(Response::new(DeleteResp {
+            position_option: Some(shared::streams::delete_resp::PositionOption::NoPosition(
+                shared::Empty {},
+            )),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn tombstone(
+        &self,
+        request: Request<TombstoneReq>,
+    ) -> Result<Response<TombstoneResp>, Status> {
+        let backend = self.backend(&request)?;
+        let options = request
+            .into_inner()
+            .options
+            .ok_or_else(|| Status::invalid_argument("missing options"))?;
+        let id = stream_id(options.stream_identifier)?;
+        let expected = options
+            .expected_stream_revision
+            .map_or(Expected::Any, Expected::from);
+        blocking(move || {
+            let current = backend.stream_version(id)?;
+            if !expected.matches(current) {
+                return Ok(Err(expected.status(current)));
+            }
+            backend.tombstone_stream(id).map(Ok)
+        })
+        .await??;
+        Ok(Response::new(TombstoneResp {
+            position_option: Some(shared::streams::tombstone_resp::PositionOption::NoPosition(
+                shared::Empty {},
+            )),
+        }))
+    }
+}
@@ -0,0 +1,233 @@
+//! Leader/follower replication for the embedded deployment model.
+//!
+//! A primary serves its store with `GrpcService`, followers subscribe to
+//! it and append every committed event to their own database in commit
+//! order. Serve reads from a follower's file with `SqliteBackend::read_only`
+//! so clients cannot write to it:
+//!
+//! ```ignore
+//! let follower = Follower::new(SqliteBackend::new(manager), "http://primary:50051")
+//!     .spawn();
+//! // ...
+//! let lag = follower.lag();
+//! info!(behind = lag.events(), "replication lag");
+//! ```
+//!
+//! The position of the primary replicated last is kept as the checkpoint
+//! `replication:<name>` of the follower, so a restarted follower resumes
+//! where it stopped. Events already stored, e.g. after a crash between the
+//! append and the checkpoint, are skipped.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::Status;
+use tracing::{debug, info, instrument, warn};
+
+use super::proto::{self, event_store_client::EventStoreClient};
+use super::{blocking, parse_id};
+use crate::backend::model::Event;
+use crate::backend::sqlite::SqliteBackend;
+
+/// How far a follower is behind its primary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicationLag {
+    /// Position of the primary up to which events were replicated.
+    pub applied_position: u64,
+    /// Latest position of the primary the follower knows of.
+    pub primary_position: u64,
+    /// Whether the follower is subscribed to the primary.
+    pub connected: bool,
+}
+
+impl ReplicationLag {
+    /// Events of the primary not replicated yet.
+    pub fn events(&self) -> u64 {
+        self.primary_position.saturating_sub(self.applied_position)
+    }
+}
+
+/// Replicates the events of a primary into a local store, see the module
+/// documentation.
+#[derive(Debug, Clone)]
+pub struct Follower {
+    backend: SqliteBackend,
+    endpoint: String,
+    name: String,
+    lag_interval: Duration,
+    retry_interval: Duration,
+}
+
+impl Follower {
+    /// Follow the `GrpcService` at `endpoint`, e.g. `http://primary:50051`.
+    pub fn new(backend: SqliteBackend, endpoint: impl Into<String>) -> Self {
+        Self {
+            backend,
+            endpoint: endpoint.into(),
+            name: "primary".to_string(),
+            lag_interval: Duration::from_secs(1),
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Name of the checkpoint and metrics label, to follow several
+    /// primaries into one store.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// How often the head position of the primary is asked for.
+    pub fn with_lag_interval(mut self, lag_interval: Duration) -> Self {
+        self.lag_interval = lag_interval;
+        self
+    }
+
+    /// How long to wait before reconnecting after the primary went away.
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    fn checkpoint(&self) -> String {
+        format!("replication:{}", self.name)
+    }
+
+    /// Replicate on the current tokio runtime until the handle is stopped
+    /// or dropped.
+    pub fn spawn(self) -> FollowerHandle {
+        let (sender, lag) = watch::channel(ReplicationLag::default());
+        let task = tokio::spawn(async move {
+            loop {
+                if let Err(status) = self.follow(&sender).await {
+                    warn!(follower = %self.name, "replication interrupted: {}", status);
+                }
+                sender.send_modify(|lag| lag.connected = false);
+                tokio::time::sleep(self.retry_interval).await;
+            }
+        });
+        FollowerHandle { lag, task }
+    }
+
+    /// Subscribe to the primary and apply its events until it goes away.
+    #[instrument(skip(self, lag), fields(follower = %self.name))]
+    async fn follow(&self, lag: &watch::Sender<ReplicationLag>) -> Result<(), Status> {
+        let mut client = EventStoreClient::connect(self.endpoint.clone())
+            .await
+            .map_err(|err| Status::unavailable(err.to_string()))?;
+        let backend = self.backend.clone();
+        let checkpoint = self.checkpoint();
+        let applied = blocking(move || backend.get_checkpoint(&checkpoint)).await?;
+        let mut events = client
+            .subscribe(proto::SubscribeRequest {
+                from_position: applied,
+                aggregate_id: None,
+            })
+            .await?
+            .into_inner();
+        let primary = head(&mut client).await?;
+        info!(applied, primary, "following primary");
+        self.report(lag, |lag| {
+            lag.applied_position = applied;
+            lag.primary_position = primary.max(applied);
+            lag.connected = true;
+        });
+        let mut ticks = tokio::time::interval(self.lag_interval);
+        loop {
+            tokio::select! {
+                next = events.next() => {
+                    let Some(recorded) = next.transpose()? else {
+                        return Err(Status::unavailable("primary closed the subscription"));
+                    };
+                    let position = recorded.position;
+                    self.apply(recorded).await?;
+                    self.report(lag, |lag| {
+                        lag.applied_position = position;
+                        lag.primary_position = lag.primary_position.max(position);
+                    });
+                }
+                _ = ticks.tick() => {
+                    let primary = head(&mut client).await?;
+                    self.report(lag, |lag| {
+                        lag.primary_position = lag.primary_position.max(primary);
+                    });
+                }
+            }
+        }
+    }
+
+    /// Append an event of the primary unless it is stored already, then
+    /// advance the checkpoint past it.
+    async fn apply(&self, recorded: proto::RecordedEvent) -> Result<(), Status> {
+        let event = Event {
+            id: parse_id(&recorded.aggregate_id)?,
+            version: recorded.version,
+            event_type: recorded.event_type,
+            schema_version: recorded.schema_version,
+            content_type: recorded.content_type,
+            data: recorded.data,
+            metadata: recorded.metadata.into_iter().collect(),
+            ..Default::default()
+        };
+        let backend = self.backend.clone();
+        let checkpoint = self.checkpoint();
+        blocking(move || {
+            if backend.stream_version(event.id)? < event.version {
+                backend.append_events(std::slice::from_ref(&event))?;
+            } else {
+                debug!(aggregate_id = %event.id, version = event.version, "already replicated");
+            }
+            backend.save_checkpoint(&checkpoint, recorded.position)
+        })
+        .await
+    }
+
+    fn report(
+        &self,
+        lag: &watch::Sender<ReplicationLag>,
+        update: impl FnOnce(&mut ReplicationLag),
+    ) {
+        lag.send_modify(update);
+        #[cfg(feature = "metrics")]
+        crate::metrics::replication_lag(&self.name, lag.borrow().events());
+    }
+}
+
+async fn head(client: &mut EventStoreClient<Channel>) -> Result<u64, Status> {
+    Ok(client
+        .head(proto::HeadRequest {})
+        .await?
+        .into_inner()
+        .position)
+}
+
+/// A running `Follower`, replication stops when the handle is dropped.
+#[derive(Debug)]
+pub struct FollowerHandle {
+    lag: watch::Receiver<ReplicationLag>,
+    task: JoinHandle<()>,
+}
+
+impl FollowerHandle {
+    pub fn lag(&self) -> ReplicationLag {
+        *self.lag.borrow()
+    }
+
+    /// Wait until the events of the primary up to `position` are
+    /// replicated, e.g. to read your own writes from a follower.
+    pub async fn wait_for(&self, position: u64) {
+        let mut lag = self.lag.clone();
+        let _ = lag.wait_for(|lag| lag.applied_position >= position).await;
+    }
+
+    pub fn stop(self) {}
+}
+
+impl Drop for FollowerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
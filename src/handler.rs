@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use rusqlite::Transaction;
+use tracing::warn;
+
+use crate::backend::{model::Event, sqlite::Error};
+
+/// Handler invoked synchronously inside the append transaction.
+///
+/// The handler gets the transaction, so same-process read models stored in
+/// the same database are updated atomically with the events.
+pub trait EventHandler: Send + Sync {
+    fn handle(&self, tx: &Transaction, event: &Event) -> Result<(), Error>;
+}
+
+impl<F> EventHandler for F
+where
+    F: Fn(&Transaction, &Event) -> Result<(), Error> + Send + Sync,
+{
+    fn handle(&self, tx: &Transaction, event: &Event) -> Result<(), Error> {
+        self(tx, event)
+    }
+}
+
+/// What happens to an append when a handler fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Roll back the whole append and return the handler's error.
+    #[default]
+    Abort,
+    /// Roll back only the writes of the failing handler and log the error.
+    LogAndContinue,
+}
+
+struct Registered {
+    name: String,
+    policy: ErrorPolicy,
+    handler: Arc<dyn EventHandler>,
+}
+
+/// Ordered list of handlers run for every appended event.
+#[derive(Default)]
+pub struct HandlerRegistry {
+    handlers: Vec<Registered>,
+}
+
+impl std::fmt::Debug for HandlerRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.handlers.iter().map(|h| (&h.name, h.policy)))
+            .finish()
+    }
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<H: EventHandler + 'static>(
+        &mut self,
+        name: &str,
+        policy: ErrorPolicy,
+        handler: H,
+    ) -> &mut Self {
+        self.handlers.push(Registered {
+            name: name.to_string(),
+            policy,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Run all handlers for `event`, each inside its own savepoint.
+    pub fn dispatch(&self, tx: &Transaction, event: &Event) -> Result<(), Error> {
+        for registered in &self.handlers {
+            tx.execute_batch("SAVEPOINT event_handler")?;
+            match registered.handler.handle(tx, event) {
+                Ok(()) => tx.execute_batch("RELEASE event_handler")?,
+                Err(err) => {
+                    tx.execute_batch("ROLLBACK TO event_handler; RELEASE event_handler")?;
+                    warn!(
+                        handler = registered.name,
+                        error = err.to_string(),
+                        "event handler failed"
+                    );
+                    if registered.policy == ErrorPolicy::Abort {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
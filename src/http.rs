@@ -0,0 +1,445 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::authorization::CallerContext;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use crate::codec;
+use crate::jsonl::Envelope;
+
+mod extract;
+mod feed;
+mod ws;
+
+pub use extract::{CorrelationId, EventStoreHandle, CORRELATION_ID_HEADER, CORRELATION_ID_KEY};
+pub use feed::{Feed, FeedEntry, Link, ALL_STREAM, FEED_CONTENT_TYPE};
+pub use ws::SubscribeQuery;
+
+/// Request header selecting the tenant, see `SqliteBackend::tenant`.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Request header making an append idempotent, see
+/// `SqliteBackend::append_if_new_command`.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Maps request headers to the caller the store acts for, e.g. by
+/// validating a bearer token. Rejections are returned as is, operations are
+/// then checked by the `Authorizer` of the backend.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<CallerContext, StatusCode>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&HeaderMap) -> Result<CallerContext, StatusCode> + Send + Sync,
+{
+    fn authenticate(&self, headers: &HeaderMap) -> Result<CallerContext, StatusCode> {
+        self(headers)
+    }
+}
+
+/// JSON API over a backend:
+///
+/// - `POST /streams/{id}` appends `{"expected_version": 0, "events": [...]}`
+/// - `GET /streams/{id}?since_version=&limit=&resolve_links=` reads a
+///   stream page
+/// - `GET /streams/{id}/snapshots` and `/streams/{id}/snapshots/{version}`
+/// - `GET /all?from_position=&limit=` reads all streams in commit order
+/// - `GET /position` returns the highest committed position
+/// - `GET /subscribe?from_position=&stream=&category=` streams events over
+///   a WebSocket, see `SubscribeQuery`
+/// - `GET /feed/{stream}` and `/feed/{stream}/{start}/{forward|backward}/{count}`
+///   page through a stream or `$all` by link relations like the EventStore
+///   Atom API, see `Feed`; `/feed/{stream}/{number}` returns one entry
+///
+/// Events are returned as `jsonl::Envelope`s. Appended events carry the
+/// `x-correlation-id` request header in their metadata, see
+/// `CorrelationId`. Appends with an `Idempotency-Key` header are applied
+/// once, retries answer `200 OK` instead of `201 Created`.
+#[derive(Clone)]
+pub struct HttpApi {
+    backend: SqliteBackend,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    poll_interval: Duration,
+}
+
+impl std::fmt::Debug for HttpApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpApi")
+            .field("backend", &self.backend)
+            .field("authenticator", &self.authenticator.is_some())
+            .finish()
+    }
+}
+
+/// Router with the endpoints of `HttpApi`, to be served on its own or
+/// nested into an existing application.
+pub fn router(backend: SqliteBackend) -> Router {
+    HttpApi::new(backend).into_router()
+}
+
+impl HttpApi {
+    pub fn new(backend: SqliteBackend) -> Self {
+        Self {
+            backend,
+            authenticator: None,
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+
+    /// Authenticate every request with `authenticator`.
+    pub fn with_authenticator<A: Authenticator + 'static>(mut self, authenticator: A) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// How often WebSocket subscriptions look for new events once caught
+    /// up.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn into_router(self) -> Router {
+        Router::new()
+            .route("/streams/{id}", get(read_stream).post(append))
+            .route("/streams/{id}/snapshots", get(snapshots))
+            .route("/streams/{id}/snapshots/{version}", get(snapshot))
+            .route("/all", get(read_all))
+            .route("/position", get(position))
+            .route("/subscribe", get(ws::subscribe))
+            .route("/feed/{stream}", get(feed::head))
+            .route("/feed/{stream}/{number}", get(feed::event))
+            .route(
+                "/feed/{stream}/{start}/{direction}/{count}",
+                get(feed::paged),
+            )
+            .with_state(self)
+    }
+
+    /// Backend scoped to the tenant and caller of the request.
+    fn backend(&self, headers: &HeaderMap) -> Result<SqliteBackend, ApiError> {
+        let mut backend = match headers.get(TENANT_HEADER) {
+            Some(tenant) => self.backend.tenant(
+                tenant
+                    .to_str()
+                    .map_err(|_| ApiError::bad_request("invalid tenant id"))?,
+            ),
+            None => self.backend.clone(),
+        };
+        if let Some(authenticator) = &self.authenticator {
+            let caller = authenticator
+                .authenticate(headers)
+                .map_err(|status| ApiError::new(status, "authentication failed"))?;
+            backend = backend.as_caller(caller);
+        }
+        Ok(backend)
+    }
+}
+
+/// Error response with a JSON body `{"error": "..."}`. Store errors
+/// convert into it with a fitting status, so handlers can return
+/// `Result<_, ApiError>` and use `?` on store calls.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Close frame ending a WebSocket after a failure.
+    fn close_frame(&self) -> axum::extract::ws::CloseFrame {
+        axum::extract::ws::CloseFrame {
+            code: axum::extract::ws::close_code::ERROR,
+            reason: self.message.clone().into(),
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let status = match err {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidUUID
+            | Error::UnknownEventType(_)
+            | Error::UnexpectedEventType { .. }
+            | Error::SchemaViolation(_)
+            | Error::SchemaVersionMismatch { .. }
+            | Error::Codec(_) => StatusCode::BAD_REQUEST,
+            Error::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::StreamDeleted(_) => StatusCode::GONE,
+            Error::StreamRenamed(_) => StatusCode::GONE,
+            Error::ReadOnly => StatusCode::METHOD_NOT_ALLOWED,
+            Error::VersionConflict | Error::WithMsg(_) => StatusCode::CONFLICT,
+            Error::Unauthorized(_) => StatusCode::FORBIDDEN,
+            Error::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, err.to_string())
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            warn!(status = %self.status, error = self.message, "request failed");
+        }
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+/// Run blocking store calls off the async workers.
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .map_err(ApiError::from)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::bad_request("invalid aggregate id"))
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+fn default_content_type() -> String {
+    codec::JSON.to_string()
+}
+
+/// Event in an append request, payloads are given like in `Envelope`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewEvent {
+    pub event_type: String,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub data: Option<Value>,
+    #[serde(default)]
+    pub data_hex: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppendRequest {
+    #[serde(default)]
+    pub expected_version: u32,
+    pub events: Vec<NewEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendResponse {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StreamQuery {
+    #[serde(default)]
+    pub since_version: u32,
+    pub limit: Option<usize>,
+    /// Return the events link events point to instead of the links, see
+    /// `SqliteBackend::resolve_links`.
+    #[serde(default)]
+    pub resolve_links: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AllQuery {
+    #[serde(default)]
+    pub from_position: u64,
+    pub limit: Option<usize>,
+}
+
+/// A page of events, `next` is the `since_version` or `from_position` of
+/// the following page and absent on the last one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page {
+    pub events: Vec<Envelope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<u64>,
+}
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+fn limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+async fn append(
+    State(api): State<HttpApi>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<AppendRequest>,
+) -> Result<(StatusCode, Json<AppendResponse>), ApiError> {
+    let backend = api.backend(&headers)?;
+    let id = parse_id(&id)?;
+    let correlation_id = CorrelationId::from_headers(&headers)?;
+    let mut events = request
+        .events
+        .into_iter()
+        .zip(request.expected_version + 1..)
+        .map(|(event, version)| {
+            Envelope {
+                position: 0,
+                aggregate_id: id,
+                version,
+                event_type: event.event_type,
+                schema_version: event.schema_version,
+                content_type: event.content_type,
+                data: event.data,
+                data_hex: event.data_hex,
+                metadata: event.metadata,
+            }
+            .into_event()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(correlation_id) = correlation_id {
+        correlation_id.apply(&mut events);
+    }
+    let version = request.expected_version + events.len() as u32;
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|key| {
+            key.to_str()
+                .map(str::to_string)
+                .map_err(|_| ApiError::bad_request("invalid idempotency key"))
+        })
+        .transpose()?;
+    let appended = blocking(move || match idempotency_key {
+        Some(key) => backend.append_if_new_command(&key, &events),
+        None => backend.append_events(&events).map(|_| true),
+    })
+    .await?;
+    let status = match appended {
+        true => StatusCode::CREATED,
+        false => StatusCode::OK,
+    };
+    Ok((status, Json(AppendResponse { version })))
+}
+
+async fn read_stream(
+    State(api): State<HttpApi>,
+    Path(id): Path<String>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Page>, ApiError> {
+    let backend = api.backend(&headers)?;
+    let id = parse_id(&id)?;
+    let opts = GetAggOpts {
+        agg_id: id,
+        since_version: query.since_version,
+    };
+    let reader = backend.clone();
+    let mut events = blocking(move || reader.get_aggretate_with_opts(id, &opts)).await?;
+    let limit = limit(query.limit);
+    let next = (events.len() > limit).then(|| events[limit - 1].version as u64);
+    events.truncate(limit);
+    if query.resolve_links {
+        events = blocking(move || backend.resolve_links(events)).await?;
+    }
+    Ok(Json(Page {
+        events: events.iter().map(Envelope::from_event).collect(),
+        next,
+    }))
+}
+
+async fn read_all(
+    State(api): State<HttpApi>,
+    Query(query): Query<AllQuery>,
+    headers: HeaderMap,
+) -> Result<Json<Page>, ApiError> {
+    let backend = api.backend(&headers)?;
+    let limit = limit(query.limit);
+    let mut events = blocking(move || backend.read_all(query.from_position, limit + 1)).await?;
+    let next = (events.len() > limit).then(|| events[limit - 1].position);
+    events.truncate(limit);
+    Ok(Json(Page {
+        events: events.iter().map(Envelope::from_event).collect(),
+        next,
+    }))
+}
+
+/// Body of `GET /position`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Position {
+    /// Highest committed global position, see
+    /// `SqliteBackend::current_position`.
+    pub position: u64,
+}
+
+async fn position(
+    State(api): State<HttpApi>,
+    headers: HeaderMap,
+) -> Result<Json<Position>, ApiError> {
+    let backend = api.backend(&headers)?;
+    let position = blocking(move || backend.current_position()).await?;
+    Ok(Json(Position { position }))
+}
+
+async fn snapshots(
+    State(api): State<HttpApi>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Envelope>>, ApiError> {
+    let backend = api.backend(&headers)?;
+    let id = parse_id(&id)?;
+    let snapshots = blocking(move || backend.get_snapshots(id)).await?;
+    Ok(Json(snapshots.iter().map(Envelope::from_event).collect()))
+}
+
+async fn snapshot(
+    State(api): State<HttpApi>,
+    Path((id, version)): Path<(String, u32)>,
+    headers: HeaderMap,
+) -> Result<Json<Envelope>, ApiError> {
+    let backend = api.backend(&headers)?;
+    let id = parse_id(&id)?;
+    let snapshot = blocking(move || backend.get_snapshot_by_version(id, version)).await?;
+    Ok(Json(Envelope::from_event(&snapshot)))
+}
@@ -0,0 +1,121 @@
+//! Building blocks for handlers of applications that embed the store in
+//! their own axum router:
+//!
+//! ```ignore
+//! async fn place_order(
+//!     State(store): State<EventStoreHandle>,
+//!     correlation_id: CorrelationId,
+//!     Json(order): Json<Order>,
+//! ) -> Result<StatusCode, ApiError> {
+//!     let mut events = vec![Event::encode(&OrderPlaced::from(&order))?];
+//!     correlation_id.apply(&mut events);
+//!     store.run(move |backend| backend.append_events(&events)).await?;
+//!     Ok(StatusCode::CREATED)
+//! }
+//!
+//! let app = Router::new()
+//!     .route("/orders", post(place_order))
+//!     .with_state(EventStoreHandle::new(backend));
+//! ```
+
+use std::ops::Deref;
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+use super::{blocking, ApiError};
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, SqliteBackend};
+
+/// Request header carrying the id that ties together everything done on
+/// behalf of one request.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Metadata key the correlation id is stored under.
+pub const CORRELATION_ID_KEY: &str = crate::backend::model::CORRELATION_ID;
+
+/// The store as router state, extracted with `State<EventStoreHandle>`.
+/// Applications with their own state type implement
+/// `FromRef<AppState> for EventStoreHandle`.
+#[derive(Debug, Clone)]
+pub struct EventStoreHandle {
+    backend: SqliteBackend,
+}
+
+impl EventStoreHandle {
+    pub fn new(backend: SqliteBackend) -> Self {
+        Self { backend }
+    }
+
+    pub fn backend(&self) -> &SqliteBackend {
+        &self.backend
+    }
+
+    /// Run `f` on the blocking pool, store errors become `ApiError`s.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, ApiError>
+    where
+        F: FnOnce(&SqliteBackend) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let backend = self.backend.clone();
+        blocking(move || f(&backend)).await
+    }
+}
+
+impl From<SqliteBackend> for EventStoreHandle {
+    fn from(backend: SqliteBackend) -> Self {
+        Self::new(backend)
+    }
+}
+
+impl Deref for EventStoreHandle {
+    type Target = SqliteBackend;
+
+    fn deref(&self) -> &SqliteBackend {
+        &self.backend
+    }
+}
+
+/// Correlation id of a request, taken from the `x-correlation-id` header
+/// or newly generated when the header is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrelationId(pub String);
+
+impl CorrelationId {
+    /// The id in the request headers, if any.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, ApiError> {
+        headers
+            .get(CORRELATION_ID_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(|id| Self(id.to_string()))
+                    .map_err(|_| ApiError::bad_request("invalid correlation id"))
+            })
+            .transpose()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Record the id in the metadata of `events` that have none yet.
+    pub fn apply(&self, events: &mut [Event]) {
+        for event in events {
+            event
+                .metadata
+                .entry(CORRELATION_ID_KEY.to_string())
+                .or_insert_with(|| self.0.clone());
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CorrelationId {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, ApiError> {
+        Ok(Self::from_headers(&parts.headers)?.unwrap_or_else(|| Self(Uuid::new_v4().to_string())))
+    }
+}
@@ -0,0 +1,317 @@
+use axum::extract::{OriginalUri, Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{blocking, parse_id, ApiError, HttpApi, MAX_LIMIT};
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use crate::jsonl::Envelope;
+
+/// Content type of feed responses, the JSON flavour of the EventStore Atom
+/// API.
+pub const FEED_CONTENT_TYPE: &str = "application/vnd.eventstore.atom+json";
+
+/// Name of the feed over all streams in commit order.
+pub const ALL_STREAM: &str = "$all";
+
+const DEFAULT_COUNT: usize = 20;
+
+/// A page of a feed. Entries are ordered newest first, `links` lead to the
+/// neighbouring pages:
+///
+/// - `first`: the newest events, the head of the stream
+/// - `last`: the oldest events
+/// - `previous`: the newer events, polled by consumers that caught up
+/// - `next`: the older events, absent at the start of the stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feed {
+    pub title: String,
+    pub id: String,
+    pub stream_id: String,
+    /// Whether the page includes the latest event.
+    pub head_of_stream: bool,
+    pub links: Vec<Link>,
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Uri of the link with the relation `relation`.
+    pub fn link(&self, relation: &str) -> Option<&str> {
+        self.links
+            .iter()
+            .find(|link| link.relation == relation)
+            .map(|link| link.uri.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub uri: String,
+    pub relation: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedEntry {
+    pub id: String,
+    /// `{number}@{stream}`
+    pub title: String,
+    pub event_type: String,
+    /// Version of the event in the stream, its position in `$all`.
+    pub event_number: u64,
+    pub content: Envelope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    All,
+    Stream(Uuid),
+}
+
+impl Source {
+    fn parse(stream: &str) -> Result<Self, ApiError> {
+        match stream {
+            ALL_STREAM => Ok(Source::All),
+            id => parse_id(id).map(Source::Stream),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Source::All => ALL_STREAM.to_string(),
+            Source::Stream(id) => id.to_string(),
+        }
+    }
+
+    fn number(&self, event: &Event) -> u64 {
+        match self {
+            Source::All => event.position,
+            Source::Stream(_) => event.version as u64,
+        }
+    }
+
+    /// Number of the latest event, streams without events do not exist.
+    fn head(&self, backend: &SqliteBackend) -> Result<u64, Error> {
+        match self {
+            Source::All => backend.current_position(),
+            Source::Stream(id) => match backend.stream_version(*id)? {
+                0 => Err(Error::NotFound),
+                version => Ok(version as u64),
+            },
+        }
+    }
+
+    /// Up to `count` events numbered after `after`, oldest first.
+    fn read(&self, backend: &SqliteBackend, after: u64, count: usize) -> Result<Vec<Event>, Error> {
+        match self {
+            Source::All => backend.read_all(after, count),
+            Source::Stream(id) => {
+                let opts = GetAggOpts {
+                    agg_id: *id,
+                    since_version: after.min(u32::MAX as u64) as u32,
+                };
+                let mut events = backend.get_aggretate_with_opts(*id, &opts)?;
+                events.truncate(count);
+                Ok(events)
+            }
+        }
+    }
+}
+
+/// Path of the feed of `stream`, keeping the prefix the router is nested
+/// under.
+fn base_uri(uri: &OriginalUri, stream: &str) -> String {
+    let path = uri.path();
+    let prefix = path.find("/feed/").map_or("", |i| &path[..i]);
+    format!("{}/feed/{}", prefix, stream)
+}
+
+fn page_uri(base: &str, start: u64, direction: Direction, count: usize) -> String {
+    let direction = match direction {
+        Direction::Forward => "forward",
+        Direction::Backward => "backward",
+    };
+    format!("{}/{}/{}/{}", base, start, direction, count)
+}
+
+fn entry(source: Source, base: &str, event: &Event) -> FeedEntry {
+    let number = source.number(event);
+    FeedEntry {
+        id: format!("{}/{}", base, number),
+        title: format!("{}@{}", number, source.name()),
+        event_type: event.event_type.clone(),
+        event_number: number,
+        content: Envelope::from_event(event),
+    }
+}
+
+fn respond<T: Serialize>(body: T) -> Response {
+    ([(CONTENT_TYPE, FEED_CONTENT_TYPE)], Json(body)).into_response()
+}
+
+/// `GET /feed/{stream}`: the head page of a stream or `$all`.
+pub(super) async fn head(
+    State(api): State<HttpApi>,
+    Path(stream): Path<String>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let backend = api.backend(&headers)?;
+    let source = Source::parse(&stream)?;
+    let base = base_uri(&uri, &source.name());
+    let feed = page(
+        backend,
+        source,
+        base,
+        uri.path().to_string(),
+        None,
+        Direction::Backward,
+        DEFAULT_COUNT,
+    )
+    .await?;
+    Ok(respond(feed))
+}
+
+/// `GET /feed/{stream}/{start}/{direction}/{count}`: `count` events from
+/// `start` on, which is a number or `head`.
+pub(super) async fn paged(
+    State(api): State<HttpApi>,
+    Path((stream, start, direction, count)): Path<(String, String, String, usize)>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let backend = api.backend(&headers)?;
+    let source = Source::parse(&stream)?;
+    let start = match start.as_str() {
+        "head" => None,
+        number => Some(
+            number
+                .parse::<u64>()
+                .map_err(|_| ApiError::bad_request("invalid start"))?,
+        ),
+    };
+    let direction = match direction.as_str() {
+        "forward" => Direction::Forward,
+        "backward" => Direction::Backward,
+        _ => {
+            return Err(ApiError::bad_request(
+                "direction must be forward or backward",
+            ))
+        }
+    };
+    if count == 0 || count > MAX_LIMIT {
+        return Err(ApiError::bad_request(format!(
+            "count must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let base = base_uri(&uri, &source.name());
+    let self_uri = uri.path().to_string();
+    let feed = page(backend, source, base, self_uri, start, direction, count).await?;
+    Ok(respond(feed))
+}
+
+/// `GET /feed/{stream}/{number}`: a single entry.
+pub(super) async fn event(
+    State(api): State<HttpApi>,
+    Path((stream, number)): Path<(String, u64)>,
+    uri: OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let backend = api.backend(&headers)?;
+    let source = Source::parse(&stream)?;
+    let base = base_uri(&uri, &source.name());
+    let events = blocking(move || source.read(&backend, number.saturating_sub(1), 1)).await?;
+    match events.first() {
+        Some(event) if source.number(event) == number => Ok(respond(entry(source, &base, event))),
+        _ => Err(Error::NotFound.into()),
+    }
+}
+
+async fn page(
+    backend: SqliteBackend,
+    source: Source,
+    base: String,
+    self_uri: String,
+    start: Option<u64>,
+    direction: Direction,
+    count: usize,
+) -> Result<Feed, ApiError> {
+    let (head, events) = blocking(move || {
+        let head = source.head(&backend)?;
+        let events = match direction {
+            Direction::Forward => {
+                source.read(&backend, start.unwrap_or(head).saturating_sub(1), count)?
+            }
+            Direction::Backward => {
+                let start = start.unwrap_or(head).min(head);
+                let mut events =
+                    source.read(&backend, start.saturating_sub(count as u64), count)?;
+                events.retain(|event| source.number(event) <= start);
+                events
+            }
+        };
+        Ok((head, events))
+    })
+    .await?;
+
+    let start = start.unwrap_or(head);
+    let newest = events.last().map(|event| source.number(event));
+    let mut links = vec![
+        Link {
+            uri: self_uri,
+            relation: "self".to_string(),
+        },
+        Link {
+            uri: format!("{}/head/backward/{}", base, count),
+            relation: "first".to_string(),
+        },
+        Link {
+            uri: page_uri(&base, 1, Direction::Forward, count),
+            relation: "last".to_string(),
+        },
+    ];
+    let previous = match (newest, direction) {
+        (Some(newest), _) => newest + 1,
+        (None, Direction::Forward) => start.max(1),
+        (None, Direction::Backward) => start.min(head) + 1,
+    };
+    links.push(Link {
+        uri: page_uri(&base, previous, Direction::Forward, count),
+        relation: "previous".to_string(),
+    });
+    let older = match direction {
+        Direction::Forward => start.saturating_sub(1),
+        Direction::Backward => start.min(head).saturating_sub(count as u64),
+    };
+    if older > 0 {
+        links.push(Link {
+            uri: page_uri(&base, older, Direction::Backward, count),
+            relation: "next".to_string(),
+        });
+    }
+
+    Ok(Feed {
+        title: format!("Event stream '{}'", source.name()),
+        id: base.clone(),
+        stream_id: source.name(),
+        head_of_stream: newest.unwrap_or(previous - 1) >= head,
+        links,
+        entries: events
+            .iter()
+            .rev()
+            .map(|event| entry(source, &base, event))
+            .collect(),
+    })
+}
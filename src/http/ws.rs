@@ -0,0 +1,102 @@
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde::Deserialize;
+use tracing::debug;
+use uuid::Uuid;
+
+use super::{blocking, parse_id, ApiError, HttpApi};
+use crate::backend::model::Event;
+use crate::backend::sqlite::SqliteBackend;
+use crate::jsonl::Envelope;
+
+/// Selects the events of a subscription, all events if neither `stream` nor
+/// `category` is given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscribeQuery {
+    /// Deliver events committed after this position.
+    #[serde(default)]
+    pub from_position: u64,
+    /// Only events of this aggregate.
+    pub stream: Option<String>,
+    /// Only events whose type starts with this prefix, e.g. `order.`.
+    pub category: Option<String>,
+}
+
+struct Filter {
+    stream: Option<Uuid>,
+    category: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, event: &Event) -> bool {
+        self.stream.is_none_or(|id| id == event.id)
+            && self
+                .category
+                .as_ref()
+                .is_none_or(|prefix| event.event_type.starts_with(prefix.as_str()))
+    }
+}
+
+/// `GET /subscribe`: upgrade to a WebSocket and send every matching event
+/// as a text message holding its `Envelope`, first the committed ones, then
+/// new ones as they are appended.
+pub(super) async fn subscribe(
+    State(api): State<HttpApi>,
+    Query(query): Query<SubscribeQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let backend = api.backend(&headers)?;
+    let filter = Filter {
+        stream: query.stream.as_deref().map(parse_id).transpose()?,
+        category: query.category,
+    };
+    let poll_interval = api.poll_interval;
+    Ok(upgrade.on_upgrade(move |socket| {
+        stream_events(socket, backend, filter, query.from_position, poll_interval)
+    }))
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    backend: SqliteBackend,
+    filter: Filter,
+    mut position: u64,
+    poll_interval: std::time::Duration,
+) {
+    loop {
+        let reader = backend.clone();
+        let events = match blocking(move || reader.read_all(position, 256)).await {
+            Ok(events) => events,
+            Err(err) => {
+                let _ = socket.send(Message::Close(Some(err.close_frame()))).await;
+                return;
+            }
+        };
+        if events.is_empty() {
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => continue,
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            }
+        }
+        for event in events {
+            position = event.position;
+            if !filter.matches(&event) {
+                continue;
+            }
+            let message = match serde_json::to_string(&Envelope::from_event(&event)) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            if socket.send(Message::Text(message.into())).await.is_err() {
+                debug!("subscriber went away");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::Error;
+use crate::codec;
+
+/// One line of a JSON Lines export. JSON payloads are embedded as is,
+/// payloads of other content types are hex encoded in `data_hex`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub position: u64,
+    pub aggregate_id: Uuid,
+    pub version: u32,
+    pub event_type: String,
+    pub schema_version: u32,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_hex: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Envelope {
+    pub fn from_event(event: &Event) -> Self {
+        let json = match event.content_type.as_str() {
+            codec::JSON => serde_json::from_slice(&event.data).ok(),
+            _ => None,
+        };
+        Self {
+            position: event.position,
+            aggregate_id: event.id,
+            version: event.version,
+            event_type: event.event_type.clone(),
+            schema_version: event.schema_version,
+            content_type: event.content_type.clone(),
+            data_hex: json.is_none().then(|| encode_hex(&event.data)),
+            data: json,
+            metadata: event.metadata.clone(),
+        }
+    }
+
+    pub fn into_event(self) -> Result<Event, Error> {
+        let data = match (self.data, self.data_hex) {
+            (Some(data), _) => serde_json::to_vec(&data)?,
+            (None, Some(hex)) => decode_hex(&hex)?,
+            (None, None) => Vec::new(),
+        };
+        Ok(Event {
+            id: self.aggregate_id,
+            version: self.version,
+            event_type: self.event_type,
+            schema_version: self.schema_version,
+            content_type: self.content_type,
+            data,
+            metadata: self.metadata,
+            position: self.position,
+        })
+    }
+}
+
+/// Selects the events written by `SqliteBackend::export_jsonl`.
+#[derive(Debug, Clone)]
+pub struct ExportOpts {
+    /// Export events after this position.
+    pub from_position: u64,
+    /// Only export these aggregates, all if empty.
+    pub aggregates: Vec<Uuid>,
+    /// Only export these event types, all if empty.
+    pub event_types: Vec<String>,
+    /// Number of events read per query.
+    pub batch_size: usize,
+}
+
+impl Default for ExportOpts {
+    fn default() -> Self {
+        Self {
+            from_position: 0,
+            aggregates: Vec::new(),
+            event_types: Vec::new(),
+            batch_size: 500,
+        }
+    }
+}
+
+/// Controls `SqliteBackend::import_jsonl`.
+#[derive(Debug, Clone)]
+pub struct ImportOpts {
+    /// Aggregate ids to replace, e.g. to clone a stream next to the
+    /// original in the same store.
+    pub remap: HashMap<Uuid, Uuid>,
+    /// Number of events written per transaction.
+    pub batch_size: usize,
+    /// Drop the secondary indexes of the event table for the duration of
+    /// the import and rebuild them at the end, see `ImportOpts::bulk_load`.
+    pub bulk_load: bool,
+}
+
+/// Events per transaction of bulk loads.
+const BULK_LOAD_BATCH_SIZE: usize = 50_000;
+
+impl Default for ImportOpts {
+    fn default() -> Self {
+        Self {
+            remap: HashMap::new(),
+            batch_size: 500,
+            bulk_load: false,
+        }
+    }
+}
+
+impl ImportOpts {
+    pub fn remap(mut self, from: Uuid, to: Uuid) -> Self {
+        self.remap.insert(from, to);
+        self
+    }
+
+    /// Fast path for restoring large exports into a store nobody else
+    /// uses meanwhile: inserts do not maintain the secondary indexes, which
+    /// are rebuilt once all events are written, and events are written in
+    /// transactions of at least 50,000 events. Reads by position are slow
+    /// until the import finished, also those of the other tenants sharing
+    /// the tables.
+    pub fn bulk_load(mut self) -> Self {
+        self.bulk_load = true;
+        self.batch_size = self.batch_size.max(BULK_LOAD_BATCH_SIZE);
+        self
+    }
+}
+
+/// Outcome of an import. Events already present with the same content are
+/// skipped, so an interrupted import can simply be run again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+pub(crate) fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(Error::WithMsg("invalid hex payload".to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|err| Error::WithMsg(format!("invalid hex payload: {}", err)))
+        })
+        .collect()
+}
@@ -1,4 +1,59 @@
+extern crate self as eventstore;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+pub mod admin_log;
+pub mod aggregate;
+#[cfg(feature = "analytics")]
+pub mod analytics;
+pub mod authorization;
 pub mod backend;
+pub mod cache;
+pub mod clock;
+pub mod codec;
+pub mod compression;
+pub mod context;
+pub mod encryption;
+pub mod event;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handler;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod ids;
+pub mod jsonl;
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod object_store;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod projection;
+pub mod quota;
+pub mod redaction;
+pub mod schedule;
+#[cfg(feature = "schema-registry")]
+pub mod schema;
+pub mod stream;
+pub mod sync;
+pub mod system;
+#[cfg(feature = "opentelemetry")]
+pub mod telemetry;
+pub mod tenant;
+pub mod testing;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod upcast;
+pub mod wal_shipping;
+
+pub use aggregate::{Aggregate, Apply, Repository};
+pub use event::DomainEvent;
+pub use eventstore_derive::{Aggregate, DomainEvent};
+#[doc(hidden)]
+pub use inventory;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -0,0 +1,77 @@
+//! Metrics recorded through the `metrics` facade when the `metrics` feature
+//! is enabled, install a recorder such as `metrics-exporter-prometheus` to
+//! expose them. Without the feature recording compiles to nothing.
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Duration;
+
+/// Counter of committed append calls.
+pub const APPENDS: &str = "eventstore_appends_total";
+/// Counter of committed events.
+pub const EVENTS_APPENDED: &str = "eventstore_events_appended_total";
+/// Counter of appends rejected because the expected version was outdated.
+pub const APPEND_CONFLICTS: &str = "eventstore_append_conflicts_total";
+/// Histogram of the duration of append calls in seconds.
+pub const APPEND_DURATION: &str = "eventstore_append_duration_seconds";
+/// Histogram of the duration of reads in seconds.
+pub const READ_DURATION: &str = "eventstore_read_duration_seconds";
+/// Counter of events read.
+pub const EVENTS_READ: &str = "eventstore_events_read_total";
+/// Histogram of the time spent waiting for a pooled connection in seconds.
+pub const POOL_WAIT: &str = "eventstore_pool_wait_seconds";
+/// Gauge of the events a projection is behind, labeled by `projection`.
+pub const SUBSCRIPTION_LAG: &str = "eventstore_subscription_lag_events";
+/// Gauge of the events a replication follower is behind its primary,
+/// labeled by `follower`.
+pub const REPLICATION_LAG: &str = "eventstore_replication_lag_events";
+/// Counter of appends rejected by a quota, labeled by `tenant`.
+pub const QUOTA_REJECTIONS: &str = "eventstore_quota_rejections_total";
+/// Counter of saved snapshots.
+pub const SNAPSHOTS_SAVED: &str = "eventstore_snapshots_saved_total";
+
+pub(crate) fn appended(events: usize, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!(APPENDS).increment(1);
+        ::metrics::counter!(EVENTS_APPENDED).increment(events as u64);
+        ::metrics::histogram!(APPEND_DURATION).record(elapsed.as_secs_f64());
+    }
+}
+
+pub(crate) fn append_conflict() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(APPEND_CONFLICTS).increment(1);
+}
+
+pub(crate) fn quota_rejected(tenant: &str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(QUOTA_REJECTIONS, "tenant" => tenant.to_string()).increment(1);
+}
+
+pub(crate) fn snapshot_saved() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(SNAPSHOTS_SAVED).increment(1);
+}
+
+pub(crate) fn read(events: usize, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!(EVENTS_READ).increment(events as u64);
+        ::metrics::histogram!(READ_DURATION).record(elapsed.as_secs_f64());
+    }
+}
+
+pub(crate) fn pool_wait(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(POOL_WAIT).record(elapsed.as_secs_f64());
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn subscription_lag(projection: &str, lag: u64) {
+    ::metrics::gauge!(SUBSCRIPTION_LAG, "projection" => projection.to_string()).set(lag as f64);
+}
+
+#[cfg(all(feature = "metrics", feature = "grpc"))]
+pub(crate) fn replication_lag(follower: &str, lag: u64) {
+    ::metrics::gauge!(REPLICATION_LAG, "follower" => follower.to_string()).set(lag as f64);
+}
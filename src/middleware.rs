@@ -0,0 +1,92 @@
+//! Layers around the appends and reads of a backend, e.g. validation,
+//! enrichment, metrics or encryption, configured with
+//! `SqliteBackend::with_middleware`:
+//!
+//! ```ignore
+//! let mut middleware = MiddlewareChain::new();
+//! middleware.register(RequireCorrelation).register(SealPayloads::new(key));
+//! let backend = SqliteBackend::new(manager).with_middleware(middleware);
+//! ```
+//!
+//! Appends pass the layers in registration order, reads in reverse, so
+//! the first layer sees events as the caller does and the last as they
+//! are stored. Every write passes the layers, also imports, scheduled
+//! events, copies of `split_stream` and `merge_streams`, tombstones and
+//! system events. Layers run on the events of reads after upcasting, not
+//! on snapshots.
+
+use std::sync::Arc;
+
+use crate::backend::{model::Event, sqlite::Error};
+
+/// A layer of a `MiddlewareChain`, every hook does nothing by default.
+pub trait Middleware: Send + Sync {
+    /// Called before `events` are validated and written, in the order
+    /// they are appended. May change them, an `Err` fails the append.
+    fn before_append(&self, events: &mut [Event]) -> Result<(), Error> {
+        let _ = events;
+        Ok(())
+    }
+
+    /// Called once `events` are committed, as they were written.
+    fn after_append(&self, events: &[Event]) {
+        let _ = events;
+    }
+
+    /// Called on the events a read returns. May change them, an `Err`
+    /// fails the read.
+    fn on_read(&self, events: &mut [Event]) -> Result<(), Error> {
+        let _ = events;
+        Ok(())
+    }
+}
+
+/// Ordered list of middleware layers.
+#[derive(Default, Clone)]
+pub struct MiddlewareChain {
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl std::fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MiddlewareChain")
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `middleware` below the layers registered before.
+    pub fn register<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn before_append(&self, events: &mut [Event]) -> Result<(), Error> {
+        for layer in &self.layers {
+            layer.before_append(events)?;
+        }
+        Ok(())
+    }
+
+    pub fn after_append(&self, events: &[Event]) {
+        for layer in &self.layers {
+            layer.after_append(events);
+        }
+    }
+
+    pub fn on_read(&self, events: &mut [Event]) -> Result<(), Error> {
+        for layer in self.layers.iter().rev() {
+            layer.on_read(events)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Relay publishing committed events to an MQTT broker, for edge devices
+//! feeding a central system:
+//!
+//! ```ignore
+//! let options = MqttOptions::new("edge-17", "broker.local", 1883);
+//! let relay = MqttRelay::new(backend, "mqtt-uplink", BrokerPublisher::new(options))
+//!     .with_topic("plants/7/{event_type}/{aggregate_id}")
+//!     .with_qos(QoS::AtLeastOnce);
+//! let handle = relay.spawn(Duration::from_secs(1));
+//! ```
+//!
+//! Messages carry the event as a JSON `Envelope`. The position of the last
+//! published event is stored as a checkpoint under the relay's name once
+//! the broker acknowledged the batch, so delivery is at-least-once: a batch
+//! interrupted by a failure or restart is published again.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rumqttc::{Client, Connection, Event as MqttEvent, Outgoing, Packet, RecvTimeoutError};
+pub use rumqttc::{MqttOptions, QoS};
+use tracing::{debug, instrument, warn};
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, SqliteBackend};
+use crate::jsonl::Envelope;
+
+/// Topic used unless `MqttRelay::with_topic` is called.
+pub const DEFAULT_TOPIC: &str = "eventstore/{event_type}/{aggregate_id}";
+
+fn mqtt_error(err: impl std::fmt::Display) -> Error {
+    Error::WithMsg(format!("mqtt: {}", err))
+}
+
+/// A message to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+}
+
+/// Delivers messages to a broker.
+pub trait Publisher: Send {
+    /// Publish `messages` in order and return once the broker has all of
+    /// them with their QoS: handed to the network for `AtMostOnce`,
+    /// acknowledged for the others.
+    fn publish(&mut self, messages: &[MqttMessage]) -> Result<(), Error>;
+}
+
+/// `Publisher` connected to a broker with rumqttc. The connection is
+/// established on the first publish and reestablished after a failed one.
+pub struct BrokerPublisher {
+    options: MqttOptions,
+    client: Client,
+    connection: Connection,
+    timeout: Duration,
+}
+
+impl std::fmt::Debug for BrokerPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BrokerPublisher")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BrokerPublisher {
+    pub fn new(options: MqttOptions) -> Self {
+        let (client, connection) = Client::new(options.clone(), 64);
+        Self {
+            options,
+            client,
+            connection,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Fail a publish if the broker did not make progress for `timeout`,
+    /// 10 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Drive the connection until the next event, returns whether it
+    /// completes the delivery of a message.
+    fn poll(&mut self) -> Result<bool, Error> {
+        let event = match self.connection.recv_timeout(self.timeout) {
+            Ok(event) => event.map_err(mqtt_error)?,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(mqtt_error("timed out waiting for the broker"))
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(mqtt_error("client closed")),
+        };
+        // QoS 0 messages are done once written, the others once acknowledged
+        Ok(matches!(
+            event,
+            MqttEvent::Outgoing(Outgoing::Publish(0))
+                | MqttEvent::Incoming(Packet::PubAck(_))
+                | MqttEvent::Incoming(Packet::PubComp(_))
+        ))
+    }
+
+    fn deliver(&mut self, messages: &[MqttMessage]) -> Result<(), Error> {
+        let mut delivered = 0;
+        for message in messages {
+            // drive the connection while the request queue is full
+            while self
+                .client
+                .try_publish(
+                    message.topic.as_str(),
+                    message.qos,
+                    false,
+                    message.payload.clone(),
+                )
+                .is_err()
+            {
+                delivered += self.poll()? as usize;
+            }
+        }
+        while delivered < messages.len() {
+            delivered += self.poll()? as usize;
+        }
+        Ok(())
+    }
+}
+
+impl Publisher for BrokerPublisher {
+    fn publish(&mut self, messages: &[MqttMessage]) -> Result<(), Error> {
+        let res = self.deliver(messages);
+        if res.is_err() {
+            // start over with a new session, so acknowledgements of this
+            // batch are not counted for the next one
+            (self.client, self.connection) = Client::new(self.options.clone(), 64);
+        }
+        res
+    }
+}
+
+/// Publishes the events of a store in commit order, one message per event.
+pub struct MqttRelay<P> {
+    backend: SqliteBackend,
+    name: String,
+    publisher: P,
+    topic: String,
+    qos: QoS,
+    batch_size: usize,
+}
+
+impl<P> std::fmt::Debug for MqttRelay<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttRelay")
+            .field("name", &self.name)
+            .field("topic", &self.topic)
+            .field("qos", &self.qos)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+/// Topic levels must not contain the wildcards `+` and `#` or separators.
+fn topic_level(value: &str) -> String {
+    value.replace(['+', '#', '/'], "_")
+}
+
+impl<P: Publisher> MqttRelay<P> {
+    /// Relay with the checkpoint `name`, publishing with QoS 1 to
+    /// `DEFAULT_TOPIC`.
+    pub fn new(backend: SqliteBackend, name: impl Into<String>, publisher: P) -> Self {
+        Self {
+            backend,
+            name: name.into(),
+            publisher,
+            topic: DEFAULT_TOPIC.to_string(),
+            qos: QoS::AtLeastOnce,
+            batch_size: 100,
+        }
+    }
+
+    /// Topic template, `{aggregate_id}`, `{event_type}` and `{version}` are
+    /// replaced with the values of the event.
+    pub fn with_topic(mut self, template: impl Into<String>) -> Self {
+        self.topic = template.into();
+        self
+    }
+
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Events published and checkpointed at once.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn publisher(&self) -> &P {
+        &self.publisher
+    }
+
+    /// The topic `event` is published to.
+    pub fn topic(&self, event: &Event) -> String {
+        self.topic
+            .replace("{aggregate_id}", &event.id.to_string())
+            .replace("{event_type}", &topic_level(&event.event_type))
+            .replace("{version}", &event.version.to_string())
+    }
+
+    fn message(&self, event: &Event) -> Result<MqttMessage, Error> {
+        Ok(MqttMessage {
+            topic: self.topic(event),
+            payload: serde_json::to_vec(&Envelope::from_event(event)).map_err(mqtt_error)?,
+            qos: self.qos,
+        })
+    }
+
+    /// Publish a single batch after the stored checkpoint. Returns the
+    /// number of published events, 0 once the relay caught up.
+    #[instrument]
+    pub fn run_once(&mut self) -> Result<usize, Error> {
+        let checkpoint = self.backend.get_checkpoint(&self.name)?;
+        let events = self.backend.read_all(checkpoint, self.batch_size)?;
+        let Some(last_position) = events.last().map(|event| event.position) else {
+            return Ok(0);
+        };
+        let messages = events
+            .iter()
+            .map(|event| self.message(event))
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.publisher.publish(&messages)?;
+        self.backend.save_checkpoint(&self.name, last_position)?;
+        debug!(
+            relay = self.name,
+            position = last_position,
+            "published events"
+        );
+        Ok(events.len())
+    }
+
+    /// Publish batches until all committed events were published, returns
+    /// the number of published events.
+    #[instrument]
+    pub fn run_until_caught_up(&mut self) -> Result<usize, Error> {
+        let mut total = 0;
+        loop {
+            match self.run_once()? {
+                0 => return Ok(total),
+                published => total += published,
+            }
+        }
+    }
+
+    /// Publish new events on a background thread, looking for them every
+    /// `interval` once caught up, until the handle is stopped. Failures
+    /// are logged and the batch is retried after `interval`.
+    pub fn spawn(mut self, interval: Duration) -> RelayHandle
+    where
+        P: 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match self.run_once() {
+                    Ok(0) => std::thread::sleep(interval),
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!(relay = self.name, "mqtt relay failed: {}", err);
+                        std::thread::sleep(interval);
+                    }
+                }
+            }
+            self.run_until_caught_up().map(|_| ())
+        });
+        RelayHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Stops a relay started with `MqttRelay::spawn`.
+#[derive(Debug)]
+pub struct RelayHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl RelayHandle {
+    /// Stop after publishing the events committed so far, returns the
+    /// outcome of that last run.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err(Error::WithMsg("mqtt relay thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+
+use crate::backend::sqlite::Error;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+/// Minimal blob storage used to ship backups and WAL segments off the host.
+/// Keys are `/` separated paths.
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+
+    /// All keys starting with `prefix`, in lexicographic order.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::WithMsg(format!("object store: {}", err))
+}
+
+/// Stores objects as files below a directory, for tests and deployments
+/// that sync a mounted volume.
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(Error::WithMsg(format!("invalid object key: {}", key)));
+        }
+        Ok(self.root.join(key))
+    }
+
+    fn collect(&self, dir: &Path, keys: &mut Vec<String>) -> Result<(), Error> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(io_error(err)),
+        };
+        for entry in entries {
+            let path = entry.map_err(io_error)?.path();
+            if path.is_dir() {
+                self.collect(&path, keys)?;
+            } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                let parts: Vec<_> = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                keys.push(parts.join("/"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        // write to a temporary file first so readers never see partial objects
+        let staged = path.with_extension("partial");
+        std::fs::write(&staged, data).map_err(io_error)?;
+        std::fs::rename(&staged, &path).map_err(io_error)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        std::fs::read(self.path(key)?).map_err(io_error)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        self.collect(&self.root, &mut keys)?;
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+}
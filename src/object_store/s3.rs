@@ -0,0 +1,100 @@
+use aws_sdk_s3::{error::DisplayErrorContext, primitives::ByteStream, Client};
+use tokio::runtime::Runtime;
+
+use crate::backend::sqlite::Error;
+use crate::object_store::ObjectStore;
+
+fn s3_error(err: impl std::error::Error) -> Error {
+    Error::WithMsg(format!("s3: {}", DisplayErrorContext(err)))
+}
+
+/// Objects in an S3 compatible bucket, use the endpoint settings of the
+/// client for MinIO and similar services.
+///
+/// Requests are driven by an own single threaded runtime, so this must not
+/// be called from within an async context.
+#[derive(Debug)]
+pub struct S3ObjectStore {
+    client: Client,
+    bucket: String,
+    runtime: Runtime,
+}
+
+impl S3ObjectStore {
+    pub fn new(client: Client, bucket: impl Into<String>) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| Error::WithMsg(format!("s3 runtime: {}", err)))?;
+        Ok(Self {
+            client,
+            bucket: bucket.into(),
+            runtime,
+        })
+    }
+}
+
+impl ObjectStore for S3ObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.runtime
+            .block_on(
+                self.client
+                    .put_object()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .body(ByteStream::from(data.to_vec()))
+                    .send(),
+            )
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.runtime.block_on(async {
+            let output = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(s3_error)?;
+            let body = output
+                .body
+                .collect()
+                .await
+                .map_err(|err| Error::WithMsg(format!("s3: {}", err)))?;
+            Ok(body.into_bytes().to_vec())
+        })
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        self.runtime.block_on(async {
+            let mut keys = Vec::new();
+            let mut continuation = None;
+            loop {
+                let output = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(&self.bucket)
+                    .prefix(prefix)
+                    .set_continuation_token(continuation)
+                    .send()
+                    .await
+                    .map_err(s3_error)?;
+                keys.extend(
+                    output
+                        .contents()
+                        .iter()
+                        .filter_map(|object| object.key().map(str::to_string)),
+                );
+                match output.next_continuation_token() {
+                    Some(token) => continuation = Some(token.to_string()),
+                    None => break,
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
@@ -0,0 +1,104 @@
+//! Columnar export of the event history for analytical engines like
+//! DuckDB or Spark, see `SqliteBackend::export_parquet`.
+//!
+//! Every event becomes a row of `schema()`. Payloads are exported as
+//! stored, JSON payloads can be parsed by the reader, e.g. with
+//! `CAST(payload AS VARCHAR)::JSON` in DuckDB.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::{
+    BinaryArray, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::Error;
+
+/// Schema of exported events:
+///
+/// | column         | type                    |
+/// |----------------|-------------------------|
+/// | position       | uint64                  |
+/// | aggregate_id   | utf8                    |
+/// | event_type     | utf8                    |
+/// | version        | uint32                  |
+/// | schema_version | uint32                  |
+/// | timestamp      | timestamp(ms, UTC)      |
+/// | content_type   | utf8                    |
+/// | payload        | binary                  |
+/// | metadata       | utf8, a JSON object     |
+///
+/// `timestamp` is the append time, null for events stored before it was
+/// recorded.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("position", DataType::UInt64, false),
+        Field::new("aggregate_id", DataType::Utf8, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new("version", DataType::UInt32, false),
+        Field::new("schema_version", DataType::UInt32, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            true,
+        ),
+        Field::new("content_type", DataType::Utf8, false),
+        Field::new("payload", DataType::Binary, false),
+        Field::new("metadata", DataType::Utf8, false),
+    ]))
+}
+
+pub(crate) fn arrow_error(err: impl std::fmt::Display) -> Error {
+    Error::WithMsg(format!("parquet: {}", err))
+}
+
+/// Rows of `events`, `timestamps` maps positions to append times in
+/// milliseconds since the Unix epoch.
+pub(crate) fn record_batch(
+    events: &[Event],
+    timestamps: &HashMap<u64, i64>,
+) -> Result<RecordBatch, Error> {
+    let metadata = events
+        .iter()
+        .map(|event| serde_json::to_string(&event.metadata))
+        .collect::<Result<Vec<_>, _>>()?;
+    RecordBatch::try_new(
+        schema(),
+        vec![
+            Arc::new(UInt64Array::from_iter_values(
+                events.iter().map(|event| event.position),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                events.iter().map(|event| event.id.to_string()),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                events.iter().map(|event| &event.event_type),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                events.iter().map(|event| event.version),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                events.iter().map(|event| event.schema_version),
+            )),
+            Arc::new(
+                TimestampMillisecondArray::from_iter(events.iter().map(|event| {
+                    timestamps
+                        .get(&event.position)
+                        .copied()
+                        .filter(|millis| *millis > 0)
+                }))
+                .with_timezone("UTC"),
+            ),
+            Arc::new(StringArray::from_iter_values(
+                events.iter().map(|event| &event.content_type),
+            )),
+            Arc::new(BinaryArray::from_iter_values(
+                events.iter().map(|event| &event.data),
+            )),
+            Arc::new(StringArray::from_iter_values(metadata)),
+        ],
+    )
+    .map_err(arrow_error)
+}
@@ -0,0 +1,342 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use tracing::{debug, instrument, warn};
+
+use crate::backend::{
+    model::Event,
+    sqlite::{Error, SqliteBackend},
+};
+
+/// Read model fed from the events of all aggregates.
+///
+/// Events of the same aggregate are always delivered in order and from a
+/// single worker, events of different aggregates may be handled concurrently.
+pub trait Projection: Send + Sync {
+    /// Unique name, used to store the checkpoint.
+    fn name(&self) -> &str;
+
+    fn handle(&self, event: &Event) -> Result<(), Error>;
+}
+
+/// Event a projection failed on, see `ProjectionRunner::with_quarantine`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedEvent {
+    pub projection: String,
+    /// The stored event, with the payload set by
+    /// `SqliteBackend::fix_quarantined` if it was fixed.
+    pub event: Event,
+    /// Error of the last attempt.
+    pub error: String,
+    pub attempts: u32,
+    /// Milliseconds since the Unix epoch.
+    pub quarantined_at: i64,
+    pub fixed: bool,
+}
+
+/// Report how many events the projection `name` is behind after reaching
+/// `position`.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn record_lag(backend: &SqliteBackend, name: &str, position: u64) -> Result<(), Error> {
+    #[cfg(feature = "metrics")]
+    crate::metrics::subscription_lag(name, backend.current_position()?.saturating_sub(position));
+    Ok(())
+}
+
+/// Runs a projection with several worker threads, partitioned by aggregate id.
+///
+/// Delivery is at-least-once: the checkpoint is advanced after a whole batch
+/// succeeded, a failing batch is handed out again on the next run.
+pub struct ProjectionRunner {
+    backend: SqliteBackend,
+    projection: Arc<dyn Projection>,
+    workers: usize,
+    batch_size: usize,
+    max_attempts: Option<u32>,
+}
+
+impl std::fmt::Debug for ProjectionRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProjectionRunner")
+            .field("projection", &self.projection.name())
+            .field("workers", &self.workers)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl ProjectionRunner {
+    pub fn new(backend: SqliteBackend, projection: Arc<dyn Projection>) -> Self {
+        Self {
+            backend,
+            projection,
+            workers: 4,
+            batch_size: 1000,
+            max_attempts: None,
+        }
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Try an event up to `max_attempts` times, then move it to the
+    /// quarantine and carry on with the next one instead of failing the
+    /// batch. Quarantined events can be inspected with
+    /// `SqliteBackend::quarantined` and handled again with
+    /// `replay_quarantined`.
+    pub fn with_quarantine(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+
+    /// Handle `event`, quarantining it if enabled and all attempts failed.
+    fn handle(&self, event: &Event) -> Result<(), Error> {
+        #[cfg(feature = "opentelemetry")]
+        let _span = crate::telemetry::event_span(event).entered();
+        let Some(max_attempts) = self.max_attempts else {
+            return self.projection.handle(event);
+        };
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.projection.handle(event) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempts >= max_attempts => {
+                    return self.backend.quarantine_event(
+                        self.projection.name(),
+                        event.position,
+                        &err.to_string(),
+                        attempts,
+                    );
+                }
+                Err(err) => debug!(error = err.to_string(), attempts, "retrying event"),
+            }
+        }
+    }
+
+    /// Process a single batch after the stored checkpoint. Returns the number
+    /// of handled events, 0 once the projection caught up.
+    #[instrument]
+    pub fn run_once(&self) -> Result<usize, Error> {
+        let name = self.projection.name();
+        let checkpoint = self.backend.get_checkpoint(name)?;
+        let events = self.backend.read_all(checkpoint, self.batch_size)?;
+        let last_position = match events.last() {
+            Some(event) => event.position,
+            None => {
+                record_lag(&self.backend, name, checkpoint)?;
+                return Ok(0);
+            }
+        };
+
+        let mut partitions: Vec<Vec<&Event>> = vec![Vec::new(); self.workers];
+        for event in &events {
+            let mut hasher = DefaultHasher::new();
+            event.id.hash(&mut hasher);
+            partitions[hasher.finish() as usize % self.workers].push(event);
+        }
+        let results: Vec<Result<(), Error>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = partitions
+                .into_iter()
+                .filter(|partition| !partition.is_empty())
+                .map(|partition| {
+                    scope.spawn(move || {
+                        partition
+                            .into_iter()
+                            .try_for_each(|event| self.handle(event))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or_else(|_| {
+                        Err(Error::WithMsg("projection worker panicked".to_string()))
+                    })
+                })
+                .collect()
+        });
+        for result in results {
+            if let Err(err) = result {
+                warn!(projection = name, error = err.to_string(), "batch failed");
+                return Err(err);
+            }
+        }
+
+        self.backend.save_checkpoint(name, last_position)?;
+        record_lag(&self.backend, name, last_position)?;
+        debug!(
+            projection = name,
+            position = last_position,
+            "advanced checkpoint"
+        );
+        Ok(events.len())
+    }
+
+    /// Process batches until all committed events were handled, returns the
+    /// number of handled events.
+    #[instrument]
+    pub fn run_until_caught_up(&self) -> Result<usize, Error> {
+        let mut total = 0;
+        loop {
+            match self.run_once()? {
+                0 => return Ok(total),
+                handled => total += handled,
+            }
+        }
+    }
+
+    /// Handle the quarantined events of the projection again in commit
+    /// order. Events that succeed leave the quarantine, the others stay with
+    /// the new error. Returns the number of released events.
+    #[instrument]
+    pub fn replay_quarantined(&self) -> Result<usize, Error> {
+        let name = self.projection.name();
+        let mut released = 0;
+        for quarantined in self.backend.quarantined(name)? {
+            let position = quarantined.event.position;
+            match self.projection.handle(&quarantined.event) {
+                Ok(()) => {
+                    self.backend.release_quarantined(name, position)?;
+                    released += 1;
+                }
+                Err(err) => self
+                    .backend
+                    .quarantine_event(name, position, &err.to_string(), 1)?,
+            }
+        }
+        Ok(released)
+    }
+
+    /// Reset the checkpoint and replay the whole store.
+    #[instrument]
+    pub fn rebuild(&self) -> Result<usize, Error> {
+        self.backend.save_checkpoint(self.projection.name(), 0)?;
+        self.run_until_caught_up()
+    }
+}
+
+/// Projection defined as a SQL statement executed once per event.
+///
+/// The statement may use the named parameters `:aggregate_id`, `:version`,
+/// `:event_type`, `:schema_version`, `:position` and `:data`, the latter is
+/// bound as text so SQLite's JSON functions can be used on it:
+///
+/// ```sql
+/// INSERT INTO item_count(sku, quantity) VALUES(json_extract(:data, '$.sku'), 1)
+///     ON CONFLICT(sku) DO UPDATE SET quantity = quantity + 1
+/// ```
+///
+/// The statement and the checkpoint are written in the same transaction.
+#[derive(Debug, Clone)]
+pub struct SqlProjection {
+    name: String,
+    setup: Option<String>,
+    statement: String,
+    event_types: Vec<String>,
+}
+
+impl SqlProjection {
+    pub fn new(name: &str, statement: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            setup: None,
+            statement: statement.to_string(),
+            event_types: Vec::new(),
+        }
+    }
+
+    /// SQL run before every batch, e.g., `CREATE TABLE IF NOT EXISTS ...`.
+    pub fn with_setup(mut self, setup: &str) -> Self {
+        self.setup = Some(setup.to_string());
+        self
+    }
+
+    /// Only run the statement for the given event types, all other events
+    /// just advance the checkpoint.
+    pub fn for_event_types(mut self, event_types: &[&str]) -> Self {
+        self.event_types = event_types.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bind_and_execute(stmt: &mut rusqlite::Statement, event: &Event) -> Result<(), Error> {
+        for idx in 1..=stmt.parameter_count() {
+            match stmt.parameter_name(idx) {
+                Some(":aggregate_id") => stmt.raw_bind_parameter(idx, event.id.to_string())?,
+                Some(":version") => stmt.raw_bind_parameter(idx, event.version)?,
+                Some(":event_type") => stmt.raw_bind_parameter(idx, &event.event_type)?,
+                Some(":schema_version") => stmt.raw_bind_parameter(idx, event.schema_version)?,
+                Some(":position") => stmt.raw_bind_parameter(idx, event.position)?,
+                Some(":data") => {
+                    stmt.raw_bind_parameter(idx, String::from_utf8_lossy(&event.data))?
+                }
+                name => {
+                    return Err(Error::WithMsg(format!(
+                        "unsupported projection parameter: {:?}",
+                        name
+                    )))
+                }
+            }
+        }
+        stmt.raw_execute()?;
+        Ok(())
+    }
+
+    /// Process a single batch of at most `batch_size` events, returns the
+    /// number of events read.
+    #[instrument(skip(backend))]
+    pub fn run_once(&self, backend: &SqliteBackend, batch_size: usize) -> Result<usize, Error> {
+        let checkpoint = backend.get_checkpoint(&self.name)?;
+        let events = backend.read_all(checkpoint, batch_size)?;
+        let last_position = match events.last() {
+            Some(event) => event.position,
+            None => {
+                record_lag(backend, &self.name, checkpoint)?;
+                return Ok(0);
+            }
+        };
+        let mut conn = backend.connection()?;
+        let tx = conn.transaction()?;
+        if let Some(setup) = &self.setup {
+            tx.execute_batch(setup)?;
+        }
+        {
+            let mut stmt = tx.prepare(&self.statement)?;
+            for event in events
+                .iter()
+                .filter(|e| self.event_types.is_empty() || self.event_types.contains(&e.event_type))
+            {
+                Self::bind_and_execute(&mut stmt, event)?;
+            }
+        }
+        backend.save_checkpoint_in(&tx, &self.name, last_position)?;
+        tx.commit()?;
+        record_lag(backend, &self.name, last_position)?;
+        Ok(events.len())
+    }
+
+    /// Process batches until the projection caught up, returns the number of
+    /// events read.
+    #[instrument(skip(backend))]
+    pub fn run_until_caught_up(&self, backend: &SqliteBackend) -> Result<usize, Error> {
+        let mut total = 0;
+        loop {
+            match self.run_once(backend, 1000)? {
+                0 => return Ok(total),
+                read => total += read,
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Limits on what tenants and streams may append, enforced by backends
+//! configured with `SqliteBackend::with_quotas`:
+//!
+//! ```ignore
+//! let backend = SqliteBackend::new(manager).with_quotas(
+//!     Quotas::default()
+//!         .with_tenant(Quota::default().with_max_events(1_000_000).with_events_per_sec(500))
+//!         .with_tenant_override("enterprise", Quota::default())
+//!         .with_stream(Quota::default().with_max_bytes(64 << 20)),
+//! );
+//! ```
+//!
+//! Appends over a limit fail with `Error::QuotaExceeded` and count as
+//! rejected in `Maintenance::quota_usage`. Quotas are checked once the
+//! expected version and idempotency checks of an append passed, repeated
+//! commands are not charged and failed appends give their rate back.
+//! Imports, scheduled events and the copies of `split_stream` and
+//! `merge_streams` are charged like appends, tombstones and the events of
+//! system streams are not. Totals are kept in the
+//! `quota_usage` table from the first append under quotas on, every
+//! backend writing the database should have the same quotas. They count
+//! appended events and payload bytes, events removed later still count.
+//! Rates are tracked per backend and its handles, with a burst of one
+//! second's worth of events.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+/// Limits of one tenant or stream, unlimited by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub events_per_sec: Option<u32>,
+    pub max_events: Option<u64>,
+    /// Total size of the payloads.
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    pub fn with_events_per_sec(mut self, events_per_sec: u32) -> Self {
+        self.events_per_sec = Some(events_per_sec);
+        self
+    }
+
+    pub fn with_max_events(mut self, max_events: u64) -> Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub(crate) fn has_totals(&self) -> bool {
+        self.max_events.is_some() || self.max_bytes.is_some()
+    }
+}
+
+/// The quotas of a store: one for every tenant, unless overridden, and
+/// one for every stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quotas {
+    tenant: Quota,
+    tenants: HashMap<String, Quota>,
+    stream: Quota,
+}
+
+impl Quotas {
+    /// Limit of each tenant without an override.
+    pub fn with_tenant(mut self, quota: Quota) -> Self {
+        self.tenant = quota;
+        self
+    }
+
+    pub fn with_tenant_override(mut self, tenant_id: &str, quota: Quota) -> Self {
+        self.tenants.insert(tenant_id.to_string(), quota);
+        self
+    }
+
+    /// Limit of each stream.
+    pub fn with_stream(mut self, quota: Quota) -> Self {
+        self.stream = quota;
+        self
+    }
+
+    pub fn tenant(&self, tenant_id: &str) -> &Quota {
+        self.tenants.get(tenant_id).unwrap_or(&self.tenant)
+    }
+
+    pub fn stream(&self) -> &Quota {
+        &self.stream
+    }
+}
+
+/// Totals of a tenant or stream, see `Maintenance::quota_usage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub tenant_id: String,
+    /// `None` for the totals of the whole tenant.
+    pub aggregate_id: Option<Uuid>,
+    pub events: u64,
+    pub bytes: u64,
+    /// Appends that failed with `Error::QuotaExceeded`.
+    pub rejected: u64,
+}
+
+/// Tenant and stream of a rate limit, `None` for the whole tenant.
+pub(crate) type BucketKey<'a> = (&'a str, Option<Uuid>);
+
+/// Token buckets of the rate limits, keyed by tenant and stream.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    buckets: Mutex<HashMap<(String, Option<Uuid>), Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: i64,
+}
+
+impl RateLimiter {
+    /// Take the events of each `(key, per_sec, events)` from the bucket of
+    /// its key at `now_millis`, or none if a bucket holds fewer. Returns
+    /// the index of the first take that is short. Buckets refill at
+    /// `per_sec` and hold at most `per_sec` tokens.
+    pub(crate) fn try_take(
+        &self,
+        takes: &[(BucketKey, u32, u64)],
+        now_millis: i64,
+    ) -> Result<(), usize> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        for (i, ((tenant_id, aggregate_id), per_sec, events)) in takes.iter().enumerate() {
+            let capacity = f64::from(*per_sec);
+            let bucket = buckets
+                .entry((tenant_id.to_string(), *aggregate_id))
+                .or_insert(Bucket {
+                    tokens: capacity,
+                    refilled_at: now_millis,
+                });
+            let elapsed = (now_millis - bucket.refilled_at).max(0) as f64 / 1000.0;
+            bucket.tokens = (bucket.tokens + elapsed * capacity).min(capacity);
+            bucket.refilled_at = now_millis.max(bucket.refilled_at);
+            if bucket.tokens < *events as f64 {
+                return Err(i);
+            }
+        }
+        for (key, _, events) in takes {
+            if let Some(bucket) = buckets.get_mut(&(key.0.to_string(), key.1)) {
+                bucket.tokens -= *events as f64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give back the events of `takes` to their buckets, for appends that
+    /// took them and failed.
+    pub(crate) fn refund(&self, takes: &[(BucketKey, u32, u64)]) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        for ((tenant_id, aggregate_id), per_sec, events) in takes {
+            if let Some(bucket) = buckets.get_mut(&(tenant_id.to_string(), *aggregate_id)) {
+                bucket.tokens = (bucket.tokens + *events as f64).min(f64::from(*per_sec));
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use serde_json::Value;
+
+use crate::backend::sqlite::Error;
+
+/// Fields of a JSON payload to overwrite, addressed by JSON pointers
+/// (RFC 6901), e.g. `/customer/email`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redaction {
+    pub fields: Vec<String>,
+    pub replacement: Value,
+    pub reason: String,
+}
+
+impl Redaction {
+    /// Redacted fields are replaced with `null` by default.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            fields: Vec::new(),
+            replacement: Value::Null,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn field(mut self, pointer: impl Into<String>) -> Self {
+        self.fields.push(pointer.into());
+        self
+    }
+
+    pub fn with_replacement(mut self, replacement: Value) -> Self {
+        self.replacement = replacement;
+        self
+    }
+
+    /// Overwrite the fields in `payload`, fails if one of them is missing.
+    pub fn apply(&self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut value: Value = serde_json::from_slice(payload)?;
+        for pointer in &self.fields {
+            let field = value
+                .pointer_mut(pointer)
+                .ok_or_else(|| Error::WithMsg(format!("field {} not found", pointer)))?;
+            *field = self.replacement.clone();
+        }
+        Ok(serde_json::to_vec(&value)?)
+    }
+}
+
+/// Audit record written for every redaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedactionRecord {
+    pub position: u64,
+    pub fields: Vec<String>,
+    pub reason: String,
+    pub redacted_at: String,
+}
@@ -0,0 +1,106 @@
+//! Events appended to their streams at a later time, e.g. the expiry of a
+//! trial or the retry of a payment:
+//!
+//! ```ignore
+//! backend.schedule_event(&trial_expired, SystemTime::now() + Duration::from_secs(14 * 86_400))?;
+//! let dispatcher = Dispatcher::new(backend.clone()).spawn();
+//! ```
+//!
+//! Scheduled events wait in the `scheduled_events` table until a
+//! `Dispatcher` or a call to `SqliteBackend::dispatch_due` finds them due
+//! by the clock of the backend. Events failing to append stay there with
+//! their error until retried or cancelled, the others are delivered.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, SqliteBackend};
+
+/// An event waiting for delivery, see `SqliteBackend::schedule_event`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledEvent {
+    /// Id to cancel the event with.
+    pub id: u64,
+    /// The event to append, its version is assigned on delivery.
+    pub event: Event,
+    /// Milliseconds since the Unix epoch.
+    pub deliver_at: i64,
+    /// Milliseconds since the Unix epoch.
+    pub scheduled_at: i64,
+    /// Why the delivery failed, the event is not delivered until it is
+    /// retried with `SqliteBackend::retry_scheduled`.
+    pub error: Option<String>,
+}
+
+/// Delivers the due scheduled events of a backend's tenant on a background
+/// thread.
+#[derive(Debug, Clone)]
+pub struct Dispatcher {
+    backend: SqliteBackend,
+    interval: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(backend: SqliteBackend) -> Self {
+        Self {
+            backend,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// How often to look for due events, the most an event is delivered
+    /// late by.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Dispatch every interval until the handle is stopped.
+    pub fn spawn(self) -> DispatcherHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match self.backend.dispatch_due() {
+                    Ok(0) => {}
+                    Ok(dispatched) => debug!(dispatched, "dispatched scheduled events"),
+                    Err(err) => warn!("dispatching scheduled events failed: {}", err),
+                }
+                std::thread::sleep(self.interval);
+            }
+        });
+        DispatcherHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Stops a dispatcher started with `Dispatcher::spawn`.
+#[derive(Debug)]
+pub struct DispatcherHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DispatcherHandle {
+    /// Stop after the current run.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Err(_)) => Err(Error::WithMsg("dispatcher thread panicked".to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for DispatcherHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
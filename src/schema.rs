@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use jsonschema::Validator;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::backend::{model::Event, sqlite::Error};
+use crate::codec;
+
+/// Source of JSON Schemas, keyed by event type and schema version.
+///
+/// Implement this for remote registries, e.g., a Confluent-style registry
+/// where the event type is used as subject.
+pub trait SchemaProvider: Send + Sync {
+    fn fetch(&self, event_type: &str, schema_version: u32) -> Result<Option<Value>, Error>;
+}
+
+/// In-memory schema provider.
+#[derive(Default, Clone)]
+pub struct StaticSchemaProvider {
+    schemas: HashMap<(String, u32), Value>,
+}
+
+impl StaticSchemaProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, event_type: &str, schema_version: u32, schema: Value) -> &mut Self {
+        self.schemas
+            .insert((event_type.to_string(), schema_version), schema);
+        self
+    }
+}
+
+impl SchemaProvider for StaticSchemaProvider {
+    fn fetch(&self, event_type: &str, schema_version: u32) -> Result<Option<Value>, Error> {
+        Ok(self
+            .schemas
+            .get(&(event_type.to_string(), schema_version))
+            .cloned())
+    }
+}
+
+/// What to do with events violating their schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Enforcement {
+    Off,
+    #[default]
+    Warn,
+    Reject,
+}
+
+/// Validates payloads on append against schemas from a `SchemaProvider`.
+/// Event types without a schema and non-JSON payloads are not validated.
+pub struct SchemaRegistry {
+    provider: Box<dyn SchemaProvider>,
+    default_enforcement: Enforcement,
+    enforcement: HashMap<String, Enforcement>,
+    validators: Mutex<HashMap<(String, u32), Arc<Validator>>>,
+}
+
+impl std::fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SchemaRegistry")
+            .field("default_enforcement", &self.default_enforcement)
+            .field("enforcement", &self.enforcement)
+            .finish()
+    }
+}
+
+impl SchemaRegistry {
+    pub fn new<P: SchemaProvider + 'static>(provider: P) -> Self {
+        Self {
+            provider: Box::new(provider),
+            default_enforcement: Enforcement::default(),
+            enforcement: HashMap::new(),
+            validators: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_default_enforcement(mut self, enforcement: Enforcement) -> Self {
+        self.default_enforcement = enforcement;
+        self
+    }
+
+    pub fn with_enforcement(mut self, event_type: &str, enforcement: Enforcement) -> Self {
+        self.enforcement.insert(event_type.to_string(), enforcement);
+        self
+    }
+
+    pub fn enforcement(&self, event_type: &str) -> Enforcement {
+        self.enforcement
+            .get(event_type)
+            .copied()
+            .unwrap_or(self.default_enforcement)
+    }
+
+    fn validator(
+        &self,
+        event_type: &str,
+        schema_version: u32,
+    ) -> Result<Option<Arc<Validator>>, Error> {
+        let key = (event_type.to_string(), schema_version);
+        if let Some(validator) = self.validators.lock().unwrap().get(&key) {
+            return Ok(Some(validator.clone()));
+        }
+        let schema = match self.provider.fetch(event_type, schema_version)? {
+            Some(schema) => schema,
+            None => return Ok(None),
+        };
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|err| Error::WithMsg(format!("invalid schema for {}: {}", event_type, err)))?;
+        let validator = Arc::new(validator);
+        self.validators
+            .lock()
+            .unwrap()
+            .insert(key, validator.clone());
+        Ok(Some(validator))
+    }
+
+    /// Validate the payload of `event`, depending on the enforcement mode of
+    /// its type violations are logged or returned as `Error::SchemaViolation`.
+    pub fn validate(&self, event: &Event) -> Result<(), Error> {
+        let enforcement = self.enforcement(&event.event_type);
+        if enforcement == Enforcement::Off || event.content_type != codec::JSON {
+            return Ok(());
+        }
+        let validator = match self.validator(&event.event_type, event.schema_version)? {
+            Some(validator) => validator,
+            None => {
+                debug!(event_type = event.event_type, "no schema registered");
+                return Ok(());
+            }
+        };
+        let violation = match serde_json::from_slice::<Value>(&event.data) {
+            Ok(payload) => validator.validate(&payload).err().map(|e| e.to_string()),
+            Err(err) => Some(format!("payload is not valid json: {}", err)),
+        };
+        match (violation, enforcement) {
+            (None, _) => Ok(()),
+            (Some(violation), Enforcement::Reject) => {
+                warn!(event_type = event.event_type, violation, "rejecting event");
+                Err(Error::SchemaViolation(violation))
+            }
+            (Some(violation), _) => {
+                warn!(event_type = event.event_type, violation, "schema violation");
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// Event type of the terminal event appended by
+/// `SqliteBackend::tombstone_stream`.
+pub const TOMBSTONE: &str = "$tombstone";
+
+/// Per-stream settings, stored with `SqliteBackend::set_stream_metadata`.
+///
+/// Events outside the retention limits are hidden from reads right away
+/// and removed from disk by `Maintenance::scavenge`. The stream keeps its
+/// version, appends continue after the latest event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamMetadata {
+    /// Keep only the latest `max_count` events.
+    pub max_count: Option<u32>,
+    /// Keep only events appended within `max_age`.
+    pub max_age: Option<Duration>,
+    /// Drop the events before this version, e.g. once a snapshot covers
+    /// them.
+    pub truncate_before: Option<u32>,
+}
+
+impl StreamMetadata {
+    pub fn with_max_count(mut self, max_count: u32) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_truncate_before(mut self, version: u32) -> Self {
+        self.truncate_before = Some(version);
+        self
+    }
+}
+
+/// A stream listed by `SqliteBackend::list_streams`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub aggregate_id: Uuid,
+    /// Version of the latest event.
+    pub version: u32,
+}
+
+/// A stream that changed, listed by `SqliteBackend::changes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub aggregate_id: Uuid,
+    /// Version of the latest event appended since the token.
+    pub version: u32,
+    /// Global position of that event.
+    pub position: u64,
+}
+
+/// What `SqliteBackend::delete_stream_with` leaves behind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeleteMode {
+    /// The aggregate id can be used again, the next append starts a new
+    /// stream at version 1.
+    #[default]
+    AllowRecreate,
+    /// Appends to the aggregate id fail with `Error::StreamDeleted`.
+    Forbid,
+}
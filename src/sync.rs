@@ -0,0 +1,387 @@
+//! Exchange of events between two stores that are written independently,
+//! e.g. the SQLite file of an edge device and the store of a server. Each
+//! side appends locally while disconnected and a sync copies the events the
+//! other side is missing:
+//!
+//! ```ignore
+//! let device = Replica::new(&device_store, "device-17");
+//! let server = Replica::new(&server_store, "server");
+//! let report = SyncEngine::new().sync(device, server)?;
+//! ```
+//!
+//! Every event originates at one source, the store it was first appended
+//! to. Copies carry the source and the position there in their metadata
+//! under `SYNC_ORIGIN` and `SYNC_SEQ`. Each store keeps a sequence vector,
+//! the highest position of every source it holds, and skips events the
+//! vector already covers, so events reach every store once also when they
+//! travel along several paths. Vector entries and read cursors are stored
+//! as checkpoints named `sync:...` of the receiving store.
+//!
+//! Copies keep the version of the event. If the receiving stream took that
+//! version in the meantime, the `ConflictStrategy` of the engine decides:
+//! fail the push, rebase the event onto the stream or ask a `Merger`. The
+//! replica receiving the conflicting event first resolves the conflict,
+//! the other one appends the resolved stream after its own events when it
+//! receives it in turn, since neither replica can drop events it stored.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tracing::{debug, info, instrument};
+
+use crate::backend::{model::Event, sqlite::Error, EventStore};
+
+/// Metadata key of the source an event was first appended to.
+pub const SYNC_ORIGIN: &str = "sync_origin";
+
+/// Metadata key of the position of an event in its source.
+pub const SYNC_SEQ: &str = "sync_seq";
+
+/// Metadata key of the version a rebased event had in its source.
+pub const ORIGINAL_VERSION: &str = "sync_original_version";
+
+/// Metadata key of events a `Merger` appended instead of a conflicting
+/// event, `<origin>:<seq>` of that event.
+pub const SYNC_RESOLVED: &str = "sync_resolved";
+
+/// One side of a sync, a store and the name of the source appending to it
+/// locally, e.g. a device id. Names must be unique among all stores that
+/// sync with each other.
+pub struct Replica<'a, S> {
+    pub store: &'a S,
+    pub source: &'a str,
+}
+
+impl<S> Clone for Replica<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for Replica<'_, S> {}
+
+impl<S> std::fmt::Debug for Replica<'_, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replica")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, S: EventStore> Replica<'a, S> {
+    pub fn new(store: &'a S, source: &'a str) -> Self {
+        Self { store, source }
+    }
+
+    /// Highest position of `origin` this store holds events up to.
+    pub fn seen(&self, origin: &str) -> Result<u64, Error> {
+        self.store.get_checkpoint(&vector_entry(origin))
+    }
+}
+
+/// Outcome of `SyncEngine::sync`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Events copied from the first replica to the second.
+    pub pushed: usize,
+    /// Events copied from the second replica to the first.
+    pub pulled: usize,
+}
+
+/// Copies events between replicas, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct SyncEngine {
+    batch_size: usize,
+    strategy: ConflictStrategy,
+}
+
+/// What a push does with an event whose version the receiving stream took
+/// since the last sync.
+#[derive(Clone, Default)]
+pub enum ConflictStrategy {
+    /// Fail the push, the stream has to be repaired by hand.
+    #[default]
+    Reject,
+    /// Append the event after the latest version of the stream, with its
+    /// original version in the metadata under `ORIGINAL_VERSION`. Both
+    /// replicas keep all events, but may hold them in a different order.
+    Rebase,
+    /// Let a `Merger` decide.
+    Merge(Arc<dyn Merger>),
+}
+
+impl std::fmt::Debug for ConflictStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictStrategy::Reject => f.write_str("Reject"),
+            ConflictStrategy::Rebase => f.write_str("Rebase"),
+            ConflictStrategy::Merge(_) => f.write_str("Merge(..)"),
+        }
+    }
+}
+
+/// Decision of a `Merger` on a conflicting event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    /// Fail the push.
+    Reject,
+    /// Drop the incoming event.
+    Discard,
+    /// Append these events after the latest version of the stream instead
+    /// of the incoming one. Aggregate id and versions are assigned by the
+    /// engine. They originate at the receiving replica and are marked with
+    /// `SYNC_RESOLVED`.
+    Append(Vec<Event>),
+}
+
+/// Resolves conflicts for `ConflictStrategy::Merge`, e.g. by folding both
+/// sides into a compensating event.
+pub trait Merger: Send + Sync {
+    /// `incoming` is the event pushed, `local` are the events of the
+    /// receiving stream from the version of `incoming` on.
+    fn merge(&self, incoming: &Event, local: &[Event]) -> Result<Resolution, Error>;
+}
+
+impl<F> Merger for F
+where
+    F: Fn(&Event, &[Event]) -> Result<Resolution, Error> + Send + Sync,
+{
+    fn merge(&self, incoming: &Event, local: &[Event]) -> Result<Resolution, Error> {
+        self(incoming, local)
+    }
+}
+
+impl Default for SyncEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checkpoint of the receiving store holding the vector entry of `origin`.
+fn vector_entry(origin: &str) -> String {
+    format!("sync:seen:{}", origin)
+}
+
+/// Checkpoint of the receiving store holding the position up to which the
+/// log of `source` was read.
+fn cursor(source: &str) -> String {
+    format!("sync:cursor:{}", source)
+}
+
+/// Source and position an event of `replica` originates from.
+fn origin_of<'e>(event: &'e Event, source: &'e str) -> Result<(&'e str, u64), Error> {
+    match event.metadata.get(SYNC_ORIGIN) {
+        Some(origin) => {
+            let seq = event
+                .metadata
+                .get(SYNC_SEQ)
+                .and_then(|seq| seq.parse().ok())
+                .ok_or_else(|| {
+                    Error::WithMsg(format!(
+                        "event {} of stream {} lacks {}",
+                        event.version, event.id, SYNC_SEQ
+                    ))
+                })?;
+            Ok((origin, seq))
+        }
+        None => Ok((source, event.position)),
+    }
+}
+
+impl SyncEngine {
+    pub fn new() -> Self {
+        Self {
+            batch_size: 500,
+            strategy: ConflictStrategy::Reject,
+        }
+    }
+
+    pub fn with_conflict_strategy(mut self, strategy: ConflictStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Read the log of the sending replica in pages of `batch_size` events.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Push `a` to `b`, then `b` to `a`.
+    #[instrument(skip(self))]
+    pub fn sync<A: EventStore, B: EventStore>(
+        &self,
+        a: Replica<A>,
+        b: Replica<B>,
+    ) -> Result<SyncReport, Error> {
+        let pushed = self.push(a, b)?;
+        let pulled = self.push(b, a)?;
+        info!(pushed, pulled, "synced replicas");
+        Ok(SyncReport { pushed, pulled })
+    }
+
+    /// Copy the events of `from` that `to` is missing, returns their number.
+    #[instrument(skip(self))]
+    pub fn push<A: EventStore, B: EventStore>(
+        &self,
+        from: Replica<A>,
+        to: Replica<B>,
+    ) -> Result<usize, Error> {
+        if from.source == to.source {
+            return Err(Error::WithMsg(format!(
+                "sync: both replicas are named {}",
+                from.source
+            )));
+        }
+        let mut position = to.store.get_checkpoint(&cursor(from.source))?;
+        let mut vector: HashMap<String, u64> = HashMap::new();
+        let mut pushed = 0;
+        loop {
+            let events = from.store.read_all(position, self.batch_size)?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            let last = last.position;
+            for event in events {
+                let (origin, seq) = origin_of(&event, from.source)?;
+                if origin == to.source {
+                    continue;
+                }
+                let seen = match vector.get(origin) {
+                    Some(seen) => *seen,
+                    None => to.seen(origin)?,
+                };
+                if seq <= seen {
+                    continue;
+                }
+                let origin = origin.to_string();
+                let mut copy = Event {
+                    position: 0,
+                    ..event
+                };
+                copy.metadata
+                    .insert(SYNC_ORIGIN.to_string(), origin.clone());
+                copy.metadata.insert(SYNC_SEQ.to_string(), seq.to_string());
+                if self.deliver(from, to, copy)? {
+                    pushed += 1;
+                }
+                to.store.save_checkpoint(&vector_entry(&origin), seq)?;
+                vector.insert(origin, seq);
+            }
+            position = last;
+            to.store.save_checkpoint(&cursor(from.source), position)?;
+        }
+        debug!(pushed, position, "pushed events");
+        Ok(pushed)
+    }
+
+    /// Append `copy` to `to` unless the stream already holds it, e.g.
+    /// because a previous sync stopped before it stored the vector, and
+    /// resolve conflicts. Returns whether events were appended.
+    fn deliver<A: EventStore, B: EventStore>(
+        &self,
+        from: Replica<A>,
+        to: Replica<B>,
+        copy: Event,
+    ) -> Result<bool, Error> {
+        match to.store.append_events(std::slice::from_ref(&copy)) {
+            Ok(()) => return Ok(true),
+            Err(err) if err.is_version_conflict() => {}
+            Err(err) => return Err(err),
+        }
+        let resolved = format!("{}:{}", copy.metadata[SYNC_ORIGIN], copy.metadata[SYNC_SEQ]);
+        let stream = to.store.get_aggretate(copy.id)?;
+        if stream
+            .iter()
+            .any(|event| is_copy_of(event, &copy, &resolved))
+        {
+            return Ok(false);
+        }
+        let head = stream.last().map_or(0, |event| event.version);
+        let local: Vec<Event> = stream
+            .into_iter()
+            .filter(|event| event.version >= copy.version)
+            .collect();
+        info!(
+            aggregate_id = %copy.id,
+            version = copy.version,
+            head,
+            strategy = ?self.strategy,
+            "sync conflict"
+        );
+        // the sender received the local events before and resolved the
+        // conflict already, resolving it here again would fork the stream
+        if self.holds_all(from, to, &local)? {
+            return rebase(to.store, copy, head);
+        }
+        match &self.strategy {
+            ConflictStrategy::Reject => Err(conflict(&copy)),
+            ConflictStrategy::Rebase => rebase(to.store, copy, head),
+            ConflictStrategy::Merge(merger) => match merger.merge(&copy, &local)? {
+                Resolution::Reject => Err(conflict(&copy)),
+                Resolution::Discard => Ok(false),
+                Resolution::Append(events) => {
+                    let events: Vec<Event> = events
+                        .into_iter()
+                        .zip(head + 1..)
+                        .map(|(mut event, version)| {
+                            event.id = copy.id;
+                            event.version = version;
+                            event.position = 0;
+                            event.metadata.remove(SYNC_ORIGIN);
+                            event.metadata.remove(SYNC_SEQ);
+                            event
+                                .metadata
+                                .insert(SYNC_RESOLVED.to_string(), resolved.clone());
+                            event
+                        })
+                        .collect();
+                    if events.is_empty() {
+                        return Ok(false);
+                    }
+                    to.store.append_events(&events)?;
+                    Ok(true)
+                }
+            },
+        }
+    }
+
+    /// Whether `from` holds all of `events` of `to`.
+    fn holds_all<A: EventStore, B: EventStore>(
+        &self,
+        from: Replica<A>,
+        to: Replica<B>,
+        events: &[Event],
+    ) -> Result<bool, Error> {
+        for event in events {
+            let (origin, seq) = origin_of(event, to.source)?;
+            if origin != from.source && from.seen(origin)? < seq {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Append `copy` after `head`, recording its original version.
+fn rebase<S: EventStore>(store: &S, mut copy: Event, head: u32) -> Result<bool, Error> {
+    copy.metadata
+        .insert(ORIGINAL_VERSION.to_string(), copy.version.to_string());
+    copy.version = head + 1;
+    store.append_events(&[copy])?;
+    Ok(true)
+}
+
+/// Whether `event` is `copy` or replaced it after a conflict.
+fn is_copy_of(event: &Event, copy: &Event, resolved: &str) -> bool {
+    (event.metadata.get(SYNC_ORIGIN) == copy.metadata.get(SYNC_ORIGIN)
+        && event.metadata.get(SYNC_SEQ) == copy.metadata.get(SYNC_SEQ))
+        || event.metadata.get(SYNC_RESOLVED).map(String::as_str) == Some(resolved)
+}
+
+fn conflict(copy: &Event) -> Error {
+    Error::WithMsg(format!(
+        "sync conflict: stream {} diverged at version {}",
+        copy.id, copy.version
+    ))
+}
@@ -0,0 +1,80 @@
+//! Store lifecycle events appended to system streams by backends
+//! configured with `SqliteBackend::with_system_streams`. The streams are
+//! read and subscribed to like any other, e.g. to notify operators:
+//!
+//! ```ignore
+//! for event in backend.get_aggretate(system::STREAMS)? {
+//!     if let Ok(deleted) = event.decode::<system::StreamDeleted>() {
+//!         alert(deleted.aggregate_id);
+//!     }
+//! }
+//! ```
+//!
+//! Every tenant has its own system streams. Events are appended in the
+//! transaction of the change they describe, except for scavenges and
+//! imports which commit in batches.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::DomainEvent;
+
+/// `$system-streams`: `StreamCreated`, `StreamDeleted` and `StreamsCopied`.
+pub const STREAMS: Uuid = Uuid::from_u128(0x2473_7973_7465_6d00_0000_0000_0000_0001);
+
+/// `$system-maintenance`: `ScavengeCompleted`.
+pub const MAINTENANCE: Uuid = Uuid::from_u128(0x2473_7973_7465_6d00_0000_0000_0000_0002);
+
+/// `$system-imports`: `ImportCompleted`.
+pub const IMPORTS: Uuid = Uuid::from_u128(0x2473_7973_7465_6d00_0000_0000_0000_0003);
+
+/// Whether `aggregate_id` is one of the system streams.
+pub fn is_system_stream(aggregate_id: Uuid) -> bool {
+    [STREAMS, MAINTENANCE, IMPORTS].contains(&aggregate_id)
+}
+
+/// The first event of a stream was appended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "$stream-created")]
+pub struct StreamCreated {
+    pub aggregate_id: Uuid,
+}
+
+/// A stream was deleted or tombstoned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "$stream-deleted")]
+pub struct StreamDeleted {
+    pub aggregate_id: Uuid,
+    /// The history was kept, see `SqliteBackend::tombstone_stream`.
+    pub tombstoned: bool,
+}
+
+/// Events were copied between streams by `Maintenance::split_stream` or
+/// `Maintenance::merge_streams`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "$streams-copied")]
+pub struct StreamsCopied {
+    pub sources: Vec<Uuid>,
+    /// Number of events copied per target stream.
+    pub copied: BTreeMap<Uuid, usize>,
+}
+
+/// A scavenge run finished, see `Maintenance::scavenge`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "$scavenge-completed")]
+pub struct ScavengeCompleted {
+    pub deleted_events: usize,
+    pub deleted_snapshots: usize,
+    pub reclaimed_bytes: u64,
+}
+
+/// An import finished, also if it failed part way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "$import-completed")]
+pub struct ImportCompleted {
+    pub imported: usize,
+    pub skipped: usize,
+    pub error: Option<String>,
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::{global, Context};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::backend::model::Event;
+
+struct MetadataInjector<'a>(&'a mut BTreeMap<String, String>);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+struct MetadataExtractor<'a>(&'a BTreeMap<String, String>);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+/// Write the trace context of the current span into `metadata` with the
+/// global text map propagator, e.g. as W3C `traceparent`.
+pub fn inject_current(metadata: &mut BTreeMap<String, String>) {
+    let cx = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Whether `inject_current` writes `key`, e.g. `traceparent`.
+pub(crate) fn is_trace_key(key: &str) -> bool {
+    global::get_text_map_propagator(|propagator| propagator.fields().any(|field| field == key))
+}
+
+/// Trace context stored in `metadata` by `inject_current`.
+pub fn extract(metadata: &BTreeMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&MetadataExtractor(metadata)))
+}
+
+/// Span for processing `event` outside of the append, continuing the trace
+/// the event was appended in.
+pub fn event_span(event: &Event) -> Span {
+    let span = tracing::info_span!(
+        "eventstore.process",
+        aggregate_id = %event.id,
+        event_type = event.event_type,
+        position = event.position
+    );
+    let _ = span.set_parent(extract(&event.metadata));
+    span
+}
@@ -0,0 +1,197 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tracing::{debug, info, instrument};
+
+use crate::backend::sqlite::{ConnectionOptions, Error, SqliteBackend, Tables};
+
+type Template = Arc<dyn Fn(SqliteBackend) -> SqliteBackend + Send + Sync>;
+
+/// Opens one database file per tenant in a directory, for isolation beyond
+/// the tenant column of `SqliteBackend::tenant`: a tenant is exported by
+/// copying its file and deleted by removing it.
+///
+/// Backends are opened on first use and kept open up to `max_open`, the
+/// least recently used one is closed beyond that. Handles returned before
+/// stay usable, the pool is closed once the last one is dropped.
+pub struct TenantManager {
+    dir: PathBuf,
+    options: ConnectionOptions,
+    tables: Tables,
+    template: Template,
+    max_open: usize,
+    open: Mutex<Open>,
+}
+
+#[derive(Default)]
+struct Open {
+    backends: HashMap<String, (SqliteBackend, u64)>,
+    tick: u64,
+}
+
+impl std::fmt::Debug for TenantManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TenantManager")
+            .field("dir", &self.dir)
+            .field("options", &self.options)
+            .field("max_open", &self.max_open)
+            .finish()
+    }
+}
+
+impl TenantManager {
+    /// Keep the tenant databases in `dir`, which is created if missing.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self::new_with_options(dir, ConnectionOptions::default())
+    }
+
+    /// Like `new`, with `options` applied to every connection of the tenant
+    /// databases, e.g. their `Durability`.
+    pub fn new_with_options<P: AsRef<Path>>(dir: P, options: ConnectionOptions) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            options,
+            tables: Tables::default(),
+            template: Arc::new(|backend| backend),
+            max_open: 64,
+            open: Mutex::new(Open::default()),
+        }
+    }
+
+    /// Number of tenant databases kept open at the same time.
+    pub fn with_max_open(mut self, max_open: usize) -> Self {
+        self.max_open = max_open.max(1);
+        self
+    }
+
+    /// Table names used in every tenant database.
+    pub fn with_tables(mut self, tables: Tables) -> Self {
+        self.tables = tables;
+        self
+    }
+
+    /// Options applied to every backend when it is opened, e.g. upcasters,
+    /// handlers or encryption.
+    pub fn with_template<F>(mut self, template: F) -> Self
+    where
+        F: Fn(SqliteBackend) -> SqliteBackend + Send + Sync + 'static,
+    {
+        self.template = Arc::new(template);
+        self
+    }
+
+    /// Backend of `tenant_id`, the database is created and migrated on
+    /// first use. Databases are opened without holding the lock, so other
+    /// tenants are served meanwhile.
+    #[instrument]
+    pub fn get(&self, tenant_id: &str) -> Result<SqliteBackend, Error> {
+        let path = self.path(tenant_id)?;
+        if let Some(backend) = self.touch(tenant_id, None) {
+            return Ok(backend);
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|err| Error::WithMsg(format!("tenant directory: {}", err)))?;
+        let backend =
+            SqliteBackend::try_with_tables(self.options.manager(&path), self.tables.clone())?;
+        let backend = (self.template)(backend);
+        Ok(self
+            .touch(tenant_id, Some(backend))
+            .expect("opened backends are inserted"))
+    }
+
+    /// Mark the backend of `tenant_id` as used and return it, inserting
+    /// `opened` unless a concurrent `get` opened the tenant first.
+    fn touch(&self, tenant_id: &str, opened: Option<SqliteBackend>) -> Option<SqliteBackend> {
+        let mut open = self.open.lock().unwrap();
+        open.tick += 1;
+        let tick = open.tick;
+        if opened.is_some()
+            && !open.backends.contains_key(tenant_id)
+            && open.backends.len() >= self.max_open
+        {
+            let lru = open
+                .backends
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(tenant, _)| tenant.clone());
+            if let Some(lru) = lru {
+                debug!(tenant = lru, "closing least recently used tenant");
+                open.backends.remove(&lru);
+            }
+        }
+        let (backend, used) = match (open.backends.entry(tenant_id.to_string()), opened) {
+            (Entry::Occupied(entry), _) => entry.into_mut(),
+            (Entry::Vacant(entry), Some(backend)) => entry.insert((backend, tick)),
+            (Entry::Vacant(_), None) => return None,
+        };
+        *used = tick;
+        Some(backend.clone())
+    }
+
+    /// Path of the database file of `tenant_id`. Tenant ids are used as
+    /// file names, so only ASCII letters, digits, `-` and `_` are accepted.
+    pub fn path(&self, tenant_id: &str) -> Result<PathBuf, Error> {
+        let valid = !tenant_id.is_empty()
+            && tenant_id.len() <= 128
+            && tenant_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::WithMsg(format!(
+                "invalid tenant id: {:?}",
+                tenant_id
+            )));
+        }
+        Ok(self.dir.join(format!("{}.db", tenant_id)))
+    }
+
+    /// Tenants with a database file, sorted.
+    pub fn tenants(&self) -> Result<Vec<String>, Error> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(Error::WithMsg(format!("tenant directory: {}", err))),
+        };
+        let mut tenants = Vec::new();
+        for entry in entries {
+            let entry =
+                entry.map_err(|err| Error::WithMsg(format!("tenant directory: {}", err)))?;
+            let name = entry.file_name();
+            let Some(tenant) = name.to_str().and_then(|name| name.strip_suffix(".db")) else {
+                continue;
+            };
+            if self.path(tenant).is_ok() {
+                tenants.push(tenant.to_string());
+            }
+        }
+        tenants.sort();
+        Ok(tenants)
+    }
+
+    /// Close the backend of `tenant_id` if it is open.
+    pub fn evict(&self, tenant_id: &str) {
+        self.open.lock().unwrap().backends.remove(tenant_id);
+    }
+
+    /// Remove the database of `tenant_id` including its WAL files. Handles
+    /// of the tenant must have been dropped, their connections would keep
+    /// the files alive on some platforms.
+    #[instrument]
+    pub fn delete_tenant(&self, tenant_id: &str) -> Result<(), Error> {
+        let path = self.path(tenant_id)?;
+        self.evict(tenant_id);
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = path.clone().into_os_string();
+            file.push(suffix);
+            match std::fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::WithMsg(format!("delete tenant: {}", err))),
+            }
+        }
+        info!(tenant_id, "deleted tenant database");
+        Ok(())
+    }
+}
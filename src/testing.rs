@@ -0,0 +1,123 @@
+use std::fmt::Debug;
+
+use r2d2_sqlite::SqliteConnectionManager;
+use uuid::Uuid;
+
+use crate::aggregate::{Aggregate, Repository};
+use crate::backend::{model::Event, sqlite::SqliteBackend};
+
+mod chaos;
+mod concurrency;
+mod factory;
+mod recording;
+mod seed;
+
+pub use chaos::ChaosBackend;
+pub use concurrency::{ConcurrentWriters, WriterReport};
+pub use factory::{EventFactory, OCCURRED_AT};
+pub use recording::{Call, RecordingBackend};
+pub use seed::{seed, SeedOpts};
+
+/// Given/When/Then harness for aggregate logic running against an in-memory
+/// backend.
+///
+/// ```ignore
+/// Scenario::<Cart>::new()
+///     .given(vec![Event::encode(&ItemAdded { .. })?])
+///     .when(|cart| cart.checkout())
+///     .then_expect(vec![Event::encode(&CheckedOut { .. })?]);
+/// ```
+///
+/// All assertions panic, it is meant to be used from tests only.
+pub struct Scenario<A: Aggregate> {
+    repository: Repository<A>,
+    aggregate_id: Uuid,
+    version: u32,
+}
+
+impl<A: Aggregate> Default for Scenario<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Aggregate> Scenario<A> {
+    pub fn new() -> Self {
+        let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+        Self {
+            repository: Repository::new(backend),
+            aggregate_id: Uuid::new_v4(),
+            version: 0,
+        }
+    }
+
+    /// Events which happened before the command, aggregate id and versions
+    /// are assigned by the scenario.
+    pub fn given(mut self, events: Vec<Event>) -> Self {
+        self.version = self
+            .repository
+            .save(self.aggregate_id, self.version, events)
+            .unwrap_or_else(|err| panic!("failed to store given events: {}", err));
+        self
+    }
+
+    /// Run the command against the state rebuilt from the given events.
+    /// Events returned by the command are stored like a repository would.
+    pub fn when<F, E>(self, command: F) -> Outcome<A, E>
+    where
+        F: FnOnce(&A) -> Result<Vec<Event>, E>,
+    {
+        let (state, version) = self
+            .repository
+            .load(self.aggregate_id)
+            .unwrap_or_else(|err| panic!("failed to load aggregate: {}", err));
+        let result = command(&state).inspect(|events| {
+            self.repository
+                .save(self.aggregate_id, version, events.clone())
+                .unwrap_or_else(|err| panic!("failed to store produced events: {}", err));
+        });
+        Outcome {
+            scenario: self,
+            result,
+        }
+    }
+}
+
+/// Result of `Scenario::when`.
+pub struct Outcome<A: Aggregate, E> {
+    scenario: Scenario<A>,
+    result: Result<Vec<Event>, E>,
+}
+
+impl<A: Aggregate, E: Debug> Outcome<A, E> {
+    /// Assert that the command produced exactly `expected`, compared by event
+    /// type and payload. Returns the state after applying the new events.
+    pub fn then_expect(self, expected: Vec<Event>) -> A {
+        let produced = match self.result {
+            Ok(events) => events,
+            Err(err) => panic!("expected events but command failed: {:?}", err),
+        };
+        let produced: Vec<_> = produced
+            .iter()
+            .map(|e| (e.event_type.as_str(), String::from_utf8_lossy(&e.data)))
+            .collect();
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|e| (e.event_type.as_str(), String::from_utf8_lossy(&e.data)))
+            .collect();
+        assert_eq!(produced, expected, "produced events differ from expected");
+        self.scenario
+            .repository
+            .load(self.scenario.aggregate_id)
+            .unwrap_or_else(|err| panic!("failed to load aggregate: {}", err))
+            .0
+    }
+
+    /// Assert that the command failed and return its error.
+    pub fn then_expect_error(self) -> E {
+        match self.result {
+            Ok(events) => panic!("expected an error but command produced {:?}", events),
+            Err(err) => err,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::ffi;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::backend::{model::Event, sqlite::Error, EventStore};
+
+/// `EventStore` wrapper injecting failures into the calls to `inner`, to
+/// exercise the retry and idempotency logic of services:
+///
+/// ```ignore
+/// let store = ChaosBackend::new(backend, 42)
+///     .with_busy_rate(0.2)
+///     .with_latency(Duration::from_millis(50))
+///     .with_commit_refusal_rate(0.1);
+/// ```
+///
+/// Faults are drawn from a generator seeded with the given seed, so the
+/// same sequence of calls meets the same faults on every run.
+pub struct ChaosBackend<B> {
+    inner: B,
+    rng: Mutex<u64>,
+    busy_rate: f64,
+    latency: Duration,
+    commit_refusal_rate: f64,
+    injected: AtomicU64,
+}
+
+impl<B: std::fmt::Debug> std::fmt::Debug for ChaosBackend<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChaosBackend")
+            .field("inner", &self.inner)
+            .field("busy_rate", &self.busy_rate)
+            .field("latency", &self.latency)
+            .field("commit_refusal_rate", &self.commit_refusal_rate)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: EventStore> ChaosBackend<B> {
+    /// Wrapper injecting no faults until configured.
+    pub fn new(inner: B, seed: u64) -> Self {
+        Self {
+            inner,
+            rng: Mutex::new(seed),
+            busy_rate: 0.0,
+            latency: Duration::ZERO,
+            commit_refusal_rate: 0.0,
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Fail this share of all calls with `SQLITE_BUSY` before they reach
+    /// the inner store.
+    pub fn with_busy_rate(mut self, rate: f64) -> Self {
+        self.busy_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay every call by a random duration of up to `max`.
+    pub fn with_latency(mut self, max: Duration) -> Self {
+        self.latency = max;
+        self
+    }
+
+    /// Report this share of appends as failed after the inner store
+    /// committed them, like a connection lost before the acknowledgement.
+    pub fn with_commit_refusal_rate(mut self, rate: f64) -> Self {
+        self.commit_refusal_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Number of failures injected so far.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    /// Next number of the splitmix64 sequence, uniform in `[0, 1)`.
+    fn next(&self) -> f64 {
+        let mut state = self.rng.lock().unwrap_or_else(|err| err.into_inner());
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn inject(&self, call: &str, message: &str) -> Error {
+        self.injected.fetch_add(1, Ordering::Relaxed);
+        debug!(call, message, "injected failure");
+        Error::Sqlite(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_BUSY),
+            Some(format!("injected: {}", message)),
+        ))
+    }
+
+    /// Delay and maybe fail a call before it reaches the inner store.
+    fn before(&self, call: &str) -> Result<(), Error> {
+        if !self.latency.is_zero() {
+            std::thread::sleep(self.latency.mul_f64(self.next()));
+        }
+        if self.next() < self.busy_rate {
+            return Err(self.inject(call, "database is locked"));
+        }
+        Ok(())
+    }
+}
+
+impl<B: EventStore> EventStore for ChaosBackend<B> {
+    fn append_events(&self, events: &[Event]) -> Result<(), Error> {
+        self.before("append_events")?;
+        self.inner.append_events(events)?;
+        if self.next() < self.commit_refusal_rate {
+            return Err(self.inject("append_events", "committed but not acknowledged"));
+        }
+        Ok(())
+    }
+
+    fn get_aggretate(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
+        self.before("get_aggretate")?;
+        self.inner.get_aggretate(aggregate_id)
+    }
+
+    fn stream_version(&self, aggregate_id: Uuid) -> Result<u32, Error> {
+        self.before("stream_version")?;
+        self.inner.stream_version(aggregate_id)
+    }
+
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, Error> {
+        self.before("read_all")?;
+        self.inner.read_all(from_position, limit)
+    }
+
+    fn save_snapshot(&self, snapshot: &Event) -> Result<(), Error> {
+        self.before("save_snapshot")?;
+        self.inner.save_snapshot(snapshot)
+    }
+
+    fn get_snapshot_by_version(&self, aggregate_id: Uuid, version: u32) -> Result<Event, Error> {
+        self.before("get_snapshot_by_version")?;
+        self.inner.get_snapshot_by_version(aggregate_id, version)
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<u64, Error> {
+        self.before("get_checkpoint")?;
+        self.inner.get_checkpoint(name)
+    }
+
+    fn save_checkpoint(&self, name: &str, position: u64) -> Result<(), Error> {
+        self.before("save_checkpoint")?;
+        self.inner.save_checkpoint(name, position)
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::ErrorCode;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::backend::{
+    model::Event,
+    sqlite::{Error, SqliteBackend},
+    EventStore,
+};
+
+/// Metadata keys identifying the append of an event.
+const WRITER: &str = "writer";
+const SEQ: &str = "seq";
+
+/// Longest pause before retrying on `SQLITE_BUSY`, the pause grows by a
+/// millisecond per attempt up to it.
+const MAX_BUSY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Threads appending to overlapping streams, to check the concurrency
+/// guarantees of an `EventStore`:
+///
+/// ```ignore
+/// let report = ConcurrentWriters::new(8, 3)
+///     .with_appends_per_writer(200)
+///     .run(&ChaosBackend::new(RecordingBackend::new(), 7).with_busy_rate(0.1));
+/// assert_eq!(report.failed, 0);
+/// ```
+///
+/// Each writer appends one event at a time to the streams in turn, reading
+/// the stream version first and retrying on version conflicts and, after a
+/// short pause, on `SQLITE_BUSY`, up to `with_max_attempts` times.
+/// Afterwards every stream must hold gapless versions from 1 in commit
+/// order and every acknowledged append must be stored, otherwise `run`
+/// panics.
+#[derive(Debug, Clone)]
+pub struct ConcurrentWriters {
+    writers: usize,
+    aggregates: usize,
+    appends_per_writer: usize,
+    max_attempts: u32,
+}
+
+/// Outcome of a `ConcurrentWriters` run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriterReport {
+    /// Appends the store reported as successful.
+    pub acknowledged: usize,
+    /// Appends given up on, after an error other than a conflict or
+    /// `SQLITE_BUSY` or after too many attempts.
+    pub failed: usize,
+    /// Attempts retried because a concurrent writer took the version.
+    pub conflicts: usize,
+    /// Attempts retried because the store was busy.
+    pub busy: usize,
+    /// Events found in the streams afterwards.
+    pub stored: usize,
+    /// Appends stored more than once, e.g. retried after the store failed
+    /// to acknowledge a commit.
+    pub duplicates: usize,
+    pub elapsed: Duration,
+}
+
+impl ConcurrentWriters {
+    /// `writers` threads sharing `aggregates` streams, 100 appends each.
+    pub fn new(writers: usize, aggregates: usize) -> Self {
+        Self {
+            writers,
+            aggregates: aggregates.max(1),
+            appends_per_writer: 100,
+            max_attempts: 1_000,
+        }
+    }
+
+    pub fn with_appends_per_writer(mut self, appends: usize) -> Self {
+        self.appends_per_writer = appends;
+        self
+    }
+
+    /// Give up on an append after `attempts` conflicts or busy errors.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Run against a `SqliteBackend` on a new temporary database file,
+    /// removed afterwards.
+    #[track_caller]
+    pub fn run_sqlite(&self) -> WriterReport {
+        let path = std::env::temp_dir().join(format!("eventstore-{}.db", Uuid::new_v4()));
+        let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+        let report = self.run(&backend);
+        drop(backend);
+        let _ = std::fs::remove_file(&path);
+        report
+    }
+
+    /// Run against `store`, on new streams. Panics if a guarantee was
+    /// violated.
+    #[track_caller]
+    pub fn run<S: EventStore>(&self, store: &S) -> WriterReport {
+        let ids: Vec<Uuid> = (0..self.aggregates).map(|_| Uuid::new_v4()).collect();
+        let started = Instant::now();
+        let results: Vec<(WriterReport, Vec<(usize, usize)>)> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..self.writers)
+                .map(|writer| {
+                    let ids = &ids;
+                    scope.spawn(move || self.write(store, ids, writer))
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().expect("writer thread panicked"))
+                .collect()
+        });
+        let mut report = WriterReport {
+            elapsed: started.elapsed(),
+            ..Default::default()
+        };
+        let mut acknowledged = Vec::new();
+        for (tally, acks) in results {
+            report.acknowledged += tally.acknowledged;
+            report.failed += tally.failed;
+            report.conflicts += tally.conflicts;
+            report.busy += tally.busy;
+            acknowledged.extend(acks);
+        }
+        self.verify(store, &ids, &acknowledged, &mut report);
+        debug!(?report, "concurrent writers finished");
+        report
+    }
+
+    /// Appends of one writer, returns its tally and acknowledged appends.
+    fn write<S: EventStore>(
+        &self,
+        store: &S,
+        ids: &[Uuid],
+        writer: usize,
+    ) -> (WriterReport, Vec<(usize, usize)>) {
+        let mut tally = WriterReport::default();
+        let mut acknowledged = Vec::with_capacity(self.appends_per_writer);
+        for seq in 0..self.appends_per_writer {
+            let id = ids[(writer + seq) % ids.len()];
+            let mut attempts = 0;
+            loop {
+                attempts += 1;
+                let res = store.stream_version(id).and_then(|version| {
+                    store.append_events(&[Event {
+                        id,
+                        version: version + 1,
+                        event_type: "ConcurrentAppend".to_string(),
+                        data: b"{}".to_vec(),
+                        metadata: [
+                            (WRITER.to_string(), writer.to_string()),
+                            (SEQ.to_string(), seq.to_string()),
+                        ]
+                        .into(),
+                        ..Default::default()
+                    }])
+                });
+                match res {
+                    Ok(()) => {
+                        tally.acknowledged += 1;
+                        acknowledged.push((writer, seq));
+                    }
+                    Err(err) if attempts < self.max_attempts && err.is_version_conflict() => {
+                        tally.conflicts += 1;
+                        continue;
+                    }
+                    Err(err) if attempts < self.max_attempts && is_busy(&err) => {
+                        tally.busy += 1;
+                        backoff(attempts);
+                        continue;
+                    }
+                    Err(err) => {
+                        debug!(writer, seq, attempts, "append failed: {}", err);
+                        tally.failed += 1;
+                    }
+                }
+                break;
+            }
+        }
+        (tally, acknowledged)
+    }
+
+    #[track_caller]
+    fn verify<S: EventStore>(
+        &self,
+        store: &S,
+        ids: &[Uuid],
+        acknowledged: &[(usize, usize)],
+        report: &mut WriterReport,
+    ) {
+        let mut stored: HashMap<(usize, usize), usize> = HashMap::new();
+        for id in ids {
+            // faults injected into the store hit these reads too
+            let mut attempts = 0;
+            let events = loop {
+                attempts += 1;
+                match store.get_aggretate(*id) {
+                    Err(err) if attempts < self.max_attempts && is_busy(&err) => {
+                        backoff(attempts);
+                        continue;
+                    }
+                    res => {
+                        break res
+                            .unwrap_or_else(|err| panic!("reading stream {} failed: {}", id, err))
+                    }
+                }
+            };
+            for (idx, event) in events.iter().enumerate() {
+                assert_eq!(
+                    event.version,
+                    idx as u32 + 1,
+                    "stream {} is not gapless: {:?}",
+                    id,
+                    events.iter().map(|e| e.version).collect::<Vec<_>>()
+                );
+                if idx > 0 {
+                    assert!(
+                        event.position > events[idx - 1].position,
+                        "version {} of stream {} was committed before version {}",
+                        event.version,
+                        id,
+                        idx
+                    );
+                }
+                let append = (parse(event, WRITER, id), parse(event, SEQ, id));
+                *stored.entry(append).or_default() += 1;
+            }
+            report.stored += events.len();
+        }
+        report.duplicates = stored.values().map(|count| count - 1).sum();
+        for (writer, seq) in acknowledged {
+            assert!(
+                stored.contains_key(&(*writer, *seq)),
+                "append {} of writer {} was acknowledged but is not stored",
+                seq,
+                writer
+            );
+        }
+    }
+}
+
+fn parse(event: &Event, key: &str, id: &Uuid) -> usize {
+    event
+        .metadata
+        .get(key)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("event {} of stream {} lacks {}", event.version, id, key))
+}
+
+/// Wait before retry `attempts` + 1 after `SQLITE_BUSY`, so a locked
+/// store is not polled in a tight loop.
+fn backoff(attempts: u32) {
+    std::thread::sleep(Duration::from_millis(u64::from(attempts)).min(MAX_BUSY_BACKOFF));
+}
+
+fn is_busy(err: &Error) -> bool {
+    matches!(err, Error::Sqlite(err) if err.sqlite_error_code() == Some(ErrorCode::DatabaseBusy))
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use crate::backend::{model::Event, sqlite::Error, EventStore};
+
+/// Metadata key of the time an `EventFactory` event occurred at, in
+/// milliseconds since the Unix epoch.
+pub const OCCURRED_AT: &str = "occurred_at";
+
+/// Events appended per call by `EventFactory::populate`.
+const POPULATE_BATCH: usize = 500;
+
+type PayloadFn = dyn Fn(usize, u32) -> Vec<u8> + Send + Sync;
+
+/// Generates reproducible streams for load tests and benchmarks: the same
+/// settings always produce the same aggregate ids, versions, payloads and
+/// timestamps.
+///
+/// ```ignore
+/// let factory = EventFactory::new(10_000, 100)
+///     .with_event_types(["OrderPlaced", "ItemAdded", "ItemAdded", "OrderShipped"])
+///     .with_payload_bytes(512);
+/// factory.populate(&backend)?;
+/// ```
+#[derive(Clone)]
+pub struct EventFactory {
+    aggregates: usize,
+    events_per_aggregate: u32,
+    seed: u64,
+    event_types: Vec<String>,
+    payload: Arc<PayloadFn>,
+    start: SystemTime,
+    interval: Duration,
+}
+
+impl fmt::Debug for EventFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventFactory")
+            .field("aggregates", &self.aggregates)
+            .field("events_per_aggregate", &self.events_per_aggregate)
+            .field("seed", &self.seed)
+            .field("event_types", &self.event_types)
+            .finish_non_exhaustive()
+    }
+}
+
+/// JSON payload of about `bytes` bytes.
+fn padded_json(bytes: usize) -> Arc<PayloadFn> {
+    Arc::new(move |aggregate, version| {
+        format!(
+            r#"{{"aggregate":{},"version":{},"padding":"{}"}}"#,
+            aggregate,
+            version,
+            "x".repeat(bytes.saturating_sub(48))
+        )
+        .into_bytes()
+    })
+}
+
+impl EventFactory {
+    /// `aggregates` streams of `events_per_aggregate` events each, with
+    /// JSON payloads of 256 bytes, one event per second from the Unix
+    /// epoch on.
+    pub fn new(aggregates: usize, events_per_aggregate: u32) -> Self {
+        Self {
+            aggregates,
+            events_per_aggregate,
+            seed: 0x5eed,
+            event_types: vec!["FixtureEvent".to_string()],
+            payload: padded_json(256),
+            start: UNIX_EPOCH,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Seed of the aggregate ids, factories with different seeds fill the
+    /// same store without sharing streams.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Event types used in turn along each stream, the first one for
+    /// version 1.
+    pub fn with_event_types<I, T>(mut self, event_types: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let event_types: Vec<String> = event_types.into_iter().map(Into::into).collect();
+        if !event_types.is_empty() {
+            self.event_types = event_types;
+        }
+        self
+    }
+
+    /// JSON payloads of about `bytes` bytes.
+    pub fn with_payload_bytes(mut self, bytes: usize) -> Self {
+        self.payload = padded_json(bytes);
+        self
+    }
+
+    /// Payloads built by `payload` from the aggregate number and version.
+    pub fn with_payload(
+        mut self,
+        payload: impl Fn(usize, u32) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Self {
+        self.payload = Arc::new(payload);
+        self
+    }
+
+    /// Stamp the first event with `start` and each following one in the
+    /// order of `events` `interval` later.
+    pub fn with_timestamps(mut self, start: SystemTime, interval: Duration) -> Self {
+        self.start = start;
+        self.interval = interval;
+        self
+    }
+
+    /// Number of events of all streams.
+    pub fn len(&self) -> usize {
+        self.aggregates * self.events_per_aggregate as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn aggregates(&self) -> usize {
+        self.aggregates
+    }
+
+    pub fn events_per_aggregate(&self) -> u32 {
+        self.events_per_aggregate
+    }
+
+    /// Id of the `n`th aggregate.
+    pub fn aggregate_id(&self, n: usize) -> Uuid {
+        Uuid::from_u128(((self.seed as u128) << 64) | n as u128)
+    }
+
+    /// Time the event `version` of the `n`th aggregate occurred at.
+    pub fn occurred_at(&self, n: usize, version: u32) -> SystemTime {
+        let index = (version.saturating_sub(1) as u64) * self.aggregates as u64 + n as u64;
+        self.start
+            + self
+                .interval
+                .saturating_mul(index.min(u32::MAX as u64) as u32)
+    }
+
+    /// Event `version` of the `n`th aggregate.
+    pub fn event(&self, n: usize, version: u32) -> Event {
+        let event_type =
+            &self.event_types[(version as usize).saturating_sub(1) % self.event_types.len()];
+        let occurred_at = self
+            .occurred_at(n, version)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Event {
+            id: self.aggregate_id(n),
+            version,
+            event_type: event_type.clone(),
+            data: (self.payload)(n, version),
+            metadata: [(OCCURRED_AT.to_string(), occurred_at.to_string())].into(),
+            ..Default::default()
+        }
+    }
+
+    /// All events, interleaving the streams like concurrent writers do.
+    pub fn events(&self) -> impl Iterator<Item = Event> + '_ {
+        (1..=self.events_per_aggregate)
+            .flat_map(move |version| (0..self.aggregates).map(move |n| self.event(n, version)))
+    }
+
+    /// Append all events to `store` in batches, returns their number.
+    pub fn populate<S: EventStore>(&self, store: &S) -> Result<usize, Error> {
+        let mut batch = Vec::with_capacity(POPULATE_BATCH);
+        for event in self.events() {
+            batch.push(event);
+            if batch.len() == POPULATE_BATCH {
+                store.append_events(&batch)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            store.append_events(&batch)?;
+        }
+        Ok(self.len())
+    }
+}
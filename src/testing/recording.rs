@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use uuid::Uuid;
+
+use crate::backend::{model::Event, sqlite::Error, EventStore};
+
+/// A call made to a `RecordingBackend`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    AppendEvents(Vec<Event>),
+    GetAggregate(Uuid),
+    StreamVersion(Uuid),
+    ReadAll { from_position: u64, limit: usize },
+    SaveSnapshot(Event),
+    GetSnapshot { aggregate_id: Uuid, version: u32 },
+    GetCheckpoint(String),
+    SaveCheckpoint { name: String, position: u64 },
+}
+
+type Matcher = Box<dyn Fn(&Call) -> bool + Send>;
+
+#[derive(Default)]
+struct State {
+    calls: Vec<Call>,
+    failures: Vec<(Matcher, Error)>,
+    streams: HashMap<Uuid, Vec<Event>>,
+    log: Vec<Event>,
+    snapshots: HashMap<(Uuid, u32), Event>,
+    checkpoints: HashMap<String, u64>,
+}
+
+/// In-memory `EventStore` recording every call, for application tests
+/// without a database:
+///
+/// ```ignore
+/// let store = RecordingBackend::new();
+/// store.fail_next(|call| matches!(call, Call::AppendEvents(_)), Error::WithMsg("disk full".into()));
+/// let orders = Repository::<Order, _>::new(store.clone());
+/// assert!(place_order(&orders).is_err());
+/// place_order(&orders)?;
+/// store.assert_appended("OrderPlaced", 1);
+/// ```
+///
+/// Appends check versions like `SqliteBackend` does. Clones share the
+/// recorded calls and the stored events. Assertions panic.
+#[derive(Clone, Default)]
+pub struct RecordingBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for RecordingBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state();
+        f.debug_struct("RecordingBackend")
+            .field("calls", &state.calls.len())
+            .field("events", &state.log.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // a failed assertion of another thread does not corrupt the state
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Fail the next call `matches` accepts with `error`. Failures are
+    /// used up in the order they were scripted.
+    pub fn fail_next(&self, matches: impl Fn(&Call) -> bool + Send + 'static, error: Error) {
+        self.state().failures.push((Box::new(matches), error));
+    }
+
+    /// Record `call` and return the failure scripted for it, if any.
+    fn record(&self, call: Call) -> Result<MutexGuard<'_, State>, Error> {
+        let mut state = self.state();
+        let failure = state
+            .failures
+            .iter()
+            .position(|(matches, _)| matches(&call));
+        state.calls.push(call);
+        match failure {
+            Some(idx) => Err(state.failures.remove(idx).1),
+            None => Ok(state),
+        }
+    }
+
+    /// All calls in the order they were made, failed ones included.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state().calls.clone()
+    }
+
+    /// Events stored by successful appends, in commit order.
+    pub fn appended(&self) -> Vec<Event> {
+        self.state().log.clone()
+    }
+
+    /// Assert that exactly `count` events of type `event_type` were
+    /// appended.
+    #[track_caller]
+    pub fn assert_appended(&self, event_type: &str, count: usize) {
+        let state = self.state();
+        let appended = state
+            .log
+            .iter()
+            .filter(|event| event.event_type == event_type)
+            .count();
+        assert_eq!(
+            appended,
+            count,
+            "expected {} {} events, appended were {:?}",
+            count,
+            event_type,
+            state
+                .log
+                .iter()
+                .map(|event| event.event_type.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// Assert that no events were appended.
+    #[track_caller]
+    pub fn assert_nothing_appended(&self) {
+        let state = self.state();
+        assert!(
+            state.log.is_empty(),
+            "expected no events, appended were {:?}",
+            state.log
+        );
+    }
+}
+
+impl EventStore for RecordingBackend {
+    fn append_events(&self, events: &[Event]) -> Result<(), Error> {
+        let mut state = self.record(Call::AppendEvents(events.to_vec()))?;
+        let mut versions: HashMap<Uuid, u32> = HashMap::new();
+        for event in events {
+            let current = match versions.get(&event.id) {
+                Some(version) => *version,
+                None => state
+                    .streams
+                    .get(&event.id)
+                    .map_or(0, |stream| stream.len() as u32),
+            };
+            if event.version != current + 1 {
+                return Err(Error::VersionConflict);
+            }
+            versions.insert(event.id, event.version);
+        }
+        for event in events {
+            let position = state.log.len() as u64 + 1;
+            let event = Event {
+                position,
+                ..event.clone()
+            };
+            state
+                .streams
+                .entry(event.id)
+                .or_default()
+                .push(event.clone());
+            state.log.push(event);
+        }
+        Ok(())
+    }
+
+    fn get_aggretate(&self, aggregate_id: Uuid) -> Result<Vec<Event>, Error> {
+        let state = self.record(Call::GetAggregate(aggregate_id))?;
+        Ok(state
+            .streams
+            .get(&aggregate_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn stream_version(&self, aggregate_id: Uuid) -> Result<u32, Error> {
+        let state = self.record(Call::StreamVersion(aggregate_id))?;
+        Ok(state
+            .streams
+            .get(&aggregate_id)
+            .map_or(0, |stream| stream.len() as u32))
+    }
+
+    fn read_all(&self, from_position: u64, limit: usize) -> Result<Vec<Event>, Error> {
+        let state = self.record(Call::ReadAll {
+            from_position,
+            limit,
+        })?;
+        Ok(state
+            .log
+            .iter()
+            .skip(from_position as usize)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn save_snapshot(&self, snapshot: &Event) -> Result<(), Error> {
+        let mut state = self.record(Call::SaveSnapshot(snapshot.clone()))?;
+        state
+            .snapshots
+            .insert((snapshot.id, snapshot.version), snapshot.clone());
+        Ok(())
+    }
+
+    fn get_snapshot_by_version(&self, aggregate_id: Uuid, version: u32) -> Result<Event, Error> {
+        let state = self.record(Call::GetSnapshot {
+            aggregate_id,
+            version,
+        })?;
+        state
+            .snapshots
+            .get(&(aggregate_id, version))
+            .cloned()
+            .ok_or(Error::NotFound)
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<u64, Error> {
+        let state = self.record(Call::GetCheckpoint(name.to_string()))?;
+        Ok(state.checkpoints.get(name).copied().unwrap_or(0))
+    }
+
+    fn save_checkpoint(&self, name: &str, position: u64) -> Result<(), Error> {
+        let mut state = self.record(Call::SaveCheckpoint {
+            name: name.to_string(),
+            position,
+        })?;
+        state.checkpoints.insert(name.to_string(), position);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, instrument};
+
+use super::EventFactory;
+use crate::backend::{sqlite::Error, EventStore};
+
+/// Event types of the seeded streams, used in turn along each stream like
+/// the life cycle of an order.
+const EVENT_TYPES: [&str; 6] = [
+    "OrderPlaced",
+    "ItemAdded",
+    "ItemAdded",
+    "ItemRemoved",
+    "PaymentReceived",
+    "OrderShipped",
+];
+
+/// Options of `seed`.
+#[derive(Debug, Clone)]
+pub struct SeedOpts {
+    pub aggregates: usize,
+    pub events_per_aggregate: u32,
+    /// Approximate size of the JSON payloads.
+    pub payload_bytes: usize,
+    /// Seed of the aggregate ids, seeding again with the same value fails
+    /// on the existing streams.
+    pub seed: u64,
+}
+
+impl Default for SeedOpts {
+    fn default() -> Self {
+        Self {
+            aggregates: 1_000,
+            events_per_aggregate: 100,
+            payload_bytes: 256,
+            seed: 0x5eed,
+        }
+    }
+}
+
+impl SeedOpts {
+    /// The factory generating the seeded events, one per second up to now.
+    pub fn factory(&self) -> EventFactory {
+        let factory = EventFactory::new(self.aggregates, self.events_per_aggregate)
+            .with_seed(self.seed)
+            .with_event_types(EVENT_TYPES)
+            .with_payload_bytes(self.payload_bytes);
+        let interval = Duration::from_secs(1);
+        let span = interval.saturating_mul(factory.len().min(u32::MAX as usize) as u32);
+        let start = SystemTime::now()
+            .checked_sub(span)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        factory.with_timestamps(start, interval)
+    }
+}
+
+/// Fill `store` with generated streams for demos, benchmarks and
+/// reproductions, returns the number of events appended.
+#[instrument(skip(store))]
+pub fn seed<S: EventStore>(store: &S, opts: &SeedOpts) -> Result<usize, Error> {
+    let seeded = opts.factory().populate(store)?;
+    info!(seeded, "seeded store");
+    Ok(seeded)
+}
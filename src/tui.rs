@@ -0,0 +1,375 @@
+//! Terminal stream browser, started with `eventstore --db <path> browse`.
+//!
+//! The left pane lists the streams with `$all` on top, the middle pane the
+//! events of the selected stream and the right pane the selected event as
+//! pretty-printed JSON. In tail mode events are picked up as they are
+//! committed by other processes.
+
+use std::time::Duration;
+
+use ratatui::crossterm::event::{
+    self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+use uuid::Uuid;
+
+use crate::backend::model::Event;
+use crate::backend::sqlite::{Error, GetAggOpts, SqliteBackend};
+use crate::jsonl::Envelope;
+use crate::stream::StreamInfo;
+
+/// Rows loaded per query while scrolling.
+const PAGE: usize = 200;
+/// Rows moved by page up and page down.
+const JUMP: usize = 20;
+const TICK: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Streams,
+    Events,
+    Detail,
+}
+
+/// State of the browser, separate from the terminal so it can be driven by
+/// key events and drawn on any ratatui backend.
+pub struct Browser {
+    backend: SqliteBackend,
+    streams: Vec<StreamInfo>,
+    streams_done: bool,
+    /// Selection in `$all` followed by `streams`.
+    stream: ListState,
+    events: Vec<Event>,
+    events_done: bool,
+    event: ListState,
+    focus: Focus,
+    detail_scroll: u16,
+    tail: bool,
+    status: String,
+    quit: bool,
+}
+
+impl Browser {
+    pub fn new(backend: SqliteBackend) -> Result<Self, Error> {
+        let mut browser = Self {
+            backend,
+            streams: Vec::new(),
+            streams_done: false,
+            stream: ListState::default().with_selected(Some(0)),
+            events: Vec::new(),
+            events_done: false,
+            event: ListState::default(),
+            focus: Focus::Streams,
+            detail_scroll: 0,
+            tail: false,
+            status: String::new(),
+            quit: false,
+        };
+        browser.load_streams()?;
+        browser.load_events();
+        Ok(browser)
+    }
+
+    /// Whether the user asked to quit.
+    pub fn is_done(&self) -> bool {
+        self.quit
+    }
+
+    /// Whether new events are followed as they are committed.
+    pub fn is_tailing(&self) -> bool {
+        self.tail
+    }
+
+    /// The stream shown in the events pane, `None` for `$all`.
+    pub fn selected_stream(&self) -> Option<Uuid> {
+        match self.stream.selected() {
+            Some(i) if i > 0 => self.streams.get(i - 1).map(|s| s.aggregate_id),
+            _ => None,
+        }
+    }
+
+    pub fn selected_event(&self) -> Option<&Event> {
+        self.event.selected().and_then(|i| self.events.get(i))
+    }
+
+    fn load_streams(&mut self) -> Result<(), Error> {
+        let after = self.streams.last().map(|s| s.aggregate_id);
+        let page = self.backend.list_streams(after, PAGE)?;
+        self.streams_done = page.len() < PAGE;
+        self.streams.extend(page);
+        Ok(())
+    }
+
+    /// Replace the events pane with the first page of the selected stream.
+    fn load_events(&mut self) {
+        self.events.clear();
+        self.events_done = false;
+        self.event.select(None);
+        self.detail_scroll = 0;
+        self.load_more_events();
+        if !self.events.is_empty() {
+            self.event.select(Some(0));
+        }
+    }
+
+    /// Append the events committed after the last one loaded, a page at a
+    /// time for `$all`.
+    fn load_more_events(&mut self) {
+        let result = match self.selected_stream() {
+            None => {
+                let from = self.events.last().map_or(0, |e| e.position);
+                self.backend
+                    .read_all(from, PAGE)
+                    .inspect(|page| self.events_done = page.len() < PAGE)
+            }
+            Some(aggregate_id) => {
+                let since_version = self.events.last().map_or(0, |e| e.version);
+                let opts = GetAggOpts {
+                    agg_id: aggregate_id,
+                    since_version,
+                };
+                self.events_done = true;
+                self.backend.get_aggretate_with_opts(aggregate_id, &opts)
+            }
+        };
+        match result {
+            Ok(events) => self.events.extend(events),
+            Err(err) => self.status = err.to_string(),
+        }
+    }
+
+    /// Pick up new events and streams, following the end of the stream in
+    /// tail mode. Called on every tick of the terminal loop.
+    pub fn refresh(&mut self) {
+        if !self.tail {
+            return;
+        }
+        let at_end = self
+            .event
+            .selected()
+            .is_none_or(|i| i + 1 >= self.events.len());
+        self.events_done = false;
+        while !self.events_done {
+            let before = self.events.len();
+            self.load_more_events();
+            if self.events.len() == before {
+                break;
+            }
+        }
+        if at_end && !self.events.is_empty() {
+            self.event.select(Some(self.events.len() - 1));
+        }
+        if self.streams_done {
+            if let Err(err) = self.load_streams() {
+                self.status = err.to_string();
+            }
+        }
+    }
+
+    /// Start over, e.g. after streams were deleted.
+    fn reload(&mut self) {
+        let selected = self.selected_stream();
+        self.streams.clear();
+        self.status.clear();
+        if let Err(err) = self.load_streams() {
+            self.status = err.to_string();
+        }
+        let index = selected
+            .and_then(|id| self.streams.iter().position(|s| s.aggregate_id == id))
+            .map_or(0, |i| i + 1);
+        self.stream.select(Some(index));
+        self.load_events();
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('t') => {
+                self.tail = !self.tail;
+                if self.tail {
+                    self.event.select(self.events.len().checked_sub(1));
+                    self.refresh();
+                }
+            }
+            KeyCode::Char('r') => self.reload(),
+            KeyCode::Tab | KeyCode::Right | KeyCode::Enter => {
+                self.focus = match self.focus {
+                    Focus::Streams => Focus::Events,
+                    _ => Focus::Detail,
+                }
+            }
+            KeyCode::BackTab | KeyCode::Left | KeyCode::Esc => {
+                self.focus = match self.focus {
+                    Focus::Detail => Focus::Events,
+                    _ => Focus::Streams,
+                }
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::PageDown => self.move_by(JUMP as isize),
+            KeyCode::PageUp => self.move_by(-(JUMP as isize)),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX),
+            _ => {}
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        match self.focus {
+            Focus::Streams => {
+                if delta > 0 && !self.streams_done {
+                    if let Err(err) = self.load_streams() {
+                        self.status = err.to_string();
+                    }
+                }
+                let previous = self.stream.selected();
+                step(&mut self.stream, self.streams.len() + 1, delta);
+                if self.stream.selected() != previous {
+                    self.load_events();
+                }
+            }
+            Focus::Events => {
+                let target = self
+                    .event
+                    .selected()
+                    .unwrap_or(0)
+                    .saturating_add_signed(delta);
+                while delta > 0 && !self.events_done && target >= self.events.len() {
+                    let before = self.events.len();
+                    self.load_more_events();
+                    if self.events.len() == before {
+                        break;
+                    }
+                }
+                step(&mut self.event, self.events.len(), delta);
+                self.detail_scroll = 0;
+            }
+            Focus::Detail => {
+                let delta = delta.clamp(-(u16::MAX as isize), u16::MAX as isize);
+                self.detail_scroll = self.detail_scroll.saturating_add_signed(delta as i16);
+            }
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [streams, events, detail] = Layout::horizontal([
+            Constraint::Length(40),
+            Constraint::Percentage(40),
+            Constraint::Fill(1),
+        ])
+        .areas(main);
+
+        let block = |title: String, focus: Focus| {
+            let block = Block::bordered().title(title);
+            if self.focus == focus {
+                block.border_style(Style::new().add_modifier(Modifier::BOLD))
+            } else {
+                block
+            }
+        };
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+
+        let items = std::iter::once("$all".to_string()).chain(
+            self.streams
+                .iter()
+                .map(|s| format!("{} v{}", s.aggregate_id, s.version)),
+        );
+        let more = if self.streams_done { "" } else { "+" };
+        let list = List::new(items)
+            .block(block(
+                format!("Streams ({}{})", self.streams.len(), more),
+                Focus::Streams,
+            ))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(list, streams, &mut self.stream);
+
+        let all = self.selected_stream().is_none();
+        let items = self.events.iter().map(|e| {
+            if all {
+                format!("{:>6} {} {}", e.position, short_id(e.id), e.event_type)
+            } else {
+                format!("v{:<4} {}", e.version, e.event_type)
+            }
+        });
+        let title = match self.selected_stream() {
+            Some(id) => format!("Events of {}", id),
+            None => "Events of $all".to_string(),
+        };
+        let list = List::new(items)
+            .block(block(title, Focus::Events))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(list, events, &mut self.event);
+
+        let text = self
+            .selected_event()
+            .map(|e| serde_json::to_string_pretty(&Envelope::from_event(e)).unwrap_or_default())
+            .unwrap_or_default();
+        let paragraph = Paragraph::new(text)
+            .block(block("Event".to_string(), Focus::Detail))
+            .wrap(Wrap { trim: false })
+            .scroll((self.detail_scroll, 0));
+        frame.render_widget(paragraph, detail);
+
+        let mode = if self.tail { "[tail] " } else { "" };
+        let help = "q quit  tab/enter focus  j/k move  pgup/pgdn page  t tail  r reload";
+        let status = if self.status.is_empty() {
+            help
+        } else {
+            &self.status
+        };
+        frame.render_widget(Line::from(format!("{}{}", mode, status)), footer);
+    }
+}
+
+/// Move the selection by `delta` rows, clamped to the list.
+fn step(state: &mut ListState, len: usize, delta: isize) {
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    let current = state.selected().unwrap_or(0);
+    state.select(Some(current.saturating_add_signed(delta).min(len - 1)));
+}
+
+fn short_id(id: Uuid) -> String {
+    id.to_string()[..8].to_string()
+}
+
+/// Run the browser on the terminal until the user quits.
+pub fn run(backend: SqliteBackend) -> Result<(), Error> {
+    let mut browser = Browser::new(backend)?;
+    let mut terminal = ratatui::try_init().map_err(io_error)?;
+    let result = (|| loop {
+        terminal
+            .draw(|frame| browser.draw(frame))
+            .map_err(io_error)?;
+        if event::poll(TICK).map_err(io_error)? {
+            if let TermEvent::Key(key) = event::read().map_err(io_error)? {
+                if key.kind == KeyEventKind::Press {
+                    browser.handle_key(key);
+                }
+            }
+        } else {
+            browser.refresh();
+        }
+        if browser.is_done() {
+            return Ok(());
+        }
+    })();
+    ratatui::try_restore().map_err(io_error)?;
+    result
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::WithMsg(err.to_string())
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::backend::{model::Event, sqlite::Error};
+
+/// Transforms an event from one schema version into the next one.
+///
+/// Upcasters may rewrite the payload as well as the event type, the chain
+/// takes care of bumping `schema_version`.
+pub trait Upcaster: Send + Sync {
+    fn upcast(&self, event: Event) -> Result<Event, Error>;
+}
+
+impl<F> Upcaster for F
+where
+    F: Fn(Event) -> Result<Event, Error> + Send + Sync,
+{
+    fn upcast(&self, event: Event) -> Result<Event, Error> {
+        self(event)
+    }
+}
+
+/// Upcasters keyed by event type and the schema version they upgrade from.
+#[derive(Default, Clone)]
+pub struct UpcasterChain {
+    upcasters: HashMap<(String, u32), Arc<dyn Upcaster>>,
+}
+
+impl UpcasterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `upcaster` for events of `event_type` stored with
+    /// `from_version`, its output is treated as `from_version + 1`.
+    pub fn register<U: Upcaster + 'static>(
+        &mut self,
+        event_type: &str,
+        from_version: u32,
+        upcaster: U,
+    ) -> &mut Self {
+        self.upcasters
+            .insert((event_type.to_string(), from_version), Arc::new(upcaster));
+        self
+    }
+
+    /// Convenience for upcasters that only reshape a JSON payload.
+    pub fn register_json<F>(&mut self, event_type: &str, from_version: u32, f: F) -> &mut Self
+    where
+        F: Fn(serde_json::Value) -> Result<serde_json::Value, Error> + Send + Sync + 'static,
+    {
+        self.register(event_type, from_version, move |mut event: Event| {
+            let value = serde_json::from_slice(&event.data)?;
+            event.data = serde_json::to_vec(&f(value)?)?;
+            Ok(event)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    /// Apply upcasters until no further one is registered for the event's
+    /// type and schema version.
+    pub fn upcast(&self, mut event: Event) -> Result<Event, Error> {
+        while let Some(upcaster) = self
+            .upcasters
+            .get(&(event.event_type.clone(), event.schema_version))
+        {
+            let from_version = event.schema_version;
+            event = upcaster.upcast(event)?;
+            event.schema_version = from_version + 1;
+        }
+        Ok(event)
+    }
+
+    pub fn upcast_all(&self, events: Vec<Event>) -> Result<Vec<Event>, Error> {
+        if self.is_empty() {
+            return Ok(events);
+        }
+        events.into_iter().map(|e| self.upcast(e)).collect()
+    }
+}
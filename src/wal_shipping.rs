@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{backup::Backup, Connection};
+use tracing::{debug, instrument, warn};
+use uuid::Uuid;
+
+use crate::backend::sqlite::Error;
+use crate::clock::{Clock, SystemClock};
+use crate::object_store::ObjectStore;
+
+const WAL_HEADER_LEN: usize = 32;
+const FRAME_HEADER_LEN: usize = 24;
+
+/// Table written by the shipper, guarantees the WAL holds a frame the
+/// shipper's read transaction can pin.
+const SEQ_TABLE: &str = "_wal_shipping";
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::WithMsg(format!("wal shipping: {}", err))
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn join(prefix: &str, rest: &str) -> String {
+    if prefix.is_empty() {
+        rest.to_string()
+    } else {
+        format!("{}/{}", prefix, rest)
+    }
+}
+
+fn wal_path(db: &Path) -> PathBuf {
+    let mut path = db.as_os_str().to_owned();
+    path.push("-wal");
+    PathBuf::from(path)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn salt(wal: &[u8]) -> [u8; 8] {
+    let mut salt = [0; 8];
+    salt.copy_from_slice(&wal[16..24]);
+    salt
+}
+
+/// End of the last commit frame after `from` belonging to the current WAL
+/// generation. Frames left over from before the last WAL restart carry
+/// other salts, frames of a transaction still being written are not
+/// followed by a commit frame yet.
+fn committed_end(wal: &[u8], from: usize) -> usize {
+    let frame_len = FRAME_HEADER_LEN + be_u32(&wal[8..12]) as usize;
+    let salt = &wal[16..24];
+    let mut end = from;
+    let mut pos = from.max(WAL_HEADER_LEN);
+    while pos + frame_len <= wal.len() {
+        let header = &wal[pos..pos + FRAME_HEADER_LEN];
+        if &header[8..16] != salt {
+            break;
+        }
+        pos += frame_len;
+        if be_u32(&header[4..8]) != 0 {
+            end = pos;
+        }
+    }
+    end
+}
+
+#[derive(Debug)]
+struct Position {
+    generation: String,
+    epoch: u32,
+    salt: [u8; 8],
+    offset: usize,
+    /// The shipper itself restarted the WAL, a new salt starts a new epoch
+    /// instead of a new generation.
+    expect_restart: bool,
+}
+
+/// Continuously copies a SQLite database to an object store, in the spirit
+/// of Litestream.
+///
+/// Every generation starts with a snapshot of the database followed by the
+/// committed WAL frames, shipped as segments while they are written. The
+/// WAL starts over after a checkpoint, every restart opens a new epoch in
+/// the generation. The shipper holds a read transaction between syncs so
+/// nobody else can restart the WAL unnoticed, if that happens anyway, e.g.
+/// while the shipper was not running, a new generation is started.
+///
+/// Object layout below the prefix:
+/// `<generation>/snapshot` and `<generation>/wal/<epoch>/<offset>-<millis>`.
+pub struct WalShipper {
+    path: PathBuf,
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    conn: Connection,
+    position: Option<Position>,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for WalShipper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalShipper")
+            .field("path", &self.path)
+            .field("prefix", &self.prefix)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WalShipper {
+    /// Ship the database file at `path`, switching it to WAL mode.
+    pub fn new<S: ObjectStore + 'static>(path: impl AsRef<Path>, store: S) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let conn = Connection::open(&path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        let mode: String = conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            return Err(Error::WithMsg(format!(
+                "wal shipping: database uses journal mode {}",
+                mode
+            )));
+        }
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, seq INTEGER NOT NULL)",
+            SEQ_TABLE
+        ))?;
+        Ok(Self {
+            path,
+            store: Arc::new(store),
+            prefix: String::new(),
+            conn,
+            position: None,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Place all objects below `prefix`, e.g. to ship several databases
+    /// into one bucket.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into().trim_matches('/').to_string();
+        self
+    }
+
+    /// Stamp generations and segments with the time of `clock`.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Ship the WAL frames committed since the last sync, starts a new
+    /// generation if necessary. Returns the number of bytes shipped.
+    #[instrument(skip(self))]
+    pub fn sync(&mut self) -> Result<usize, Error> {
+        let Some(position) = self.position.as_mut() else {
+            return self.start_generation();
+        };
+        let Some(wal) = read_wal(&self.path)? else {
+            return Ok(0);
+        };
+        if salt(&wal) != position.salt {
+            if !position.expect_restart {
+                warn!("WAL was restarted behind the shipper's back, starting a new generation");
+                return self.start_generation();
+            }
+            position.epoch += 1;
+            position.offset = 0;
+            position.salt = salt(&wal);
+        }
+        position.expect_restart = false;
+        self.ship(&wal)
+    }
+
+    /// Ship all committed frames, then checkpoint them into the database so
+    /// the WAL starts over instead of growing without bound.
+    #[instrument(skip(self))]
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        self.sync()?;
+        self.release()?;
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        // writers are blocked from here on, no frame can slip past
+        let res = (|| {
+            let Some(wal) = read_wal(&self.path)? else {
+                return Ok(false);
+            };
+            let position = self.position.as_ref().expect("position after sync");
+            if salt(&wal) != position.salt {
+                return Ok(false);
+            }
+            self.ship(&wal)?;
+            Connection::open(&self.path)?.query_row(
+                "PRAGMA wal_checkpoint(PASSIVE)",
+                [],
+                |_| Ok(()),
+            )?;
+            self.bump()?;
+            Ok(true)
+        })();
+        self.finish(&res)?;
+        match res {
+            Ok(true) => {
+                if let Some(position) = self.position.as_mut() {
+                    position.expect_restart = true;
+                }
+                self.pin()?;
+                self.sync().map(|_| ())
+            }
+            // the WAL restarted between the sync and taking the lock
+            Ok(false) => self.start_generation().map(|_| ()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sync every `interval` and checkpoint on every `checkpoint_every`th
+    /// sync on a background thread until the handle is stopped.
+    pub fn spawn(mut self, interval: Duration, checkpoint_every: u32) -> ShippingHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut syncs = 0u32;
+            while !stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                syncs = syncs.wrapping_add(1);
+                let res = if checkpoint_every > 0 && syncs.is_multiple_of(checkpoint_every) {
+                    self.checkpoint()
+                } else {
+                    self.sync().map(|_| ())
+                };
+                if let Err(err) = res {
+                    warn!("wal shipping failed: {}", err);
+                }
+            }
+            self.sync().map(|_| ())
+        });
+        ShippingHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn start_generation(&mut self) -> Result<usize, Error> {
+        let generation = format!(
+            "{:016}-{}",
+            millis(self.clock.now()),
+            &Uuid::new_v4().simple().to_string()[..8]
+        );
+        debug!(generation, "starting wal shipping generation");
+        self.release()?;
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        let res = (|| {
+            let snapshot = self.snapshot()?;
+            self.store.put(
+                &join(&self.prefix, &format!("{}/snapshot", generation)),
+                &snapshot,
+            )?;
+            let wal = read_wal(&self.path)?;
+            self.position = Some(Position {
+                generation,
+                epoch: 0,
+                salt: wal.as_deref().map(salt).unwrap_or_default(),
+                offset: 0,
+                expect_restart: true,
+            });
+            let shipped = match wal {
+                Some(wal) => self.ship(&wal)?,
+                None => 0,
+            };
+            self.bump()?;
+            Ok(shipped)
+        })();
+        if res.is_err() {
+            self.position = None;
+        }
+        self.finish(&res)?;
+        self.pin()?;
+        res
+    }
+
+    /// Copy the database while writers are blocked by the caller.
+    fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        let staged =
+            std::env::temp_dir().join(format!("eventstore-snapshot-{}.db", Uuid::new_v4()));
+        let res = (|| {
+            let source = Connection::open(&self.path)?;
+            let mut target = Connection::open(&staged)?;
+            Backup::new(&source, &mut target)?.run_to_completion(256, Duration::ZERO, None)?;
+            drop(target);
+            std::fs::read(&staged).map_err(io_error)
+        })();
+        let _ = std::fs::remove_file(&staged);
+        res
+    }
+
+    fn ship(&mut self, wal: &[u8]) -> Result<usize, Error> {
+        let position = self.position.as_mut().expect("shipping without generation");
+        let end = committed_end(wal, position.offset);
+        if end <= position.offset {
+            return Ok(0);
+        }
+        let key = join(
+            &self.prefix,
+            &format!(
+                "{}/wal/{:08}/{:016}-{:016}",
+                position.generation,
+                position.epoch,
+                position.offset,
+                millis(self.clock.now())
+            ),
+        );
+        self.store.put(&key, &wal[position.offset..end])?;
+        let shipped = end - position.offset;
+        position.offset = end;
+        Ok(shipped)
+    }
+
+    fn bump(&self) -> Result<(), Error> {
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (id, seq) VALUES (1, 1) ON CONFLICT (id) DO UPDATE SET seq = seq + 1",
+                SEQ_TABLE
+            ),
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Open a read transaction, keeps others from restarting the WAL.
+    fn pin(&self) -> Result<(), Error> {
+        self.conn.execute_batch("BEGIN")?;
+        self.conn
+            .query_row(&format!("SELECT seq FROM {}", SEQ_TABLE), [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn release(&self) -> Result<(), Error> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    fn finish<T>(&self, res: &Result<T, Error>) -> Result<(), Error> {
+        self.conn.execute_batch(match res {
+            Ok(_) => "COMMIT",
+            Err(_) => "ROLLBACK",
+        })?;
+        Ok(())
+    }
+}
+
+fn read_wal(db: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match std::fs::read(wal_path(db)) {
+        Ok(wal) if wal.len() >= WAL_HEADER_LEN => Ok(Some(wal)),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(io_error(err)),
+    }
+}
+
+/// Stops a shipper started with `WalShipper::spawn`.
+#[derive(Debug)]
+pub struct ShippingHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl ShippingHandle {
+    /// Stop after a final sync, returns its outcome.
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err(Error::WithMsg("wal shipping thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ShippingHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Restore the database as of `point_in_time` from objects shipped by a
+/// `WalShipper` with the given prefix into a new file at `target`.
+///
+/// Uses the latest generation started before `point_in_time` and replays
+/// its WAL segments shipped up to then.
+#[instrument(skip(store, target))]
+pub fn restore_from(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    point_in_time: SystemTime,
+    target: impl AsRef<Path>,
+) -> Result<(), Error> {
+    let prefix = prefix.trim_matches('/');
+    let until = millis(point_in_time);
+    let keys = store.list(&join(prefix, ""))?;
+    let relative = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            key[prefix.len() + 1..].to_string()
+        }
+    };
+
+    let generation = keys
+        .iter()
+        .map(|key| relative(key))
+        .filter_map(|key| key.strip_suffix("/snapshot").map(str::to_string))
+        .filter(|generation| {
+            generation
+                .split('-')
+                .next()
+                .and_then(|started| started.parse::<u64>().ok())
+                .is_some_and(|started| started <= until)
+        })
+        .max()
+        .ok_or_else(|| Error::WithMsg("wal shipping: no generation before point in time".into()))?;
+
+    let mut epochs: BTreeMap<u32, Vec<(usize, u64, String)>> = BTreeMap::new();
+    let segments = join(prefix, &format!("{}/wal/", generation));
+    for key in keys.iter().filter(|key| key.starts_with(&segments)) {
+        let invalid = || Error::WithMsg(format!("wal shipping: invalid segment key {}", key));
+        let (epoch, name) = key[segments.len()..].split_once('/').ok_or_else(invalid)?;
+        let (offset, shipped) = name.split_once('-').ok_or_else(invalid)?;
+        epochs
+            .entry(epoch.parse().map_err(|_| invalid())?)
+            .or_default()
+            .push((
+                offset.parse().map_err(|_| invalid())?,
+                shipped.parse().map_err(|_| invalid())?,
+                key.clone(),
+            ));
+    }
+
+    let target = target.as_ref();
+    for path in [target.to_path_buf(), wal_path(target), shm_path(target)] {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(io_error(err)),
+            _ => {}
+        }
+    }
+    let snapshot = store.get(&join(prefix, &format!("{}/snapshot", generation)))?;
+    std::fs::write(target, snapshot).map_err(io_error)?;
+    Connection::open(target)?.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+
+    for (epoch, mut segments) in epochs {
+        segments.sort();
+        let mut wal = Vec::new();
+        let mut complete = true;
+        for (offset, shipped, key) in segments {
+            if shipped > until {
+                complete = false;
+                break;
+            }
+            if offset != wal.len() {
+                return Err(Error::WithMsg(format!(
+                    "wal shipping: segment missing at offset {} of epoch {}",
+                    wal.len(),
+                    epoch
+                )));
+            }
+            wal.extend(store.get(&key)?);
+        }
+        if !wal.is_empty() {
+            debug!(epoch, bytes = wal.len(), "replaying wal");
+            std::fs::write(wal_path(target), &wal).map_err(io_error)?;
+            // SQLite recovers the WAL when the first connection opens it
+            let _ = std::fs::remove_file(shm_path(target));
+            Connection::open(target)?
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        }
+        if !complete {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn shm_path(db: &Path) -> PathBuf {
+    let mut path = db.as_os_str().to_owned();
+    path.push("-shm");
+    PathBuf::from(path)
+}
@@ -2,15 +2,66 @@ use eventstore::backend::{
     model::Event,
     sqlite::{Error, SqliteBackend},
 };
+use eventstore::event::EventRegistry;
+use eventstore::testing::Scenario;
+use eventstore::{Aggregate, Apply, DomainEvent, Repository};
 use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
 use tracing::debug_span;
 
+#[derive(Debug, PartialEq, Serialize, Deserialize, DomainEvent)]
+struct ItemAdded {
+    sku: String,
+    quantity: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "item.removed")]
+struct ItemRemoved {
+    sku: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "PriceSet", schema_version = 2)]
+struct PriceSetV2 {
+    cents: u64,
+}
+
+#[derive(Debug, Clone, Default, Aggregate)]
+#[aggregate(events(ItemAdded, ItemRemoved))]
+struct Cart {
+    items: std::collections::BTreeMap<String, u32>,
+}
+
+impl Cart {
+    fn remove(&self, sku: &str) -> Result<Vec<Event>, String> {
+        if !self.items.contains_key(sku) {
+            return Err(format!("{} not in cart", sku));
+        }
+        Ok(vec![Event::encode(&ItemRemoved {
+            sku: sku.to_string(),
+        })
+        .unwrap()])
+    }
+}
+
+impl Apply<ItemAdded> for Cart {
+    fn apply(&mut self, event: ItemAdded) {
+        *self.items.entry(event.sku).or_default() += event.quantity;
+    }
+}
+
+impl Apply<ItemRemoved> for Cart {
+    fn apply(&mut self, event: ItemRemoved) {
+        self.items.remove(&event.sku);
+    }
+}
+
 /// Helper method
 ///
 /// # Panics
 ///
 /// Panics if .
-
 fn assert_get_aggreate_since_version_of_len(
     aggregate_id: uuid::Uuid,
     since_version: u32,
@@ -58,7 +109,7 @@ fn assert_get_aggreate_of_len(
     };
 }
 
-fn assert_gap_less_version(events: &Vec<Event>) {
+fn assert_gap_less_version(events: &[Event]) {
     let mut last_version = 0;
     for (idx, ev) in events.iter().enumerate() {
         if last_version == 0 {
@@ -98,8 +149,9 @@ fn fetch_empty_then_insert_multiple_and_retrieve_list_using_since_version_2() {
             id: aggregate_id,
             version: i,
             data: vec![],
+            ..Default::default()
         };
-        let _res = backend.append_event(&event).unwrap();
+        backend.append_event(&event).unwrap();
     }
     assert_get_aggreate_since_version_of_len(aggregate_id, 2, &backend, 8);
 }
@@ -116,8 +168,9 @@ fn fetch_empty_then_insert_multiple_and_retrieve_list_using_since_version_9() {
             id: aggregate_id,
             version: i,
             data: vec![],
+            ..Default::default()
         };
-        let _res = backend.append_event(&event).unwrap();
+        backend.append_event(&event).unwrap();
     }
     assert_get_aggreate_since_version_of_len(aggregate_id, 9, &backend, 1);
 }
@@ -134,8 +187,9 @@ fn fetch_empty_then_insert_multiple_and_retrieve_list_using_since_version_1() {
             id: aggregate_id,
             version: i,
             data: vec![],
+            ..Default::default()
         };
-        let _res = backend.append_event(&event).unwrap();
+        backend.append_event(&event).unwrap();
     }
     assert_get_aggreate_since_version_of_len(aggregate_id, 1, &backend, 9);
 }
@@ -152,8 +206,9 @@ fn fetch_empty_then_insert_multiple_and_retrieve_list() {
             id: aggregate_id,
             version: i,
             data: vec![],
+            ..Default::default()
         };
-        let _res = backend.append_event(&event).unwrap();
+        backend.append_event(&event).unwrap();
     }
     assert_get_aggreate_of_len(aggregate_id, &backend, 10);
 }
@@ -169,8 +224,9 @@ fn fetch_empty_then_insert() {
         id: aggregate_id,
         version: 1,
         data: vec![],
+        ..Default::default()
     };
-    let _res = backend.append_event(&event).unwrap();
+    backend.append_event(&event).unwrap();
     assert_get_aggreate_of_len(aggregate_id, &backend, 1);
 }
 
@@ -185,11 +241,44 @@ fn fetch_empty_then_insert_with_conflicting_version() {
         id: aggregate_id,
         version: 2,
         data: vec![],
+        ..Default::default()
     };
     let res = backend.append_event(&event);
     assert!(res.is_err(), "expected Err but got Ok");
 }
 
+#[test_log::test]
+fn append_rejects_versions_taken_by_concurrent_writers() {
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id: aggregate_id,
+        version,
+        ..Default::default()
+    };
+    {
+        // a row written without the index being updated yet
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute(
+            "INSERT INTO eventstore(aggregate_id, data, version) VALUES(?, '{}', 1)",
+            [aggregate_id.as_bytes().to_vec()],
+        )
+        .unwrap();
+    }
+    for res in [
+        backend.append_event(&event(1)),
+        backend.append_events(&[event(1), event(2)]),
+    ] {
+        assert!(matches!(res, Err(Error::VersionConflict)));
+    }
+    assert_eq!(backend.stream_version(aggregate_id).unwrap(), 0);
+
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
 #[test_log::test]
 fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot() {
     let _span = debug_span!("test-main-span").entered();
@@ -208,8 +297,9 @@ fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot() {
         id: aggregate_id,
         version: 1,
         data: vec![1, 2, 3, 4],
+        ..Default::default()
     };
-    let _res = backend
+    backend
         .save_snapshot(&event)
         .expect("failed to save snapshot");
     let snapshots = backend
@@ -226,13 +316,14 @@ fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot() {
         id: aggregate_id,
         version: 1,
         data: vec![7, 9, 6, 5],
+        ..Default::default()
     };
-    let _res = backend
+    backend
         .save_snapshot(&event)
         .expect("failed to save snapshot");
     let snapshots = backend
         .get_snapshots(aggregate_id)
-        .expect(format!("failed to retrieve snapshots, agg id = {}", aggregate_id).as_str());
+        .unwrap_or_else(|_| panic!("failed to retrieve snapshots, agg id = {}", aggregate_id));
     assert!(
         snapshots.len() == 1,
         "expected 1 but got {}, agg id = {}",
@@ -258,8 +349,9 @@ fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot_using_get_snapshot()
         id: aggregate_id,
         version: 1,
         data: vec![1, 2, 3, 4],
+        ..Default::default()
     };
-    let _res = backend
+    backend
         .save_snapshot(&event)
         .expect("failed to save snapshot");
     let snapshot = backend
@@ -271,8 +363,9 @@ fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot_using_get_snapshot()
         id: aggregate_id,
         version: 1,
         data: vec![7, 9, 6, 5],
+        ..Default::default()
     };
-    let _res = backend
+    backend
         .save_snapshot(&event)
         .expect("failed to save snapshot");
     let snapshot = backend
@@ -280,3 +373,6757 @@ fn snapshot_fetch_empty_then_insert_then_overwrite_snapshot_using_get_snapshot()
         .expect("failed to retrieve snapshot");
     assert!(snapshot.data == vec![7, 9, 6, 5]);
 }
+
+#[test_log::test]
+fn derived_event_types() {
+    assert_eq!(ItemAdded::event_type(), "ItemAdded");
+    assert_eq!(ItemRemoved::event_type(), "item.removed");
+}
+
+#[test_log::test]
+fn append_domain_event_and_decode_via_registry() {
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let aggregate_id = uuid::Uuid::parse_str("0b0e3c4c-43a5-4f63-a2e4-7c5a3f1b6a21").unwrap();
+    let added = ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 2,
+    };
+    let mut event = Event::encode(&added).expect("failed to encode event");
+    event.id = aggregate_id;
+    event.version = 1;
+    backend.append_event(&event).unwrap();
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].event_type, "ItemAdded");
+    assert_eq!(events[0].decode::<ItemAdded>().unwrap(), added);
+    match events[0].decode::<ItemRemoved>() {
+        Err(Error::UnexpectedEventType { .. }) => {}
+        res => panic!("expected UnexpectedEventType but got {:?}", res),
+    };
+
+    let decoded = EventRegistry::global()
+        .decode(&events[0])
+        .expect("ItemAdded should be registered");
+    assert_eq!(decoded.downcast_ref::<ItemAdded>(), Some(&added));
+    assert!(EventRegistry::global().contains("item.removed"));
+    match EventRegistry::new().decode(&events[0]) {
+        Err(Error::UnknownEventType(event_type)) => assert_eq!(event_type, "ItemAdded"),
+        res => panic!("expected UnknownEventType but got {:?}", res.err()),
+    };
+}
+
+#[test_log::test]
+fn repository_save_and_load_derived_aggregate() {
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let repository = Repository::<Cart>::new(backend);
+    let aggregate_id = uuid::Uuid::parse_str("5b0e2a7e-4f38-4d1f-9b5a-0f6f8f1c2d3e").unwrap();
+    assert_eq!(Cart::aggregate_type(), "Cart");
+
+    let (cart, version) = repository.load(aggregate_id).unwrap();
+    assert!(cart.items.is_empty());
+    assert_eq!(version, 0);
+
+    let events = vec![
+        Event::encode(&ItemAdded {
+            sku: "abc".to_string(),
+            quantity: 2,
+        })
+        .unwrap(),
+        Event::encode(&ItemAdded {
+            sku: "def".to_string(),
+            quantity: 1,
+        })
+        .unwrap(),
+        Event::encode(&ItemRemoved {
+            sku: "def".to_string(),
+        })
+        .unwrap(),
+    ];
+    let version = repository.save(aggregate_id, version, events).unwrap();
+    assert_eq!(version, 3);
+
+    let (cart, version) = repository.load(aggregate_id).unwrap();
+    assert_eq!(version, 3);
+    assert_eq!(cart.items.get("abc"), Some(&2));
+    assert_eq!(cart.items.len(), 1);
+
+    let stale = vec![Event::encode(&ItemRemoved {
+        sku: "abc".to_string(),
+    })
+    .unwrap()];
+    assert!(repository.save(aggregate_id, 1, stale).is_err());
+    assert_get_aggreate_of_len(aggregate_id, repository.backend(), 3);
+}
+
+#[test_log::test]
+fn recording_backend_records_calls_and_scripted_failures() {
+    use eventstore::backend::EventStore;
+    use eventstore::testing::{Call, RecordingBackend};
+
+    let _span = debug_span!("test-main-span").entered();
+    let store = RecordingBackend::new();
+    let repository = Repository::<Cart, _>::new(store.clone());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let add = |sku: &str| {
+        vec![Event::encode(&ItemAdded {
+            sku: sku.to_string(),
+            quantity: 1,
+        })
+        .unwrap()]
+    };
+
+    store.fail_next(
+        |call| matches!(call, Call::AppendEvents(_)),
+        Error::WithMsg("disk full".to_string()),
+    );
+    assert!(repository.save(aggregate_id, 0, add("abc")).is_err());
+    store.assert_nothing_appended();
+    assert_eq!(repository.save(aggregate_id, 0, add("abc")).unwrap(), 1);
+    assert_eq!(repository.save(aggregate_id, 1, add("def")).unwrap(), 2);
+    // a stale expected version conflicts like with a real store
+    assert!(repository.save(aggregate_id, 1, add("ghi")).is_err());
+    store.assert_appended("ItemAdded", 2);
+    store.assert_appended("item.removed", 0);
+
+    let (cart, version) = repository.load(aggregate_id).unwrap();
+    assert_eq!((cart.items.len(), version), (2, 2));
+    assert_eq!(store.stream_version(aggregate_id).unwrap(), 2);
+    assert_eq!(
+        store
+            .read_all(1, 10)
+            .unwrap()
+            .iter()
+            .map(|event| event.position)
+            .collect::<Vec<_>>(),
+        vec![2]
+    );
+    let calls = store.calls();
+    assert_eq!(calls.len(), 7);
+    assert!(matches!(&calls[4], Call::GetAggregate(id) if *id == aggregate_id));
+}
+
+#[test_log::test]
+fn chaos_backend_injects_seeded_failures() {
+    use eventstore::backend::EventStore;
+    use eventstore::testing::{ChaosBackend, RecordingBackend};
+
+    let _span = debug_span!("test-main-span").entered();
+    let aggregate_id = uuid::Uuid::new_v4();
+    let outcomes = |seed| {
+        let store = ChaosBackend::new(RecordingBackend::new(), seed).with_busy_rate(0.5);
+        let outcomes: Vec<bool> = (0..32)
+            .map(|_| store.stream_version(aggregate_id).is_ok())
+            .collect();
+        assert_eq!(
+            store.injected(),
+            outcomes.iter().filter(|ok| !**ok).count() as u64
+        );
+        outcomes
+    };
+    let first = outcomes(7);
+    assert_eq!(first, outcomes(7));
+    assert_ne!(first, outcomes(8));
+    assert!(first.contains(&true) && first.contains(&false));
+
+    // a busy store retried until the append goes through
+    let repository = Repository::<Cart, _>::new(
+        ChaosBackend::new(RecordingBackend::new(), 1).with_busy_rate(0.5),
+    );
+    let added = Event::encode(&ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 1,
+    })
+    .unwrap();
+    while repository
+        .save(aggregate_id, 0, vec![added.clone()])
+        .is_err()
+    {}
+    repository.backend().inner().assert_appended("ItemAdded", 1);
+
+    // a refused commit is stored nonetheless, the retry conflicts
+    let repository = Repository::<Cart, _>::new(
+        ChaosBackend::new(RecordingBackend::new(), 1).with_commit_refusal_rate(1.0),
+    );
+    assert!(matches!(
+        repository.save(aggregate_id, 0, vec![added.clone()]),
+        Err(Error::Sqlite(_))
+    ));
+    assert!(matches!(
+        repository.save(aggregate_id, 0, vec![added]),
+        Err(Error::VersionConflict)
+    ));
+    repository.backend().inner().assert_appended("ItemAdded", 1);
+}
+
+#[test_log::test]
+fn event_factory_generates_reproducible_streams() {
+    use eventstore::testing::{EventFactory, OCCURRED_AT};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let factory = EventFactory::new(3, 4)
+        .with_seed(9)
+        .with_event_types(["Opened", "Touched"])
+        .with_payload(|n, version| format!("{}-{}", n, version).into_bytes())
+        .with_timestamps(
+            UNIX_EPOCH + Duration::from_secs(60),
+            Duration::from_millis(10),
+        );
+    assert_eq!(factory.len(), 12);
+    let events: Vec<_> = factory.events().collect();
+    assert_eq!(events, factory.events().collect::<Vec<_>>());
+    assert_eq!(
+        events
+            .iter()
+            .take(4)
+            .map(|event| (event.id, event.version))
+            .collect::<Vec<_>>(),
+        vec![
+            (factory.aggregate_id(0), 1),
+            (factory.aggregate_id(1), 1),
+            (factory.aggregate_id(2), 1),
+            (factory.aggregate_id(0), 2)
+        ]
+    );
+    assert_eq!(events[3].event_type, "Touched");
+    assert_eq!(events[3].data, b"0-2");
+    assert_eq!(events[3].metadata[OCCURRED_AT], "60030");
+    assert_ne!(
+        factory.aggregate_id(0),
+        EventFactory::new(3, 4).aggregate_id(0)
+    );
+
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    assert_eq!(factory.populate(&backend).unwrap(), 12);
+    let stream = backend.get_aggretate(factory.aggregate_id(2)).unwrap();
+    assert_eq!(
+        stream.iter().map(|event| event.version).collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+}
+
+#[test_log::test]
+fn seed_fills_store_with_generated_streams() {
+    use eventstore::testing::{seed, SeedOpts};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let opts = SeedOpts {
+        aggregates: 4,
+        events_per_aggregate: 7,
+        payload_bytes: 300,
+        ..Default::default()
+    };
+    assert_eq!(seed(&backend, &opts).unwrap(), 28);
+    assert_eq!(backend.list_streams(None, 10).unwrap().len(), 4);
+    let stream = backend
+        .get_aggretate(opts.factory().aggregate_id(3))
+        .unwrap();
+    assert_eq!(stream.len(), 7);
+    assert_eq!(stream[0].event_type, "OrderPlaced");
+    assert!(stream[0].data.len() > 250);
+    // the same seed hits the existing streams
+    assert!(seed(&backend, &opts).is_err());
+    seed(&backend, &SeedOpts { seed: 2, ..opts }).unwrap();
+    assert_eq!(backend.read_all(0, 100).unwrap().len(), 56);
+}
+
+#[test_log::test]
+fn concurrent_writers_keep_streams_gapless() {
+    use eventstore::testing::{ChaosBackend, ConcurrentWriters, RecordingBackend};
+
+    let _span = debug_span!("test-main-span").entered();
+    let writers = ConcurrentWriters::new(6, 2).with_appends_per_writer(25);
+
+    let report = writers.run_sqlite();
+    assert_eq!(report.acknowledged + report.failed, 150);
+    assert_eq!(report.failed, 0);
+    assert_eq!(report.stored, 150);
+    assert_eq!(report.duplicates, 0);
+
+    let chaos = ChaosBackend::new(RecordingBackend::new(), 3)
+        .with_busy_rate(0.2)
+        .with_commit_refusal_rate(0.1);
+    let report = writers.run(&chaos);
+    assert!(report.busy > 0);
+    assert_eq!(report.failed, 0);
+    // refused commits were stored and stored again by the retry
+    assert_eq!(report.stored, 150 + report.duplicates);
+    assert!(report.duplicates > 0);
+}
+
+#[test_log::test]
+fn load_aggregates_reads_many_streams_at_once() {
+    use eventstore::stream::StreamMetadata;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let repository = Repository::<Cart>::new(backend.clone());
+    let ids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    for (n, aggregate_id) in ids.iter().enumerate() {
+        let events = (0..=n)
+            .map(|i| {
+                Event::encode(&ItemAdded {
+                    sku: format!("sku-{}", i),
+                    quantity: 1,
+                })
+                .unwrap()
+            })
+            .collect();
+        repository.save(*aggregate_id, 0, events).unwrap();
+    }
+    backend
+        .set_stream_metadata(ids[2], &StreamMetadata::default().with_max_count(2))
+        .unwrap();
+    let unknown = uuid::Uuid::new_v4();
+
+    let loaded = backend
+        .load_aggregates(&[ids[0], ids[1], ids[2], unknown, ids[0]])
+        .unwrap();
+    assert_eq!(loaded.len(), 4);
+    let versions = |id| {
+        loaded[&id]
+            .iter()
+            .map(|event: &Event| event.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(ids[0]), vec![1]);
+    assert_eq!(versions(ids[1]), vec![1, 2]);
+    assert_eq!(versions(ids[2]), vec![2, 3]);
+    assert!(loaded[&unknown].is_empty());
+
+    let carts = repository.load_many(&[ids[1], unknown, ids[1]]).unwrap();
+    assert_eq!(
+        carts
+            .iter()
+            .map(|(cart, version)| (cart.items.len(), *version))
+            .collect::<Vec<_>>(),
+        vec![(2, 2), (0, 0), (2, 2)]
+    );
+
+    backend.tombstone_stream(ids[1]).unwrap();
+    assert!(matches!(
+        backend.load_aggregates(&ids),
+        Err(Error::StreamDeleted(id)) if id == ids[1]
+    ));
+}
+
+#[test_log::test]
+fn load_aggregate_reads_snapshot_and_later_events() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let repository = Repository::<Cart>::new(backend.clone());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events = (1..=5)
+        .map(|i| {
+            Event::encode(&ItemAdded {
+                sku: format!("sku-{}", i),
+                quantity: 1,
+            })
+            .unwrap()
+        })
+        .collect();
+    repository.save(aggregate_id, 0, events).unwrap();
+
+    let loaded = backend.load_aggregate(aggregate_id).unwrap();
+    assert!(loaded.snapshot.is_none());
+    assert_eq!(loaded.events, backend.get_aggretate(aggregate_id).unwrap());
+    assert_eq!(loaded.version(), 5);
+
+    for version in [2, 3] {
+        backend
+            .save_snapshot(&Event {
+                id: aggregate_id,
+                version,
+                event_type: "CartSnapshot".to_string(),
+                data: format!("{{\"items\":{}}}", version).into_bytes(),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let loaded = backend.load_aggregate(aggregate_id).unwrap();
+    let snapshot = loaded.snapshot.as_ref().unwrap();
+    assert_eq!(snapshot.version, 3);
+    assert_eq!(snapshot.data, b"{\"items\":3}");
+    assert_eq!(
+        loaded.events.iter().map(|e| e.version).collect::<Vec<_>>(),
+        vec![4, 5]
+    );
+    assert!(loaded.events.iter().all(|e| e.position > 0));
+
+    let unknown = backend.load_aggregate(uuid::Uuid::new_v4()).unwrap();
+    assert_eq!((unknown.version(), unknown.snapshot), (0, None));
+
+    backend.tombstone_stream(aggregate_id).unwrap();
+    assert!(matches!(
+        backend.load_aggregate(aggregate_id),
+        Err(Error::StreamDeleted(id)) if id == aggregate_id
+    ));
+}
+
+#[test_log::test]
+fn cursors_page_through_listings() {
+    use eventstore::backend::sqlite::Cursor;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let repository = Repository::<Cart>::new(backend.clone());
+    let ids: Vec<_> = (0..5).map(|_| uuid::Uuid::new_v4()).collect();
+    for aggregate_id in &ids {
+        let events = vec![
+            Event::encode(&ItemAdded {
+                sku: "abc".to_string(),
+                quantity: 1,
+            })
+            .unwrap(),
+            Event::encode(&ItemRemoved {
+                sku: "abc".to_string(),
+            })
+            .unwrap(),
+        ];
+        repository.save(*aggregate_id, 0, events).unwrap();
+    }
+
+    let mut positions = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = backend.read_all_page(cursor.as_ref(), 3).unwrap();
+        positions.extend(page.items.iter().map(|event| event.position));
+        // cursors survive a round trip through their string form
+        match page.next {
+            Some(next) => cursor = Some(next.to_string().parse::<Cursor>().unwrap()),
+            None => break,
+        }
+    }
+    let all: Vec<_> = backend
+        .read_all(0, 100)
+        .unwrap()
+        .iter()
+        .map(|event| event.position)
+        .collect();
+    assert_eq!(positions, all);
+
+    let mut streams = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = backend.list_streams_page(cursor.as_ref(), 2).unwrap();
+        streams.extend(page.items.iter().map(|stream| stream.aggregate_id));
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(streams, sorted);
+
+    let first = backend.read_by_type("item.removed", None, 4).unwrap();
+    assert_eq!(first.items.len(), 4);
+    assert!(first.items.iter().all(|e| e.event_type == "item.removed"));
+    let rest = backend
+        .read_by_type("item.removed", first.next.as_ref(), 4)
+        .unwrap();
+    assert_eq!((rest.items.len(), rest.next), (1, None));
+    assert!(rest.items[0].position > first.items[3].position);
+
+    // cursors are tied to the kind of listing
+    assert!(backend.list_streams_page(first.next.as_ref(), 2).is_err());
+    assert!("p12".parse::<Cursor>().is_err());
+    assert_eq!(
+        serde_json::to_string(&Cursor::after_position(255)).unwrap(),
+        "\"p00000000000000ff\""
+    );
+}
+
+#[test_log::test]
+fn shared_events_do_not_copy_payloads() {
+    use eventstore::backend::model::SharedEvent;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_read_cache(10);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let added = ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 2,
+    };
+    backend
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            ..Event::encode(&added).unwrap()
+        })
+        .unwrap();
+
+    let events = backend.read_all_shared(0, 10).unwrap();
+    let fanned_out: Vec<SharedEvent> = vec![events[0].clone(); 3];
+    assert!(fanned_out
+        .iter()
+        .all(|event| event.data.as_ptr() == events[0].data.as_ptr()));
+    assert_eq!(fanned_out[2].decode::<ItemAdded>().unwrap(), added);
+
+    // served from the cache without copying
+    let cached = backend.get_aggretate_shared(aggregate_id).unwrap();
+    let again = backend.get_aggretate_shared(aggregate_id).unwrap();
+    assert_eq!(cached[0].data.as_ptr(), again[0].data.as_ptr());
+    assert_eq!(
+        Event::from(again[0].clone()),
+        backend.get_aggretate(aggregate_id).unwrap()[0]
+    );
+}
+
+#[test_log::test]
+fn large_payloads_are_streamed() {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let payload: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+    let header = Event {
+        id: aggregate_id,
+        version: 40,
+        event_type: "CartSnapshot".to_string(),
+        ..Default::default()
+    };
+
+    let mut writer = backend
+        .snapshot_writer(&header, payload.len() as u64)
+        .unwrap();
+    for chunk in payload.chunks(64 * 1024) {
+        writer.write_all(chunk).unwrap();
+    }
+    assert!(writer.write_all(b"x").is_err());
+    writer.finish().unwrap();
+    assert_eq!(
+        backend
+            .get_snapshot_by_version(aggregate_id, 40)
+            .unwrap()
+            .data,
+        payload
+    );
+
+    let mut reader = backend.open_snapshot_payload(aggregate_id, 40).unwrap();
+    assert_eq!(reader.len(), payload.len() as u64);
+    let mut streamed = Vec::new();
+    reader.read_to_end(&mut streamed).unwrap();
+    assert_eq!(streamed, payload);
+    let mut tail = Vec::new();
+    reader.seek(SeekFrom::End(-17)).unwrap();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, payload[payload.len() - 17..]);
+    drop(reader);
+
+    // writers dropped before all bytes are written save nothing
+    let mut writer = backend
+        .snapshot_writer(
+            &Event {
+                version: 41,
+                ..header.clone()
+            },
+            10,
+        )
+        .unwrap();
+    writer.write_all(b"abc").unwrap();
+    assert!(writer.finish().is_err());
+    assert!(matches!(
+        backend.open_snapshot_payload(aggregate_id, 41),
+        Err(Error::NotFound)
+    ));
+
+    backend
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            data: payload.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+    let position = backend.read_all(0, 1).unwrap()[0].position;
+    let mut streamed = Vec::new();
+    backend
+        .open_event_payload(position)
+        .unwrap()
+        .read_to_end(&mut streamed)
+        .unwrap();
+    assert_eq!(streamed, payload);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn read_cache_serves_aggregates_until_they_change() {
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path)).with_read_cache(2);
+    let repository = Repository::<Cart>::new(backend.clone());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let added = |sku: &str| {
+        Event::encode(&ItemAdded {
+            sku: sku.to_string(),
+            quantity: 1,
+        })
+        .unwrap()
+    };
+    repository
+        .save(aggregate_id, 0, vec![added("abc"), added("def")])
+        .unwrap();
+    assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 2);
+    let (cart, version) = repository.load_cached(aggregate_id).unwrap();
+    assert_eq!((cart.items.len(), version), (2, 2));
+    assert_eq!(backend.read_cache().unwrap().len(), 2);
+
+    {
+        // not seen by the cache, which only knows about its own writes
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute("DELETE FROM eventstore WHERE version = 1", [])
+            .unwrap();
+    }
+    assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 2);
+    assert_eq!(repository.load_cached(aggregate_id).unwrap().1, 2);
+
+    // appends drop the cached events and state of the stream
+    repository
+        .save(aggregate_id, 2, vec![added("ghi")])
+        .unwrap();
+    assert_eq!(backend.read_cache().unwrap().len(), 0);
+    assert_eq!(
+        backend
+            .get_aggretate(aggregate_id)
+            .unwrap()
+            .iter()
+            .map(|event| event.version)
+            .collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    let (cart, version) = repository.load_cached(aggregate_id).unwrap();
+    assert_eq!((cart.items.len(), version), (2, 3));
+
+    // the least recently used entry is evicted
+    let other = uuid::Uuid::new_v4();
+    repository.save(other, 0, vec![added("abc")]).unwrap();
+    assert_eq!(backend.get_aggretate(other).unwrap().len(), 1);
+    assert_eq!(backend.read_cache().unwrap().len(), 2);
+    assert_eq!(repository.load_cached(aggregate_id).unwrap().1, 3);
+    assert_eq!(backend.read_cache().unwrap().len(), 2);
+
+    drop(repository);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn read_cache_drops_aggregates_when_events_expire() {
+    use eventstore::clock::ManualClock;
+    use eventstore::stream::StreamMetadata;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path))
+        .with_clock(clock.clone())
+        .with_read_cache(4);
+    let repository = Repository::<Cart>::new(backend.clone());
+    let (expiring, aged) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    let added = Event::encode(&ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 1,
+    })
+    .unwrap();
+    repository
+        .save(
+            expiring,
+            0,
+            vec![
+                added.clone().with_ttl(Duration::from_secs(60)),
+                added.clone(),
+            ],
+        )
+        .unwrap();
+    repository.save(aged, 0, vec![added.clone()]).unwrap();
+    clock.advance(Duration::from_secs(30));
+    repository.save(aged, 1, vec![added]).unwrap();
+    backend
+        .set_stream_metadata(
+            aged,
+            &StreamMetadata::default().with_max_age(Duration::from_secs(60)),
+        )
+        .unwrap();
+
+    for aggregate_id in [expiring, aged] {
+        assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 2);
+        assert_eq!(
+            repository.load_cached(aggregate_id).unwrap().0.items["abc"],
+            2
+        );
+    }
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(backend.get_aggretate(aged).unwrap().len(), 2);
+
+    // the first events are gone from reads and from the cache
+    clock.advance(Duration::from_secs(20));
+    for aggregate_id in [expiring, aged] {
+        assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 1);
+        assert_eq!(
+            repository.load_cached(aggregate_id).unwrap().0.items["abc"],
+            1
+        );
+    }
+
+    drop(repository);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn scenario_given_when_then() {
+    let added = Event::encode(&ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 1,
+    })
+    .unwrap();
+    let removed = Event::encode(&ItemRemoved {
+        sku: "abc".to_string(),
+    })
+    .unwrap();
+    let cart = Scenario::<Cart>::new()
+        .given(vec![added])
+        .when(|cart| cart.remove("abc"))
+        .then_expect(vec![removed]);
+    assert!(cart.items.is_empty());
+
+    let err = Scenario::<Cart>::new()
+        .when(|cart| cart.remove("abc"))
+        .then_expect_error();
+    assert_eq!(err, "abc not in cart");
+}
+
+#[test_log::test]
+fn upcast_old_schema_versions_on_read() {
+    let _span = debug_span!("test-main-span").entered();
+    let mut upcasters = eventstore::upcast::UpcasterChain::new();
+    upcasters.register_json("ItemAdded", 1, |mut value| {
+        let quantity = value.as_object_mut().and_then(|v| v.remove("qty"));
+        value["quantity"] = quantity.unwrap_or_default();
+        Ok(value)
+    });
+    let manager = SqliteConnectionManager::memory();
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(manager).with_upcasters(upcasters);
+    let aggregate_id = uuid::Uuid::parse_str("7c1d5a0e-2b7f-4f0e-8a51-3c9d2e6b4f10").unwrap();
+    let old = Event {
+        id: aggregate_id,
+        version: 1,
+        event_type: "ItemAdded".to_string(),
+        schema_version: 1,
+        data: br#"{"sku":"abc","qty":3}"#.to_vec(),
+        ..Default::default()
+    };
+    let current = Event {
+        id: aggregate_id,
+        version: 2,
+        event_type: "ItemAdded".to_string(),
+        schema_version: 2,
+        data: br#"{"sku":"def","quantity":1}"#.to_vec(),
+        ..Default::default()
+    };
+    backend.append_events(&[old, current]).unwrap();
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e.schema_version == 2));
+    let payload: serde_json::Value = serde_json::from_slice(&events[0].data).unwrap();
+    assert_eq!(payload, serde_json::json!({"sku": "abc", "quantity": 3}));
+    let payload: serde_json::Value = serde_json::from_slice(&events[1].data).unwrap();
+    assert_eq!(payload, serde_json::json!({"sku": "def", "quantity": 1}));
+}
+
+#[cfg(feature = "schema-registry")]
+#[test_log::test]
+fn schema_registry_warns_or_rejects_invalid_payloads() {
+    use eventstore::schema::{Enforcement, SchemaRegistry, StaticSchemaProvider};
+
+    let _span = debug_span!("test-main-span").entered();
+    let mut provider = StaticSchemaProvider::new();
+    provider.insert(
+        "ItemAdded",
+        1,
+        serde_json::json!({
+            "type": "object",
+            "required": ["sku", "quantity"],
+            "properties": {"quantity": {"type": "integer", "minimum": 1}}
+        }),
+    );
+    provider.insert(
+        "item.removed",
+        1,
+        serde_json::json!({"type": "object", "required": ["sku"]}),
+    );
+    let registry = SchemaRegistry::new(provider)
+        .with_default_enforcement(Enforcement::Reject)
+        .with_enforcement("item.removed", Enforcement::Warn);
+    let manager = SqliteConnectionManager::memory();
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(manager).with_schema_registry(registry);
+    let aggregate_id = uuid::Uuid::parse_str("b3c1f8c2-7a4d-4b8e-9f21-5e6d7c8b9a01").unwrap();
+
+    let invalid = Event {
+        id: aggregate_id,
+        version: 1,
+        event_type: "ItemAdded".to_string(),
+        data: br#"{"sku":"abc","quantity":0}"#.to_vec(),
+        ..Default::default()
+    };
+    match backend.append_event(&invalid) {
+        Err(Error::SchemaViolation(_)) => {}
+        res => panic!("expected SchemaViolation but got {:?}", res),
+    };
+    assert_get_aggreate_of_len(aggregate_id, &backend, 0);
+
+    let mut valid = Event::encode(&ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 1,
+    })
+    .unwrap();
+    valid.id = aggregate_id;
+    valid.version = 1;
+    backend.append_event(&valid).unwrap();
+
+    let warned = Event {
+        id: aggregate_id,
+        version: 2,
+        event_type: "item.removed".to_string(),
+        data: br#"{}"#.to_vec(),
+        ..Default::default()
+    };
+    backend.append_event(&warned).unwrap();
+    assert_get_aggreate_of_len(aggregate_id, &backend, 2);
+}
+
+#[test_log::test]
+fn schema_version_is_stored_and_checked_on_decode() {
+    let _span = debug_span!("test-main-span").entered();
+    let aggregate_id = uuid::Uuid::parse_str("e1a2b3c4-d5e6-4f70-8192-a3b4c5d6e7f8").unwrap();
+    let old = Event {
+        id: aggregate_id,
+        version: 1,
+        event_type: "PriceSet".to_string(),
+        schema_version: 1,
+        data: br#"{"price":1.5}"#.to_vec(),
+        ..Default::default()
+    };
+    let mut current = Event::encode(&PriceSetV2 { cents: 200 }).unwrap();
+    assert_eq!(current.schema_version, 2);
+    current.id = aggregate_id;
+    current.version = 2;
+
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    backend.append_events(&[old, current]).unwrap();
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].schema_version, 1);
+    assert_eq!(events[1].schema_version, 2);
+    match events[0].decode::<PriceSetV2>() {
+        Err(Error::SchemaVersionMismatch {
+            expected: 2,
+            actual: 1,
+        }) => {}
+        res => panic!("expected SchemaVersionMismatch but got {:?}", res),
+    };
+    assert_eq!(
+        events[1].decode::<PriceSetV2>().unwrap(),
+        PriceSetV2 { cents: 200 }
+    );
+
+    let mut upcasters = eventstore::upcast::UpcasterChain::new();
+    upcasters.register_json("PriceSet", 1, |value| {
+        let cents = (value["price"].as_f64().unwrap_or_default() * 100.0).round() as u64;
+        Ok(serde_json::json!({ "cents": cents }))
+    });
+    let backend = backend.with_upcasters(upcasters);
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(
+        events[0].decode::<PriceSetV2>().unwrap(),
+        PriceSetV2 { cents: 150 }
+    );
+}
+
+#[derive(Default)]
+struct VersionsSeen {
+    seen: std::sync::Mutex<std::collections::HashMap<uuid::Uuid, Vec<u32>>>,
+}
+
+impl eventstore::projection::Projection for VersionsSeen {
+    fn name(&self) -> &str {
+        "versions-seen"
+    }
+
+    fn handle(&self, event: &Event) -> Result<(), Error> {
+        self.seen
+            .lock()
+            .unwrap()
+            .entry(event.id)
+            .or_default()
+            .push(event.version);
+        Ok(())
+    }
+}
+
+#[test_log::test]
+fn parallel_projection_keeps_per_aggregate_order() {
+    use eventstore::projection::ProjectionRunner;
+
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let aggregate_ids: Vec<_> = (0..5).map(|_| uuid::Uuid::new_v4()).collect();
+    for version in 1..=20 {
+        for aggregate_id in &aggregate_ids {
+            let event = Event {
+                id: *aggregate_id,
+                version,
+                ..Default::default()
+            };
+            backend.append_event(&event).unwrap();
+        }
+    }
+    let all = backend.read_all(0, 1000).unwrap();
+    assert_eq!(all.len(), 100);
+    assert!(all.windows(2).all(|w| w[0].position < w[1].position));
+    assert_eq!(backend.read_all(all[97].position, 10).unwrap().len(), 2);
+
+    let projection = std::sync::Arc::new(VersionsSeen::default());
+    let runner = ProjectionRunner::new(backend.clone(), projection.clone())
+        .with_workers(4)
+        .with_batch_size(7);
+    assert_eq!(runner.run_until_caught_up().unwrap(), 100);
+    assert_eq!(runner.run_once().unwrap(), 0);
+    assert_eq!(
+        backend.get_checkpoint("versions-seen").unwrap(),
+        all[99].position
+    );
+    let expected: Vec<u32> = (1..=20).collect();
+    for aggregate_id in &aggregate_ids {
+        assert_eq!(projection.seen.lock().unwrap()[aggregate_id], expected);
+    }
+
+    projection.seen.lock().unwrap().clear();
+    assert_eq!(runner.rebuild().unwrap(), 100);
+    assert_eq!(projection.seen.lock().unwrap().len(), 5);
+}
+
+#[test_log::test]
+fn sync_handlers_run_inside_append_transaction() {
+    use eventstore::handler::{ErrorPolicy, HandlerRegistry};
+
+    let _span = debug_span!("test-main-span").entered();
+    let mut handlers = HandlerRegistry::new();
+    handlers.register(
+        "event-count",
+        ErrorPolicy::Abort,
+        |tx: &rusqlite::Transaction, event: &Event| {
+            if event.event_type == "reject" {
+                return Err(Error::WithMsg("rejected by handler".to_string()));
+            }
+            tx.execute(
+                "CREATE TABLE IF NOT EXISTS event_count(position INTEGER)",
+                [],
+            )?;
+            tx.execute(
+                "INSERT INTO event_count(position) VALUES(?)",
+                [event.position],
+            )?;
+            Ok(())
+        },
+    );
+    handlers.register(
+        "flaky",
+        ErrorPolicy::LogAndContinue,
+        |tx: &rusqlite::Transaction, _event: &Event| {
+            tx.execute("DELETE FROM event_count", [])?;
+            Err(Error::WithMsg("always fails".to_string()))
+        },
+    );
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let manager = SqliteConnectionManager::file(&path);
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager).with_handlers(handlers);
+    let aggregate_id = uuid::Uuid::parse_str("a0b1c2d3-e4f5-4a6b-8c7d-9e0f1a2b3c4d").unwrap();
+    for version in 1..=2 {
+        let event = Event {
+            id: aggregate_id,
+            version,
+            ..Default::default()
+        };
+        backend.append_event(&event).unwrap();
+    }
+    let rejected = Event {
+        id: aggregate_id,
+        version: 3,
+        event_type: "reject".to_string(),
+        ..Default::default()
+    };
+    assert!(backend.append_event(&rejected).is_err());
+    assert_get_aggreate_of_len(aggregate_id, &backend, 2);
+
+    let positions: Vec<u64> = backend
+        .get_aggretate(aggregate_id)
+        .unwrap()
+        .iter()
+        .map(|e| e.position)
+        .collect();
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT position FROM event_count ORDER BY position")
+        .unwrap();
+    let counted: Vec<u64> = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(counted, positions);
+    drop(stmt);
+    drop(conn);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn batch_append_interleaves_streams_across_insert_chunks() {
+    use eventstore::handler::{ErrorPolicy, HandlerRegistry};
+    use std::sync::{Arc, Mutex};
+
+    let _span = debug_span!("test-main-span").entered();
+    let dispatched = Arc::new(Mutex::new(Vec::new()));
+    let mut handlers = HandlerRegistry::new();
+    let seen = dispatched.clone();
+    handlers.register(
+        "positions",
+        ErrorPolicy::Abort,
+        move |_tx: &rusqlite::Transaction, event: &Event| {
+            seen.lock()
+                .unwrap()
+                .push((event.id, event.version, event.position));
+            Ok(())
+        },
+    );
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::memory())
+            .with_handlers(handlers);
+    let ids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    backend
+        .append_event(&Event {
+            id: ids[0],
+            version: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    let events: Vec<Event> = (0..150u32)
+        .map(|i| Event {
+            id: ids[i as usize % 3],
+            version: i / 3 + 1 + u32::from(i % 3 == 0),
+            data: format!("{{\"i\":{}}}", i).into_bytes(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+
+    let all = backend.read_all(1, 1000).unwrap();
+    assert_eq!(all.len(), 150);
+    for (stored, event) in all.iter().zip(&events) {
+        assert_eq!((stored.id, stored.version), (event.id, event.version));
+        assert_eq!(stored.data, event.data);
+    }
+    assert!(all.windows(2).all(|w| w[1].position == w[0].position + 1));
+    assert_eq!(backend.stream_version(ids[0]).unwrap(), 51);
+    assert_eq!(backend.stream_version(ids[1]).unwrap(), 50);
+    let dispatched = dispatched.lock().unwrap().clone();
+    assert_eq!(dispatched.len(), 151);
+    assert!(dispatched[1..]
+        .iter()
+        .zip(&all)
+        .all(|(d, e)| *d == (e.id, e.version, e.position)));
+
+    // a gap in one stream rejects the whole batch
+    let conflicting = [
+        Event {
+            id: ids[1],
+            version: 51,
+            ..Default::default()
+        },
+        Event {
+            id: ids[2],
+            version: 52,
+            ..Default::default()
+        },
+    ];
+    assert!(backend.append_events(&conflicting).is_err());
+    assert_eq!(backend.stream_version(ids[1]).unwrap(), 50);
+    assert_eq!(backend.read_all(0, 1000).unwrap().len(), 151);
+}
+
+#[test_log::test]
+fn sql_projection_counts_items_per_sku() {
+    use eventstore::projection::SqlProjection;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let repository = Repository::<Cart>::new(backend.clone());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let added = |sku: &str, quantity| {
+        Event::encode(&ItemAdded {
+            sku: sku.to_string(),
+            quantity,
+        })
+        .unwrap()
+    };
+    let removed = Event::encode(&ItemRemoved {
+        sku: "abc".to_string(),
+    })
+    .unwrap();
+    repository
+        .save(
+            aggregate_id,
+            0,
+            vec![added("abc", 2), added("def", 1), removed],
+        )
+        .unwrap();
+
+    let projection = SqlProjection::new(
+        "item-quantity",
+        "INSERT INTO item_quantity(sku, quantity)
+            VALUES(json_extract(:data, '$.sku'), json_extract(:data, '$.quantity'))
+            ON CONFLICT(sku) DO UPDATE SET quantity = quantity + excluded.quantity",
+    )
+    .with_setup("CREATE TABLE IF NOT EXISTS item_quantity(sku TEXT PRIMARY KEY, quantity INTEGER)")
+    .for_event_types(&["ItemAdded"]);
+    assert_eq!(projection.run_until_caught_up(&backend).unwrap(), 3);
+    repository
+        .save(aggregate_id, 3, vec![added("abc", 5)])
+        .unwrap();
+    assert_eq!(projection.run_until_caught_up(&backend).unwrap(), 1);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let quantity: u32 = conn
+        .query_row(
+            "SELECT quantity FROM item_quantity WHERE sku = 'abc'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(quantity, 7);
+    assert_eq!(
+        backend.get_checkpoint("item-quantity").unwrap(),
+        backend.read_all(0, 10).unwrap()[3].position
+    );
+    drop(conn);
+    drop(repository);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn events_store_their_content_type() {
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    assert_eq!(backend.codec(), eventstore::codec::Codec::Json);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let mut event = backend
+        .encode(&ItemRemoved {
+            sku: "abc".to_string(),
+        })
+        .unwrap();
+    event.id = aggregate_id;
+    event.version = 1;
+    backend.append_event(&event).unwrap();
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].content_type, eventstore::codec::JSON);
+
+    let unknown = Event {
+        content_type: "text/plain".to_string(),
+        ..events[0].clone()
+    };
+    match unknown.decode::<ItemRemoved>() {
+        Err(Error::Codec(_)) => {}
+        res => panic!("expected Codec error but got {:?}", res),
+    };
+}
+
+#[cfg(all(feature = "cbor", feature = "bincode", feature = "msgpack"))]
+#[test_log::test]
+fn mixed_codecs_remain_readable() {
+    use eventstore::codec::Codec;
+
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let aggregate_id = uuid::Uuid::new_v4();
+    let mut backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    for (idx, codec) in [Codec::Json, Codec::Cbor, Codec::Bincode, Codec::MessagePack]
+        .into_iter()
+        .enumerate()
+    {
+        backend = backend.with_codec(codec);
+        let mut event = backend
+            .encode(&ItemAdded {
+                sku: format!("sku-{}", idx),
+                quantity: idx as u32 + 1,
+            })
+            .unwrap();
+        event.id = aggregate_id;
+        event.version = idx as u32 + 1;
+        backend.append_event(&event).unwrap();
+    }
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events.len(), 4);
+    for (idx, event) in events.iter().enumerate() {
+        let decoded = event.decode::<ItemAdded>().unwrap();
+        assert_eq!(decoded.sku, format!("sku-{}", idx));
+        assert_eq!(decoded.quantity, idx as u32 + 1);
+    }
+    let (cart, _) = Repository::<Cart>::new(backend).load(aggregate_id).unwrap();
+    assert_eq!(cart.items.len(), 4);
+}
+
+#[test_log::test]
+fn metadata_is_persisted() {
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let mut event = Event {
+        id: aggregate_id,
+        version: 1,
+        ..Default::default()
+    };
+    event
+        .metadata
+        .insert("correlation_id".to_string(), "abc".to_string());
+    backend.append_event(&event).unwrap();
+    backend.save_snapshot(&event).unwrap();
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].metadata, event.metadata);
+    let snapshot = backend.get_snapshot_by_version(aggregate_id, 1).unwrap();
+    assert_eq!(snapshot.metadata, event.metadata);
+}
+
+#[cfg(feature = "protobuf")]
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+struct HostPinged {
+    #[prost(string, tag = "1")]
+    host: String,
+    #[prost(uint32, tag = "2")]
+    latency_ms: u32,
+}
+
+#[cfg(feature = "protobuf")]
+impl prost::Name for HostPinged {
+    const NAME: &'static str = "HostPinged";
+    const PACKAGE: &'static str = "monitoring.v1";
+}
+
+#[cfg(feature = "protobuf")]
+#[test_log::test]
+fn append_and_read_protobuf_messages() {
+    use eventstore::codec::protobuf::TYPE_URL;
+
+    let _span = debug_span!("test-main-span").entered();
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let pinged = HostPinged {
+        host: "db-1".to_string(),
+        latency_ms: 12,
+    };
+    backend.append_message(aggregate_id, 1, &pinged).unwrap();
+    let mut json = Event::encode(&ItemRemoved {
+        sku: "abc".to_string(),
+    })
+    .unwrap();
+    json.id = aggregate_id;
+    json.version = 2;
+    backend.append_event(&json).unwrap();
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].event_type, "monitoring.v1.HostPinged");
+    assert_eq!(
+        events[0].metadata.get(TYPE_URL).map(String::as_str),
+        Some("/monitoring.v1.HostPinged")
+    );
+    assert!(events[1].decode_message::<HostPinged>().is_err());
+    assert_eq!(
+        backend.get_messages::<HostPinged>(aggregate_id).unwrap(),
+        vec![pinged]
+    );
+}
+
+/// Newer reader view of `ItemAdded`, implemented by hand so it does not
+/// replace `ItemAdded` in the global registry.
+#[cfg(feature = "avro")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ItemAddedWithNote {
+    sku: String,
+    quantity: u32,
+    note: String,
+}
+
+#[cfg(feature = "avro")]
+impl DomainEvent for ItemAddedWithNote {
+    fn event_type() -> &'static str {
+        "ItemAdded"
+    }
+}
+
+#[cfg(feature = "avro")]
+#[test_log::test]
+fn avro_payloads_resolve_against_newer_schema() {
+    use eventstore::codec::avro::{AvroSchemaStore, AVRO, FINGERPRINT};
+
+    let _span = debug_span!("test-main-span").entered();
+    let mut schemas = AvroSchemaStore::new();
+    let v1 = schemas
+        .register_str(
+            r#"{"type": "record", "name": "ItemAdded", "fields": [
+                {"name": "sku", "type": "string"},
+                {"name": "quantity", "type": "long"}
+            ]}"#,
+        )
+        .unwrap();
+    let v2 = schemas
+        .register_str(
+            r#"{"type": "record", "name": "ItemAdded", "fields": [
+                {"name": "sku", "type": "string"},
+                {"name": "quantity", "type": "long"},
+                {"name": "note", "type": "string", "default": ""}
+            ]}"#,
+        )
+        .unwrap();
+    assert_ne!(v1, v2);
+
+    let manager = SqliteConnectionManager::memory();
+    let backend = eventstore::backend::sqlite::SqliteBackend::new(manager);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let added = ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 3,
+    };
+    let mut event = schemas.encode(&v1, &added).unwrap();
+    event.id = aggregate_id;
+    event.version = 1;
+    backend.append_event(&event).unwrap();
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].content_type, AVRO);
+    assert_eq!(events[0].metadata.get(FINGERPRINT), Some(&v1));
+    assert_eq!(schemas.decode::<ItemAdded>(&v1, &events[0]).unwrap(), added);
+    assert_eq!(
+        schemas
+            .decode::<ItemAddedWithNote>(&v2, &events[0])
+            .unwrap(),
+        ItemAddedWithNote {
+            sku: "abc".to_string(),
+            quantity: 3,
+            note: String::new(),
+        }
+    );
+    assert!(AvroSchemaStore::new()
+        .decode::<ItemAdded>(&v1, &events[0])
+        .is_err());
+}
+
+#[cfg(feature = "compression")]
+#[test_log::test]
+fn large_payloads_are_compressed_transparently() {
+    use eventstore::compression::Compression;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::file(&path))
+            .with_compression(Compression {
+                threshold: 64,
+                ..Default::default()
+            });
+    let aggregate_id = uuid::Uuid::new_v4();
+    let large = Event {
+        id: aggregate_id,
+        version: 1,
+        data: "abc".repeat(1000).into_bytes(),
+        ..Default::default()
+    };
+    let small = Event {
+        id: aggregate_id,
+        version: 2,
+        data: b"abc".to_vec(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[large.clone(), small.clone()])
+        .unwrap();
+    backend.save_snapshot(&large).unwrap();
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events[0].data, large.data);
+    assert_eq!(events[1].data, small.data);
+    let snapshot = backend.get_snapshot_by_version(aggregate_id, 1).unwrap();
+    assert_eq!(snapshot.data, large.data);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let stored: Vec<(usize, String)> = conn
+        .prepare("SELECT length(data), compression FROM eventstore ORDER BY version")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert!(stored[0].0 < 3000);
+    assert_eq!(stored[0].1, "zstd");
+    assert_eq!(stored[1], (3, String::new()));
+    drop(conn);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "compression")]
+#[test_log::test]
+fn small_payloads_are_compressed_with_trained_dictionary() {
+    use eventstore::compression::Compression;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::file(&path))
+            .with_compression(Compression::default());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let payload = |i: u32| {
+        format!(
+            r#"{{"sku":"article-{:04}","quantity":{},"warehouse":"central-warehouse-north","currency":"EUR"}}"#,
+            i * 7919 % 10000,
+            i % 13
+        )
+        .into_bytes()
+    };
+    let events: Vec<Event> = (1..=200)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            event_type: "ItemAdded".to_string(),
+            data: payload(version),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+
+    let dictionary = backend.train_dictionary("ItemAdded", 200, 4096).unwrap();
+    let later = Event {
+        id: aggregate_id,
+        version: 201,
+        event_type: "ItemAdded".to_string(),
+        data: payload(201),
+        ..Default::default()
+    };
+    backend.append_event(&later).unwrap();
+
+    let stored = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(stored.len(), 201);
+    assert_eq!(stored[0].data, events[0].data);
+    assert_eq!(stored[200].data, later.data);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let (length, compression): (usize, String) = conn
+        .query_row(
+            "SELECT length(data), compression FROM eventstore WHERE version = 201",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(compression, format!("zstd-dict:{}", dictionary));
+    assert!(length < later.data.len());
+    drop(conn);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "encryption")]
+#[test_log::test]
+fn payloads_are_encrypted_at_rest_and_keys_rotate() {
+    use eventstore::encryption::{keys::StaticKeyProvider, AesGcmEncryptor};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let old = backend
+        .clone()
+        .with_encryptor(AesGcmEncryptor::new(StaticKeyProvider::new("k1", [1; 32])))
+        .with_encrypted_metadata();
+    let aggregate_id = uuid::Uuid::new_v4();
+    let mut first = Event {
+        id: aggregate_id,
+        version: 1,
+        data: br#"{"secret":"first"}"#.to_vec(),
+        ..Default::default()
+    };
+    first
+        .metadata
+        .insert("user".to_string(), "alice".to_string());
+    old.append_event(&first).unwrap();
+
+    let rotated = backend.clone().with_encryptor(AesGcmEncryptor::new(
+        StaticKeyProvider::new("k2", [2; 32]).with_key("k1", [1; 32]),
+    ));
+    let second = Event {
+        id: aggregate_id,
+        version: 2,
+        data: br#"{"secret":"second"}"#.to_vec(),
+        ..Default::default()
+    };
+    rotated.append_event(&second).unwrap();
+    rotated.save_snapshot(&second).unwrap();
+
+    let events = rotated.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(
+        events,
+        vec![first.clone(), second.clone()]
+            .into_iter()
+            .zip([1, 2])
+            .map(|(e, position)| Event { position, ..e })
+            .collect::<Vec<_>>()
+    );
+    let snapshot = rotated.get_snapshot_by_version(aggregate_id, 2).unwrap();
+    assert_eq!(snapshot.data, second.data);
+    // without the key or with the wrong one reads fail
+    assert!(backend.get_aggretate(aggregate_id).is_err());
+    let wrong = backend.clone().with_encryptor(AesGcmEncryptor::new(
+        StaticKeyProvider::new("k2", [9; 32]).with_key("k1", [9; 32]),
+    ));
+    assert!(matches!(
+        wrong.get_aggretate(aggregate_id),
+        Err(Error::Encryption(_))
+    ));
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let stored: Vec<(Vec<u8>, String)> = conn
+        .prepare("SELECT data, key_id FROM eventstore ORDER BY version")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(stored[0].1, "k1");
+    assert_eq!(stored[1].1, "k2");
+    assert!(!String::from_utf8_lossy(&stored[0].0).contains("first"));
+    let metadata_type: String = conn
+        .query_row(
+            "SELECT typeof(metadata) FROM eventstore WHERE version = 1",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(metadata_type, "blob");
+    drop(conn);
+    drop((backend, old, rotated));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn key_providers_resolve_keys() {
+    use eventstore::encryption::keys::{
+        EnvKeyProvider, EnvelopeKeyProvider, FileKeyProvider, KeyProvider, KeyUnwrapper,
+    };
+
+    let _span = debug_span!("test-main-span").entered();
+    let hex = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    let dir = std::env::temp_dir().join(format!("eventstore-keys-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("raw.key"), [2u8; 32]).unwrap();
+    std::fs::write(dir.join("hex.key"), format!("{}\n", hex)).unwrap();
+    let files = FileKeyProvider::new(&dir, "raw");
+    assert_eq!(files.current_key_id().unwrap(), "raw");
+    assert_eq!(files.key("raw").unwrap(), [2; 32]);
+    assert_eq!(files.key("hex").unwrap(), [1; 32]);
+    assert!(files.key("missing").is_err());
+    assert!(files.key("../raw").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let env = EnvKeyProvider::new("EVENTSTORE_TEST_KEY_", "key-1");
+    assert_eq!(env.variable("key-1"), "EVENTSTORE_TEST_KEY_KEY_1");
+    std::env::set_var(env.variable("key-1"), hex);
+    assert_eq!(env.key("key-1").unwrap(), [1; 32]);
+    assert!(env.key("key-2").is_err());
+
+    struct Xor;
+    impl KeyUnwrapper for Xor {
+        fn unwrap_key(&self, wrapped: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(wrapped.iter().map(|b| b ^ 0xff).collect())
+        }
+    }
+    let envelope = EnvelopeKeyProvider::new(Xor, "wrapped", vec![0xfe; 32]);
+    assert_eq!(envelope.key("wrapped").unwrap(), [1; 32]);
+    assert!(envelope.key("other").is_err());
+}
+
+#[cfg(feature = "encryption")]
+#[test_log::test]
+fn forgotten_subjects_keep_stream_structure() {
+    use eventstore::encryption::FORGOTTEN;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory())
+        .with_crypto_shredding()
+        .with_encrypted_metadata();
+    let alice = uuid::Uuid::new_v4();
+    let bob = uuid::Uuid::new_v4();
+    for (id, name) in [(alice, "alice"), (bob, "bob")] {
+        for version in 1..=2 {
+            let mut event = Event {
+                id,
+                version,
+                data: format!(r#"{{"name":"{}"}}"#, name).into_bytes(),
+                ..Default::default()
+            };
+            event
+                .metadata
+                .insert("ip".to_string(), "127.0.0.1".to_string());
+            backend.append_event(&event).unwrap();
+        }
+    }
+
+    backend.forget(&alice.to_string()).unwrap();
+
+    let forgotten = backend.get_aggretate(alice).unwrap();
+    assert_eq!(
+        forgotten.iter().map(|e| e.version).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert!(forgotten.iter().all(|e| e.data.is_empty()));
+    assert!(forgotten
+        .iter()
+        .all(
+            |e| e.metadata.get(FORGOTTEN).map(String::as_str) == Some("true")
+                && !e.metadata.contains_key("ip")
+        ));
+
+    let kept = backend.get_aggretate(bob).unwrap();
+    assert_eq!(kept[1].data, br#"{"name":"bob"}"#.to_vec());
+    assert_eq!(kept[1].metadata.get("ip").unwrap(), "127.0.0.1");
+    assert!(!kept[1].metadata.contains_key(FORGOTTEN));
+
+    let err = backend
+        .append_event(&Event {
+            id: alice,
+            version: 3,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert!(matches!(err, Error::Encryption(_)));
+}
+
+#[test_log::test]
+fn redact_event_rewrites_fields_and_records_audit() {
+    use eventstore::redaction::Redaction;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    backend
+        .append_events(&[
+            Event {
+                id: aggregate_id,
+                version: 1,
+                data: br#"{"customer":{"name":"Jane","email":"jane@example.com"},"total":3}"#
+                    .to_vec(),
+                ..Default::default()
+            },
+            Event {
+                id: aggregate_id,
+                version: 2,
+                data: br#"{"total":4}"#.to_vec(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    let position = backend.get_aggretate(aggregate_id).unwrap()[0].position;
+
+    let redaction = Redaction::new("erasure request #12").field("/customer/email");
+    backend.redact_event(position, &redaction).unwrap();
+    let missing = Redaction::new("typo").field("/customer/phone");
+    assert!(backend.redact_event(position, &missing).is_err());
+
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&events[0].data).unwrap(),
+        serde_json::json!({"customer": {"name": "Jane", "email": null}, "total": 3})
+    );
+    assert_eq!(events[1].data, br#"{"total":4}"#.to_vec());
+
+    let audit = backend.get_redactions(position).unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].fields, vec!["/customer/email".to_string()]);
+    assert_eq!(audit[0].reason, "erasure request #12");
+}
+
+#[test_log::test]
+fn oversized_payloads_are_rejected() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_max_event_size(16);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let small = Event {
+        id: aggregate_id,
+        version: 1,
+        data: b"{}".to_vec(),
+        ..Default::default()
+    };
+    let large = Event {
+        id: aggregate_id,
+        version: 2,
+        data: vec![b' '; 17],
+        ..Default::default()
+    };
+    let err = backend.append_events(&[small.clone(), large]).unwrap_err();
+    assert!(matches!(err, Error::PayloadTooLarge { size: 17, max: 16 }));
+    // nothing of the batch was written
+    assert!(backend.get_aggretate(aggregate_id).unwrap().is_empty());
+    backend.append_event(&small).unwrap();
+}
+
+#[test_log::test]
+fn typed_reads_negotiate_codec_per_event() {
+    use eventstore::codec::Transcoders;
+
+    let _span = debug_span!("test-main-span").entered();
+    let mut transcoders = Transcoders::new();
+    transcoders.register("text/csv", "item.removed", |event: &Event| {
+        let sku = String::from_utf8_lossy(&event.data).trim().to_string();
+        Ok(serde_json::to_vec(&serde_json::json!({ "sku": sku }))?)
+    });
+    let backend =
+        SqliteBackend::new(SqliteConnectionManager::memory()).with_transcoders(transcoders);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let legacy = Event {
+        id: aggregate_id,
+        version: 1,
+        event_type: "item.removed".to_string(),
+        content_type: "text/csv".to_string(),
+        data: b"abc\n".to_vec(),
+        ..Default::default()
+    };
+    let current = Event {
+        id: aggregate_id,
+        version: 2,
+        ..backend
+            .encode(&ItemRemoved {
+                sku: "def".to_string(),
+            })
+            .unwrap()
+    };
+    backend.append_events(&[legacy.clone(), current]).unwrap();
+
+    let removed: Vec<ItemRemoved> = backend.get_events(aggregate_id).unwrap();
+    assert_eq!(
+        removed.iter().map(|e| e.sku.as_str()).collect::<Vec<_>>(),
+        vec!["abc", "def"]
+    );
+    // without a transcoder the content type is rejected
+    assert!(matches!(
+        legacy.decode::<ItemRemoved>(),
+        Err(Error::Codec(_))
+    ));
+}
+
+#[cfg(feature = "protobuf")]
+#[derive(Debug, PartialEq, Serialize, Deserialize, DomainEvent)]
+#[event(name = "monitoring.v1.HostPinged")]
+struct HostPingedView {
+    host: String,
+    latency_ms: u32,
+}
+
+#[cfg(feature = "protobuf")]
+#[test_log::test]
+fn protobuf_history_is_readable_as_domain_event() {
+    use eventstore::codec::Transcoders;
+
+    let _span = debug_span!("test-main-span").entered();
+    let mut transcoders = Transcoders::new();
+    transcoders.register_message::<HostPinged>();
+    let backend =
+        SqliteBackend::new(SqliteConnectionManager::memory()).with_transcoders(transcoders);
+    let aggregate_id = uuid::Uuid::new_v4();
+    backend
+        .append_message(
+            aggregate_id,
+            1,
+            &HostPinged {
+                host: "db-1".to_string(),
+                latency_ms: 12,
+            },
+        )
+        .unwrap();
+    let json = Event {
+        id: aggregate_id,
+        version: 2,
+        ..Event::encode(&HostPingedView {
+            host: "db-2".to_string(),
+            latency_ms: 7,
+        })
+        .unwrap()
+    };
+    backend.append_event(&json).unwrap();
+
+    assert_eq!(
+        backend.get_events::<HostPingedView>(aggregate_id).unwrap(),
+        vec![
+            HostPingedView {
+                host: "db-1".to_string(),
+                latency_ms: 12,
+            },
+            HostPingedView {
+                host: "db-2".to_string(),
+                latency_ms: 7,
+            },
+        ]
+    );
+}
+
+#[test_log::test]
+fn migrations_upgrade_legacy_databases() {
+    use eventstore::backend::sqlite::migrations::MIGRATIONS;
+    use eventstore::backend::sqlite::{convert_ids_to_blob, IdFormat, Tables};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let aggregate_id = uuid::Uuid::new_v4();
+    // tables as created before migrations existed
+    let legacy = rusqlite::Connection::open(&path).unwrap();
+    legacy
+        .execute_batch(
+            "CREATE TABLE eventstore(aggregate_id TEXT, data BLOB, version INTEGER);
+            CREATE TABLE aggregate_index(aggregate_id TEXT PRIMARY KEY, type_name TEXT, version INTEGER);
+            CREATE TABLE snapshot(aggregate_id TEXT, data BLOB, version INTEGER);
+            CREATE TABLE snapshot_index(aggregate_id TEXT PRIMARY KEY, type_name TEXT, version INTEGER);",
+        )
+        .unwrap();
+    for version in 1..=2 {
+        legacy
+            .execute(
+                "INSERT INTO eventstore(aggregate_id, data, version) VALUES(?, ?, ?)",
+                rusqlite::params![aggregate_id.to_string(), b"{}".to_vec(), version],
+            )
+            .unwrap();
+    }
+    legacy
+        .execute(
+            "INSERT INTO aggregate_index(aggregate_id, type_name, version) VALUES(?, 'x', 2)",
+            rusqlite::params![aggregate_id.to_string()],
+        )
+        .unwrap();
+    drop(legacy);
+
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    assert_eq!(
+        backend.schema_version().unwrap(),
+        MIGRATIONS.last().unwrap().version
+    );
+    let events = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .map(|e| (e.version, e.position))
+            .collect::<Vec<_>>(),
+        vec![(1, 1), (2, 2)]
+    );
+    backend
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 3,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    drop(backend);
+
+    // opening an up to date database applies nothing
+    let reopened = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    assert_eq!(reopened.get_aggretate(aggregate_id).unwrap().len(), 3);
+    // the text ids are kept until converted
+    assert_eq!(reopened.id_format(), IdFormat::Text);
+    drop(reopened);
+
+    // the three events, the index entry and the latest event by type
+    assert_eq!(convert_ids_to_blob(&path, &Tables::default()).unwrap(), 5);
+    let converted = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    assert_eq!(converted.id_format(), IdFormat::Blob);
+    assert_eq!(converted.get_aggretate(aggregate_id).unwrap().len(), 3);
+    converted
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 4,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(converted.maintenance().integrity_check().unwrap().is_ok());
+    drop(converted);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn stream_reads_use_the_stream_index() {
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events: Vec<_> = (1..=3)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+    assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 3);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let plan: Vec<String> = conn
+        .prepare(
+            "EXPLAIN QUERY PLAN SELECT aggregate_id, data, version, event_type, schema_version,
+                content_type, metadata, compression, key_id, position FROM eventstore
+                WHERE tenant_id = '' AND aggregate_id = ? AND created_at >= 0 ORDER BY version",
+        )
+        .unwrap()
+        .query_map([aggregate_id.as_bytes().to_vec()], |row| row.get(3))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        plan,
+        vec![
+            "SEARCH eventstore USING INDEX eventstore_stream_idx (tenant_id=? AND aggregate_id=?)"
+        ]
+    );
+    // payloads are stored once, in the rows
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_index_info('eventstore_stream_idx')")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        columns,
+        [
+            "tenant_id",
+            "aggregate_id",
+            "version",
+            "created_at",
+            "expires_at"
+        ]
+    );
+
+    drop(conn);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn optimizer_analyzes_after_write_bursts() {
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let append = |aggregate_id, count| {
+        let events: Vec<_> = (1..=count)
+            .map(|version| Event {
+                id: aggregate_id,
+                version,
+                event_type: "Touched".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        backend.append_events(&events).unwrap();
+    };
+    let analyzed = |backend: &SqliteBackend| -> bool {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE name = 'sqlite_stat1'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+            && backend.maintenance().written_since_analyze() == 0
+    };
+
+    append(uuid::Uuid::new_v4(), 5);
+    let maintenance = backend.maintenance();
+    assert_eq!(maintenance.written_since_analyze(), 5);
+    assert!(!maintenance.optimize_after_writes(10).unwrap());
+    assert_eq!(maintenance.written_since_analyze(), 5);
+    assert!(maintenance.optimize_after_writes(5).unwrap());
+    assert!(analyzed(&backend));
+
+    // handles share the counter
+    let optimizer = backend.spawn_optimizer(std::time::Duration::from_millis(10), 3);
+    append(uuid::Uuid::new_v4(), 3);
+    let started = std::time::Instant::now();
+    while !analyzed(&backend) {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    optimizer.stop().unwrap();
+    // stopping does not wait for the interval
+    let idle = backend.spawn_optimizer(std::time::Duration::from_secs(3600), 3);
+    let stopping = std::time::Instant::now();
+    idle.stop().unwrap();
+    assert!(stopping.elapsed() < std::time::Duration::from_secs(5));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn durability_profiles_configure_connections() {
+    use eventstore::backend::sqlite::Durability;
+
+    let _span = debug_span!("test-main-span").entered();
+    for (profile, synchronous) in [
+        (Durability::Durable, 2),
+        (Durability::Balanced, 1),
+        (Durability::Fast, 0),
+    ] {
+        let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+        let pool = r2d2::Pool::new(profile.manager(&path)).unwrap();
+        let conn = pool.get().unwrap();
+        let setting = |pragma: &str| -> i64 {
+            conn.query_row(&format!("PRAGMA {}", pragma), [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(setting("synchronous"), synchronous);
+        assert_eq!(
+            setting("wal_autocheckpoint"),
+            profile.wal_autocheckpoint() as i64
+        );
+        drop(conn);
+
+        let backend = SqliteBackend::new(profile.manager(&path));
+        let aggregate_id = uuid::Uuid::new_v4();
+        backend
+            .append_event(&Event {
+                id: aggregate_id,
+                version: 1,
+                data: b"{}".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 1);
+        let journal_mode: String = rusqlite::Connection::open(&path)
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        drop(backend);
+        drop(pool);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
+
+#[test_log::test]
+fn connection_options_tune_mmap_and_cache_size() {
+    use eventstore::backend::sqlite::{ConnectionOptions, Durability, Tables};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let options = ConnectionOptions::new()
+        .with_durability(Durability::Durable)
+        .with_mmap_size(64 << 20)
+        .with_cache_size_kib(8 << 10);
+    let pool = r2d2::Pool::new(options.manager(&path)).unwrap();
+    let conn = pool.get().unwrap();
+    let setting = |pragma: &str| -> i64 {
+        conn.query_row(&format!("PRAGMA {}", pragma), [], |row| row.get(0))
+            .unwrap()
+    };
+    assert_eq!(setting("synchronous"), 2);
+    assert_eq!(setting("mmap_size"), 64 << 20);
+    // negative cache sizes are in KiB
+    assert_eq!(setting("cache_size"), -(8 << 10));
+    drop(conn);
+
+    let backend = SqliteBackend::new(options.manager(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    backend
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    let replica = SqliteBackend::read_only_with_options(&path, Tables::default(), options).unwrap();
+    assert_eq!(replica.get_aggretate(aggregate_id).unwrap().len(), 1);
+
+    drop(replica);
+    drop(backend);
+    drop(pool);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test_log::test]
+fn prefixed_tables_share_one_database() {
+    use eventstore::backend::sqlite::Tables;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let billing = SqliteBackend::with_tables(
+        SqliteConnectionManager::file(&path),
+        Tables::with_prefix("billing_"),
+    );
+    let shipping = SqliteBackend::with_tables(
+        SqliteConnectionManager::file(&path),
+        Tables::with_prefix("shipping_"),
+    );
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = Event {
+        id: aggregate_id,
+        version: 1,
+        data: b"{}".to_vec(),
+        ..Default::default()
+    };
+    billing.append_event(&event).unwrap();
+    // the same aggregate id and version do not collide across contexts
+    shipping.append_event(&event).unwrap();
+    billing.save_checkpoint("invoices", 1).unwrap();
+
+    assert_eq!(billing.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert_eq!(shipping.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert_eq!(shipping.get_checkpoint("invoices").unwrap(), 0);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let tables: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE '%eventstore' ORDER BY name")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(tables, vec!["billing_eventstore", "shipping_eventstore"]);
+    assert!(Tables::with_prefix("billing-").validate().is_err());
+
+    drop((conn, billing, shipping));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn online_backup_is_readable() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events: Vec<Event> = (1..=3)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    backend.backup_to(&path).unwrap();
+    let restored = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    assert_eq!(
+        restored
+            .get_aggretate(aggregate_id)
+            .unwrap()
+            .iter()
+            .map(|e| e.data.clone())
+            .collect::<Vec<_>>(),
+        events.iter().map(|e| e.data.clone()).collect::<Vec<_>>()
+    );
+    drop(restored);
+    std::fs::remove_file(&path).unwrap();
+
+    let mut streamed = Vec::new();
+    let written = backend.backup_to_writer(&mut streamed).unwrap();
+    assert_eq!(written as usize, streamed.len());
+    assert!(streamed.starts_with(b"SQLite format 3\0"));
+}
+
+#[test_log::test]
+fn wal_shipping_restores_point_in_time() {
+    use eventstore::object_store::LocalObjectStore;
+    use eventstore::wal_shipping::{restore_from, WalShipper};
+
+    let _span = debug_span!("test-main-span").entered();
+    let dir = std::env::temp_dir().join(format!("eventstore-{}", uuid::Uuid::new_v4()));
+    let path = dir.join("store.db");
+    let bucket = dir.join("bucket");
+    std::fs::create_dir_all(&dir).unwrap();
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let mut shipper = WalShipper::new(&path, LocalObjectStore::new(&bucket))
+        .unwrap()
+        .with_prefix("stores/main");
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version: u32| Event {
+        id: aggregate_id,
+        version,
+        data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+        ..Default::default()
+    };
+
+    backend.append_events(&[event(1)]).unwrap();
+    shipper.sync().unwrap();
+    backend.append_events(&[event(2)]).unwrap();
+    assert!(shipper.sync().unwrap() > 0);
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let before_checkpoint = std::time::SystemTime::now();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+
+    backend.append_events(&[event(3)]).unwrap();
+    shipper.checkpoint().unwrap();
+    backend.append_events(&[event(4)]).unwrap();
+    let handle = shipper.spawn(std::time::Duration::from_millis(1), 2);
+    handle.stop().unwrap();
+
+    let versions = |time| {
+        let restored = dir.join(format!("restored-{}.db", uuid::Uuid::new_v4()));
+        restore_from(
+            &LocalObjectStore::new(&bucket),
+            "stores/main",
+            time,
+            &restored,
+        )
+        .unwrap();
+        SqliteBackend::new(SqliteConnectionManager::file(&restored))
+            .get_aggretate(aggregate_id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(before_checkpoint), vec![1, 2]);
+    assert_eq!(versions(std::time::SystemTime::now()), vec![1, 2, 3, 4]);
+    assert!(restore_from(
+        &LocalObjectStore::new(&bucket),
+        "stores/main",
+        std::time::UNIX_EPOCH,
+        dir.join("too-early.db"),
+    )
+    .is_err());
+
+    drop(backend);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test_log::test]
+fn export_jsonl_writes_one_envelope_per_line() {
+    use eventstore::jsonl::{Envelope, ExportOpts};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let first = uuid::Uuid::new_v4();
+    let second = uuid::Uuid::new_v4();
+    let mut metadata = std::collections::BTreeMap::new();
+    metadata.insert("user".to_string(), "alice".to_string());
+    backend
+        .append_events(&[
+            Event {
+                id: first,
+                version: 1,
+                event_type: "created".to_string(),
+                data: br#"{"name":"first"}"#.to_vec(),
+                metadata,
+                ..Default::default()
+            },
+            Event {
+                id: second,
+                version: 1,
+                event_type: "created".to_string(),
+                content_type: "application/octet-stream".to_string(),
+                data: vec![0, 1, 254, 255],
+                ..Default::default()
+            },
+            Event {
+                id: first,
+                version: 2,
+                event_type: "renamed".to_string(),
+                data: br#"{"name":"again"}"#.to_vec(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+    let mut out = Vec::new();
+    let opts = ExportOpts {
+        batch_size: 2,
+        ..Default::default()
+    };
+    assert_eq!(backend.export_jsonl(&mut out, &opts).unwrap(), 3);
+    let envelopes: Vec<Envelope> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        envelopes.iter().map(|e| e.position).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(
+        envelopes[0].data,
+        Some(serde_json::json!({"name": "first"}))
+    );
+    assert_eq!(envelopes[0].metadata["user"], "alice");
+    assert_eq!(envelopes[1].data_hex.as_deref(), Some("0001feff"));
+    assert_eq!(
+        envelopes[1].clone().into_event().unwrap().data,
+        vec![0, 1, 254, 255]
+    );
+
+    let mut out = Vec::new();
+    let opts = ExportOpts {
+        from_position: 1,
+        aggregates: vec![first],
+        ..Default::default()
+    };
+    assert_eq!(backend.export_jsonl(&mut out, &opts).unwrap(), 1);
+    let envelope: eventstore::jsonl::Envelope =
+        serde_json::from_slice(out.trim_ascii_end()).unwrap();
+    assert_eq!((envelope.aggregate_id, envelope.version), (first, 2));
+
+    let opts = ExportOpts {
+        event_types: vec!["renamed".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(backend.export_jsonl(&mut Vec::new(), &opts).unwrap(), 1);
+}
+
+#[cfg(feature = "parquet")]
+#[test_log::test]
+fn export_parquet_writes_one_row_per_event() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{TimestampMillisecondType, UInt32Type, UInt64Type};
+    use arrow_array::Array;
+    use eventstore::jsonl::ExportOpts;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let first = uuid::Uuid::new_v4();
+    let second = uuid::Uuid::new_v4();
+    let mut metadata = std::collections::BTreeMap::new();
+    metadata.insert("user".to_string(), "alice".to_string());
+    backend
+        .append_events(&[
+            Event {
+                id: first,
+                version: 1,
+                event_type: "created".to_string(),
+                data: br#"{"name":"first"}"#.to_vec(),
+                metadata,
+                ..Default::default()
+            },
+            Event {
+                id: second,
+                version: 1,
+                event_type: "created".to_string(),
+                content_type: "application/octet-stream".to_string(),
+                data: vec![0, 1, 254, 255],
+                ..Default::default()
+            },
+            Event {
+                id: first,
+                version: 2,
+                event_type: "renamed".to_string(),
+                data: br#"{"name":"again"}"#.to_vec(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+    let path = std::env::temp_dir().join(format!("eventstore-{}.parquet", uuid::Uuid::new_v4()));
+    let opts = ExportOpts {
+        batch_size: 2,
+        ..Default::default()
+    };
+    assert_eq!(backend.export_parquet(&path, &opts).unwrap(), 3);
+    let reader =
+        ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(reader.metadata().num_row_groups(), 2);
+    assert_eq!(reader.schema(), &eventstore::parquet::schema());
+    let batches = reader
+        .build()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+    let column = |name: &str| -> Vec<arrow_array::ArrayRef> {
+        batches
+            .iter()
+            .map(|batch| batch.column_by_name(name).unwrap().clone())
+            .collect()
+    };
+    let positions: Vec<u64> = column("position")
+        .iter()
+        .flat_map(|array| array.as_primitive::<UInt64Type>().values().to_vec())
+        .collect();
+    assert_eq!(positions, [1, 2, 3]);
+    let versions: Vec<u32> = column("version")
+        .iter()
+        .flat_map(|array| array.as_primitive::<UInt32Type>().values().to_vec())
+        .collect();
+    assert_eq!(versions, [1, 1, 2]);
+    let strings = |name: &str| -> Vec<String> {
+        column(name)
+            .iter()
+            .flat_map(|array| {
+                let array = array.as_string::<i32>();
+                (0..array.len())
+                    .map(|i| array.value(i).to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    };
+    assert_eq!(
+        strings("aggregate_id"),
+        [first.to_string(), second.to_string(), first.to_string()]
+    );
+    assert_eq!(strings("event_type"), ["created", "created", "renamed"]);
+    assert_eq!(strings("metadata")[0], r#"{"user":"alice"}"#);
+    let timestamps = column("timestamp");
+    let timestamps = timestamps[0].as_primitive::<TimestampMillisecondType>();
+    assert_eq!(timestamps.null_count(), 0);
+    assert!(timestamps.value(0) > 1_600_000_000_000);
+    let payloads = column("payload");
+    let payloads = payloads[0].as_binary::<i32>();
+    assert_eq!(payloads.value(0), br#"{"name":"first"}"#);
+    assert_eq!(payloads.value(1), &[0, 1, 254, 255]);
+
+    let opts = ExportOpts {
+        aggregates: vec![second],
+        ..Default::default()
+    };
+    assert_eq!(backend.export_parquet(&path, &opts).unwrap(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "analytics")]
+#[test_log::test]
+fn analytics_aggregates_events_with_sql() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use arrow_array::Array;
+    use eventstore::analytics::Analytics;
+    use eventstore::jsonl::ExportOpts;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let stream = uuid::Uuid::new_v4();
+    let events: Vec<_> = ["created", "renamed", "renamed"]
+        .iter()
+        .zip(1..)
+        .map(|(event_type, version)| Event {
+            id: stream,
+            version,
+            event_type: event_type.to_string(),
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.parquet", uuid::Uuid::new_v4()));
+    backend
+        .export_parquet(&path, &ExportOpts::default())
+        .unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let from_store = Analytics::from_store(&backend, &ExportOpts::default()).unwrap();
+        let from_parquet = Analytics::from_parquet(&path).await.unwrap();
+        for analytics in [from_store, from_parquet] {
+            let batches = analytics.events_per_type_per_day().await.unwrap();
+            let rows: Vec<(String, bool, i64)> = batches
+                .iter()
+                .flat_map(|batch| {
+                    let types = batch.column(0).as_string::<i32>().clone();
+                    let days = batch.column(1).clone();
+                    let counts = batch.column(2).as_primitive::<Int64Type>().clone();
+                    (0..batch.num_rows()).map(move |i| {
+                        (types.value(i).to_string(), days.is_null(i), counts.value(i))
+                    })
+                })
+                .collect();
+            assert_eq!(
+                rows,
+                [
+                    ("created".to_string(), false, 1),
+                    ("renamed".to_string(), false, 2)
+                ]
+            );
+
+            let batches = analytics
+                .sql("SELECT max(version) AS version FROM events WHERE event_type = 'renamed'")
+                .await
+                .unwrap();
+            let version = batches[0]
+                .column(0)
+                .as_primitive::<arrow_array::types::UInt32Type>();
+            assert_eq!(version.value(0), 3);
+        }
+        assert!(Analytics::from_store(&backend, &ExportOpts::default())
+            .unwrap()
+            .sql("SELECT nope FROM events")
+            .await
+            .is_err());
+    });
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn import_jsonl_is_idempotent_and_remaps_aggregates() {
+    use eventstore::context::AppendContext;
+    use eventstore::jsonl::{ExportOpts, ImportOpts, ImportSummary};
+
+    let _span = debug_span!("test-main-span").entered();
+    let source = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events: Vec<Event> = (1..=3)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            data: format!(r#"{{ "n": {}, "a": true }}"#, version).into_bytes(),
+            ..Default::default()
+        })
+        .collect();
+    source.append_events(&events).unwrap();
+    let mut export = Vec::new();
+    source
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+
+    let target = SqliteBackend::new(SqliteConnectionManager::memory());
+    target.append_events(&events[..1]).unwrap();
+    let opts = ImportOpts {
+        batch_size: 2,
+        ..Default::default()
+    };
+    let request =
+        |id: &str| target.with_append_context(AppendContext::default().with_request_id(id));
+    assert_eq!(
+        request("import-1")
+            .import_jsonl(export.as_slice(), &opts)
+            .unwrap(),
+        ImportSummary {
+            imported: 2,
+            skipped: 1
+        }
+    );
+    // also when re-run in another request
+    assert_eq!(
+        request("import-2")
+            .import_jsonl(export.as_slice(), &opts)
+            .unwrap(),
+        ImportSummary {
+            imported: 0,
+            skipped: 3
+        }
+    );
+    assert_eq!(target.get_aggretate(aggregate_id).unwrap().len(), 3);
+
+    let clone = uuid::Uuid::new_v4();
+    let summary = target
+        .import_jsonl(
+            export.as_slice(),
+            &ImportOpts::default().remap(aggregate_id, clone),
+        )
+        .unwrap();
+    assert_eq!(summary.imported, 3);
+    assert_eq!(
+        target
+            .get_aggretate(clone)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+
+    // gaps and conflicting history are rejected
+    let gap = String::from_utf8(export.clone()).unwrap();
+    let gap: String = gap
+        .lines()
+        .skip(1)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let fresh = SqliteBackend::new(SqliteConnectionManager::memory());
+    assert!(fresh
+        .import_jsonl(gap.as_bytes(), &ImportOpts::default())
+        .is_err());
+    let conflicting = SqliteBackend::new(SqliteConnectionManager::memory());
+    conflicting
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            data: br#"{"n":99}"#.to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(conflicting
+        .import_jsonl(export.as_slice(), &ImportOpts::default())
+        .is_err());
+}
+
+#[test_log::test]
+fn bulk_load_import_rebuilds_indexes() {
+    use eventstore::jsonl::{ExportOpts, ImportOpts, ImportSummary};
+
+    let _span = debug_span!("test-main-span").entered();
+    let source = SqliteBackend::new(SqliteConnectionManager::memory());
+    let events: Vec<Event> = (0..10)
+        .map(|_| uuid::Uuid::new_v4())
+        .flat_map(|id| {
+            (1..=5).map(move |version| Event {
+                id,
+                version,
+                data: br#"{}"#.to_vec(),
+                ..Default::default()
+            })
+        })
+        .collect();
+    source.append_events(&events).unwrap();
+    let mut export = Vec::new();
+    source
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let target = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let indexes = || -> Vec<String> {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'eventstore' ORDER BY name")
+            .unwrap();
+        let names = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        names
+    };
+    let before = indexes();
+    assert!(before.len() > 1);
+
+    let opts = ImportOpts::default().bulk_load();
+    assert_eq!(
+        target.import_jsonl(export.as_slice(), &opts).unwrap(),
+        ImportSummary {
+            imported: 50,
+            skipped: 0
+        }
+    );
+    assert_eq!(indexes(), before);
+    assert_eq!(target.read_all(0, 100).unwrap().len(), 50);
+    assert_eq!(target.get_aggretate(events[0].id).unwrap().len(), 5);
+    assert!(target.maintenance().integrity_check().unwrap().is_ok());
+
+    // failed imports rebuild the indexes as well
+    let gap: String = String::from_utf8(export)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let opts = opts.remap(events[0].id, uuid::Uuid::new_v4());
+    assert!(target.import_jsonl(gap.as_bytes(), &opts).is_err());
+    assert_eq!(indexes(), before);
+
+    drop(target);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn maintenance_vacuums_checkpoints_and_analyzes() {
+    use eventstore::backend::sqlite::CheckpointMode;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+    }
+    let aggregate_id = uuid::Uuid::new_v4();
+    backend
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            data: br#"{"n":1}"#.to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+
+    let maintenance = backend.maintenance();
+    let checkpoint = maintenance
+        .wal_checkpoint(CheckpointMode::Truncate)
+        .unwrap();
+    assert!(!checkpoint.busy);
+    assert_eq!(
+        std::fs::metadata(format!("{}-wal", path.display()))
+            .unwrap()
+            .len(),
+        0
+    );
+    maintenance.analyze().unwrap();
+    maintenance.vacuum().unwrap();
+    assert_eq!(backend.get_aggretate(aggregate_id).unwrap().len(), 1);
+
+    drop(backend);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test_log::test]
+fn integrity_check_reports_index_inconsistencies() {
+    use eventstore::backend::sqlite::IntegrityIssue;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events: Vec<Event> = (1..=2)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            data: br#"{}"#.to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+    assert!(backend.maintenance().integrity_check().unwrap().is_ok());
+
+    {
+        // duplicates are only possible without the unique version index
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(&format!(
+            "DROP INDEX eventstore_agg_id_idx;
+            UPDATE aggregate_index SET version = 1 WHERE aggregate_id = X'{id}';
+            INSERT INTO aggregate_index(aggregate_id, type_name, version) VALUES('orphan', '', 3);
+            INSERT INTO eventstore(aggregate_id, data, version) VALUES(X'{id}', '{{}}', 2);",
+            id = aggregate_id.simple()
+        ))
+        .unwrap();
+    }
+    let report = backend.maintenance().integrity_check().unwrap();
+    assert!(report.sqlite.is_empty());
+    assert_eq!(
+        report.issues,
+        vec![
+            IntegrityIssue::IndexVersionMismatch {
+                aggregate_id: aggregate_id.to_string(),
+                indexed: Some(1),
+                stored: 2,
+            },
+            IntegrityIssue::OrphanedIndexEntry {
+                aggregate_id: "orphan".to_string(),
+                version: 3,
+            },
+            IntegrityIssue::DuplicateVersion {
+                aggregate_id: aggregate_id.to_string(),
+                version: 2,
+            },
+        ]
+    );
+
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "metrics")]
+#[test_log::test]
+fn metrics_track_appends_conflicts_reads_and_lag() {
+    use eventstore::metrics;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    ::metrics::with_local_recorder(&recorder, || {
+        let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+        let aggregate_id = uuid::Uuid::new_v4();
+        let event = Event {
+            id: aggregate_id,
+            version: 1,
+            data: br#"{"sku":"a"}"#.to_vec(),
+            ..Default::default()
+        };
+        backend.append_event(&event).unwrap();
+        assert!(backend.append_event(&event).is_err());
+        backend.get_aggretate(aggregate_id).unwrap();
+        backend.save_snapshot(&event).unwrap();
+        let mut writer = backend.snapshot_writer(&event, 2).unwrap();
+        std::io::Write::write_all(&mut writer, b"{}").unwrap();
+        writer.finish().unwrap();
+        eventstore::projection::SqlProjection::new(
+            "seen",
+            "INSERT INTO seen(position) VALUES(:position)",
+        )
+        .with_setup("CREATE TABLE IF NOT EXISTS seen(position INTEGER)")
+        .run_until_caught_up(&backend)
+        .unwrap();
+    });
+
+    let values: std::collections::HashMap<String, DebugValue> = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+        .collect();
+    assert_eq!(values[metrics::APPENDS], DebugValue::Counter(1));
+    assert_eq!(values[metrics::EVENTS_APPENDED], DebugValue::Counter(1));
+    assert_eq!(values[metrics::APPEND_CONFLICTS], DebugValue::Counter(1));
+    assert_eq!(values[metrics::SNAPSHOTS_SAVED], DebugValue::Counter(2));
+    assert!(matches!(&values[metrics::READ_DURATION], DebugValue::Histogram(h) if !h.is_empty()));
+    assert!(matches!(&values[metrics::POOL_WAIT], DebugValue::Histogram(h) if !h.is_empty()));
+    assert_eq!(
+        values[metrics::SUBSCRIPTION_LAG],
+        DebugValue::Gauge(0.0.into())
+    );
+}
+
+#[cfg(feature = "opentelemetry")]
+#[test_log::test]
+fn trace_context_is_propagated_through_metadata() {
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = SdkTracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("eventstore-test")));
+    tracing::subscriber::with_default(subscriber, || {
+        let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_trace_context();
+        let aggregate_id = uuid::Uuid::new_v4();
+        let request = tracing::info_span!("request");
+        let trace_id = request.context().span().span_context().trace_id();
+        request.in_scope(|| {
+            backend
+                .append_event(&Event {
+                    id: aggregate_id,
+                    version: 1,
+                    data: br#"{}"#.to_vec(),
+                    ..Default::default()
+                })
+                .unwrap()
+        });
+
+        let event = backend.get_aggretate(aggregate_id).unwrap().pop().unwrap();
+        assert!(event.metadata["traceparent"].contains(&trace_id.to_string()));
+        let processing = eventstore::telemetry::event_span(&event);
+        assert_eq!(
+            processing.context().span().span_context().trace_id(),
+            trace_id
+        );
+
+        // scheduled events continue the trace that scheduled them, imports
+        // get the one of the importing span
+        let reminder = uuid::Uuid::new_v4();
+        request.in_scope(|| {
+            backend
+                .schedule_event(
+                    &Event {
+                        id: reminder,
+                        data: br#"{}"#.to_vec(),
+                        ..Default::default()
+                    },
+                    std::time::SystemTime::now(),
+                )
+                .unwrap()
+        });
+        let imported = uuid::Uuid::new_v4();
+        let source = SqliteBackend::new(SqliteConnectionManager::memory());
+        source
+            .append_event(&Event {
+                id: imported,
+                version: 1,
+                data: br#"{}"#.to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut export = Vec::new();
+        source
+            .export_jsonl(&mut export, &Default::default())
+            .unwrap();
+        let import = tracing::info_span!("import");
+        let import_trace_id = import.context().span().span_context().trace_id();
+        import.in_scope(|| {
+            assert_eq!(backend.dispatch_due().unwrap(), 1);
+            backend
+                .import_jsonl(export.as_slice(), &Default::default())
+                .unwrap();
+        });
+        let traceparent = |id| {
+            let event = backend.get_aggretate(id).unwrap().pop().unwrap();
+            event.metadata["traceparent"].clone()
+        };
+        assert!(traceparent(reminder).contains(&trace_id.to_string()));
+        assert!(traceparent(imported).contains(&import_trace_id.to_string()));
+    });
+}
+
+#[test_log::test]
+fn retention_hides_and_scavenges_expired_events() {
+    use eventstore::clock::ManualClock;
+    use eventstore::stream::StreamMetadata;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_clock(clock.clone());
+    let counted = uuid::Uuid::new_v4();
+    let aged = uuid::Uuid::new_v4();
+    let event = |id, version| Event {
+        id,
+        version,
+        data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+        ..Default::default()
+    };
+    backend
+        .set_stream_metadata(counted, &StreamMetadata::default().with_max_count(2))
+        .unwrap();
+    assert_eq!(
+        backend.get_stream_metadata(counted).unwrap().max_count,
+        Some(2)
+    );
+    backend
+        .append_events(&(1..=5).map(|v| event(counted, v)).collect::<Vec<_>>())
+        .unwrap();
+    backend.append_events(&[event(aged, 1)]).unwrap();
+    clock.advance(Duration::from_secs(3600));
+    backend.append_events(&[event(aged, 2)]).unwrap();
+    backend
+        .set_stream_metadata(
+            aged,
+            &StreamMetadata::default().with_max_age(Duration::from_secs(1800)),
+        )
+        .unwrap();
+
+    let versions = |id| {
+        backend
+            .get_aggretate(id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(counted), vec![4, 5]);
+    assert_eq!(versions(aged), vec![2]);
+    assert_eq!(backend.read_all(0, 100).unwrap().len(), 3);
+
+    assert_eq!(backend.maintenance().scavenge().unwrap().deleted_events, 4);
+    // appends continue after the latest version
+    backend.append_event(&event(counted, 6)).unwrap();
+    assert_eq!(versions(counted), vec![5, 6]);
+}
+
+#[test_log::test]
+fn ordered_ids_sort_by_creation() {
+    use eventstore::clock::ManualClock;
+    use eventstore::ids::{created_at, ordered_id, OrderedIds};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    let clock = ManualClock::new(start);
+    let ids = OrderedIds::new(clock.clone());
+    let mut created = vec![ids.next_id(), ids.next_id()];
+    clock.advance(Duration::from_millis(5));
+    created.push(ids.next_id());
+    // a clock going back does not break the order
+    clock.set(start - Duration::from_secs(1));
+    created.push(ids.next_id());
+    let sorted = {
+        let mut sorted = created.clone();
+        sorted.sort();
+        sorted
+    };
+    assert_eq!(created, sorted);
+    assert_eq!(created_at(created[0]), Some(start));
+    assert_eq!(
+        created_at(created[2]),
+        Some(start + Duration::from_millis(5))
+    );
+    assert_eq!(created_at(uuid::Uuid::new_v4()), None);
+    assert!(ordered_id() < ordered_id());
+
+    // the stored bytes keep the order
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    for id in created.iter().rev() {
+        backend
+            .append_event(&Event {
+                id: *id,
+                version: 1,
+                data: b"{}".to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+    let listed: Vec<_> = backend
+        .list_streams(None, 10)
+        .unwrap()
+        .into_iter()
+        .map(|stream| stream.aggregate_id)
+        .collect();
+    assert_eq!(listed, created);
+}
+
+#[test_log::test]
+fn commands_append_their_events_once() {
+    use eventstore::clock::ManualClock;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_clock(clock.clone());
+    let id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        ..Default::default()
+    };
+    assert!(backend
+        .append_if_new_command("deposit-1", &[event(1), event(2)])
+        .unwrap());
+    // the retry is acknowledged without appending again
+    assert!(!backend
+        .append_if_new_command("deposit-1", &[event(1), event(2)])
+        .unwrap());
+    assert!(!backend
+        .append_if_new_command("deposit-1", &[event(3)])
+        .unwrap());
+    assert_eq!(backend.stream_version(id).unwrap(), 2);
+
+    // a failed append leaves the command to be retried
+    assert!(backend
+        .append_if_new_command("deposit-2", &[event(2)])
+        .is_err());
+    assert!(backend
+        .append_if_new_command("deposit-2", &[event(3)])
+        .unwrap());
+    // command ids are scoped to the tenant
+    assert!(backend
+        .tenant("acme")
+        .append_if_new_command("deposit-1", &[event(1)])
+        .unwrap());
+
+    clock.advance(Duration::from_secs(3600));
+    assert!(backend
+        .append_if_new_command("deposit-3", &[event(4)])
+        .unwrap());
+    assert_eq!(
+        backend
+            .prune_processed_commands(Duration::from_secs(60))
+            .unwrap(),
+        2
+    );
+    assert!(!backend
+        .append_if_new_command("deposit-3", &[event(5)])
+        .unwrap());
+    assert!(backend
+        .append_if_new_command("deposit-1", &[event(5)])
+        .unwrap());
+    assert_eq!(backend.stream_version(id).unwrap(), 5);
+}
+
+#[test_log::test]
+fn link_events_resolve_to_their_targets() {
+    use eventstore::backend::model::{LINK_EVENT_TYPE, LINK_TO};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (order, payment, customer) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let event = |id, version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: format!("{{\"n\":{}}}", version).into_bytes(),
+        ..Default::default()
+    };
+    let placed = event(order, 1, "OrderPlaced");
+    let paid = event(payment, 1, "PaymentReceived");
+    let shipped = event(order, 2, "OrderShipped");
+    backend
+        .append_events(&[placed.clone(), paid.clone(), shipped.clone()])
+        .unwrap();
+
+    let links: Vec<Event> = [&placed, &paid, &shipped]
+        .into_iter()
+        .enumerate()
+        .map(|(i, target)| Event {
+            id: customer,
+            version: i as u32 + 1,
+            ..Event::link_to(target)
+        })
+        .collect();
+    backend.append_events(&links).unwrap();
+
+    // links are stored without payload and read as they are
+    let stored = backend.get_aggretate(customer).unwrap();
+    assert_eq!(stored.len(), 3);
+    assert!(stored.iter().all(|link| link.event_type == LINK_EVENT_TYPE));
+    assert!(stored.iter().all(|link| link.data.is_empty()));
+    assert_eq!(stored[1].link_target(), Some((payment, 1)));
+
+    let resolved = backend.get_aggretate_resolved(customer).unwrap();
+    let types: Vec<&str> = resolved.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(types, ["OrderPlaced", "PaymentReceived", "OrderShipped"]);
+    assert_eq!(resolved[2].id, order);
+    assert_eq!(resolved[2].data, shipped.data);
+    // pages of all streams are resolved the same way
+    let page = backend.read_all(0, 100).unwrap();
+    assert_eq!(backend.resolve_links(page).unwrap().len(), 6);
+
+    // links to deleted streams are left out
+    backend.delete_stream(payment).unwrap();
+    let resolved = backend.get_aggretate_resolved(customer).unwrap();
+    let types: Vec<&str> = resolved.iter().map(|e| e.event_type.as_str()).collect();
+    assert_eq!(types, ["OrderPlaced", "OrderShipped"]);
+
+    let mut broken = Event::link_to(&placed);
+    broken.id = customer;
+    broken.version = 4;
+    broken
+        .metadata
+        .insert(LINK_TO.to_string(), "not a link".to_string());
+    assert!(backend.append_event(&broken).is_err());
+}
+
+#[test_log::test]
+fn aliased_and_renamed_streams_keep_their_history() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (legacy, alias, renamed) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let event = |id, version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[event(legacy, 1), event(legacy, 2)])
+        .unwrap();
+
+    backend.alias_stream(legacy, alias).unwrap();
+    assert_eq!(backend.resolve_stream(alias).unwrap(), legacy);
+    // appends under the alias continue the stream
+    backend.append_event(&event(alias, 3)).unwrap();
+    assert_eq!(backend.stream_version(alias).unwrap(), 3);
+    let events = backend.get_aggretate(alias).unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(events.iter().all(|event| event.id == alias));
+    assert_eq!(backend.get_aggretate(legacy).unwrap().len(), 3);
+    assert_eq!(backend.load_aggregate(alias).unwrap().version(), 3);
+    // history is not rewritten
+    assert!(backend
+        .read_all(0, 10)
+        .unwrap()
+        .iter()
+        .all(|event| event.id == legacy));
+
+    // streams with events and aliases cannot become aliases
+    assert!(backend.alias_stream(alias, legacy).is_err());
+    assert!(backend.alias_stream(legacy, alias).is_err());
+
+    backend.rename_stream(legacy, renamed).unwrap();
+    assert!(matches!(
+        backend.get_aggretate(legacy),
+        Err(Error::StreamRenamed(id)) if id == renamed
+    ));
+    assert!(matches!(
+        backend.append_event(&event(legacy, 4)),
+        Err(Error::StreamRenamed(_))
+    ));
+    backend.append_event(&event(renamed, 4)).unwrap();
+    let events = backend.get_aggretate(renamed).unwrap();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[3].id, renamed);
+    // the alias of the old id still works
+    assert_eq!(backend.stream_version(alias).unwrap(), 4);
+}
+
+#[test_log::test]
+fn aliases_resolve_for_every_single_stream_operation() {
+    use eventstore::stream::StreamMetadata;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path)).with_read_cache(4);
+    let repository = Repository::<Cart>::new(backend.clone());
+    let (cart, alias, other) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let added = Event::encode(&ItemAdded {
+        sku: "abc".to_string(),
+        quantity: 1,
+    })
+    .unwrap();
+    repository
+        .save(cart, 0, vec![added.clone(), added.clone()])
+        .unwrap();
+    repository.save(other, 0, vec![added]).unwrap();
+    backend.alias_stream(cart, alias).unwrap();
+
+    assert_eq!(repository.load_cached(alias).unwrap().1, 2);
+    assert_eq!(repository.load_cached(cart).unwrap().1, 2);
+    let loaded = backend.load_aggregates(&[alias, cart, other]).unwrap();
+    assert_eq!(loaded[&alias].len(), 2);
+    assert!(loaded[&alias].iter().all(|event| event.id == alias));
+    assert!(loaded[&cart].iter().all(|event| event.id == cart));
+    assert_eq!(loaded[&other].len(), 1);
+
+    backend
+        .save_snapshot(&Event {
+            id: alias,
+            version: 2,
+            data: b"{}".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(backend.get_snapshots(cart).unwrap().len(), 1);
+    assert_eq!(backend.get_snapshot_by_version(alias, 2).unwrap().id, alias);
+    let mut writer = backend
+        .snapshot_writer(
+            &Event {
+                id: alias,
+                version: 3,
+                ..Default::default()
+            },
+            2,
+        )
+        .unwrap();
+    std::io::Write::write_all(&mut writer, b"[]").unwrap();
+    writer.finish().unwrap();
+    let mut streamed = Vec::new();
+    std::io::Read::read_to_end(
+        &mut backend.open_snapshot_payload(cart, 3).unwrap(),
+        &mut streamed,
+    )
+    .unwrap();
+    assert_eq!(streamed, b"[]");
+    let loaded = backend.load_aggregate(alias).unwrap();
+    assert_eq!(loaded.snapshot.unwrap().version, 3);
+    assert!(loaded.events.is_empty());
+
+    backend
+        .set_stream_metadata(alias, &StreamMetadata::default().with_max_count(1))
+        .unwrap();
+    assert_eq!(
+        backend.get_stream_metadata(cart).unwrap().max_count,
+        Some(1)
+    );
+    assert_eq!(backend.get_aggretate(cart).unwrap().len(), 1);
+
+    backend.tombstone_stream(alias).unwrap();
+    assert!(matches!(
+        backend.get_aggretate(cart),
+        Err(Error::StreamDeleted(_))
+    ));
+
+    {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let kinds: (String, String) = conn
+            .query_row(
+                "SELECT typeof(alias), typeof(aggregate_id) FROM stream_aliases",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(kinds, ("blob".to_string(), "blob".to_string()));
+    }
+
+    backend
+        .schedule_event(
+            &Event {
+                id: alias,
+                version: 1,
+                event_type: "ItemAdded".to_string(),
+                ..Default::default()
+            },
+            std::time::SystemTime::now() + std::time::Duration::from_secs(3600),
+        )
+        .unwrap();
+    backend.delete_stream(alias).unwrap();
+    assert_eq!(backend.stream_version(cart).unwrap(), 0);
+    assert!(backend.get_snapshots(cart).unwrap().is_empty());
+    assert!(backend.scheduled_events().unwrap().is_empty());
+    // the alias went with the stream
+    backend
+        .append_event(&Event {
+            id: alias,
+            version: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(backend.stream_version(alias).unwrap(), 1);
+    assert_eq!(backend.stream_version(cart).unwrap(), 0);
+    drop((backend, repository));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn streams_split_and_merge_with_provenance() {
+    use eventstore::backend::model::PROVENANCE;
+    use eventstore::system::{self, StreamsCopied};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_system_streams();
+    let (account, checking, savings, merged) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let event = |id, version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[
+            event(account, 1, "AccountOpened"),
+            event(account, 2, "CheckingDeposited"),
+            event(account, 3, "SavingsDeposited"),
+            event(account, 4, "CheckingWithdrawn"),
+        ])
+        .unwrap();
+
+    let maintenance = backend.maintenance();
+    let copied = maintenance
+        .split_stream(account, |event| {
+            match event.event_type.starts_with("Savings") {
+                true => Some(savings),
+                false if event.version > 1 => Some(checking),
+                false => None,
+            }
+        })
+        .unwrap();
+    assert_eq!(copied, [(checking, 2), (savings, 1)].into());
+    let events = backend.get_aggretate(checking).unwrap();
+    let types: Vec<(&str, u32)> = events
+        .iter()
+        .map(|e| (e.event_type.as_str(), e.version))
+        .collect();
+    assert_eq!(types, [("CheckingDeposited", 1), ("CheckingWithdrawn", 2)]);
+    assert_eq!(
+        events[1].metadata.get(PROVENANCE),
+        Some(&format!("4@{}", account))
+    );
+    // the source stays until it is deleted
+    assert_eq!(backend.stream_version(account).unwrap(), 4);
+
+    // merges interleave by commit order and continue the target
+    backend
+        .append_event(&event(savings, 2, "SavingsWithdrawn"))
+        .unwrap();
+    backend
+        .append_event(&event(merged, 1, "LedgerOpened"))
+        .unwrap();
+    assert_eq!(
+        maintenance
+            .merge_streams(&[savings, checking], merged)
+            .unwrap(),
+        4
+    );
+    let types: Vec<String> = backend
+        .get_aggretate(merged)
+        .unwrap()
+        .into_iter()
+        .map(|e| format!("{}:{}", e.version, e.event_type))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "1:LedgerOpened",
+            "2:CheckingDeposited",
+            "3:SavingsDeposited",
+            "4:CheckingWithdrawn",
+            "5:SavingsWithdrawn"
+        ]
+    );
+    assert!(maintenance.merge_streams(&[savings], savings).is_err());
+    assert_eq!(backend.stream_version(savings).unwrap(), 2);
+
+    // both copies are recorded for operators
+    let operations: Vec<(String, Option<uuid::Uuid>)> = backend
+        .admin_log(0, 10)
+        .unwrap()
+        .into_iter()
+        .map(|entry| (entry.operation, entry.aggregate_id))
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            ("split_stream".to_string(), Some(account)),
+            ("merge_streams".to_string(), Some(merged))
+        ]
+    );
+    let copies: Vec<StreamsCopied> = backend
+        .get_aggretate(system::STREAMS)
+        .unwrap()
+        .iter()
+        .filter_map(|event| event.decode().ok())
+        .collect();
+    assert_eq!(
+        copies,
+        [
+            StreamsCopied {
+                sources: vec![account],
+                copied: [(checking, 2), (savings, 1)].into(),
+            },
+            StreamsCopied {
+                sources: vec![savings, checking],
+                copied: [(merged, 4)].into(),
+            }
+        ]
+    );
+}
+
+#[test_log::test]
+fn system_streams_record_store_lifecycle() {
+    use eventstore::jsonl::{ExportOpts, ImportOpts};
+    use eventstore::system::{self, ImportCompleted, StreamCreated, StreamDeleted};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_system_streams();
+    let (a, b, c) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let event = |id, version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        data: b"{}".to_vec(),
+        ..Default::default()
+    };
+    backend.append_event(&event(a, 1)).unwrap();
+    backend.append_event(&event(a, 2)).unwrap();
+    backend
+        .append_events(&[event(b, 1), event(c, 1), event(c, 2)])
+        .unwrap();
+    backend.tombstone_stream(b).unwrap();
+    backend.delete_stream(c).unwrap();
+
+    let events = backend.get_aggretate(system::STREAMS).unwrap();
+    let created: Vec<uuid::Uuid> = events
+        .iter()
+        .filter_map(|event| event.decode::<StreamCreated>().ok())
+        .map(|created| created.aggregate_id)
+        .collect();
+    assert_eq!(created, [a, b, c]);
+    let deleted: Vec<StreamDeleted> = events
+        .iter()
+        .filter_map(|event| event.decode().ok())
+        .collect();
+    assert_eq!(
+        deleted,
+        [
+            StreamDeleted {
+                aggregate_id: b,
+                tombstoned: true
+            },
+            StreamDeleted {
+                aggregate_id: c,
+                tombstoned: false
+            }
+        ]
+    );
+
+    backend.maintenance().scavenge().unwrap();
+    assert_eq!(
+        backend.get_aggretate(system::MAINTENANCE).unwrap()[0].event_type,
+        "$scavenge-completed"
+    );
+
+    let mut export = Vec::new();
+    backend
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+    let target = SqliteBackend::new(SqliteConnectionManager::memory()).with_system_streams();
+    target
+        .import_jsonl(export.as_slice(), &ImportOpts::default())
+        .unwrap();
+    let imports = target.get_aggretate(system::IMPORTS).unwrap();
+    assert_eq!(imports.len(), 1);
+    let completed: ImportCompleted = imports[0].decode().unwrap();
+    assert_eq!(completed.error, None);
+    assert!(completed.imported > 0);
+
+    // without the option nothing is recorded
+    let plain = SqliteBackend::new(SqliteConnectionManager::memory());
+    plain.append_event(&event(a, 1)).unwrap();
+    assert!(plain.get_aggretate(system::STREAMS).unwrap().is_empty());
+}
+
+#[test_log::test]
+fn valid_time_queries_see_backdated_corrections() {
+    use eventstore::clock::ManualClock;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let day = Duration::from_secs(86_400);
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let clock = ManualClock::new(start);
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_clock(clock.clone());
+    let policy = uuid::Uuid::new_v4();
+    let event = |version, event_type: &str| Event {
+        id: policy,
+        version,
+        event_type: event_type.to_string(),
+        ..Default::default()
+    };
+    let types =
+        |events: Vec<Event>| -> Vec<String> { events.into_iter().map(|e| e.event_type).collect() };
+
+    backend.append_event(&event(1, "PolicyIssued")).unwrap();
+    clock.advance(day * 10);
+    backend
+        .append_event(&event(2, "PremiumRaised").with_valid_time(start + day * 5, None))
+        .unwrap();
+    clock.advance(day * 10);
+    // a discount granted for days 2 to 5, recorded on day 20
+    let correction =
+        event(3, "DiscountGranted").with_valid_time(start + day * 2, Some(start + day * 5));
+    assert_eq!(correction.valid_to(), Some(start + day * 5));
+    backend.append_event(&correction).unwrap();
+
+    assert_eq!(
+        types(
+            backend
+                .get_aggregate_valid_at(policy, start + day * 3)
+                .unwrap()
+        ),
+        ["PolicyIssued", "DiscountGranted"]
+    );
+    assert_eq!(
+        types(
+            backend
+                .get_aggregate_valid_at(policy, start + day * 6)
+                .unwrap()
+        ),
+        ["PolicyIssued", "PremiumRaised"]
+    );
+    assert!(backend
+        .get_aggregate_valid_at(policy, start - day)
+        .unwrap()
+        .is_empty());
+    // on day 15 the discount was not known yet
+    assert_eq!(
+        types(
+            backend
+                .get_aggregate_bitemporal(policy, start + day * 3, start + day * 15)
+                .unwrap()
+        ),
+        ["PolicyIssued"]
+    );
+    // backdated events apply before the later ones in effect
+    assert_eq!(
+        types(
+            backend
+                .get_aggregate_valid_at(policy, start + day * 30)
+                .unwrap()
+        ),
+        ["PolicyIssued", "PremiumRaised"]
+    );
+
+    let inverted = event(4, "DiscountGranted").with_valid_time(start + day, Some(start));
+    assert!(backend.append_event(&inverted).is_err());
+}
+
+#[test_log::test]
+fn change_feed_resumes_from_its_token() {
+    use eventstore::stream::Change;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    let event = |id, version| Event {
+        id,
+        version,
+        event_type: "Changed".to_string(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[event(a, 1), event(a, 2), event(b, 1)])
+        .unwrap();
+
+    let (changes, token) = backend.changes(None, 100).unwrap();
+    let summary = |changes: &[Change]| -> Vec<(uuid::Uuid, u32)> {
+        changes
+            .iter()
+            .map(|c| (c.aggregate_id, c.version))
+            .collect()
+    };
+    assert_eq!(summary(&changes), [(a, 2), (b, 1)]);
+
+    backend.append_event(&event(a, 3)).unwrap();
+    let (changes, token) = backend.changes(Some(&token), 100).unwrap();
+    assert_eq!(summary(&changes), [(a, 3)]);
+    let (changes, same) = backend.changes(Some(&token), 100).unwrap();
+    assert!(changes.is_empty());
+    assert_eq!(same, token);
+
+    // small limits page through the changes
+    let (changes, token) = backend.changes(None, 2).unwrap();
+    assert_eq!(summary(&changes), [(a, 2)]);
+    let resumed: eventstore::backend::sqlite::Cursor = token.to_string().parse().unwrap();
+    let (changes, _) = backend.changes(Some(&resumed), 2).unwrap();
+    assert_eq!(summary(&changes), [(b, 1), (a, 3)]);
+}
+
+#[test_log::test]
+fn latest_events_by_type_are_looked_up() {
+    use eventstore::clock::ManualClock;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    let event = |id, version, event_type: &str, price: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: price.as_bytes().to_vec(),
+        ..Default::default()
+    };
+    let prices = |events: Vec<Event>| -> Vec<(uuid::Uuid, String)> {
+        events
+            .into_iter()
+            .map(|e| (e.id, String::from_utf8(e.data).unwrap()))
+            .collect()
+    };
+
+    for backend in [
+        SqliteBackend::new(SqliteConnectionManager::memory()).with_latest_by_type(),
+        SqliteBackend::new(SqliteConnectionManager::memory()),
+    ] {
+        let backend = backend.with_clock(clock.clone());
+        backend
+            .append_events(&[
+                event(a, 1, "PriceUpdated", "10"),
+                event(b, 1, "PriceUpdated", "20"),
+                event(a, 2, "PriceUpdated", "11"),
+            ])
+            .unwrap();
+        backend
+            .append_event(&event(a, 3, "Renamed", "apple"))
+            .unwrap();
+        backend
+            .append_event(&event(b, 2, "PriceUpdated", "21"))
+            .unwrap();
+
+        assert_eq!(
+            prices(backend.latest_by_type("PriceUpdated").unwrap()),
+            [(a, "11".to_string()), (b, "21".to_string())]
+        );
+        let latest = backend.latest_of_type(a, "PriceUpdated").unwrap().unwrap();
+        assert_eq!((latest.version, latest.data), (2, b"11".to_vec()));
+        assert!(backend.latest_of_type(b, "Renamed").unwrap().is_none());
+        assert!(backend.latest_by_type("Deleted").unwrap().is_empty());
+        assert_eq!(backend.maintenance().rebuild_latest_by_type().unwrap(), 3);
+
+        // scavenged and deleted events give way to the ones before them
+        backend
+            .append_event(&event(b, 3, "PriceUpdated", "22").with_ttl(Duration::from_secs(60)))
+            .unwrap();
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(backend.maintenance().scavenge().unwrap().deleted_events, 1);
+        assert_eq!(
+            prices(backend.latest_by_type("PriceUpdated").unwrap()),
+            [(a, "11".to_string()), (b, "21".to_string())]
+        );
+        backend.delete_stream(a).unwrap();
+        backend
+            .append_event(&event(a, 1, "Renamed", "pear"))
+            .unwrap();
+        assert_eq!(
+            prices(backend.latest_by_type("PriceUpdated").unwrap()),
+            [(b, "21".to_string())]
+        );
+        assert!(backend.latest_of_type(a, "PriceUpdated").unwrap().is_none());
+        assert_eq!(backend.maintenance().rebuild_latest_by_type().unwrap(), 2);
+    }
+}
+
+#[test_log::test]
+fn analytics_count_events_and_sample_subscription_lag() {
+    use eventstore::clock::ManualClock;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let minute = Duration::from_secs(60);
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_040);
+    let clock = ManualClock::new(start);
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory())
+        .with_clock(clock.clone())
+        .with_analytics();
+    let id = uuid::Uuid::new_v4();
+    let event = |version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        ..Default::default()
+    };
+
+    backend
+        .append_events(&[
+            event(1, "Opened"),
+            event(2, "Deposited"),
+            event(3, "Deposited"),
+        ])
+        .unwrap();
+    backend.save_checkpoint("balances", 1).unwrap();
+    clock.advance(minute);
+    backend.append_event(&event(4, "Deposited")).unwrap();
+    backend.save_checkpoint("balances", 4).unwrap();
+    clock.advance(minute * 60);
+    backend.append_event(&event(5, "Closed")).unwrap();
+    assert_eq!(backend.analytics().sample_lag().unwrap(), 1);
+
+    let analytics = backend.analytics();
+    let counts = |interval| -> Vec<(String, u64)> {
+        analytics
+            .events_per_type(interval, start)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.event_type, c.events))
+            .collect()
+    };
+    let count = |event_type: &str, events| (event_type.to_string(), events);
+    assert_eq!(
+        counts(minute),
+        [
+            count("Deposited", 2),
+            count("Opened", 1),
+            count("Deposited", 1),
+            count("Closed", 1)
+        ]
+    );
+    let hourly = analytics.events_per_type(minute * 60, start).unwrap();
+    assert_eq!(
+        hourly[0].start,
+        UNIX_EPOCH + Duration::from_secs(1_699_999_200)
+    );
+    assert_eq!(hourly.iter().map(|c| c.events).sum::<u64>(), 5);
+    assert!(analytics
+        .events_per_type(Duration::from_secs(30), start)
+        .is_err());
+
+    let lag: Vec<(u64, u64)> = analytics
+        .subscription_lag("balances", minute, start)
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.lag, s.max_lag))
+        .collect();
+    assert_eq!(lag, [(2, 2), (0, 0), (1, 1)]);
+    let daily = analytics
+        .subscription_lag("balances", minute * 60 * 24, start)
+        .unwrap();
+    assert_eq!((daily[0].lag, daily[0].max_lag), (1, 2));
+
+    assert_eq!(analytics.prune(minute * 30).unwrap(), 5);
+    assert_eq!(counts(minute), [count("Closed", 1)]);
+}
+
+#[test_log::test]
+fn append_context_is_stored_in_event_metadata() {
+    use eventstore::context::{AppendContext, REQUEST_ID, SOURCE_SERVICE, USER_ID};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let context = AppendContext::default()
+        .with_user_id("alice")
+        .with_request_id("req-1")
+        .with_source_service("checkout");
+    let repository = Repository::<Cart>::new(backend.clone()).with_append_context(context.clone());
+    let cart_id = uuid::Uuid::new_v4();
+    let added = |sku: &str| {
+        Event::encode(&ItemAdded {
+            sku: sku.to_string(),
+            quantity: 1,
+        })
+        .unwrap()
+    };
+    repository.save(cart_id, 0, vec![added("abc")]).unwrap();
+
+    // explicit metadata wins over the context
+    let mut explicit = added("def");
+    explicit.id = cart_id;
+    explicit.version = 2;
+    explicit
+        .metadata
+        .insert(USER_ID.to_string(), "bob".to_string());
+    backend
+        .with_append_context(context)
+        .append_if_new_command("cmd-1", &[explicit])
+        .unwrap();
+    backend
+        .append_event(&Event {
+            id: cart_id,
+            version: 3,
+            ..added("ghi")
+        })
+        .unwrap();
+
+    let events = backend.get_aggretate(cart_id).unwrap();
+    let get = |event: &Event, key: &str| event.metadata.get(key).cloned();
+    assert_eq!(get(&events[0], USER_ID).as_deref(), Some("alice"));
+    assert_eq!(get(&events[0], REQUEST_ID).as_deref(), Some("req-1"));
+    assert_eq!(get(&events[0], SOURCE_SERVICE).as_deref(), Some("checkout"));
+    assert_eq!(get(&events[1], USER_ID).as_deref(), Some("bob"));
+    assert_eq!(get(&events[1], REQUEST_ID).as_deref(), Some("req-1"));
+    // the handle the context was attached to is unchanged
+    assert_eq!(get(&events[2], USER_ID), None);
+
+    // scheduled events keep the context they were scheduled with, copies
+    // get the one of the handle copying them
+    let reminder = uuid::Uuid::new_v4();
+    backend
+        .with_append_context(AppendContext::default().with_user_id("carol"))
+        .schedule_event(
+            &Event {
+                id: reminder,
+                ..added("jkl")
+            },
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+    let service = backend.with_append_context(AppendContext::default().with_source_service("ops"));
+    assert_eq!(service.dispatch_due().unwrap(), 1);
+    let merged = uuid::Uuid::new_v4();
+    service
+        .maintenance()
+        .merge_streams(&[reminder], merged)
+        .unwrap();
+    for id in [reminder, merged] {
+        let event = &backend.get_aggretate(id).unwrap()[0];
+        assert_eq!(get(event, USER_ID).as_deref(), Some("carol"));
+        assert_eq!(get(event, SOURCE_SERVICE).as_deref(), Some("ops"));
+    }
+}
+
+#[test_log::test]
+fn middleware_layers_wrap_appends_and_reads() {
+    use eventstore::middleware::{Middleware, MiddlewareChain};
+    use std::sync::{Arc, Mutex};
+
+    struct RejectForbidden;
+    impl Middleware for RejectForbidden {
+        fn before_append(&self, events: &mut [Event]) -> Result<(), Error> {
+            match events.iter().any(|e| e.event_type == "Forbidden") {
+                true => Err(Error::WithMsg("forbidden".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    /// Stores payloads reversed, a stand-in for encryption.
+    struct Reverse;
+    impl Middleware for Reverse {
+        fn before_append(&self, events: &mut [Event]) -> Result<(), Error> {
+            events.iter_mut().for_each(|e| e.data.reverse());
+            Ok(())
+        }
+        fn on_read(&self, events: &mut [Event]) -> Result<(), Error> {
+            events.iter_mut().for_each(|e| e.data.reverse());
+            Ok(())
+        }
+    }
+
+    struct Positions(Arc<Mutex<Vec<u64>>>);
+    impl Middleware for Positions {
+        fn after_append(&self, events: &[Event]) {
+            let mut positions = self.0.lock().unwrap();
+            positions.extend(events.iter().map(|e| e.position));
+        }
+    }
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let positions = Arc::new(Mutex::new(Vec::new()));
+    let mut middleware = MiddlewareChain::new();
+    middleware
+        .register(RejectForbidden)
+        .register(Reverse)
+        .register(Positions(positions.clone()));
+    let backend =
+        SqliteBackend::new(SqliteConnectionManager::file(&path)).with_middleware(middleware);
+    let id = uuid::Uuid::new_v4();
+    let event = |version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: b"abc".to_vec(),
+        ..Default::default()
+    };
+
+    backend
+        .append_events(&[event(1, "Opened"), event(2, "Renamed")])
+        .unwrap();
+    assert!(backend.append_event(&event(3, "Forbidden")).is_err());
+    backend.append_event(&event(3, "Closed")).unwrap();
+    assert_eq!(*positions.lock().unwrap(), [1, 2, 3]);
+
+    let events = backend.get_aggretate(id).unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(events.iter().all(|e| e.data == b"abc"));
+    assert_eq!(backend.read_all(0, 10).unwrap()[2].data, b"abc");
+    // stored as the last layer left them
+    let plain = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    assert_eq!(plain.get_aggretate(id).unwrap()[0].data, b"cba");
+}
+
+#[test_log::test]
+fn middleware_sees_scheduled_and_imported_events() {
+    use eventstore::jsonl::{ExportOpts, ImportOpts, ImportSummary};
+    use eventstore::middleware::{Middleware, MiddlewareChain};
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    /// Records the event types appended and stores payloads reversed.
+    struct Record(Arc<Mutex<Vec<String>>>);
+    impl Middleware for Record {
+        fn before_append(&self, events: &mut [Event]) -> Result<(), Error> {
+            events.iter_mut().for_each(|e| e.data.reverse());
+            Ok(())
+        }
+        fn after_append(&self, events: &[Event]) {
+            let mut seen = self.0.lock().unwrap();
+            seen.extend(events.iter().map(|e| e.event_type.clone()));
+        }
+        fn on_read(&self, events: &mut [Event]) -> Result<(), Error> {
+            events.iter_mut().for_each(|e| e.data.reverse());
+            Ok(())
+        }
+    }
+
+    let _span = debug_span!("test-main-span").entered();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut middleware = MiddlewareChain::new();
+    middleware.register(Record(seen.clone()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_middleware(middleware);
+    let event = |id, version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: b"abc".to_vec(),
+        ..Default::default()
+    };
+
+    let scheduled = uuid::Uuid::new_v4();
+    backend
+        .schedule_event(&event(scheduled, 0, "Reminded"), SystemTime::now())
+        .unwrap();
+    assert_eq!(backend.dispatch_due().unwrap(), 1);
+
+    let source = SqliteBackend::new(SqliteConnectionManager::memory());
+    let imported = uuid::Uuid::new_v4();
+    source
+        .append_events(&[event(imported, 1, "Opened"), event(imported, 2, "Closed")])
+        .unwrap();
+    let mut export = Vec::new();
+    source
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+    for summary in [
+        ImportSummary {
+            imported: 2,
+            skipped: 0,
+        },
+        // compared with the stored events as read back
+        ImportSummary {
+            imported: 0,
+            skipped: 2,
+        },
+    ] {
+        assert_eq!(
+            backend
+                .import_jsonl(export.as_slice(), &ImportOpts::default())
+                .unwrap(),
+            summary
+        );
+    }
+
+    assert_eq!(*seen.lock().unwrap(), ["Reminded", "Opened", "Closed"]);
+    for id in [scheduled, imported] {
+        let events = backend.get_aggretate(id).unwrap();
+        assert!(events.iter().all(|e| e.data == b"abc"));
+    }
+}
+
+#[test_log::test]
+fn quotas_skip_repeated_commands_and_refund_failed_appends() {
+    use eventstore::clock::ManualClock;
+    use eventstore::quota::{Quota, Quotas};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory())
+        .with_clock(clock)
+        .with_quotas(
+            Quotas::default()
+                .with_tenant(Quota::default().with_max_events(3).with_events_per_sec(2)),
+        );
+    let id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        data: b"1".to_vec(),
+        ..Default::default()
+    };
+
+    assert!(backend
+        .append_if_new_command("deposit-1", &[event(1)])
+        .unwrap());
+    // retries of the command are neither charged nor rejected
+    for _ in 0..3 {
+        assert!(!backend
+            .append_if_new_command("deposit-1", &[event(1)])
+            .unwrap());
+    }
+    // a version conflict gives its rate back
+    let err = backend.append_event(&event(1)).unwrap_err();
+    assert!(!matches!(err, Error::QuotaExceeded(_)), "{}", err);
+    backend.append_event(&event(2)).unwrap();
+
+    let usage = backend.maintenance().quota_usage().unwrap();
+    let tenant = usage
+        .iter()
+        .find(|usage| usage.aggregate_id.is_none())
+        .unwrap();
+    assert_eq!((tenant.events, tenant.rejected), (2, 0));
+
+    // deleting the stream takes its events off the totals
+    backend.delete_stream(id).unwrap();
+    let usage = backend.maintenance().quota_usage().unwrap();
+    assert_eq!(usage.len(), 1);
+    assert_eq!((usage[0].events, usage[0].bytes), (0, 0));
+}
+
+#[test_log::test]
+fn quotas_charge_imports_scheduled_events_and_merges() {
+    use eventstore::jsonl::{ExportOpts, ImportOpts};
+    use eventstore::quota::{Quota, Quotas};
+    use std::time::SystemTime;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory())
+        .with_quotas(Quotas::default().with_tenant(Quota::default().with_max_events(3)));
+    let event = |id, version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        data: b"1".to_vec(),
+        ..Default::default()
+    };
+    let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+    backend.append_events(&[event(a, 1), event(a, 2)]).unwrap();
+    backend
+        .schedule_event(&event(b, 0), SystemTime::now())
+        .unwrap();
+    assert_eq!(backend.dispatch_due().unwrap(), 1);
+    // the tenant is at its limit of 3 events
+    let err = backend
+        .maintenance()
+        .merge_streams(&[a], uuid::Uuid::new_v4())
+        .unwrap_err();
+    assert!(matches!(err, Error::QuotaExceeded(_)), "{}", err);
+    let source = SqliteBackend::new(SqliteConnectionManager::memory());
+    source
+        .append_event(&event(uuid::Uuid::new_v4(), 1))
+        .unwrap();
+    let mut export = Vec::new();
+    source
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+    let err = backend
+        .import_jsonl(export.as_slice(), &ImportOpts::default())
+        .unwrap_err();
+    assert!(matches!(err, Error::QuotaExceeded(_)), "{}", err);
+    let over = backend
+        .schedule_event(&event(b, 0), SystemTime::now())
+        .unwrap();
+    // set aside as failed, the quota will not free up by itself
+    assert_eq!(backend.dispatch_due().unwrap(), 0);
+    let scheduled = backend.scheduled_events().unwrap();
+    assert_eq!(scheduled[0].id, over);
+    assert!(scheduled[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("limited to 3 events"));
+    // tombstones are not charged
+    backend.tombstone_stream(a).unwrap();
+
+    let usage = backend.maintenance().quota_usage().unwrap();
+    let tenant = usage
+        .iter()
+        .find(|usage| usage.aggregate_id.is_none())
+        .unwrap();
+    assert_eq!((tenant.events, tenant.rejected), (3, 3));
+}
+
+#[cfg(feature = "compression")]
+#[test_log::test]
+fn quotas_charge_stored_payload_sizes() {
+    use eventstore::compression::Compression;
+    use eventstore::quota::{Quota, Quotas};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path))
+        .with_compression(Compression {
+            threshold: 64,
+            ..Default::default()
+        })
+        .with_quotas(Quotas::default().with_stream(Quota::default().with_max_bytes(1000)));
+    let id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id,
+        version,
+        data: "abc".repeat(1000).into_bytes(),
+        ..Default::default()
+    };
+
+    // 3000 bytes each, compressed well below the limit
+    backend.append_events(&[event(1), event(2)]).unwrap();
+    backend.append_event(&event(3)).unwrap();
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let stored: u64 = conn
+        .query_row("SELECT SUM(length(data)) FROM eventstore", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    let usage = backend.maintenance().quota_usage().unwrap();
+    let stream = usage
+        .iter()
+        .find(|usage| usage.aggregate_id == Some(id))
+        .unwrap();
+    assert_eq!((stream.events, stream.bytes), (3, stored));
+    drop((conn, backend));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn quotas_reject_appends_over_the_limits() {
+    use eventstore::clock::ManualClock;
+    use eventstore::quota::{Quota, QuotaUsage, Quotas};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory())
+        .with_clock(clock.clone())
+        .with_quotas(
+            Quotas::default()
+                .with_tenant(Quota::default().with_max_events(5).with_events_per_sec(3))
+                .with_tenant_override("enterprise", Quota::default())
+                .with_stream(Quota::default().with_max_bytes(8)),
+        );
+    let event = |id, version| Event {
+        id,
+        version,
+        event_type: "Deposited".to_string(),
+        data: b"1234".to_vec(),
+        ..Default::default()
+    };
+    let (a, b, c) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+
+    backend.append_events(&[event(a, 1), event(a, 2)]).unwrap();
+    // the stream holds at most 8 bytes of payload
+    let err = backend.append_event(&event(a, 3)).unwrap_err();
+    assert!(matches!(err, Error::QuotaExceeded(_)), "{}", err);
+    // one event is left of the burst of 3 per second
+    let err = backend
+        .append_events(&[event(b, 1), event(b, 2)])
+        .unwrap_err();
+    assert!(matches!(err, Error::QuotaExceeded(_)), "{}", err);
+    backend.append_event(&event(b, 1)).unwrap();
+    assert!(matches!(
+        backend.append_event(&event(b, 2)),
+        Err(Error::QuotaExceeded(_))
+    ));
+
+    // the rate refills with time, the tenant total of 5 events stays
+    clock.advance(Duration::from_secs(1));
+    backend.append_events(&[event(b, 2), event(c, 1)]).unwrap();
+    clock.advance(Duration::from_secs(1));
+    assert!(matches!(
+        backend.append_event(&event(c, 2)),
+        Err(Error::QuotaExceeded(_))
+    ));
+    assert_eq!(backend.stream_version(c).unwrap(), 1);
+
+    // overrides and other tenants have their own totals
+    let enterprise = backend.tenant("enterprise");
+    for _ in 0..10 {
+        enterprise
+            .append_event(&event(uuid::Uuid::new_v4(), 1))
+            .unwrap();
+    }
+    backend.tenant("acme").append_event(&event(a, 1)).unwrap();
+
+    let usage = backend.maintenance().quota_usage().unwrap();
+    let tenant = |tenant_id: &str| {
+        usage
+            .iter()
+            .find(|usage| usage.tenant_id == tenant_id && usage.aggregate_id.is_none())
+            .cloned()
+    };
+    assert_eq!(
+        tenant(""),
+        Some(QuotaUsage {
+            tenant_id: String::new(),
+            aggregate_id: None,
+            events: 5,
+            bytes: 20,
+            rejected: 3,
+        })
+    );
+    assert_eq!(tenant("enterprise"), None);
+    assert_eq!(tenant("acme").map(|usage| usage.events), Some(1));
+    let stream_a = usage
+        .iter()
+        .find(|usage| usage.tenant_id.is_empty() && usage.aggregate_id == Some(a))
+        .unwrap();
+    assert_eq!((stream_a.bytes, stream_a.rejected), (8, 1));
+    assert_eq!(backend.maintenance().stats().unwrap().quota_rejected, 4);
+}
+
+#[test_log::test]
+fn causation_tree_follows_cascades_across_streams() {
+    use eventstore::backend::model::CORRELATION_ID;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (order, payment, stock) = (
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+        uuid::Uuid::new_v4(),
+    );
+    let event = |id, version, event_type: &str| {
+        Event {
+            id,
+            version,
+            event_type: event_type.to_string(),
+            ..Default::default()
+        }
+        .with_event_id("shop")
+    };
+    let placed = event(order, 1, "OrderPlaced");
+    let requested = event(payment, 1, "PaymentRequested").caused_by(&placed);
+    let reserved = event(stock, 1, "StockReserved").caused_by(&placed);
+    // no id of its own, ends the cascade
+    let captured = Event {
+        id: payment,
+        version: 2,
+        event_type: "PaymentCaptured".to_string(),
+        ..Default::default()
+    }
+    .caused_by(&requested);
+    let confirmed = event(order, 2, "OrderConfirmed");
+    backend.append_event(&placed).unwrap();
+    backend
+        .append_events(&[requested.clone(), reserved])
+        .unwrap();
+    backend
+        .append_events(&[captured, confirmed.clone()])
+        .unwrap();
+
+    let tree = backend
+        .get_causation_tree(placed.event_id().unwrap())
+        .unwrap();
+    assert_eq!(tree.event.event_type, "OrderPlaced");
+    assert_eq!(
+        tree.events()
+            .iter()
+            .map(|e| e.event_type.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "OrderPlaced",
+            "PaymentRequested",
+            "PaymentCaptured",
+            "StockReserved"
+        ]
+    );
+    assert_eq!(tree.caused.len(), 2);
+    assert_eq!(tree.caused[0].caused.len(), 1);
+    let correlation = placed.event_id().unwrap().to_string();
+    assert!(tree
+        .events()
+        .iter()
+        .skip(1)
+        .all(|e| e.metadata[CORRELATION_ID] == correlation));
+
+    let branch = backend
+        .get_causation_tree(requested.event_id().unwrap())
+        .unwrap();
+    assert_eq!(branch.events().len(), 2);
+    let leaf = backend
+        .get_causation_tree(confirmed.event_id().unwrap())
+        .unwrap();
+    assert!(leaf.caused.is_empty());
+    assert!(matches!(
+        backend.get_causation_tree(uuid::Uuid::new_v4()),
+        Err(eventstore::backend::sqlite::Error::NotFound)
+    ));
+}
+
+#[test_log::test]
+fn event_ids_are_derived_deterministically() {
+    use eventstore::ids::{event_id, EVENT_ID};
+
+    let _span = debug_span!("test-main-span").entered();
+    let aggregate_id = uuid::Uuid::new_v4();
+    let id = event_id(aggregate_id, 3, "billing");
+    assert_eq!(id, event_id(aggregate_id, 3, "billing"));
+    assert_eq!(id.get_version_num(), 5);
+    assert_ne!(id, event_id(aggregate_id, 4, "billing"));
+    assert_ne!(id, event_id(aggregate_id, 3, "shipping"));
+    assert_ne!(id, event_id(uuid::Uuid::new_v4(), 3, "billing"));
+
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let events: Vec<_> = (1..=3)
+        .map(|version| {
+            Event {
+                id: aggregate_id,
+                version,
+                data: b"{}".to_vec(),
+                ..Default::default()
+            }
+            .with_event_id("billing")
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+    // a replay of the upstream stream is recognized by the stored ids
+    let stored = backend.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(stored[2].event_id(), Some(id));
+    assert_eq!(stored[2].metadata[EVENT_ID], id.to_string());
+    assert!(stored
+        .iter()
+        .zip(&events)
+        .all(|(stored, replayed)| stored.event_id() == replayed.event_id()));
+    assert_eq!(Event::default().event_id(), None);
+}
+
+#[test_log::test]
+fn sync_engine_exchanges_events_between_replicas() {
+    use eventstore::sync::{Replica, SyncEngine, SyncReport, SYNC_ORIGIN};
+
+    let _span = debug_span!("test-main-span").entered();
+    let device = SqliteBackend::new(SqliteConnectionManager::memory());
+    let other_device = SqliteBackend::new(SqliteConnectionManager::memory());
+    let server = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (d1, d2, s) = (
+        Replica::new(&device, "device-1"),
+        Replica::new(&other_device, "device-2"),
+        Replica::new(&server, "server"),
+    );
+    let engine = SyncEngine::new().with_batch_size(2);
+    let order = uuid::Uuid::new_v4();
+    let catalog = uuid::Uuid::new_v4();
+    let event = |id, version| Event {
+        id,
+        version,
+        data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+        ..Default::default()
+    };
+    let versions = |store: &SqliteBackend, id| {
+        store
+            .get_aggretate(id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>()
+    };
+
+    // both sides appended while disconnected
+    device
+        .append_events(&[event(order, 1), event(order, 2)])
+        .unwrap();
+    server.append_event(&event(catalog, 1)).unwrap();
+    assert_eq!(
+        engine.sync(d1, s).unwrap(),
+        SyncReport {
+            pushed: 2,
+            pulled: 1
+        }
+    );
+    assert_eq!(engine.sync(d1, s).unwrap(), SyncReport::default());
+    assert_eq!(versions(&server, order), vec![1, 2]);
+    assert_eq!(
+        server.get_aggretate(order).unwrap()[0].metadata[SYNC_ORIGIN],
+        "device-1"
+    );
+
+    // events reach other devices through the server
+    assert_eq!(engine.sync(d2, s).unwrap().pulled, 3);
+    other_device.append_event(&event(order, 3)).unwrap();
+    assert_eq!(engine.sync(d2, s).unwrap().pushed, 1);
+    assert_eq!(engine.sync(d1, s).unwrap().pulled, 1);
+    assert_eq!(versions(&device, order), vec![1, 2, 3]);
+    // the appended event is the fourth in the log of device-2
+    assert_eq!(d1.seen("device-2").unwrap(), 4);
+    // the vectors already cover everything the devices hold
+    assert_eq!(engine.sync(d1, d2).unwrap(), SyncReport::default());
+
+    // divergent writes fail the sync
+    device.append_event(&event(catalog, 2)).unwrap();
+    server.append_event(&event(catalog, 2)).unwrap();
+    assert!(engine.sync(d1, s).is_err());
+}
+
+#[test_log::test]
+fn sync_conflict_strategies_resolve_divergent_streams() {
+    use eventstore::sync::{
+        ConflictStrategy, Replica, Resolution, SyncEngine, SyncReport, ORIGINAL_VERSION,
+        SYNC_RESOLVED,
+    };
+    use std::sync::Arc;
+
+    let _span = debug_span!("test-main-span").entered();
+    let device = SqliteBackend::new(SqliteConnectionManager::memory());
+    let server = SqliteBackend::new(SqliteConnectionManager::memory());
+    let (d, s) = (
+        Replica::new(&device, "device"),
+        Replica::new(&server, "server"),
+    );
+    let event = |id, version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: b"{}".to_vec(),
+        ..Default::default()
+    };
+    let types = |store: &SqliteBackend, id| {
+        store
+            .get_aggretate(id)
+            .unwrap()
+            .iter()
+            .map(|e| (e.version, e.event_type.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    let rebased = uuid::Uuid::new_v4();
+    device.append_event(&event(rebased, 1, "Opened")).unwrap();
+    device.append_event(&event(rebased, 2, "Renamed")).unwrap();
+    server.append_event(&event(rebased, 1, "Opened")).unwrap();
+    assert!(SyncEngine::new().sync(d, s).is_err());
+    let engine = SyncEngine::new().with_conflict_strategy(ConflictStrategy::Rebase);
+    let report = engine.sync(d, s).unwrap();
+    assert_eq!(
+        report,
+        SyncReport {
+            pushed: 2,
+            pulled: 1
+        }
+    );
+    let stream = server.get_aggretate(rebased).unwrap();
+    assert_eq!(stream.len(), 3);
+    assert_eq!(stream[1].metadata[ORIGINAL_VERSION], "1");
+    assert_eq!(types(&device, rebased).len(), 3);
+    assert_eq!(engine.sync(d, s).unwrap(), SyncReport::default());
+
+    // drop an event equal to one of the server, merge different ones
+    let engine = SyncEngine::new().with_conflict_strategy(ConflictStrategy::Merge(Arc::new(
+        |incoming: &Event, local: &[Event]| {
+            if local.iter().any(|e| e.event_type == incoming.event_type) {
+                return Ok(Resolution::Discard);
+            }
+            Ok(Resolution::Append(vec![Event {
+                event_type: format!("{}+{}", local[0].event_type, incoming.event_type),
+                data: b"{}".to_vec(),
+                ..Default::default()
+            }]))
+        },
+    )));
+    let discarded = uuid::Uuid::new_v4();
+    device.append_event(&event(discarded, 1, "Priced")).unwrap();
+    server.append_event(&event(discarded, 1, "Priced")).unwrap();
+    assert_eq!(
+        engine.sync(d, s).unwrap(),
+        SyncReport {
+            pushed: 0,
+            pulled: 1
+        }
+    );
+    assert_eq!(types(&server, discarded), vec![(1, "Priced".to_string())]);
+    // the device can not drop its own event, it takes the one of the server
+    assert_eq!(types(&device, discarded).len(), 2);
+
+    let merged = uuid::Uuid::new_v4();
+    device.append_event(&event(merged, 1, "Opened")).unwrap();
+    engine.sync(d, s).unwrap();
+    device
+        .append_event(&event(merged, 2, "Discounted"))
+        .unwrap();
+    server.append_event(&event(merged, 2, "Stocked")).unwrap();
+    engine.sync(d, s).unwrap();
+    let stream = server.get_aggretate(merged).unwrap();
+    assert_eq!(stream[2].event_type, "Stocked+Discounted");
+    assert!(stream[2].metadata[SYNC_RESOLVED].starts_with("device:"));
+    // the device adopts the resolution of the server
+    assert_eq!(
+        types(&device, merged),
+        vec![
+            (1, "Opened".to_string()),
+            (2, "Discounted".to_string()),
+            (3, "Stocked".to_string()),
+            (4, "Stocked+Discounted".to_string())
+        ]
+    );
+    assert_eq!(engine.sync(d, s).unwrap(), SyncReport::default());
+}
+
+#[test_log::test]
+fn current_position_is_the_committed_watermark() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    assert_eq!(backend.current_position().unwrap(), 0);
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id: aggregate_id,
+        version,
+        event_type: "Counted".to_string(),
+        ..Default::default()
+    };
+    backend.append_events(&[event(1), event(2)]).unwrap();
+    let positions: Vec<u64> = backend
+        .read_all(0, 10)
+        .unwrap()
+        .iter()
+        .map(|e| e.position)
+        .collect();
+    assert_eq!(backend.current_position().unwrap(), positions[1]);
+
+    // A rejected append does not move the watermark.
+    assert!(backend.append_events(&[event(2)]).is_err());
+    assert_eq!(backend.current_position().unwrap(), positions[1]);
+
+    // Each tenant has its own watermark.
+    let tenant = backend.tenant("acme");
+    assert_eq!(tenant.current_position().unwrap(), 0);
+    tenant.append_events(&[event(1)]).unwrap();
+    assert!(tenant.current_position().unwrap() > positions[1]);
+    assert_eq!(backend.current_position().unwrap(), positions[1]);
+}
+
+#[test_log::test]
+fn scheduled_events_are_appended_when_due() {
+    use eventstore::clock::ManualClock;
+    use eventstore::schedule::Dispatcher;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let clock = ManualClock::new(start);
+    // the dispatcher thread needs the same database as the test
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend =
+        SqliteBackend::new(SqliteConnectionManager::file(&path)).with_clock(clock.clone());
+    let trial = uuid::Uuid::new_v4();
+    let payment = uuid::Uuid::new_v4();
+    let event = |id, event_type: &str| Event {
+        id,
+        event_type: event_type.to_string(),
+        metadata: [("reason".to_string(), "timer".to_string())].into(),
+        ..Default::default()
+    };
+    backend
+        .append_event(&Event {
+            version: 1,
+            ..event(trial, "TrialStarted")
+        })
+        .unwrap();
+    let expiry = backend
+        .schedule_event(
+            &event(trial, "TrialExpired"),
+            start + Duration::from_secs(14 * 86_400),
+        )
+        .unwrap();
+    let retry = backend
+        .schedule_event(
+            &event(payment, "PaymentRetried"),
+            start + Duration::from_secs(3600),
+        )
+        .unwrap();
+    let cancelled = backend
+        .schedule_event(
+            &event(payment, "PaymentRetried"),
+            start + Duration::from_secs(7200),
+        )
+        .unwrap();
+    assert_eq!(
+        backend
+            .scheduled_events()
+            .unwrap()
+            .iter()
+            .map(|s| s.id)
+            .collect::<Vec<_>>(),
+        vec![retry, cancelled, expiry]
+    );
+    assert_eq!(backend.dispatch_due().unwrap(), 0);
+
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(backend.dispatch_due().unwrap(), 1);
+    let retried = backend.get_aggretate(payment).unwrap();
+    assert_eq!(retried.len(), 1);
+    assert_eq!(retried[0].event_type, "PaymentRetried");
+    assert_eq!(retried[0].metadata["reason"], "timer");
+    backend.cancel_scheduled(cancelled).unwrap();
+    assert!(matches!(
+        backend.cancel_scheduled(cancelled),
+        Err(eventstore::backend::sqlite::Error::NotFound)
+    ));
+
+    // Delivered after the events appended in the meantime.
+    backend
+        .append_event(&Event {
+            version: 2,
+            ..event(trial, "TrialExtended")
+        })
+        .unwrap();
+    clock.advance(Duration::from_secs(14 * 86_400));
+    let dispatcher = Dispatcher::new(backend.clone())
+        .with_interval(Duration::from_millis(10))
+        .spawn();
+    let deadline = SystemTime::now() + Duration::from_secs(10);
+    while backend.stream_version(trial).unwrap() < 3 && SystemTime::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    dispatcher.stop().unwrap();
+    let trial_events = backend.get_aggretate(trial).unwrap();
+    assert_eq!(
+        trial_events
+            .iter()
+            .map(|e| (e.version, e.event_type.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, "TrialStarted"),
+            (2, "TrialExtended"),
+            (3, "TrialExpired")
+        ]
+    );
+    assert!(backend.scheduled_events().unwrap().is_empty());
+    assert_eq!(backend.get_aggretate(payment).unwrap().len(), 1);
+}
+
+#[test_log::test]
+fn failed_scheduled_events_do_not_block_the_schedule() {
+    use eventstore::authorization::{CallerContext, Operation};
+    use std::time::SystemTime;
+
+    let _span = debug_span!("test-main-span").entered();
+    let denied = uuid::Uuid::new_v4();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_authorizer(
+        move |operation, stream, caller: &CallerContext| match (
+            operation,
+            stream,
+            caller.principal.as_deref(),
+        ) {
+            (_, _, Some("admin")) => Ok(()),
+            (Operation::Append, Some(stream), _) if stream == denied => {
+                Err("admins only".to_string())
+            }
+            _ => Ok(()),
+        },
+    );
+    let admin = backend.as_caller(CallerContext::default().with_principal("admin"));
+    let limited = backend.clone().with_max_event_size(4);
+    let event = |id, data: &[u8]| Event {
+        id,
+        event_type: "Reminded".to_string(),
+        data: data.to_vec(),
+        ..Default::default()
+    };
+    let (large, small) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    let forbidden = admin
+        .schedule_event(&event(denied, b"{}"), SystemTime::now())
+        .unwrap();
+    let failing = admin
+        .schedule_event(&event(large, b"too large"), SystemTime::now())
+        .unwrap();
+    admin
+        .schedule_event(&event(small, b"ok"), SystemTime::now())
+        .unwrap();
+
+    // the denied and the too large event are set aside, the last is delivered
+    assert_eq!(limited.dispatch_due().unwrap(), 1);
+    assert_eq!(limited.dispatch_due().unwrap(), 0);
+    let scheduled = admin.scheduled_events().unwrap();
+    assert_eq!(
+        scheduled.iter().map(|s| s.id).collect::<Vec<_>>(),
+        [forbidden, failing]
+    );
+    assert!(scheduled[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("admins only"));
+    assert!(scheduled[1].error.as_deref().unwrap().contains("too large"));
+    assert_eq!(admin.stream_version(small).unwrap(), 1);
+
+    admin.retry_scheduled(forbidden).unwrap();
+    admin.retry_scheduled(failing).unwrap();
+    assert_eq!(admin.dispatch_due().unwrap(), 2);
+    assert_eq!(admin.stream_version(denied).unwrap(), 1);
+    assert_eq!(admin.get_aggretate(large).unwrap()[0].data, b"too large");
+    assert!(admin.scheduled_events().unwrap().is_empty());
+}
+
+#[cfg(feature = "encryption")]
+#[test_log::test]
+fn scheduled_payloads_are_encrypted_at_rest() {
+    use eventstore::encryption::{keys::StaticKeyProvider, AesGcmEncryptor};
+    use std::time::SystemTime;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path))
+        .with_encryptor(AesGcmEncryptor::new(StaticKeyProvider::new("k1", [1; 32])));
+    let aggregate_id = uuid::Uuid::new_v4();
+    backend
+        .schedule_event(
+            &Event {
+                id: aggregate_id,
+                data: br#"{"secret":"due"}"#.to_vec(),
+                ..Default::default()
+            },
+            SystemTime::now(),
+        )
+        .unwrap();
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let (data, key_id, id_type): (Vec<u8>, String, String) = conn
+        .query_row(
+            "SELECT data, key_id, typeof(aggregate_id) FROM scheduled_events",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert!(!String::from_utf8_lossy(&data).contains("secret"));
+    assert_eq!((key_id.as_str(), id_type.as_str()), ("k1", "blob"));
+    assert_eq!(
+        backend.scheduled_events().unwrap()[0].event.data,
+        br#"{"secret":"due"}"#
+    );
+
+    assert_eq!(backend.dispatch_due().unwrap(), 1);
+    assert_eq!(
+        backend.get_aggretate(aggregate_id).unwrap()[0].data,
+        br#"{"secret":"due"}"#
+    );
+}
+
+#[test_log::test]
+fn events_with_ttl_expire_from_reads_and_scavenge() {
+    use eventstore::clock::ManualClock;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_clock(clock.clone());
+    let id = uuid::Uuid::new_v4();
+    let event = |version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[
+            event(1, "DeviceRegistered"),
+            event(2, "Heartbeat").with_ttl(Duration::from_secs(60)),
+        ])
+        .unwrap();
+    backend
+        .append_event(&event(3, "Heartbeat").with_ttl(Duration::from_secs(120)))
+        .unwrap();
+    backend.append_event(&event(4, "DeviceRenamed")).unwrap();
+    let versions = || {
+        backend
+            .get_aggretate(id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(), vec![1, 2, 3, 4]);
+    assert_eq!(
+        backend.get_aggretate(id).unwrap()[1].ttl(),
+        Some(Duration::from_secs(60))
+    );
+    // handles with other clocks share the pooled connections
+    let later = backend.clone().with_clock(ManualClock::new(
+        UNIX_EPOCH + Duration::from_secs(1_700_000_120),
+    ));
+    for _ in 0..3 {
+        assert_eq!(later.get_aggretate(id).unwrap().len(), 2);
+        assert_eq!(versions(), vec![1, 2, 3, 4]);
+    }
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(versions(), vec![1, 3, 4]);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(versions(), vec![1, 4]);
+    assert_eq!(backend.read_all(0, 10).unwrap().len(), 2);
+    // the stream keeps counting past expired events
+    assert_eq!(backend.stream_version(id).unwrap(), 4);
+
+    let report = backend.maintenance().scavenge().unwrap();
+    assert_eq!(report.deleted_events, 2);
+    assert_eq!(versions(), vec![1, 4]);
+
+    let invalid = Event {
+        metadata: [(
+            eventstore::backend::model::TTL.to_string(),
+            "soon".to_string(),
+        )]
+        .into(),
+        ..event(5, "Heartbeat")
+    };
+    assert!(backend.append_event(&invalid).is_err());
+}
+
+#[test_log::test]
+fn manual_clock_drives_retention_without_sleeping() {
+    use eventstore::clock::{Clock, ManualClock};
+    use eventstore::stream::StreamMetadata;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    let backend =
+        SqliteBackend::new(SqliteConnectionManager::file(&path)).with_clock(clock.clone());
+    let id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id,
+        version,
+        data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+        ..Default::default()
+    };
+    backend
+        .set_stream_metadata(
+            id,
+            &StreamMetadata::default().with_max_age(Duration::from_secs(3600)),
+        )
+        .unwrap();
+    backend.append_events(&[event(1), event(2)]).unwrap();
+    clock.advance(Duration::from_secs(1800));
+    backend.append_event(&event(3)).unwrap();
+
+    let versions = || {
+        backend
+            .get_aggretate(id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(), vec![1, 2, 3]);
+    clock.advance(Duration::from_secs(1800));
+    assert_eq!(versions(), vec![3]);
+    assert_eq!(backend.read_all(0, 10).unwrap().len(), 1);
+    // other handles and pooled connections follow the same clock
+    assert_eq!(backend.tenant("").get_aggretate(id).unwrap().len(), 1);
+    clock.advance(Duration::from_secs(1800));
+    assert!(versions().is_empty());
+
+    backend.delete_stream(id).unwrap();
+    let log = backend.admin_log(0, 10).unwrap();
+    assert_eq!(log.last().unwrap().recorded_at, clock.now_millis());
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn delete_stream_removes_events_snapshots_and_index() {
+    use eventstore::stream::DeleteMode;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let event = |id, version| Event {
+        id,
+        version,
+        data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+        ..Default::default()
+    };
+    let reused = uuid::Uuid::new_v4();
+    let gone = uuid::Uuid::new_v4();
+    backend
+        .append_events(&[event(reused, 1), event(reused, 2), event(gone, 1)])
+        .unwrap();
+    backend.save_snapshot(&event(reused, 2)).unwrap();
+
+    backend.delete_stream(reused).unwrap();
+    assert!(backend.get_aggretate(reused).unwrap().is_empty());
+    assert!(backend.get_snapshots(reused).unwrap().is_empty());
+    assert_eq!(backend.read_all(0, 10).unwrap().len(), 1);
+    backend.append_event(&event(reused, 1)).unwrap();
+    assert_eq!(backend.get_aggretate(reused).unwrap().len(), 1);
+
+    backend
+        .delete_stream_with(gone, DeleteMode::Forbid)
+        .unwrap();
+    assert!(matches!(
+        backend.append_event(&event(gone, 1)),
+        Err(Error::StreamDeleted(id)) if id == gone
+    ));
+    assert!(backend.maintenance().integrity_check().unwrap().is_ok());
+}
+
+#[test_log::test]
+fn tombstoned_streams_reject_appends_and_keep_history() {
+    use eventstore::stream::TOMBSTONE;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id: aggregate_id,
+        version,
+        data: br#"{}"#.to_vec(),
+        ..Default::default()
+    };
+    backend.append_events(&[event(1), event(2)]).unwrap();
+    backend.tombstone_stream(aggregate_id).unwrap();
+
+    assert!(matches!(
+        backend.append_event(&event(4)),
+        Err(Error::StreamDeleted(id)) if id == aggregate_id
+    ));
+    assert!(matches!(
+        backend.get_aggretate(aggregate_id),
+        Err(Error::StreamDeleted(_))
+    ));
+    assert!(backend.tombstone_stream(aggregate_id).is_err());
+    let history = backend
+        .get_aggretate_including_tombstoned(aggregate_id)
+        .unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|e| (e.version, e.event_type.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(1, ""), (2, ""), (3, TOMBSTONE)]
+    );
+    assert_eq!(backend.read_all(0, 10).unwrap().len(), 3);
+}
+
+#[test_log::test]
+fn truncate_before_hides_events_below_version() {
+    use eventstore::stream::StreamMetadata;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let events: Vec<Event> = (1..=4)
+        .map(|version| Event {
+            id: aggregate_id,
+            version,
+            data: br#"{}"#.to_vec(),
+            ..Default::default()
+        })
+        .collect();
+    backend.append_events(&events).unwrap();
+    backend
+        .set_stream_metadata(aggregate_id, &StreamMetadata::default().with_max_count(10))
+        .unwrap();
+    backend.truncate_before(aggregate_id, 3).unwrap();
+
+    assert_eq!(
+        backend.get_stream_metadata(aggregate_id).unwrap(),
+        StreamMetadata::default()
+            .with_max_count(10)
+            .with_truncate_before(3)
+    );
+    assert_eq!(
+        backend
+            .get_aggretate(aggregate_id)
+            .unwrap()
+            .iter()
+            .map(|e| e.version)
+            .collect::<Vec<_>>(),
+        vec![3, 4]
+    );
+    assert_eq!(backend.maintenance().scavenge().unwrap().deleted_events, 2);
+    assert_eq!(backend.read_all(0, 10).unwrap().len(), 2);
+}
+
+#[test_log::test]
+fn scavenge_purges_tombstoned_streams_in_batches() {
+    use eventstore::backend::sqlite::ScavengeOpts;
+    use eventstore::stream::{StreamMetadata, TOMBSTONE};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let closed = uuid::Uuid::new_v4();
+    let capped = uuid::Uuid::new_v4();
+    let event = |id, version| Event {
+        id,
+        version,
+        data: vec![b'x'; 4096],
+        content_type: "application/octet-stream".to_string(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&(1..=5).map(|v| event(closed, v)).collect::<Vec<_>>())
+        .unwrap();
+    backend.save_snapshot(&event(closed, 5)).unwrap();
+    backend.tombstone_stream(closed).unwrap();
+    backend
+        .set_stream_metadata(capped, &StreamMetadata::default().with_max_count(1))
+        .unwrap();
+    backend
+        .append_events(&(1..=3).map(|v| event(capped, v)).collect::<Vec<_>>())
+        .unwrap();
+
+    let report = backend.maintenance().scavenge().unwrap();
+    assert_eq!((report.deleted_events, report.deleted_snapshots), (2, 1));
+    assert_eq!(
+        backend
+            .get_aggretate_including_tombstoned(closed)
+            .unwrap()
+            .len(),
+        6
+    );
+
+    let report = backend
+        .maintenance()
+        .scavenge_with(&ScavengeOpts {
+            batch_size: 2,
+            purge_tombstoned: true,
+        })
+        .unwrap();
+    assert_eq!(report.deleted_events, 5);
+    assert!(report.reclaimed_bytes >= 5 * 4096);
+    let remaining = backend.get_aggretate_including_tombstoned(closed).unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].event_type, TOMBSTONE);
+
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn read_only_backend_rejects_writes() {
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let writer = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id: aggregate_id,
+        version,
+        ..Default::default()
+    };
+    writer.append_event(&event(1)).unwrap();
+
+    let reader = SqliteBackend::read_only(&path).unwrap();
+    assert_eq!(reader.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert!(matches!(
+        reader.append_event(&event(2)),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        reader.save_snapshot(&event(1)),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        reader.delete_stream(aggregate_id),
+        Err(Error::ReadOnly)
+    ));
+    assert!(matches!(
+        reader.maintenance().vacuum(),
+        Err(Error::ReadOnly)
+    ));
+    assert!(reader.maintenance().integrity_check().unwrap().is_ok());
+
+    writer.append_event(&event(2)).unwrap();
+    assert_eq!(reader.get_aggretate(aggregate_id).unwrap().len(), 2);
+
+    drop(reader);
+    drop(writer);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn tenants_are_isolated() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let acme = backend.tenant("acme");
+    let globex = backend.tenant("globex");
+    assert_eq!(acme.tenant_id(), "acme");
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version| Event {
+        id: aggregate_id,
+        version,
+        ..Default::default()
+    };
+    acme.append_events(&[event(1), event(2)]).unwrap();
+    globex.append_event(&event(1)).unwrap();
+    assert!(globex.append_event(&event(3)).is_err());
+
+    assert_eq!(acme.get_aggretate(aggregate_id).unwrap().len(), 2);
+    assert_eq!(globex.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert!(backend.get_aggretate(aggregate_id).unwrap().is_empty());
+    assert_eq!(acme.read_all(0, 10).unwrap().len(), 2);
+    assert_eq!(globex.read_all(0, 10).unwrap().len(), 1);
+    assert!(backend.read_all(0, 10).unwrap().is_empty());
+
+    acme.save_checkpoint("report", 2).unwrap();
+    assert_eq!(acme.get_checkpoint("report").unwrap(), 2);
+    assert_eq!(globex.get_checkpoint("report").unwrap(), 0);
+
+    acme.delete_stream(aggregate_id).unwrap();
+    assert!(acme.get_aggretate(aggregate_id).unwrap().is_empty());
+    assert_eq!(globex.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert!(backend.maintenance().integrity_check().unwrap().is_ok());
+}
+
+#[test_log::test]
+fn tenant_manager_opens_one_database_per_tenant() {
+    use eventstore::tenant::TenantManager;
+
+    let _span = debug_span!("test-main-span").entered();
+    let dir = std::env::temp_dir().join(format!("eventstore-tenants-{}", uuid::Uuid::new_v4()));
+    let manager = TenantManager::new(&dir)
+        .with_max_open(1)
+        .with_template(|backend| backend.with_max_event_size(16));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = Event {
+        id: aggregate_id,
+        version: 1,
+        ..Default::default()
+    };
+    manager.get("acme").unwrap().append_event(&event).unwrap();
+    manager.get("globex").unwrap().append_event(&event).unwrap();
+    // acme was evicted and is opened again from its file
+    let acme = manager.get("acme").unwrap();
+    assert_eq!(acme.get_aggretate(aggregate_id).unwrap().len(), 1);
+    let large = Event {
+        version: 2,
+        data: vec![b' '; 32],
+        ..event.clone()
+    };
+    assert!(matches!(
+        acme.append_event(&large),
+        Err(Error::PayloadTooLarge { .. })
+    ));
+    assert_eq!(manager.tenants().unwrap(), vec!["acme", "globex"]);
+    assert!(manager.get("../escape").is_err());
+
+    drop(acme);
+    manager.delete_tenant("acme").unwrap();
+    assert_eq!(manager.tenants().unwrap(), vec!["globex"]);
+    manager.delete_tenant("globex").unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test_log::test]
+fn tenant_manager_applies_connection_options() {
+    use eventstore::backend::sqlite::{ConnectionOptions, Durability};
+    use eventstore::tenant::TenantManager;
+
+    let _span = debug_span!("test-main-span").entered();
+    let dir = std::env::temp_dir().join(format!("eventstore-tenants-{}", uuid::Uuid::new_v4()));
+    let manager = TenantManager::new_with_options(
+        &dir,
+        ConnectionOptions::new().with_durability(Durability::Fast),
+    );
+    // concurrent first uses share one backend
+    let opened: Vec<_> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| manager.get("acme").unwrap()))
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect()
+    });
+    let aggregate_id = uuid::Uuid::new_v4();
+    opened[0]
+        .append_event(&Event {
+            id: aggregate_id,
+            version: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(opened
+        .iter()
+        .all(|backend| backend.stream_version(aggregate_id).unwrap() == 1));
+    let journal_mode: String = rusqlite::Connection::open(manager.path("acme").unwrap())
+        .unwrap()
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+
+    drop(opened);
+    manager.delete_tenant("acme").unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test_log::test]
+fn authorizer_denies_operations_per_caller() {
+    use eventstore::authorization::{CallerContext, Operation};
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory()).with_authorizer(
+        |operation, _stream, caller: &CallerContext| match (operation, caller.principal.as_deref())
+        {
+            (Operation::Read, Some(_)) | (_, Some("admin")) => Ok(()),
+            (_, Some(principal)) => Err(format!("{} may only read", principal)),
+            (_, None) => Err("anonymous".to_string()),
+        },
+    );
+    let admin = backend.as_caller(CallerContext::default().with_principal("admin"));
+    let reader = backend.as_caller(
+        CallerContext::default()
+            .with_principal("alice")
+            .with_attribute("team", "reporting"),
+    );
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = Event {
+        id: aggregate_id,
+        version: 1,
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        backend.get_aggretate(aggregate_id),
+        Err(Error::Unauthorized(reason)) if reason == "anonymous"
+    ));
+    assert!(matches!(
+        reader.append_event(&event),
+        Err(Error::Unauthorized(reason)) if reason == "alice may only read"
+    ));
+    admin.append_event(&event).unwrap();
+    assert_eq!(reader.get_aggretate(aggregate_id).unwrap().len(), 1);
+    assert!(matches!(
+        reader.delete_stream(aggregate_id),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reader.maintenance().vacuum(),
+        Err(Error::Unauthorized(_))
+    ));
+    assert_eq!(reader.current_position().unwrap(), 1);
+    assert!(matches!(
+        backend.current_position(),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        backend.get_checkpoint("projection"),
+        Err(Error::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reader.save_checkpoint("projection", 1),
+        Err(Error::Unauthorized(_))
+    ));
+    admin.save_checkpoint("projection", 1).unwrap();
+    assert_eq!(reader.get_checkpoint("projection").unwrap(), 1);
+}
+
+#[test_log::test]
+fn admin_log_records_destructive_operations() {
+    use eventstore::authorization::CallerContext;
+    use eventstore::jsonl::{ExportOpts, ImportOpts};
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path))
+        .as_caller(CallerContext::default().with_principal("ops"));
+    let deleted = uuid::Uuid::new_v4();
+    let copied = uuid::Uuid::new_v4();
+    backend
+        .append_event(&Event {
+            id: deleted,
+            version: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    let mut export = Vec::new();
+    backend
+        .export_jsonl(&mut export, &ExportOpts::default())
+        .unwrap();
+    backend
+        .import_jsonl(
+            export.as_slice(),
+            &ImportOpts::default().remap(deleted, copied),
+        )
+        .unwrap();
+    backend.delete_stream(deleted).unwrap();
+    backend.maintenance().scavenge().unwrap();
+
+    let log = backend.admin_log(0, 10).unwrap();
+    let operations: Vec<_> = log.iter().map(|entry| entry.operation.as_str()).collect();
+    assert_eq!(
+        operations,
+        vec!["import_jsonl", "delete_stream", "scavenge"]
+    );
+    assert!(log
+        .iter()
+        .all(|entry| entry.principal.as_deref() == Some("ops")));
+    assert_eq!(log[0].details["imported"], 1);
+    assert_eq!(log[1].aggregate_id, Some(deleted));
+    assert_eq!(backend.admin_log(log[1].id, 10).unwrap().len(), 1);
+    assert!(backend.tenant("other").admin_log(0, 10).unwrap().is_empty());
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    assert!(conn.execute("DELETE FROM admin_log", []).is_err());
+    assert!(conn
+        .execute("UPDATE admin_log SET principal = 'someone else'", [])
+        .is_err());
+    drop(conn);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[derive(Default)]
+struct RejectsPoison {
+    handled: std::sync::Mutex<Vec<u32>>,
+}
+
+impl eventstore::projection::Projection for RejectsPoison {
+    fn name(&self) -> &str {
+        "rejects-poison"
+    }
+
+    fn handle(&self, event: &Event) -> Result<(), Error> {
+        if event.data == b"poison" {
+            return Err(Error::WithMsg("can not parse payload".to_string()));
+        }
+        self.handled.lock().unwrap().push(event.version);
+        Ok(())
+    }
+}
+
+#[test_log::test]
+fn failing_projection_events_are_quarantined_and_replayed() {
+    use eventstore::projection::ProjectionRunner;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version, data: &[u8]| Event {
+        id: aggregate_id,
+        version,
+        data: data.to_vec(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[event(1, b"{}"), event(2, b"poison"), event(3, b"{}")])
+        .unwrap();
+
+    let projection = std::sync::Arc::new(RejectsPoison::default());
+    let runner = ProjectionRunner::new(backend.clone(), projection.clone()).with_quarantine(3);
+    assert_eq!(runner.run_until_caught_up().unwrap(), 3);
+    assert_eq!(*projection.handled.lock().unwrap(), vec![1, 3]);
+    let quarantined = backend.quarantined("rejects-poison").unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].event.version, 2);
+    assert_eq!(quarantined[0].attempts, 3);
+    assert!(quarantined[0].error.contains("can not parse payload"));
+
+    assert_eq!(runner.replay_quarantined().unwrap(), 0);
+    assert_eq!(
+        backend.quarantined("rejects-poison").unwrap()[0].attempts,
+        4
+    );
+
+    let position = quarantined[0].event.position;
+    backend
+        .fix_quarantined("rejects-poison", position, b"{}".to_vec())
+        .unwrap();
+    assert!(backend.quarantined("rejects-poison").unwrap()[0].fixed);
+    assert_eq!(runner.replay_quarantined().unwrap(), 1);
+    assert_eq!(*projection.handled.lock().unwrap(), vec![1, 3, 2]);
+    assert!(backend.quarantined("rejects-poison").unwrap().is_empty());
+    assert_eq!(
+        backend.get_aggretate(aggregate_id).unwrap()[1].data,
+        b"poison"
+    );
+    assert!(matches!(
+        backend.discard_quarantined("rejects-poison", position),
+        Err(Error::NotFound)
+    ));
+
+    drop(runner);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test_log::test]
+fn list_streams_pages_and_stats_count_rows() {
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let mut ids: Vec<_> = (0..3).map(|_| uuid::Uuid::new_v4()).collect();
+    ids.sort();
+    for (i, id) in ids.iter().enumerate() {
+        for version in 1..=i as u32 + 1 {
+            backend
+                .append_event(&Event {
+                    id: *id,
+                    version,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    let page = backend.list_streams(None, 2).unwrap();
+    assert_eq!(
+        page.iter()
+            .map(|s| (s.aggregate_id, s.version))
+            .collect::<Vec<_>>(),
+        vec![(ids[0], 1), (ids[1], 2)]
+    );
+    let rest = backend.list_streams(Some(ids[1]), 2).unwrap();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].version, 3);
+    assert!(backend
+        .tenant("other")
+        .list_streams(None, 10)
+        .unwrap()
+        .is_empty());
+    assert_eq!(backend.stream_version(ids[2]).unwrap(), 3);
+    assert_eq!(backend.stream_version(uuid::Uuid::new_v4()).unwrap(), 0);
+
+    let stats = backend.maintenance().stats().unwrap();
+    assert_eq!(stats.events, 6);
+    assert_eq!(stats.streams, 3);
+    assert_eq!(stats.snapshots, 0);
+    assert_eq!(stats.head_position, 6);
+    assert!(stats.size_bytes > 0);
+    assert_eq!(stats.schema_version, backend.schema_version().unwrap());
+}
+
+#[cfg(feature = "cli")]
+#[test_log::test]
+fn cli_appends_reads_and_verifies() {
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_eventstore"))
+            .arg("--db")
+            .arg(&path)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let aggregate_id = uuid::Uuid::new_v4().to_string();
+
+    let out = run(&["append", &aggregate_id, "Deposited", r#"{"amount":5}"#]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "1");
+    let out = run(&[
+        "append",
+        &aggregate_id,
+        "Deposited",
+        "{}",
+        "--expected-version",
+        "0",
+    ]);
+    assert!(!out.status.success());
+
+    let out = run(&["read", &aggregate_id]);
+    assert!(out.status.success(), "{:?}", out);
+    let envelope: eventstore::jsonl::Envelope = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(envelope.event_type, "Deposited");
+    assert_eq!(envelope.data, Some(serde_json::json!({"amount": 5})));
+
+    let out = run(&["list-streams"]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim(),
+        format!("{}\t1", aggregate_id)
+    );
+    let out = run(&["stats"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("events\t1"));
+    let out = run(&["verify"]);
+    assert!(out.status.success(), "{:?}", out);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "tui")]
+#[test_log::test]
+fn tui_browser_pages_streams_and_tails_new_events() {
+    use eventstore::tui::Browser;
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use ratatui::Terminal;
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend = SqliteBackend::new(SqliteConnectionManager::memory());
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version, event_type: &str| Event {
+        id: aggregate_id,
+        version,
+        event_type: event_type.to_string(),
+        data: serde_json::to_vec(&serde_json::json!({ "amount": version })).unwrap(),
+        ..Default::default()
+    };
+    backend.append_event(&event(1, "Opened")).unwrap();
+    backend.append_event(&event(2, "Deposited")).unwrap();
+
+    let mut browser = Browser::new(backend.clone()).unwrap();
+    let mut terminal = Terminal::new(TestBackend::new(160, 30)).unwrap();
+    let mut screen = |browser: &mut Browser| {
+        terminal.draw(|frame| browser.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let press = |browser: &mut Browser, code| browser.handle_key(KeyEvent::from(code));
+
+    let text = screen(&mut browser);
+    assert!(text.contains("$all"));
+    assert!(text.contains(&aggregate_id.to_string()));
+    assert!(text.contains("Opened"));
+    assert!(text.contains("\"amount\": 1"));
+
+    press(&mut browser, KeyCode::Down);
+    assert_eq!(browser.selected_stream(), Some(aggregate_id));
+    press(&mut browser, KeyCode::Tab);
+    press(&mut browser, KeyCode::Down);
+    assert_eq!(browser.selected_event().unwrap().version, 2);
+    assert!(screen(&mut browser).contains("\"event_type\": \"Deposited\""));
+
+    press(&mut browser, KeyCode::Char('t'));
+    assert!(browser.is_tailing());
+    backend.append_event(&event(3, "Withdrawn")).unwrap();
+    browser.refresh();
+    assert_eq!(browser.selected_event().unwrap().version, 3);
+    assert!(screen(&mut browser).contains("[tail]"));
+
+    press(&mut browser, KeyCode::Char('q'));
+    assert!(browser.is_done());
+}
+
+#[cfg(feature = "grpc")]
+#[test_log::test]
+fn grpc_server_appends_reads_and_subscribes() {
+    use eventstore::grpc::proto::event_store_client::EventStoreClient;
+    use eventstore::grpc::proto::{
+        AppendRequest, EventData, ReadAllRequest, ReadStreamRequest, SubscribeRequest,
+    };
+    use eventstore::grpc::GrpcService;
+    use tokio_stream::StreamExt;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = GrpcService::new(backend.clone())
+            .with_poll_interval(std::time::Duration::from_millis(10));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let mut client = EventStoreClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let aggregate_id = uuid::Uuid::new_v4().to_string();
+        let data = |event_type: &str| EventData {
+            event_type: event_type.to_string(),
+            content_type: "application/json".to_string(),
+            data: b"{}".to_vec(),
+            ..Default::default()
+        };
+        let appended = client
+            .append(AppendRequest {
+                aggregate_id: aggregate_id.clone(),
+                expected_version: 0,
+                events: vec![data("Opened"), data("Renamed")],
+            })
+            .await
+            .unwrap();
+        assert_eq!(appended.into_inner().version, 2);
+        let conflict = client
+            .append(AppendRequest {
+                aggregate_id: aggregate_id.clone(),
+                expected_version: 0,
+                events: vec![data("Opened")],
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(conflict.code(), tonic::Code::FailedPrecondition);
+
+        let stream = client
+            .read_stream(ReadStreamRequest {
+                aggregate_id: aggregate_id.clone(),
+                since_version: 1,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.events.len(), 1);
+        assert_eq!(stream.events[0].event_type, "Renamed");
+        let all = client
+            .read_all(ReadAllRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(all.events.len(), 2);
+
+        let mut subscription = client
+            .subscribe(SubscribeRequest {
+                from_position: all.events[0].position,
+                aggregate_id: Some(aggregate_id.clone()),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let first = subscription.next().await.unwrap().unwrap();
+        assert_eq!(first.event_type, "Renamed");
+        client
+            .append(AppendRequest {
+                aggregate_id: aggregate_id.clone(),
+                expected_version: 2,
+                events: vec![data("Closed")],
+            })
+            .await
+            .unwrap();
+        let live = subscription.next().await.unwrap().unwrap();
+        assert_eq!((live.event_type.as_str(), live.version), ("Closed", 3));
+    });
+    drop(runtime);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "grpc")]
+#[test_log::test]
+fn follower_replicates_primary_and_reports_lag() {
+    use eventstore::grpc::replication::Follower;
+    use eventstore::grpc::GrpcService;
+    use std::time::Duration;
+
+    let _span = debug_span!("test-main-span").entered();
+    let primary = SqliteBackend::new(SqliteConnectionManager::memory());
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let replica = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event = |version: u32| Event {
+        id: aggregate_id,
+        version,
+        event_type: "Replicated".to_string(),
+        data: format!("{{\"n\":{}}}", version).into_bytes(),
+        metadata: [("origin".to_string(), "primary".to_string())].into(),
+        ..Default::default()
+    };
+    primary.append_events(&[event(1), event(2)]).unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service =
+            GrpcService::new(primary.clone()).with_poll_interval(Duration::from_millis(10));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let follower = Follower::new(replica.clone(), format!("http://{}", addr))
+            .with_lag_interval(Duration::from_millis(10))
+            .with_retry_interval(Duration::from_millis(10))
+            .spawn();
+        tokio::time::timeout(Duration::from_secs(10), follower.wait_for(2))
+            .await
+            .unwrap();
+
+        primary.append_events(&[event(3)]).unwrap();
+        let head = primary.read_all(0, 10).unwrap().last().unwrap().position;
+        tokio::time::timeout(Duration::from_secs(10), follower.wait_for(head))
+            .await
+            .unwrap();
+        let lag = follower.lag();
+        assert!(lag.connected);
+        assert_eq!(lag.applied_position, head);
+        assert_eq!(lag.events(), 0);
+        follower.stop();
+    });
+    drop(runtime);
+
+    let reader = SqliteBackend::read_only(&path).unwrap();
+    let replicated = reader.get_aggretate(aggregate_id).unwrap();
+    assert_eq!(
+        replicated
+            .iter()
+            .map(|e| (e.version, e.data.clone(), e.metadata["origin"].as_str()))
+            .collect::<Vec<_>>(),
+        (1..=3)
+            .map(|v| (v, format!("{{\"n\":{}}}", v).into_bytes(), "primary"))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(reader.get_checkpoint("replication:primary").unwrap(), 3);
+    assert!(matches!(
+        reader.append_events(&[event(4)]),
+        Err(eventstore::backend::sqlite::Error::ReadOnly)
+    ));
+
+    // A restarted follower resumes from its checkpoint.
+    primary.append_events(&[event(4)]).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(GrpcService::new(primary.clone()).into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let follower = Follower::new(replica.clone(), format!("http://{}", addr)).spawn();
+        tokio::time::timeout(Duration::from_secs(10), follower.wait_for(4))
+            .await
+            .unwrap();
+    });
+    drop(runtime);
+    assert_eq!(replica.stream_version(aggregate_id).unwrap(), 4);
+    assert_eq!(replica.read_all(0, 10).unwrap().len(), 4);
+    drop((reader, replica));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "grpc")]
+#[test_log::test]
+fn esdb_streams_service_appends_reads_and_subscribes() {
+    use eventstore::grpc::esdb::proto::event_store::client::streams::{
+        append_req, append_resp, read_req, read_resp, streams_client::StreamsClient, AppendReq,
+        ReadReq, ReadResp,
+    };
+    use eventstore::grpc::esdb::proto::event_store::client::{
+        uuid as proto_uuid, Empty, StreamIdentifier, Uuid as ProtoUuid,
+    };
+    use eventstore::grpc::esdb::{EsdbService, EVENT_ID_KEY};
+    use read_req::options::{
+        all_options::AllOption, stream_options::RevisionOption, AllOptions, CountOption,
+        ReadDirection, StreamOption, StreamOptions, SubscriptionOptions,
+    };
+    use tokio_stream::StreamExt;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let aggregate_id = uuid::Uuid::new_v4();
+    let event_id = uuid::Uuid::new_v4();
+    let identifier = || {
+        Some(StreamIdentifier {
+            stream_name: aggregate_id.to_string().into_bytes(),
+        })
+    };
+    let append = |expected, event_types: &[&str]| {
+        let options = append_req::Options {
+            stream_identifier: identifier(),
+            expected_stream_revision: Some(expected),
+        };
+        let mut messages = vec![AppendReq {
+            content: Some(append_req::Content::Options(options)),
+        }];
+        for event_type in event_types {
+            messages.push(AppendReq {
+                content: Some(append_req::Content::ProposedMessage(
+                    append_req::ProposedMessage {
+                        id: Some(ProtoUuid {
+                            value: Some(proto_uuid::Value::String(event_id.to_string())),
+                        }),
+                        metadata: [
+                            ("type".to_string(), event_type.to_string()),
+                            ("content-type".to_string(), "application/json".to_string()),
+                        ]
+                        .into(),
+                        custom_metadata: br#"{"source":"test"}"#.to_vec(),
+                        data: b"{}".to_vec(),
+                    },
+                )),
+            });
+        }
+        tokio_stream::iter(messages)
+    };
+    let read = |stream_option, direction: ReadDirection, count_option| ReadReq {
+        options: Some(read_req::Options {
+            stream_option: Some(stream_option),
+            read_direction: direction as i32,
+            count_option: Some(count_option),
+            ..Default::default()
+        }),
+    };
+    let event = |response: ReadResp| match response.content {
+        Some(read_resp::Content::Event(event)) => event.event.unwrap(),
+        other => panic!("expected an event, got {:?}", other),
+    };
+
+    async fn next(subscription: &mut tonic::Streaming<ReadResp>) -> read_resp::Content {
+        subscription.next().await.unwrap().unwrap().content.unwrap()
+    }
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = EsdbService::new(backend.clone())
+            .with_poll_interval(std::time::Duration::from_millis(10));
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let mut client = StreamsClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        use append_req::options::ExpectedStreamRevision;
+        let appended = client
+            .append(append(
+                ExpectedStreamRevision::NoStream(Empty {}),
+                &["Opened", "Renamed"],
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        match appended.result {
+            Some(append_resp::Result::Success(success)) => assert_eq!(
+                success.current_revision_option,
+                Some(append_resp::success::CurrentRevisionOption::CurrentRevision(1))
+            ),
+            other => panic!("append failed: {:?}", other),
+        }
+        let conflict = client
+            .append(append(ExpectedStreamRevision::Revision(0), &["Late"]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            conflict.result,
+            Some(append_resp::Result::WrongExpectedVersion(_))
+        ));
+        let stored = backend.get_aggretate(aggregate_id).unwrap();
+        assert_eq!(stored[0].metadata[EVENT_ID_KEY], event_id.to_string());
+        assert_eq!(stored[0].metadata["source"], "test");
+
+        let stream = StreamOption::Stream(StreamOptions {
+            stream_identifier: identifier(),
+            revision_option: Some(RevisionOption::End(Empty {})),
+        });
+        let mut responses = client
+            .read(read(
+                stream,
+                ReadDirection::Backwards,
+                CountOption::Count(10),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        let latest = event(responses.next().await.unwrap().unwrap());
+        assert_eq!(latest.stream_revision, 1);
+        assert_eq!(latest.metadata["type"], "Renamed");
+        assert_eq!(
+            std::str::from_utf8(&latest.custom_metadata).unwrap(),
+            r#"{"source":"test"}"#
+        );
+        assert_eq!(
+            event(responses.next().await.unwrap().unwrap()).stream_revision,
+            0
+        );
+        assert!(responses.next().await.is_none());
+
+        let missing = StreamOption::Stream(StreamOptions {
+            stream_identifier: Some(StreamIdentifier {
+                stream_name: uuid::Uuid::new_v4().to_string().into_bytes(),
+            }),
+            revision_option: Some(RevisionOption::Start(Empty {})),
+        });
+        let mut responses = client
+            .read(read(
+                missing,
+                ReadDirection::Forwards,
+                CountOption::Count(10),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            responses.next().await.unwrap().unwrap().content,
+            Some(read_resp::Content::StreamNotFound(_))
+        ));
+
+        let all = StreamOption::All(AllOptions {
+            all_option: Some(AllOption::Start(Empty {})),
+        });
+        let mut subscription = client
+            .read(read(
+                all,
+                ReadDirection::Forwards,
+                CountOption::Subscription(SubscriptionOptions {}),
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            next(&mut subscription).await,
+            read_resp::Content::Confirmation(_)
+        ));
+        for revision in 0..2 {
+            match next(&mut subscription).await {
+                read_resp::Content::Event(read) => {
+                    assert_eq!(read.event.unwrap().stream_revision, revision)
+                }
+                other => panic!("expected an event, got {:?}", other),
+            }
+        }
+        assert!(matches!(
+            next(&mut subscription).await,
+            read_resp::Content::CaughtUp(_)
+        ));
+        client
+            .append(append(ExpectedStreamRevision::Revision(1), &["Closed"]))
+            .await
+            .unwrap();
+        match next(&mut subscription).await {
+            read_resp::Content::Event(read) => {
+                let live = read.event.unwrap();
+                assert_eq!((live.stream_revision, live.commit_position), (2, 3));
+            }
+            other => panic!("expected an event, got {:?}", other),
+        }
+    });
+    drop(runtime);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "actix")]
+#[test_log::test]
+fn actix_streams_reads_and_subscriptions() {
+    use actix_web::body::MessageBody;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse, Responder};
+    use eventstore::actix::{self, EventStream, Store, StoreError, EVENT_STREAM, NDJSON};
+    use eventstore::jsonl::Envelope;
+
+    async fn append(store: Store, body: String) -> Result<HttpResponse, StoreError> {
+        let events: Vec<Event> = serde_json::from_str::<Vec<Envelope>>(&body)
+            .unwrap()
+            .into_iter()
+            .map(|envelope| envelope.into_event().unwrap())
+            .collect();
+        web::block(move || store.append_events(&events))
+            .await
+            .unwrap()?;
+        Ok(HttpResponse::Created().finish())
+    }
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let first = uuid::Uuid::new_v4();
+    let second = uuid::Uuid::new_v4();
+    let event = |id, version| Envelope {
+        position: 0,
+        aggregate_id: id,
+        version,
+        event_type: "Touched".to_string(),
+        schema_version: 1,
+        content_type: "application/json".to_string(),
+        data: Some(serde_json::json!({ "n": version })),
+        data_hex: None,
+        metadata: Default::default(),
+    };
+
+    actix_web::rt::System::new().block_on(async {
+        let app = test::init_service(
+            App::new()
+                .app_data(actix::app_data(backend.clone()))
+                .configure(actix::configure)
+                .route("/append", web::post().to(append)),
+        )
+        .await;
+        let post = |events: Vec<Envelope>| {
+            test::TestRequest::post()
+                .uri("/append")
+                .set_payload(serde_json::to_string(&events).unwrap())
+                .to_request()
+        };
+        let response = test::call_service(&app, post(vec![event(first, 1), event(first, 2)])).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = test::call_service(&app, post(vec![event(second, 1)])).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = test::call_service(&app, post(vec![event(first, 2)])).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert!(body["error"].is_string());
+
+        let lines = |body: actix_web::web::Bytes| {
+            std::str::from_utf8(&body)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<Envelope>(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let request = test::TestRequest::get()
+            .uri(&format!("/streams/{}?since_version=1", first))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.headers().get("content-type").unwrap(), NDJSON);
+        let events = lines(test::read_body(response).await);
+        assert_eq!(events.iter().map(|e| e.version).collect::<Vec<_>>(), [2]);
+
+        let request = test::TestRequest::get()
+            .uri("/all?from_position=1")
+            .to_request();
+        let events = lines(test::call_and_read_body(&app, request).await);
+        assert_eq!(
+            events.iter().map(|e| e.position).collect::<Vec<_>>(),
+            [2, 3]
+        );
+
+        let request = test::TestRequest::get().uri("/streams/nope").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/subscribe?stream={}", first))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            EVENT_STREAM
+        );
+        async fn next(body: &mut std::pin::Pin<Box<actix_web::body::BoxBody>>) -> String {
+            let chunk = std::future::poll_fn(|cx| body.as_mut().poll_next(cx)).await;
+            String::from_utf8(chunk.unwrap().unwrap().to_vec()).unwrap()
+        }
+        let mut body = Box::pin(response.into_body());
+        assert!(next(&mut body)
+            .await
+            .starts_with("id: 1\nevent: Touched\ndata: {"));
+        assert!(next(&mut body).await.starts_with("id: 2\n"));
+
+        let response = test::call_service(&app, post(vec![event(first, 3)])).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(next(&mut body).await.starts_with("id: 4\n"));
+
+        let followed = EventStream::all(backend.clone(), 3)
+            .with_stream_filter(first)
+            .follow(std::time::Duration::from_millis(10));
+        let request = test::TestRequest::default().to_http_request();
+        let mut body = Box::pin(followed.respond_to(&request).into_body());
+        assert!(next(&mut body).await.starts_with("id: 4\n"));
+    });
+
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "graphql")]
+#[test_log::test]
+fn graphql_pages_streams_and_subscribes() {
+    use eventstore::backend::sqlite::Cursor;
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let mut ids = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+    ids.sort();
+    for id in ids {
+        let events: Vec<_> = (1..=3)
+            .map(|version| Event {
+                id,
+                version,
+                event_type: "Touched".to_string(),
+                data: format!(r#"{{"n":{}}}"#, version).into_bytes(),
+                ..Default::default()
+            })
+            .collect();
+        backend.append_events(&events).unwrap();
+    }
+    let schema = eventstore::graphql::schema(backend.clone(), std::time::Duration::from_millis(10));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let query = |query: String| {
+        let response = runtime.block_on(schema.execute(query));
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    };
+
+    let data = query(
+        "{ streams(first: 1) { edges { cursor node { id version } } pageInfo { hasNextPage } } }"
+            .to_string(),
+    );
+    assert_eq!(
+        data["streams"],
+        json!({
+            "edges": [{ "cursor": Cursor::after_stream(ids[0]).to_string(), "node": { "id": ids[0].to_string(), "version": 3 } }],
+            "pageInfo": { "hasNextPage": true },
+        })
+    );
+    let data = query(format!(
+        r#"{{ streams(after: "{}") {{ nodes {{ id }} pageInfo {{ hasNextPage hasPreviousPage }} }} }}"#,
+        Cursor::after_stream(ids[0])
+    ));
+    assert_eq!(
+        data["streams"]["nodes"],
+        json!([{ "id": ids[1].to_string() }])
+    );
+    assert_eq!(
+        data["streams"]["pageInfo"],
+        json!({ "hasNextPage": false, "hasPreviousPage": true })
+    );
+
+    let data = query(format!(
+        r#"{{ stream(id: "{}") {{ version events(first: 1, after: "1") {{ edges {{ cursor node {{ version data }} }} pageInfo {{ hasNextPage }} }} }} }}"#,
+        ids[1]
+    ));
+    assert_eq!(
+        data["stream"],
+        json!({
+            "version": 3,
+            "events": {
+                "edges": [{ "cursor": "2", "node": { "version": 2, "data": { "n": 2 } } }],
+                "pageInfo": { "hasNextPage": true },
+            },
+        })
+    );
+    let data = query(format!(
+        r#"{{ stream(id: "{}") {{ version }} }}"#,
+        uuid::Uuid::new_v4()
+    ));
+    assert_eq!(data["stream"], json!(null));
+
+    let data = query(format!(
+        r#"{{ events(after: "{}", first: 10) {{ nodes {{ position aggregateId }} }} }}"#,
+        Cursor::after_position(4)
+    ));
+    let positions: Vec<_> = data["events"]["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["position"].as_u64().unwrap())
+        .collect();
+    assert_eq!(positions, [5, 6]);
+    let response =
+        runtime.block_on(schema.execute(r#"{ events(after: "x") { nodes { position } } }"#));
+    assert_eq!(response.errors[0].message, "invalid cursor");
+
+    runtime.block_on(async {
+        let mut events = schema.execute_stream(format!(
+            r#"subscription {{ events(fromPosition: 2, stream: "{}") {{ position version }} }}"#,
+            ids[0]
+        ));
+        let response = events.next().await.unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap()["events"],
+            json!({ "position": 3, "version": 3 })
+        );
+        backend
+            .append_events(&[Event {
+                id: ids[0],
+                version: 4,
+                event_type: "Touched".to_string(),
+                data: b"{}".to_vec(),
+                ..Default::default()
+            }])
+            .unwrap();
+        let response = events.next().await.unwrap();
+        assert_eq!(
+            response.data.into_json().unwrap()["events"],
+            json!({ "position": 7, "version": 4 })
+        );
+    });
+
+    drop(schema);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "mqtt")]
+#[test_log::test]
+fn mqtt_relay_publishes_events_with_checkpoints() {
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    use eventstore::backend::sqlite::Error;
+    use eventstore::mqtt::{BrokerPublisher, MqttMessage, MqttOptions, MqttRelay, Publisher, QoS};
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        published: Arc<Mutex<Vec<MqttMessage>>>,
+        fail: Arc<Mutex<bool>>,
+    }
+
+    impl Publisher for Recorder {
+        fn publish(&mut self, messages: &[MqttMessage]) -> Result<(), Error> {
+            if *self.fail.lock().unwrap() {
+                return Err(Error::WithMsg("broker down".to_string()));
+            }
+            self.published.lock().unwrap().extend_from_slice(messages);
+            Ok(())
+        }
+    }
+
+    let _span = debug_span!("test-main-span").entered();
+    let backend =
+        eventstore::backend::sqlite::SqliteBackend::new(SqliteConnectionManager::memory());
+    let sensor = uuid::Uuid::new_v4();
+    let append = |version: u32, event_type: &str| {
+        backend
+            .append_event(&Event {
+                id: sensor,
+                version,
+                event_type: event_type.to_string(),
+                data: br#"{"celsius":21}"#.to_vec(),
+                ..Default::default()
+            })
+            .unwrap();
+    };
+    append(1, "TemperatureRead");
+    append(2, "Sensor/Reset#1");
+
+    let recorder = Recorder::default();
+    let mut relay = MqttRelay::new(backend.clone(), "uplink", recorder.clone())
+        .with_topic("site/{event_type}/{aggregate_id}")
+        .with_qos(QoS::ExactlyOnce)
+        .with_batch_size(1);
+    assert_eq!(relay.run_until_caught_up().unwrap(), 2);
+    {
+        let published = recorder.published.lock().unwrap();
+        let topics: Vec<_> = published.iter().map(|m| m.topic.clone()).collect();
+        assert_eq!(
+            topics,
+            [
+                format!("site/TemperatureRead/{}", sensor),
+                format!("site/Sensor_Reset_1/{}", sensor)
+            ]
+        );
+        assert!(published.iter().all(|m| m.qos == QoS::ExactlyOnce));
+        let envelope: serde_json::Value = serde_json::from_slice(&published[0].payload).unwrap();
+        assert_eq!(envelope["data"]["celsius"], 21);
+        assert_eq!(envelope["version"], 1);
+    }
+    let head = backend.read_all(0, 10).unwrap()[1].position;
+    assert_eq!(backend.get_checkpoint("uplink").unwrap(), head);
+
+    // a failed batch is not checkpointed and published again
+    append(3, "TemperatureRead");
+    *recorder.fail.lock().unwrap() = true;
+    assert!(relay.run_once().is_err());
+    assert_eq!(backend.get_checkpoint("uplink").unwrap(), head);
+    *recorder.fail.lock().unwrap() = false;
+    assert_eq!(relay.run_once().unwrap(), 1);
+    assert_eq!(recorder.published.lock().unwrap().len(), 3);
+
+    // against a broker acknowledging QoS 1 publishes
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut topics = Vec::new();
+        loop {
+            let mut header = [0u8; 1];
+            // until the relay disconnects
+            if socket.read_exact(&mut header).is_err() {
+                return topics;
+            }
+            let (mut len, mut shift) = (0usize, 0);
+            loop {
+                let mut byte = [0u8; 1];
+                socket.read_exact(&mut byte).unwrap();
+                len |= ((byte[0] & 0x7f) as usize) << shift;
+                shift += 7;
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; len];
+            socket.read_exact(&mut body).unwrap();
+            match header[0] >> 4 {
+                1 => socket.write_all(&[0x20, 2, 0, 0]).unwrap(),
+                3 => {
+                    let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    topics.push(String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap());
+                    let pkid = &body[2 + topic_len..4 + topic_len];
+                    socket.write_all(&[0x40, 2, pkid[0], pkid[1]]).unwrap();
+                }
+                _ => {}
+            }
+        }
+    });
+    let publisher = BrokerPublisher::new(MqttOptions::new("edge-1", "127.0.0.1", port))
+        .with_timeout(std::time::Duration::from_secs(5));
+    let mut relay = MqttRelay::new(backend.clone(), "broker", publisher).with_batch_size(2);
+    assert_eq!(relay.run_until_caught_up().unwrap(), 3);
+    drop(relay);
+    let topics = broker.join().unwrap();
+    assert_eq!(topics[0], format!("eventstore/TemperatureRead/{}", sensor));
+    assert_eq!(topics.len(), 3);
+    assert_eq!(
+        backend.get_checkpoint("broker").unwrap(),
+        backend.get_checkpoint("uplink").unwrap()
+    );
+}
+
+#[cfg(feature = "http")]
+#[test_log::test]
+fn http_router_appends_and_pages_through_streams() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use eventstore::authorization::CallerContext;
+    use eventstore::http::HttpApi;
+    use tower::ServiceExt;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let router = HttpApi::new(backend.clone())
+        .with_authenticator(|headers: &axum::http::HeaderMap| {
+            match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                Some("Bearer secret") => Ok(CallerContext::default().with_principal("test")),
+                _ => Err(StatusCode::UNAUTHORIZED),
+            }
+        })
+        .into_router();
+    let aggregate_id = uuid::Uuid::new_v4();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let call = |method: &str, uri: String, body: Option<serde_json::Value>| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer secret")
+            .header("content-type", "application/json")
+            .body(match body {
+                Some(body) => Body::from(body.to_string()),
+                None => Body::empty(),
+            })
+            .unwrap();
+        let router = router.clone();
+        runtime.block_on(async move {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        })
+    };
+
+    let events: Vec<_> = (1..=3)
+        .map(|n| serde_json::json!({ "event_type": "Counted", "data": { "n": n } }))
+        .collect();
+    let (status, body) = call(
+        "POST",
+        format!("/streams/{}", aggregate_id),
+        Some(serde_json::json!({ "expected_version": 0, "events": events })),
+    );
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["version"], 3);
+    let (status, _) = call(
+        "POST",
+        format!("/streams/{}", aggregate_id),
+        Some(serde_json::json!({ "expected_version": 0, "events": [] })),
+    );
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = call(
+        "POST",
+        format!("/streams/{}", aggregate_id),
+        Some(serde_json::json!({ "expected_version": 1, "events": [{ "event_type": "Late" }] })),
+    );
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, page) = call("GET", format!("/streams/{}?limit=2", aggregate_id), None);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["events"].as_array().unwrap().len(), 2);
+    assert_eq!(page["events"][0]["data"]["n"], 1);
+    assert_eq!(page["next"], 2);
+    let (_, page) = call(
+        "GET",
+        format!("/streams/{}?since_version=2&limit=2", aggregate_id),
+        None,
+    );
+    assert_eq!(page["events"][0]["version"], 3);
+    assert!(page.get("next").is_none());
+    let (_, page) = call("GET", "/all?limit=10".to_string(), None);
+    assert_eq!(page["events"].as_array().unwrap().len(), 3);
+    let (status, position) = call("GET", "/position".to_string(), None);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(position["position"], backend.current_position().unwrap());
+
+    let (status, _) = call(
+        "GET",
+        format!("/streams/{}/snapshots/1", aggregate_id),
+        None,
+    );
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call("GET", "/streams/not-a-uuid".to_string(), None);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let retried = || {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("/streams/{}", aggregate_id))
+            .header("authorization", "Bearer secret")
+            .header("content-type", "application/json")
+            .header("idempotency-key", "late-1")
+            .body(Body::from(
+                serde_json::json!({ "expected_version": 3, "events": [{ "event_type": "Late" }] })
+                    .to_string(),
+            ))
+            .unwrap();
+        runtime
+            .block_on(router.clone().oneshot(request))
+            .unwrap()
+            .status()
+    };
+    assert_eq!(retried(), StatusCode::CREATED);
+    assert_eq!(retried(), StatusCode::OK);
+    assert_eq!(backend.stream_version(aggregate_id).unwrap(), 4);
+
+    let unauthenticated = Request::builder().uri("/all").body(Body::empty()).unwrap();
+    let response = runtime
+        .block_on(router.clone().oneshot(unauthenticated))
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    drop(router);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "http")]
+#[test_log::test]
+fn atom_feed_pages_by_link_relations() {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use eventstore::http::{Feed, FEED_CONTENT_TYPE};
+    use tower::ServiceExt;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let router = axum::Router::new().nest("/api", eventstore::http::router(backend.clone()));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let get = |uri: &str| {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let router = router.clone();
+        runtime.block_on(async move {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let content_type = response.headers().get("content-type").cloned();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            if status == StatusCode::OK {
+                assert_eq!(content_type.unwrap(), FEED_CONTENT_TYPE);
+            }
+            (status, body)
+        })
+    };
+    let feed = |uri: &str| {
+        let (status, body) = get(uri);
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        serde_json::from_slice::<Feed>(&body).unwrap()
+    };
+    let numbers = |feed: &Feed| {
+        feed.entries
+            .iter()
+            .map(|entry| entry.event_number)
+            .collect::<Vec<_>>()
+    };
+
+    let stream = uuid::Uuid::new_v4();
+    let other = uuid::Uuid::new_v4();
+    for (id, versions) in [(stream, 1..=5), (other, 1..=2)] {
+        for version in versions {
+            backend
+                .append_event(&Event {
+                    id,
+                    version,
+                    event_type: "Counted".to_string(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+    }
+
+    let head = feed(&format!("/api/feed/{}/head/backward/2", stream));
+    assert_eq!(numbers(&head), vec![5, 4]);
+    assert!(head.head_of_stream);
+    assert_eq!(head.entries[0].title, format!("5@{}", stream));
+    let older = feed(head.link("next").unwrap());
+    assert_eq!(numbers(&older), vec![3, 2]);
+    assert!(!older.head_of_stream);
+    let oldest = feed(older.link("next").unwrap());
+    assert_eq!(numbers(&oldest), vec![1]);
+    assert!(oldest.link("next").is_none());
+    let last = feed(head.link("last").unwrap());
+    assert_eq!(numbers(&last), vec![2, 1]);
+    let newer = feed(last.link("previous").unwrap());
+    assert_eq!(numbers(&newer), vec![4, 3]);
+
+    // consumers poll the previous link of the head page for new events
+    let poll = head.link("previous").unwrap().to_string();
+    assert!(feed(&poll).entries.is_empty());
+    backend
+        .append_event(&Event {
+            id: stream,
+            version: 6,
+            event_type: "Counted".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(numbers(&feed(&poll)), vec![6]);
+
+    let all = feed("/api/feed/$all");
+    assert_eq!(numbers(&all), (1..=8).rev().collect::<Vec<_>>());
+    assert_eq!(all.stream_id, "$all");
+    assert_eq!(all.link("self"), Some("/api/feed/$all"));
+    let (status, body) = get(&all.entries[0].id);
+    assert_eq!(status, StatusCode::OK);
+    let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(entry["content"]["aggregate_id"], stream.to_string());
+
+    let (status, _) = get(&format!("/api/feed/{}", uuid::Uuid::new_v4()));
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get(&format!("/api/feed/{}/1/sideways/2", stream));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    drop(router);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "http")]
+#[test_log::test]
+fn axum_handlers_use_store_state_and_correlation_ids() {
+    use axum::body::Body;
+    use axum::extract::State;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::post;
+    use eventstore::http::{
+        ApiError, CorrelationId, EventStoreHandle, CORRELATION_ID_HEADER, CORRELATION_ID_KEY,
+    };
+    use tower::ServiceExt;
+
+    async fn append(
+        State(store): State<EventStoreHandle>,
+        correlation_id: CorrelationId,
+        body: String,
+    ) -> Result<StatusCode, ApiError> {
+        let (id, version) = body.split_once('@').unwrap();
+        let mut events = vec![Event {
+            id: uuid::Uuid::parse_str(id).unwrap(),
+            version: version.parse().unwrap(),
+            event_type: "Touched".to_string(),
+            data: b"{}".to_vec(),
+            ..Default::default()
+        }];
+        correlation_id.apply(&mut events);
+        store
+            .run(move |backend| backend.append_events(&events))
+            .await?;
+        Ok(StatusCode::CREATED)
+    }
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let app = axum::Router::new()
+        .route("/touch", post(append))
+        .with_state(EventStoreHandle::new(backend.clone()))
+        .nest("/api", eventstore::http::router(backend.clone()));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let send = |request: Request<Body>| {
+        let app = app.clone();
+        runtime.block_on(async move {
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).ok(),
+            )
+        })
+    };
+    let stream = uuid::Uuid::new_v4();
+
+    let request = Request::post("/touch")
+        .header(CORRELATION_ID_HEADER, "req-1")
+        .body(Body::from(format!("{}@1", stream)))
+        .unwrap();
+    assert_eq!(send(request).0, StatusCode::CREATED);
+    let request = Request::post("/touch")
+        .body(Body::from(format!("{}@2", stream)))
+        .unwrap();
+    assert_eq!(send(request).0, StatusCode::CREATED);
+    let request = Request::post("/touch")
+        .body(Body::from(format!("{}@2", stream)))
+        .unwrap();
+    let (status, body) = send(request);
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body.unwrap()["error"].is_string());
+
+    let request = Request::post(format!("/api/streams/{}", stream))
+        .header(CORRELATION_ID_HEADER, "req-3")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            r#"{"expected_version": 2, "events": [{"event_type": "Touched", "data": {}}]}"#,
+        ))
+        .unwrap();
+    assert_eq!(send(request).0, StatusCode::CREATED);
+
+    let events = backend.get_aggretate(stream).unwrap();
+    let ids: Vec<_> = events
+        .iter()
+        .map(|event| event.metadata[CORRELATION_ID_KEY].clone())
+        .collect();
+    assert_eq!(ids[0], "req-1");
+    assert!(uuid::Uuid::parse_str(&ids[1]).is_ok());
+    assert_eq!(ids[2], "req-3");
+
+    drop(app);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "http")]
+#[test_log::test]
+fn websocket_subscription_streams_matching_events() {
+    use eventstore::http::HttpApi;
+    use futures_util::StreamExt;
+
+    let _span = debug_span!("test-main-span").entered();
+    let path = std::env::temp_dir().join(format!("eventstore-{}.db", uuid::Uuid::new_v4()));
+    let backend = SqliteBackend::new(SqliteConnectionManager::file(&path));
+    let orders = uuid::Uuid::new_v4();
+    let event = |id, version, event_type: &str| Event {
+        id,
+        version,
+        event_type: event_type.to_string(),
+        data: b"{}".to_vec(),
+        ..Default::default()
+    };
+    backend
+        .append_events(&[
+            event(orders, 1, "order.placed"),
+            event(orders, 2, "invoice.sent"),
+        ])
+        .unwrap();
+    let router = HttpApi::new(backend.clone())
+        .with_poll_interval(std::time::Duration::from_millis(10))
+        .into_router();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{}/subscribe?stream={}&category=order.",
+            addr, orders
+        ))
+        .await
+        .unwrap();
+        let parse = |message: tokio_tungstenite::tungstenite::Message| {
+            serde_json::from_str::<serde_json::Value>(message.to_text().unwrap()).unwrap()
+        };
+        let first = parse(socket.next().await.unwrap().unwrap());
+        assert_eq!(first["event_type"], "order.placed");
+        let writer = backend.clone();
+        tokio::task::spawn_blocking(move || {
+            writer
+                .append_events(&[
+                    event(uuid::Uuid::new_v4(), 1, "order.placed"),
+                    event(orders, 3, "order.shipped"),
+                ])
+                .unwrap()
+        })
+        .await
+        .unwrap();
+        let live = parse(socket.next().await.unwrap().unwrap());
+        assert_eq!(live["event_type"], "order.shipped");
+        assert_eq!(live["version"], 3);
+    });
+    drop(runtime);
+    drop(backend);
+    std::fs::remove_file(&path).unwrap();
+}